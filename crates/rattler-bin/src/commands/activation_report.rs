@@ -0,0 +1,52 @@
+use std::{fs, path::PathBuf, str::FromStr};
+
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use rattler_shell::{
+    activation::{ActivationVariables, Activator},
+    shell::ShellEnum,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// Target prefix to activate (defaults to `.prefix`)
+    #[clap(long, short, default_value = ".prefix")]
+    target_prefix: PathBuf,
+
+    /// The shell to run the activation with (defaults to the shell in `SHELL`)
+    #[clap(long)]
+    shell: Option<String>,
+
+    /// Only show the steps that change this environment variable
+    #[clap(long)]
+    var: Option<String>,
+
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+pub fn activation_report(opt: Opt) -> miette::Result<()> {
+    let shell = match opt.shell {
+        Some(shell) => ShellEnum::from_str(&shell).into_diagnostic()?,
+        None => ShellEnum::from_env().unwrap_or_default(),
+    };
+    let prefix = fs::canonicalize(&opt.target_prefix).into_diagnostic()?;
+
+    let activator = Activator::from_path(&prefix, shell, Platform::current()).into_diagnostic()?;
+    let variables = ActivationVariables::from_env().into_diagnostic()?;
+    let mut report = activator
+        .activation_report(variables, None)
+        .into_diagnostic()?;
+
+    if let Some(var) = &opt.var {
+        report.steps.retain(|step| step.diff.contains(var));
+    }
+
+    if opt.json {
+        println!("{}", report.to_json().into_diagnostic()?);
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+
+use miette::IntoDiagnostic;
+use rattler_conda_types::{compression_level::CompressionLevel, package::ArchiveType};
+use rattler_package_streaming::convert::{convert_package, ConvertOptions};
+
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The package archives to convert
+    #[clap(required = true)]
+    packages: Vec<PathBuf>,
+
+    /// The directory to write the converted packages to (defaults to the
+    /// directory of each package)
+    #[clap(long, short)]
+    output_dir: Option<PathBuf>,
+
+    /// The compression level to use (zstd: -7-22, bzip2: 1-9)
+    #[clap(long)]
+    compression_level: Option<i32>,
+
+    /// The base 2 logarithm of the zstd window size
+    #[clap(long)]
+    zstd_window_log: Option<u32>,
+
+    /// The number of threads to use for zstd compression
+    #[clap(long)]
+    threads: Option<u32>,
+}
+
+pub fn convert(opt: Opt) -> miette::Result<()> {
+    let options = ConvertOptions {
+        compression_level: opt
+            .compression_level
+            .map_or(CompressionLevel::Default, CompressionLevel::Numeric),
+        zstd_window_log: opt.zstd_window_log,
+        compression_num_threads: opt.threads,
+    };
+
+    for package in opt.packages {
+        let file_name = package
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (name, archive_type) = ArchiveType::split_str(&file_name)
+            .ok_or_else(|| miette::miette!("{} is not a package archive", package.display()))?;
+        let target_type = match archive_type {
+            ArchiveType::TarBz2 => ArchiveType::Conda,
+            ArchiveType::Conda => ArchiveType::TarBz2,
+        };
+
+        let output_dir = opt
+            .output_dir
+            .clone()
+            .or_else(|| package.parent().map(PathBuf::from))
+            .unwrap_or_default();
+        let destination = output_dir.join(format!("{name}{}", target_type.extension()));
+
+        convert_package(&package, &destination, &options).into_diagnostic()?;
+        println!("{} -> {}", package.display(), destination.display());
+    }
+
+    Ok(())
+}
@@ -15,20 +15,22 @@ use itertools::Itertools;
 use miette::{Context, IntoDiagnostic};
 use rattler::{
     default_cache_dir,
-    install::{IndicatifReporter, Installer, Transaction, TransactionOperation},
+    install::{
+        link_script::LinkScriptSandbox, IndicatifReporter, Installer, Transaction,
+        TransactionOperation,
+    },
     package_cache::PackageCache,
 };
 use rattler_conda_types::{
     Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, Matches, PackageName,
     ParseStrictness, Platform, PrefixRecord, RepoDataRecord, Version,
 };
-use rattler_networking::{AuthenticationMiddleware, AuthenticationStorage};
+use rattler_networking::{AuthenticationStorage, ClientConfig, RecordingMiddleware};
 use rattler_repodata_gateway::{Gateway, RepoData, SourceConfig};
 use rattler_solve::{
     libsolv_c::{self},
     resolvo, SolverImpl, SolverTask,
 };
-use reqwest::Client;
 
 use crate::global_multi_progress;
 
@@ -66,6 +68,29 @@ pub struct Opt {
 
     #[clap(long, group = "deps_mode")]
     no_deps: bool,
+
+    /// Record all HTTP interactions to the given directory
+    #[clap(long, conflicts_with = "replay_http")]
+    record_http: Option<PathBuf>,
+
+    /// Replay HTTP interactions from the given directory instead of accessing
+    /// the network
+    #[clap(long)]
+    replay_http: Option<PathBuf>,
+
+    /// The path to a config file to read credential helpers, proxies, TLS
+    /// settings and mirrors from. Uses the same configuration format as pixi.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Run the link scripts of the packages in a sandbox that only allows
+    /// writing to the target prefix. Requires the `sandbox` feature.
+    #[clap(long)]
+    sandbox_link_scripts: bool,
+
+    /// Allow the sandboxed link scripts to access the network.
+    #[clap(long, requires = "sandbox_link_scripts")]
+    allow_link_script_network: bool,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -154,22 +179,34 @@ pub async fn create(opt: Opt) -> miette::Result<()> {
     // `repodata.json` that should be available from the corresponding Url. The
     // code below also displays a nice CLI progress-bar to give users some more
     // information about what is going on.
-    let download_client = Client::builder()
-        .no_gzip()
-        .build()
-        .expect("failed to create client");
-
-    let download_client = reqwest_middleware::ClientBuilder::new(download_client)
-        .with_arc(Arc::new(
-            AuthenticationMiddleware::from_env_and_defaults().into_diagnostic()?,
-        ))
-        .with(rattler_networking::OciMiddleware)
-        .with(rattler_networking::S3Middleware::new(
-            HashMap::new(),
+    let config = opt
+        .config
+        .as_ref()
+        .map(|path| rattler_config::config::ConfigBase::<()>::load_from_files([path]))
+        .transpose()
+        .into_diagnostic()?;
+    let (auth_storage, client_config) = match &config {
+        Some(config) => (
+            AuthenticationStorage::from_config(config).into_diagnostic()?,
+            ClientConfig::from_config(config),
+        ),
+        None => (
             AuthenticationStorage::from_env_and_defaults().into_diagnostic()?,
-        ))
-        .with(rattler_networking::GCSMiddleware)
-        .build();
+            ClientConfig::default(),
+        ),
+    };
+
+    // The same client is used for the repodata, the package downloads and the
+    // S3 and OCI channels.
+    let mut middleware: Vec<Arc<dyn reqwest_middleware::Middleware>> = Vec::new();
+    if let Some(cassette) = &opt.record_http {
+        middleware.push(Arc::new(RecordingMiddleware::record(cassette)));
+    } else if let Some(cassette) = &opt.replay_http {
+        middleware.push(Arc::new(RecordingMiddleware::replay(cassette)));
+    }
+    let download_client = client_config
+        .build_with_middleware(auth_storage, middleware)
+        .into_diagnostic()?;
 
     // Get the package names from the matchspecs so we can only load the package
     // records that we need.
@@ -307,7 +344,13 @@ pub async fn create(opt: Opt) -> miette::Result<()> {
     }
 
     let install_start = Instant::now();
-    let result = Installer::new()
+    let mut installer = Installer::new();
+    if opt.sandbox_link_scripts {
+        installer.set_link_script_sandbox(
+            LinkScriptSandbox::new().with_network(opt.allow_link_script_network),
+        );
+    }
+    let result = installer
         .with_download_client(download_client)
         .with_target_platform(install_platform)
         .with_installed_packages(installed_packages)
@@ -1,4 +1,6 @@
+pub mod activation_report;
 pub mod auth;
+pub mod convert;
 pub mod create;
 pub mod menu;
 pub mod virtual_packages;
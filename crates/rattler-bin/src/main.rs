@@ -40,7 +40,9 @@ struct Opt {
 /// Different commands supported by `rattler`.
 #[derive(Debug, clap::Subcommand)]
 enum Command {
+    ActivationReport(commands::activation_report::Opt),
     Auth(commands::auth::Opt),
+    Convert(commands::convert::Opt),
     Create(commands::create::Opt),
     VirtualPackages(commands::virtual_packages::Opt),
     InstallMenu(commands::menu::InstallOpt),
@@ -49,8 +51,20 @@ enum Command {
 }
 
 /// Entry point of the `rattler` cli.
-#[tokio::main]
-async fn main() -> miette::Result<()> {
+fn main() -> miette::Result<()> {
+    // Sandboxed link scripts are run by starting this executable again, which
+    // has to happen before the runtime spawns any threads.
+    #[cfg(feature = "sandbox")]
+    rattler::install::link_script::init_sandbox();
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .into_diagnostic()?
+        .block_on(async_main())
+}
+
+async fn async_main() -> miette::Result<()> {
     // Parse the command line arguments
     let opt = Opt::parse();
 
@@ -79,7 +93,9 @@ async fn main() -> miette::Result<()> {
 
     // Dispatch the selected comment
     match opt.command {
+        Command::ActivationReport(opts) => commands::activation_report::activation_report(opts),
         Command::Auth(opts) => commands::auth::auth(opts).await,
+        Command::Convert(opts) => commands::convert::convert(opts),
         Command::Create(opts) => commands::create::create(opts).await,
         Command::VirtualPackages(opts) => commands::virtual_packages::virtual_packages(opts),
         Command::InstallMenu(opts) => commands::menu::install_menu(opts).await,
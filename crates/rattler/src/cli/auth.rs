@@ -1,7 +1,11 @@
 //! This module contains CLI common entrypoint for authentication.
+use std::time::Duration;
+
 use clap::Parser;
 use rattler_networking::{
-    authentication_storage::AuthenticationStorageError, Authentication, AuthenticationStorage,
+    authentication_storage::AuthenticationStorageError,
+    oauth::{self, DevicePoll, OAuthError},
+    Authentication, AuthenticationStorage,
 };
 use reqwest::{header::CONTENT_TYPE, Client};
 use serde_json::json;
@@ -11,7 +15,9 @@ use url::Url;
 /// Command line arguments that contain authentication data
 #[derive(Parser, Debug)]
 struct LoginArgs {
-    /// The host to authenticate with (e.g. prefix.dev)
+    /// The host to authenticate with (e.g. prefix.dev). Credentials can be
+    /// scoped to a path with a URL prefix or glob, e.g.
+    /// `https://artifacts.corp.example/conda/team-a/*`
     host: String,
 
     /// The token to use (for authentication with prefix.dev)
@@ -41,20 +47,38 @@ struct LoginArgs {
     /// The S3 session token
     #[clap(long, requires_all = ["s3_access_key_id"])]
     s3_session_token: Option<String>,
+
+    /// The issuer URL of an OIDC provider to log in with the
+    /// device flow
+    #[clap(long, requires_all = ["client_id"], conflicts_with_all = ["token", "username", "password", "conda_token", "s3_access_key_id"])]
+    oidc_issuer: Option<Url>,
+
+    /// The OAuth client id to request tokens for
+    #[clap(long, requires_all = ["oidc_issuer"])]
+    client_id: Option<String>,
+
+    /// The scopes to request from the OIDC provider
+    #[clap(long = "scope", requires_all = ["oidc_issuer"])]
+    scopes: Vec<String>,
 }
 
 #[derive(Parser, Debug)]
 struct LogoutArgs {
-    /// The host to remove authentication for
+    /// The host or scope to remove authentication for
     host: String,
 }
 
 #[derive(Parser, Debug)]
 enum Subcommand {
-    /// Store authentication information for a given host
-    Login(LoginArgs),
-    /// Remove authentication information for a given host
+    /// Store authentication information for a given host or scope
+    #[clap(alias = "add")]
+    Login(Box<LoginArgs>),
+    /// Remove authentication information for a given host or scope
+    #[clap(alias = "remove")]
     Logout(LogoutArgs),
+    /// List the hosts and scopes that authentication information is stored
+    /// for
+    List,
 }
 
 /// Login to prefix.dev or anaconda.org servers to access private channels
@@ -114,14 +138,30 @@ pub enum AuthenticationCLIError {
     /// Token is unauthorized or invalid
     #[error("Unauthorized or invalid token")]
     UnauthorizedToken,
+
+    /// The OIDC provider returned an error
+    #[error("Failed to log in with the OIDC provider")]
+    OAuthError(#[from] OAuthError),
+
+    /// The user did not complete the device login in time
+    #[error("The device code expired before the login was completed")]
+    DeviceCodeExpired,
 }
 
 fn get_url(url: &str) -> Result<String, AuthenticationCLIError> {
-    // parse as url and extract host without scheme or port
+    // parse as url and extract host without scheme or port, unless the
+    // credentials are scoped to a path
     let host = if url.contains("://") {
-        url::Url::parse(url)?.host_str().unwrap().to_string()
+        let parsed = url::Url::parse(url)?;
+        let path = parsed.path().trim_end_matches('/');
+        if !path.is_empty() {
+            return Ok(url.trim_end_matches('/').to_string());
+        }
+        parsed.host_str().unwrap().to_string()
+    } else if url.trim_end_matches('/').contains('/') {
+        return Ok(url.trim_end_matches('/').to_string());
     } else {
-        url.to_string()
+        url.trim_end_matches('/').to_string()
     };
 
     let host = if host.matches('.').count() == 1 {
@@ -152,7 +192,9 @@ async fn login(
     args: LoginArgs,
     storage: AuthenticationStorage,
 ) -> Result<(), AuthenticationCLIError> {
-    let auth = if let Some(conda_token) = args.conda_token {
+    let auth = if let (Some(issuer), Some(client_id)) = (&args.oidc_issuer, &args.client_id) {
+        device_login(issuer, client_id, &args.scopes).await?
+    } else if let Some(conda_token) = args.conda_token {
         Authentication::CondaToken(conda_token)
     } else if let Some(username) = args.username {
         if args.password.is_none() {
@@ -219,6 +261,39 @@ async fn login(
     Ok(())
 }
 
+/// Logs in with the device authorization grant of an OIDC provider:
+/// the user is asked to confirm a code in the browser while the token endpoint
+/// is polled.
+async fn device_login(
+    issuer: &Url,
+    client_id: &str,
+    scopes: &[String],
+) -> Result<Authentication, AuthenticationCLIError> {
+    let client = Client::new();
+    let metadata = oauth::discover(&client, issuer).await?;
+    let device = oauth::request_device_authorization(&client, &metadata, client_id, scopes).await?;
+
+    match &device.verification_uri_complete {
+        Some(uri) => eprintln!("Open {uri} in your browser to log in"),
+        None => eprintln!(
+            "Open {} in your browser and enter the code {}",
+            device.verification_uri, device.user_code
+        ),
+    }
+
+    let mut interval = Duration::from_secs(device.interval);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(device.expires_in);
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(interval).await;
+        match oauth::poll_device_token(&client, &metadata, client_id, &device).await? {
+            DevicePoll::Complete(auth) => return Ok(auth),
+            DevicePoll::Pending => {}
+            DevicePoll::SlowDown => interval += Duration::from_secs(5),
+        }
+    }
+    Err(AuthenticationCLIError::DeviceCodeExpired)
+}
+
 /// Validates a token with prefix.dev by making a GraphQL API call
 ///
 /// Returns `Ok(true)` if the token is valid, `Ok(false)` if invalid,
@@ -290,13 +365,25 @@ fn logout(args: LogoutArgs, storage: AuthenticationStorage) -> Result<(), Authen
     Ok(())
 }
 
+fn list(storage: &AuthenticationStorage) -> Result<(), AuthenticationCLIError> {
+    let entries = storage.list()?;
+    if entries.is_empty() {
+        eprintln!("No stored credentials found");
+    }
+    for (key, auth) in entries {
+        println!("{key}\t{}", auth.method());
+    }
+    Ok(())
+}
+
 /// CLI entrypoint for authentication
 pub async fn execute(args: Args) -> Result<(), AuthenticationCLIError> {
     let storage = AuthenticationStorage::from_env_and_defaults()?;
 
     match args.subcommand {
-        Subcommand::Login(args) => login(args, storage).await,
+        Subcommand::Login(args) => login(*args, storage).await,
         Subcommand::Logout(args) => logout(args, storage),
+        Subcommand::List => list(&storage),
     }
 }
 
@@ -331,6 +418,9 @@ mod tests {
             s3_access_key_id: None,
             s3_secret_access_key: None,
             s3_session_token: None,
+            oidc_issuer: None,
+            client_id: None,
+            scopes: Vec::new(),
         }
     }
 
@@ -509,6 +599,58 @@ mod tests {
         assert!(matches!(result, Err(AuthenticationCLIError::S3BadMethod)));
     }
 
+    #[tokio::test]
+    async fn test_login_scoped_credentials() {
+        let (storage, _temp_dir) = create_test_storage();
+        for (scope, token) in [
+            ("https://artifacts.corp.example/conda/team-a/*", "team-a"),
+            ("artifacts.corp.example/conda/team-b/", "team-b"),
+            ("corp.example", "corp"),
+        ] {
+            let mut args = create_login_args(scope);
+            args.token = Some(token.to_string());
+            login(args, storage.clone()).await.unwrap();
+        }
+
+        assert_eq!(
+            storage.list().unwrap().keys().collect::<Vec<_>>(),
+            [
+                "*.corp.example",
+                "artifacts.corp.example/conda/team-b",
+                "https://artifacts.corp.example/conda/team-a/*",
+            ]
+        );
+
+        let token = |url: &str| match storage.get_by_url(url).unwrap().1 {
+            Some(Authentication::BearerToken(token)) => token,
+            auth => panic!("unexpected credentials {auth:?}"),
+        };
+        assert_eq!(
+            token("https://artifacts.corp.example/conda/team-a/noarch/repodata.json"),
+            "team-a"
+        );
+        assert_eq!(
+            token("https://artifacts.corp.example/conda/team-b/noarch/repodata.json"),
+            "team-b"
+        );
+        assert_eq!(
+            token("https://artifacts.corp.example/conda/team-c/noarch/repodata.json"),
+            "corp"
+        );
+
+        logout(
+            LogoutArgs {
+                host: "https://artifacts.corp.example/conda/team-a/*".to_string(),
+            },
+            storage.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            token("https://artifacts.corp.example/conda/team-a/noarch/repodata.json"),
+            "corp"
+        );
+    }
+
     #[tokio::test]
     async fn test_login_s3_credentials_with_non_s3_host() {
         let (storage, _temp_dir) = create_test_storage();
@@ -519,4 +661,82 @@ mod tests {
         let result = login(args, storage).await;
         assert!(matches!(result, Err(AuthenticationCLIError::S3BadMethod)));
     }
+
+    #[tokio::test]
+    async fn test_login_oidc_device_flow() {
+        let (storage, _temp_dir) = create_test_storage();
+
+        let mut server = Server::new_async().await;
+        let issuer = server.url();
+        let discovery = server
+            .mock("GET", "/.well-known/openid-configuration")
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "token_endpoint": format!("{issuer}/token"),
+                    "device_authorization_endpoint": format!("{issuer}/device"),
+                })
+                .to_string(),
+            )
+            .create();
+        let device = server
+            .mock("POST", "/device")
+            .match_body(mockito::Matcher::UrlEncoded(
+                "scope".to_string(),
+                "openid offline_access".to_string(),
+            ))
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "device_code": "device-code",
+                    "user_code": "ABCD-EFGH",
+                    "verification_uri": format!("{issuer}/activate"),
+                    "expires_in": 60,
+                    "interval": 0
+                })
+                .to_string(),
+            )
+            .create();
+        let token = server
+            .mock("POST", "/token")
+            .match_body(mockito::Matcher::UrlEncoded(
+                "device_code".to_string(),
+                "device-code".to_string(),
+            ))
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "access_token": "access",
+                    "refresh_token": "refresh",
+                    "expires_in": 3600
+                })
+                .to_string(),
+            )
+            .create();
+
+        let mut args = create_login_args("internal.example.com");
+        args.oidc_issuer = Some(issuer.parse().unwrap());
+        args.client_id = Some("rattler".to_string());
+        args.scopes = vec!["openid".to_string(), "offline_access".to_string()];
+
+        login(args, storage.clone()).await.unwrap();
+        discovery.assert();
+        device.assert();
+        token.assert();
+
+        let Some(Authentication::OAuth {
+            access_token,
+            refresh_token,
+            token_endpoint,
+            client_id,
+            ..
+        }) = storage.get("internal.example.com").unwrap()
+        else {
+            panic!("expected OAuth credentials to be stored");
+        };
+        assert_eq!(access_token, "access");
+        assert_eq!(refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(token_endpoint, format!("{issuer}/token"));
+        assert_eq!(client_id, "rattler");
+    }
 }
@@ -7,14 +7,16 @@ use std::{
 
 use indexmap::IndexSet;
 use itertools::Itertools;
-use rattler_conda_types::{prefix::Prefix, prefix_record::PathType, PackageRecord, PrefixRecord};
+use rattler_conda_types::{
+    menuinst::MenuMode, prefix::Prefix, prefix_record::PathType, PackageRecord, PrefixRecord,
+};
 use simple_spawn_blocking::{tokio::run_blocking_task, Cancelled};
 use thiserror::Error;
 use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
 
 use super::{
     clobber_registry::{ClobberError, ClobberRegistry, ClobberedPath},
-    link_script::{PrePostLinkError, PrePostLinkResult},
+    link_script::{LinkScriptSandbox, PrePostLinkError, PrePostLinkResult},
     unlink::{recursively_remove_empty_directories, UnlinkError},
     Transaction, TransactionOperation,
 };
@@ -31,6 +33,8 @@ pub struct InstallDriver {
     io_concurrency_semaphore: Option<Arc<Semaphore>>,
     pub(crate) clobber_registry: Arc<Mutex<ClobberRegistry>>,
     execute_link_scripts: bool,
+    pub(crate) link_script_sandbox: Option<LinkScriptSandbox>,
+    menu_mode: Option<MenuMode>,
 }
 
 impl Default for InstallDriver {
@@ -48,6 +52,8 @@ pub struct InstallDriverBuilder {
     io_concurrency_semaphore: Option<Arc<Semaphore>>,
     clobber_registry: Option<ClobberRegistry>,
     execute_link_scripts: bool,
+    link_script_sandbox: Option<LinkScriptSandbox>,
+    menu_mode: Option<MenuMode>,
 }
 
 /// The result of the post-processing step.
@@ -113,6 +119,22 @@ impl InstallDriverBuilder {
         }
     }
 
+    /// Sets the sandbox in which link scripts are run. By default, link
+    /// scripts are run without a sandbox.
+    pub fn with_link_script_sandbox(self, link_script_sandbox: Option<LinkScriptSandbox>) -> Self {
+        Self {
+            link_script_sandbox,
+            ..self
+        }
+    }
+
+    /// Sets whether to install the menu items (`Menu/*.json`) of newly
+    /// installed packages, and for whom. By default, no menu items are
+    /// installed.
+    pub fn with_menu_mode(self, menu_mode: Option<MenuMode>) -> Self {
+        Self { menu_mode, ..self }
+    }
+
     pub fn finish(self) -> InstallDriver {
         InstallDriver {
             io_concurrency_semaphore: self.io_concurrency_semaphore,
@@ -122,6 +144,8 @@ impl InstallDriverBuilder {
                 .map(Arc::new)
                 .unwrap_or_default(),
             execute_link_scripts: self.execute_link_scripts,
+            link_script_sandbox: self.link_script_sandbox,
+            menu_mode: self.menu_mode,
         }
     }
 }
@@ -237,12 +261,62 @@ impl InstallDriver {
             None
         };
 
+        self.install_menu_items(transaction, &prefix_records, target_prefix);
+
         Ok(PostProcessResult {
             post_link_result,
             clobbered_paths,
         })
     }
 
+    /// Installs the menu items of all packages that were installed by the
+    /// transaction, if a menu mode is set. The menu items of packages that
+    /// were removed or updated are removed in [`Self::pre_process`].
+    fn install_menu_items<Old, New: AsRef<PackageRecord>>(
+        &self,
+        transaction: &Transaction<Old, New>,
+        prefix_records: &[PrefixRecord],
+        target_prefix: &Prefix,
+    ) {
+        let Some(menu_mode) = self.menu_mode else {
+            return;
+        };
+
+        let installed_packages = transaction
+            .installed_packages()
+            .map(|record| &record.as_ref().name)
+            .collect::<HashSet<_>>();
+
+        for record in prefix_records.iter().filter(|record| {
+            installed_packages.contains(&record.repodata_record.package_record.name)
+        }) {
+            // The record is written back with the installed menu items. Read
+            // it again because resolving clobbered paths might have changed
+            // it on disk.
+            let path = target_prefix
+                .path()
+                .join("conda-meta")
+                .join(record.file_name());
+            let result = PrefixRecord::from_path(&path)
+                .map_err(rattler_menuinst::MenuInstError::from)
+                .and_then(|record| {
+                    rattler_menuinst::install_menuitems_for_record(
+                        target_prefix,
+                        &record,
+                        transaction.platform,
+                        menu_mode,
+                    )
+                });
+            if let Err(e) = result {
+                tracing::warn!(
+                    "Failed to install menu items of {}: {}",
+                    record.repodata_record.package_record.name.as_normalized(),
+                    e
+                );
+            }
+        }
+    }
+
     /// Remove all empty directories that are not part of the new prefix
     /// records.
     pub fn remove_empty_directories<Old: Borrow<PrefixRecord>, New>(
@@ -24,6 +24,10 @@ pub enum InstallerError {
     #[error("failed to fetch {0}")]
     FailedToFetch(String, #[source] PackageCacheError),
 
+    /// The signature of a package could not be verified
+    #[error("the signature of {0} could not be verified")]
+    SignatureVerificationFailed(String, #[source] PackageCacheError),
+
     /// Failed to link a certain package
     #[error("failed to link {0}")]
     LinkError(String, #[source] InstallError),
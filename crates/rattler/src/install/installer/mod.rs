@@ -5,6 +5,7 @@ mod reporter;
 pub(crate) mod result_record;
 
 use std::{
+    cmp::Reverse,
     collections::{HashMap, HashSet},
     future::ready,
     io,
@@ -20,7 +21,7 @@ use crate::{
     default_cache_dir,
     install::{
         clobber_registry::ClobberedPath,
-        link_script::{LinkScriptError, PrePostLinkResult},
+        link_script::{LinkScriptError, LinkScriptSandbox, PrePostLinkResult},
     },
     package_cache::PackageCache,
 };
@@ -32,13 +33,19 @@ pub use indicatif::{
     ProgressFormatter,
 };
 use itertools::Itertools;
-use rattler_cache::package_cache::{CacheLock, CacheReporter};
+use rattler_cache::package_cache::{
+    CacheLock, CacheReporter, DownloadLimits, DownloadPriority, DownloadScheduler,
+    PackageCacheError,
+};
 use rattler_conda_types::{
+    menuinst::MenuMode,
     prefix_record::{Link, LinkType},
-    MatchSpec, PackageName, Platform, PrefixRecord, RepoDataRecord,
+    MatchSpec, PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord,
 };
 use rattler_networking::retry_policies::default_retry_policy;
 use rattler_networking::LazyClient;
+use rattler_package_streaming::ExtractError;
+use rattler_signatures::SignatureVerifier;
 use rayon::prelude::*;
 pub use reporter::Reporter;
 use simple_spawn_blocking::tokio::run_blocking_task;
@@ -51,13 +58,36 @@ pub struct LinkOptions {
     pub allow_ref_links: Option<bool>,
 }
 
+/// Determines which packages are downloaded first when not all packages can
+/// be downloaded at the same time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadOrder {
+    /// Download the largest packages first so that the longest downloads
+    /// overlap with the rest of the installation.
+    #[default]
+    LargestFirst,
+
+    /// Download the smallest packages first so that linking can start as soon
+    /// as possible.
+    SmallestFirst,
+
+    /// Download packages on the critical path first: dependencies are
+    /// downloaded before the packages that depend on them.
+    CriticalPath,
+}
+
 /// An installer that can install packages into a prefix.
 #[derive(Default)]
 pub struct Installer {
     installed: Option<Vec<PrefixRecord>>,
     package_cache: Option<PackageCache>,
     downloader: Option<LazyClient>,
+    download_limits: Option<DownloadLimits>,
+    download_order: DownloadOrder,
+    signature_verifier: Option<SignatureVerifier>,
     execute_link_scripts: bool,
+    link_script_sandbox: Option<LinkScriptSandbox>,
+    menu_mode: Option<MenuMode>,
     io_semaphore: Option<Arc<Semaphore>>,
     reporter: Option<Arc<dyn Reporter>>,
     target_platform: Option<Platform>,
@@ -158,6 +188,57 @@ impl Installer {
         self
     }
 
+    /// Runs link scripts in the given sandbox, which only allows them to
+    /// write to the target prefix and a temporary directory. This only has an
+    /// effect if link scripts are executed, see
+    /// [`Self::with_execute_link_scripts`].
+    ///
+    /// On Linux a script that violates the sandbox makes the link scripts
+    /// fail with a [`LinkScriptError`]. On macOS violations are denied but
+    /// not reported, so only scripts that fail because of them are reported
+    /// as failed. See [`LinkScriptSandbox`] for the requirements of
+    /// sandboxing.
+    #[must_use]
+    pub fn with_link_script_sandbox(self, sandbox: LinkScriptSandbox) -> Self {
+        Self {
+            link_script_sandbox: Some(sandbox),
+            ..self
+        }
+    }
+
+    /// Runs link scripts in the given sandbox.
+    ///
+    /// This function is similar to [`Self::with_link_script_sandbox`], but
+    /// modifies an existing instance.
+    pub fn set_link_script_sandbox(&mut self, sandbox: LinkScriptSandbox) -> &mut Self {
+        self.link_script_sandbox = Some(sandbox);
+        self
+    }
+
+    /// Installs the menu items (`Menu/*.json`) of every newly installed
+    /// package, for the current user or for all users depending on
+    /// `menu_mode`. The installed menu items are recorded in the
+    /// `installed_system_menus` of the [`PrefixRecord`] of the package and are
+    /// removed again when the package is removed or updated.
+    ///
+    /// By default, no menu items are installed.
+    #[must_use]
+    pub fn with_menu_mode(self, menu_mode: MenuMode) -> Self {
+        Self {
+            menu_mode: Some(menu_mode),
+            ..self
+        }
+    }
+
+    /// Installs the menu items of every newly installed package.
+    ///
+    /// This function is similar to [`Self::with_menu_mode`], but modifies an
+    /// existing instance.
+    pub fn set_menu_mode(&mut self, menu_mode: MenuMode) -> &mut Self {
+        self.menu_mode = Some(menu_mode);
+        self
+    }
+
     /// Sets the package cache to use.
     #[must_use]
     pub fn with_package_cache(self, package_cache: PackageCache) -> Self {
@@ -194,6 +275,69 @@ impl Installer {
         self
     }
 
+    /// Sets limits for the bandwidth and the number of concurrent downloads
+    /// that are used to fetch packages. These limits are independent of the IO
+    /// concurrency limit.
+    #[must_use]
+    pub fn with_download_limits(self, limits: DownloadLimits) -> Self {
+        Self {
+            download_limits: Some(limits),
+            ..self
+        }
+    }
+
+    /// Sets limits for the bandwidth and the number of concurrent downloads.
+    ///
+    /// This function is similar to [`Self::with_download_limits`], but
+    /// modifies an existing instance.
+    pub fn set_download_limits(&mut self, limits: DownloadLimits) -> &mut Self {
+        self.download_limits = Some(limits);
+        self
+    }
+
+    /// Sets the order in which packages are downloaded. This only has an
+    /// effect if downloads have to wait for each other, e.g. because of
+    /// [`Self::with_download_limits`].
+    #[must_use]
+    pub fn with_download_order(self, order: DownloadOrder) -> Self {
+        Self {
+            download_order: order,
+            ..self
+        }
+    }
+
+    /// Sets the order in which packages are downloaded.
+    ///
+    /// This function is similar to [`Self::with_download_order`], but modifies
+    /// an existing instance.
+    pub fn set_download_order(&mut self, order: DownloadOrder) -> &mut Self {
+        self.download_order = order;
+        self
+    }
+
+    /// Verifies the signature of every package that is downloaded before it
+    /// is extracted to the package cache. Installing a package whose signature
+    /// cannot be verified fails with
+    /// [`InstallerError::SignatureVerificationFailed`].
+    ///
+    /// Packages that are already in the cache are not verified again.
+    #[must_use]
+    pub fn with_signature_verifier(self, verifier: SignatureVerifier) -> Self {
+        Self {
+            signature_verifier: Some(verifier),
+            ..self
+        }
+    }
+
+    /// Verifies the signature of every package that is downloaded.
+    ///
+    /// This function is similar to [`Self::with_signature_verifier`], but
+    /// modifies an existing instance.
+    pub fn set_signature_verifier(&mut self, verifier: SignatureVerifier) -> &mut Self {
+        self.signature_verifier = Some(verifier);
+        self
+    }
+
     /// Sets a reporter that will receive events during the installation
     /// process.
     #[must_use]
@@ -474,10 +618,20 @@ impl Installer {
                     .join(rattler_cache::PACKAGE_CACHE_DIR),
             )
         });
+        let package_cache = match self.download_limits {
+            Some(limits) => package_cache.with_download_scheduler(DownloadScheduler::new(limits)),
+            None => package_cache,
+        };
+        let package_cache = match self.signature_verifier {
+            Some(verifier) => package_cache.with_signature_verifier(verifier),
+            None => package_cache,
+        };
 
         // Construct a driver.
         let driver = InstallDriver::builder()
             .execute_link_scripts(self.execute_link_scripts)
+            .with_link_script_sandbox(self.link_script_sandbox)
+            .with_menu_mode(self.menu_mode)
             .with_io_concurrency_semaphore(
                 self.io_semaphore.unwrap_or(Arc::new(Semaphore::new(100))),
             )
@@ -544,18 +698,15 @@ impl Installer {
         }
 
         let mut pending_link_futures = FuturesUnordered::new();
+        let download_priorities = download_priorities(&transaction, self.download_order);
         // Execute the operations (install) in the transaction.
         for (operation_idx, operation) in transaction
             .operations
             .iter()
             .enumerate()
-            .sorted_by_key(|(_, op)| {
-                op.record_to_install()
-                    .and_then(|r| r.package_record.size)
-                    .unwrap_or(0)
-            })
-            .rev()
+            .sorted_by_key(|(operation_idx, _)| download_priorities[*operation_idx])
         {
+            let download_priority = download_priorities[operation_idx];
             let downloader = &downloader;
             let package_cache = &package_cache;
             let reporter = self.reporter.clone();
@@ -585,6 +736,7 @@ impl Installer {
                             &record,
                             downloader,
                             &package_cache,
+                            download_priority,
                             populate_cache_report.clone(),
                         )
                         .await?;
@@ -751,6 +903,7 @@ async fn populate_cache(
     record: &RepoDataRecord,
     downloader: LazyClient,
     cache: &PackageCache,
+    priority: DownloadPriority,
     reporter: Option<(Arc<dyn Reporter>, usize)>,
 ) -> Result<CacheLock, InstallerError> {
     struct CacheReporterBridge {
@@ -781,11 +934,12 @@ async fn populate_cache(
     }
 
     cache
-        .get_or_fetch_from_url_with_retry(
+        .get_or_fetch_from_url_with_priority(
             &record.package_record,
             record.url.clone(),
             downloader,
             default_retry_policy(),
+            priority,
             reporter.map(|(reporter, cache_index)| {
                 Arc::new(CacheReporterBridge {
                     reporter,
@@ -794,7 +948,66 @@ async fn populate_cache(
             }),
         )
         .await
-        .map_err(|e| InstallerError::FailedToFetch(record.file_name.clone(), e))
+        .map_err(|e| {
+            let signature_failed = matches!(
+                &e,
+                PackageCacheError::FetchError(err)
+                    if matches!(
+                        err.downcast_ref::<ExtractError>(),
+                        Some(ExtractError::SignatureVerificationFailed(..))
+                    )
+            );
+            if signature_failed {
+                InstallerError::SignatureVerificationFailed(record.file_name.clone(), e)
+            } else {
+                InstallerError::FailedToFetch(record.file_name.clone(), e)
+            }
+        })
+}
+
+/// Determines the download priority of every operation in the transaction
+/// according to the requested order. Operations that do not install a package
+/// are placed last.
+fn download_priorities(
+    transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+    order: DownloadOrder,
+) -> Vec<DownloadPriority> {
+    let to_install = transaction
+        .operations
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, operation)| Some((idx, operation.record_to_install()?)))
+        .collect_vec();
+    let size = |record: &RepoDataRecord| record.package_record.size.unwrap_or(0);
+
+    let ordered = match order {
+        DownloadOrder::LargestFirst => to_install
+            .iter()
+            .sorted_by_key(|(_, record)| Reverse(size(record)))
+            .map(|(idx, _)| *idx)
+            .collect_vec(),
+        DownloadOrder::SmallestFirst => to_install
+            .iter()
+            .sorted_by_key(|(_, record)| size(record))
+            .map(|(idx, _)| *idx)
+            .collect_vec(),
+        DownloadOrder::CriticalPath => {
+            let operation_by_name: HashMap<_, _> = to_install
+                .iter()
+                .map(|(idx, record)| (&record.package_record.name, *idx))
+                .collect();
+            PackageRecord::sort_topologically(to_install.iter().map(|(_, r)| *r).collect())
+                .into_iter()
+                .map(|record| operation_by_name[&record.package_record.name])
+                .collect_vec()
+        }
+    };
+
+    let mut priorities = vec![DownloadPriority::MAX; transaction.operations.len()];
+    for (priority, idx) in ordered.into_iter().enumerate() {
+        priorities[idx] = priority as DownloadPriority;
+    }
+    priorities
 }
 
 /// Updates only the `requested_specs` fields in a conda-meta JSON file.
@@ -973,6 +1186,7 @@ mod tests {
     use url::Url;
 
     use super::*;
+    use crate::install::TransactionOperation;
 
     /// Creates a test environment with a temporary directory and prefix
     fn create_test_environment() -> (TempDir, Prefix) {
@@ -1037,6 +1251,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_download_priorities() {
+        // `a` depends on `b` which depends on `c`.
+        let record = |name: &str, size: u64, depends: &[&str]| {
+            let mut record = create_dummy_repo_record();
+            record.package_record.name = PackageName::new_unchecked(name);
+            record.package_record.size = Some(size);
+            record.package_record.depends = depends.iter().map(ToString::to_string).collect();
+            record
+        };
+        let transaction = Transaction::<PrefixRecord, RepoDataRecord> {
+            operations: vec![
+                TransactionOperation::Install(record("a", 10, &["b"])),
+                TransactionOperation::Install(record("c", 20, &[])),
+                TransactionOperation::Install(record("b", 30, &["c"])),
+            ],
+            python_info: None,
+            current_python_info: None,
+            platform: Platform::current(),
+            unchanged: Vec::new(),
+        };
+
+        assert_eq!(
+            download_priorities(&transaction, DownloadOrder::LargestFirst),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            download_priorities(&transaction, DownloadOrder::SmallestFirst),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            download_priorities(&transaction, DownloadOrder::CriticalPath),
+            vec![2, 0, 1]
+        );
+    }
+
     #[test]
     fn test_spec_mapping_helper() {
         // Test the spec mapping functionality
@@ -1136,6 +1386,28 @@ mod tests {
         assert_eq!(mapping[&numpy_name], vec!["numpy >=1.20"]);
     }
 
+    #[tokio::test]
+    async fn test_install_unsigned_package() {
+        let (_temp_dir, target_prefix) = create_test_environment();
+        let cache_dir = TempDir::new().unwrap();
+        let trust_root = rattler_signatures::TrustRoot::from_pem_file(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data/signatures/ca.pem"),
+        )
+        .unwrap();
+
+        let result = Installer::new()
+            .with_package_cache(PackageCache::new(cache_dir.path()))
+            .with_signature_verifier(SignatureVerifier::new(trust_root).with_identity_policy(
+                rattler_signatures::IdentityPolicy::new("publisher@example.com"),
+            ))
+            .install(&target_prefix, vec![create_dummy_repo_record()])
+            .await;
+        assert!(
+            matches!(result, Err(InstallerError::SignatureVerificationFailed(..))),
+            "{result:?}"
+        );
+    }
+
     #[tokio::test]
     async fn test_install_with_requested_specs_e2e() {
         let (_temp_dir, target_prefix) = create_test_environment();
@@ -1443,4 +1715,238 @@ mod tests {
             result.err()
         );
     }
+
+    /// Returns a menu schema with a single menu item called `menu_item_name`.
+    #[cfg(target_os = "linux")]
+    fn menu_json(menu_item_name: &str) -> String {
+        serde_json::json!({
+            "$schema": "https://schemas.conda.org/menuinst-1.schema.json",
+            "menu_name": "Menu App",
+            "menu_items": [{
+                "name": menu_item_name,
+                "description": "A menu item",
+                "command": ["{{ PREFIX }}/bin/menu-app"],
+                "platforms": { "linux": {} }
+            }]
+        })
+        .to_string()
+    }
+
+    /// Builds a package with a single menu item called `menu_item_name` and
+    /// returns its record.
+    #[cfg(target_os = "linux")]
+    fn create_menu_package(dir: &Path, version: &str, menu_item_name: &str) -> RepoDataRecord {
+        create_package(
+            dir,
+            "menu-app",
+            version,
+            &[],
+            &[("Menu/menu-app.json", &menu_json(menu_item_name))],
+        )
+    }
+
+    /// Builds a package with the given files and returns its record.
+    #[cfg(target_os = "linux")]
+    fn create_package(
+        dir: &Path,
+        name: &str,
+        version: &str,
+        depends: &[&str],
+        files: &[(&str, &str)],
+    ) -> RepoDataRecord {
+        let package_dir = dir.join(format!("{name}-{version}"));
+        fs_err::create_dir_all(package_dir.join("info")).unwrap();
+
+        let index_json = serde_json::json!({
+            "name": name,
+            "version": version,
+            "build": "0",
+            "build_number": 0,
+            "depends": depends,
+            "subdir": Platform::current().to_string(),
+        });
+        fs_err::write(package_dir.join("info/index.json"), index_json.to_string()).unwrap();
+        let paths = files
+            .iter()
+            .map(|(path, _)| serde_json::json!({"_path": path, "path_type": "hardlink"}))
+            .collect::<Vec<_>>();
+        fs_err::write(
+            package_dir.join("info/paths.json"),
+            serde_json::json!({"paths_version": 1, "paths": paths}).to_string(),
+        )
+        .unwrap();
+
+        let mut package_files = vec![
+            package_dir.join("info/index.json"),
+            package_dir.join("info/paths.json"),
+        ];
+        for (path, content) in files {
+            let path = package_dir.join(path);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(&path, content).unwrap();
+            package_files.push(path);
+        }
+
+        let file_name = format!("{name}-{version}-0.tar.bz2");
+        let package_path = dir.join(&file_name);
+        rattler_package_streaming::write::write_tar_bz2_package(
+            fs_err::File::create(&package_path).unwrap(),
+            &package_dir,
+            &package_files,
+            rattler_conda_types::compression_level::CompressionLevel::Default,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let index_json: IndexJson = read_package_file(&package_path).unwrap();
+        RepoDataRecord {
+            package_record: PackageRecord::from_index_json(index_json, None, None, None).unwrap(),
+            file_name,
+            url: Url::from_file_path(package_path).unwrap(),
+            channel: Some("local".to_string()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_install_menu_items() {
+        use rattler_conda_types::menuinst::Tracker;
+
+        let (_temp_dir, target_prefix) = create_test_environment();
+        let packages_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let xdg_dir = TempDir::new().unwrap();
+
+        let desktop_files = |prefix: &Prefix| {
+            let records = PrefixRecord::collect_from_prefix::<PrefixRecord>(prefix).unwrap();
+            records
+                .iter()
+                .flat_map(|record| &record.installed_system_menus)
+                .flat_map(|tracker| match tracker {
+                    Tracker::Linux(tracker) => tracker.paths.clone(),
+                    _ => unreachable!(),
+                })
+                .filter(|path| path.extension().is_some_and(|ext| ext == "desktop"))
+                .collect::<Vec<_>>()
+        };
+        let installer = || {
+            Installer::new()
+                .with_package_cache(PackageCache::new(cache_dir.path()))
+                .with_menu_mode(MenuMode::User)
+        };
+
+        temp_env::async_with_vars(
+            [
+                ("XDG_DATA_HOME", Some(xdg_dir.path().join("data"))),
+                ("XDG_CONFIG_HOME", Some(xdg_dir.path().join("config"))),
+            ],
+            async {
+                // Installing the package installs its menu item.
+                let v1 = create_menu_package(packages_dir.path(), "1.0", "Menu App One");
+                installer().install(&target_prefix, vec![v1]).await.unwrap();
+                let v1_files = desktop_files(&target_prefix);
+                assert_eq!(v1_files.len(), 1);
+                assert!(v1_files[0].starts_with(xdg_dir.path()));
+                assert!(v1_files[0].is_file());
+
+                // Upgrading the package replaces the menu item.
+                let v2 = create_menu_package(packages_dir.path(), "2.0", "Menu App Two");
+                installer().install(&target_prefix, vec![v2]).await.unwrap();
+                let v2_files = desktop_files(&target_prefix);
+                assert_eq!(v2_files.len(), 1);
+                assert_ne!(v1_files, v2_files);
+                assert!(!v1_files[0].exists());
+                assert!(v2_files[0].is_file());
+
+                // Removing the package removes the menu item.
+                installer()
+                    .install(&target_prefix, Vec::new())
+                    .await
+                    .unwrap();
+                assert!(!v2_files[0].exists());
+            },
+        )
+        .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_install_menu_items_of_clobbered_package() {
+        let (_temp_dir, target_prefix) = create_test_environment();
+        let packages_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let xdg_dir = TempDir::new().unwrap();
+
+        // Both packages contain `bin/tool`. The menu package depends on the
+        // other one so it wins, even though it might have been linked first.
+        let menu_app = create_package(
+            packages_dir.path(),
+            "menu-app",
+            "1.0",
+            &["other"],
+            &[
+                ("bin/tool", "menu-app"),
+                ("Menu/menu-app.json", &menu_json("Menu App")),
+            ],
+        );
+        let other = create_package(
+            packages_dir.path(),
+            "other",
+            "1.0",
+            &[],
+            &[("bin/tool", "other")],
+        );
+
+        temp_env::async_with_vars(
+            [
+                ("XDG_DATA_HOME", Some(xdg_dir.path().join("data"))),
+                ("XDG_CONFIG_HOME", Some(xdg_dir.path().join("config"))),
+            ],
+            async {
+                Installer::new()
+                    .with_package_cache(PackageCache::new(cache_dir.path()))
+                    .with_menu_mode(MenuMode::User)
+                    .install(&target_prefix, vec![menu_app.clone(), other])
+                    .await
+                    .unwrap();
+            },
+        )
+        .await;
+
+        assert_eq!(
+            fs_err::read_to_string(target_prefix.path().join("bin/tool")).unwrap(),
+            "menu-app"
+        );
+
+        // Installing the menu items keeps the paths that were updated when
+        // the clobbered files were resolved.
+        let record = read_prefix_record(&get_meta_file_path(&target_prefix, &menu_app));
+        assert_eq!(record.installed_system_menus.len(), 1);
+        assert!(record
+            .paths_data
+            .paths
+            .iter()
+            .any(|path| path.relative_path == Path::new("bin/tool")));
+        for record in PrefixRecord::collect_from_prefix::<PrefixRecord>(&target_prefix).unwrap() {
+            for path in &record.paths_data.paths {
+                assert!(
+                    target_prefix.path().join(&path.relative_path).is_file(),
+                    "{} does not exist",
+                    path.relative_path.display()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_install_without_menu_mode_skips_menu_items() {
+        let (_temp_dir, target_prefix) = create_test_environment();
+        let repo_record = create_dummy_repo_record();
+
+        install_and_verify_success(Installer::new(), &target_prefix, repo_record.clone()).await;
+
+        let record = read_prefix_record(&get_meta_file_path(&target_prefix, &repo_record));
+        assert!(record.installed_system_menus.is_empty());
+    }
 }
@@ -1,26 +1,266 @@
 //! Functions for running link scripts (pre-unlink and post-link) for a package
+//!
+//! Link scripts can run arbitrary code. With the `sandbox` feature they can be
+//! run in a sandbox that restricts them to writing to the target prefix, see
+//! [`LinkScriptSandbox`].
 use std::{
     borrow::Borrow,
     collections::{HashMap, HashSet},
     fmt::{Display, Formatter},
-    path::Path,
+    path::{Path, PathBuf},
+    process::{Command, Output},
 };
 
 use rattler_conda_types::{PackageName, PackageRecord, Platform, PrefixRecord};
-use rattler_shell::shell::{Bash, CmdExe, ShellEnum};
+use rattler_shell::{
+    run::{RunError, RunOptions},
+    shell::{Bash, CmdExe, ShellEnum},
+};
 use thiserror::Error;
 
 use super::{InstallDriver, Transaction};
 
+/// Whether link scripts can be run in a sandbox in this build of rattler.
+const SANDBOX_SUPPORTED: bool = cfg!(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+));
+
 /// Error type for link script errors
 #[derive(Debug, thiserror::Error)]
 pub enum LinkScriptError {
     /// An error occurred while reading the message file
     #[error("{0}")]
     IoError(String, #[source] std::io::Error),
+
+    /// A sandboxed link script tried to write outside of the target prefix
+    /// and its temporary directory.
+    #[error("the {script_type} script of {} tried to write to {} outside of the sandbox", package.as_source(), path.display())]
+    SandboxWriteViolation {
+        /// The package that contains the link script.
+        package: PackageName,
+        /// The type of the link script.
+        script_type: LinkScriptType,
+        /// The path that the script tried to write to.
+        path: PathBuf,
+    },
+
+    /// A sandboxed link script tried to access the network although this is
+    /// not allowed by the [`LinkScriptSandbox`].
+    #[error("the {script_type} script of {} tried to connect to {address} from the sandbox", package.as_source())]
+    SandboxNetworkViolation {
+        /// The package that contains the link script.
+        package: PackageName,
+        /// The type of the link script.
+        script_type: LinkScriptType,
+        /// The address that the script tried to connect to.
+        address: String,
+    },
+
+    /// Link scripts should be sandboxed but this build of rattler does not
+    /// support sandboxing.
+    #[error("link scripts cannot be run in a sandbox, this requires the `sandbox` feature on Linux or macOS")]
+    SandboxUnsupported,
+}
+
+/// Runs link scripts in a sandbox.
+///
+/// A sandboxed link script can read and execute the directories of the
+/// operating system and the target prefix, but it can only write to the
+/// target prefix and to a private temporary directory that is passed in the
+/// `TMPDIR`, `TMP` and `TEMP` environment variables.
+/// Network access is denied unless it is allowed with
+/// [`LinkScriptSandbox::with_network`].
+///
+/// Violations are only reported on Linux, where they fail the link scripts
+/// with [`LinkScriptError::SandboxWriteViolation`] or
+/// [`LinkScriptError::SandboxNetworkViolation`]. On macOS the sandbox only
+/// denies the operation: a script that fails because of it is reported like
+/// any other failing link script, and a script that ignores the error
+/// succeeds.
+///
+/// Sandboxing requires the `sandbox` feature and Linux (`x86_64` or `aarch64`)
+/// or macOS, otherwise running the link scripts fails with
+/// [`LinkScriptError::SandboxUnsupported`]. The sandbox is set up by running
+/// the current executable again, which therefore has to call `init_sandbox`
+/// of this module at the very start of `main`, before any threads are
+/// spawned.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkScriptSandbox {
+    allow_network: bool,
+}
+
+impl LinkScriptSandbox {
+    /// Constructs a sandbox that denies network access.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether link scripts may access the network.
+    #[must_use]
+    pub fn with_network(self, allow_network: bool) -> Self {
+        Self { allow_network }
+    }
+
+    /// Returns true if link scripts may access the network.
+    pub fn allows_network(&self) -> bool {
+        self.allow_network
+    }
+}
+
+#[cfg(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+))]
+use rattler_sandbox::ViolationReport;
+
+/// Without sandbox support there are no violation reports.
+#[cfg(not(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+)))]
+type ViolationReport = std::convert::Infallible;
+
+/// Runs the shell `command` in the `sandbox`, allowing writes to the target
+/// prefix and the private temporary directory of the sandbox.
+#[cfg(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+))]
+fn sandboxed_command(
+    sandbox: &LinkScriptSandbox,
+    command: &Command,
+    target_prefix: &Path,
+) -> std::io::Result<(Command, ViolationReport)> {
+    use rattler_sandbox::{SandboxPolicy, SandboxProfile};
+
+    let mut policy = SandboxPolicy::from_profile(SandboxProfile::LinkScript, target_prefix)
+        .with_network(sandbox.allow_network);
+
+    // The activated script is written to the temporary directory of the
+    // system, which is hidden from the sandbox.
+    for arg in command.get_args() {
+        if Path::new(arg).is_file() {
+            policy = policy.with_read(fs_err::canonicalize(arg)?);
+        }
+    }
+
+    let (mut sandboxed, report) = policy.command_with_report(command.get_program())?;
+    sandboxed.args(command.get_args());
+    Ok((sandboxed, report))
+}
+
+/// Returns the violations that the sandbox reported for a finished script.
+#[cfg(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+))]
+fn read_violations(report: ViolationReport) -> std::io::Result<Vec<SandboxViolation>> {
+    use rattler_sandbox::Violation;
+
+    Ok(report
+        .violations()?
+        .into_iter()
+        .map(|violation| match violation {
+            Violation::Write { path } => SandboxViolation::Write(path),
+            Violation::Network { address } => SandboxViolation::Network(address),
+        })
+        .collect())
+}
+
+#[cfg(not(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+)))]
+fn sandboxed_command(
+    _sandbox: &LinkScriptSandbox,
+    _command: &Command,
+    _target_prefix: &Path,
+) -> std::io::Result<(Command, ViolationReport)> {
+    unreachable!("sandboxing is not supported, this is checked before running link scripts")
+}
+
+#[cfg(not(all(
+    feature = "sandbox",
+    any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    )
+)))]
+fn read_violations(report: ViolationReport) -> std::io::Result<Vec<SandboxViolation>> {
+    match report {}
+}
+
+/// A violation of the sandbox by a link script.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(
+    not(all(
+        feature = "sandbox",
+        any(
+            all(target_os = "linux", target_arch = "x86_64"),
+            all(target_os = "linux", target_arch = "aarch64"),
+            target_os = "macos",
+        )
+    )),
+    allow(dead_code)
+)]
+enum SandboxViolation {
+    Write(PathBuf),
+    Network(String),
+}
+
+impl Display for SandboxViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxViolation::Write(path) => write!(f, "write to {}", path.display()),
+            SandboxViolation::Network(address) => write!(f, "connection to {address}"),
+        }
+    }
+}
+
+/// Handles the sandbox trampoline of [`LinkScriptSandbox`]. If the current
+/// process was started to run a sandboxed link script, this sets up the
+/// sandbox, runs the script and exits. Otherwise, it does nothing.
+///
+/// This has to be called at the very start of `main`, before any threads are
+/// spawned.
+#[cfg(feature = "sandbox")]
+pub fn init_sandbox() {
+    #[cfg(any(
+        all(target_os = "linux", target_arch = "x86_64"),
+        all(target_os = "linux", target_arch = "aarch64"),
+        target_os = "macos",
+    ))]
+    rattler_sandbox::init_sandbox();
 }
 
 /// The type of link script to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LinkScriptType {
     /// The pre-unlink script (run before the package is unlinked)
     /// This is stored in the environment as `bin/.{name}-pre-unlink.sh` or
@@ -92,6 +332,49 @@ pub fn run_link_scripts<'a>(
     target_prefix: &Path,
     platform: &Platform,
 ) -> Result<PrePostLinkResult, LinkScriptError> {
+    run_link_scripts_impl(
+        link_script_type,
+        prefix_records,
+        target_prefix,
+        platform,
+        None,
+    )
+}
+
+/// Run the link scripts for a given package in a sandbox.
+///
+/// Unlike [`run_link_scripts`], this stops at the first script that fails
+/// because of the sandbox and returns
+/// [`LinkScriptError::SandboxWriteViolation`] or
+/// [`LinkScriptError::SandboxNetworkViolation`]. The sandbox reports these
+/// violations on Linux, on macOS such a script is only reported as failed.
+pub fn run_link_scripts_in_sandbox<'a>(
+    link_script_type: LinkScriptType,
+    prefix_records: impl Iterator<Item = &'a PrefixRecord>,
+    target_prefix: &Path,
+    platform: &Platform,
+    sandbox: &LinkScriptSandbox,
+) -> Result<PrePostLinkResult, LinkScriptError> {
+    run_link_scripts_impl(
+        link_script_type,
+        prefix_records,
+        target_prefix,
+        platform,
+        Some(sandbox),
+    )
+}
+
+fn run_link_scripts_impl<'a>(
+    link_script_type: LinkScriptType,
+    prefix_records: impl Iterator<Item = &'a PrefixRecord>,
+    target_prefix: &Path,
+    platform: &Platform,
+    sandbox: Option<&LinkScriptSandbox>,
+) -> Result<PrePostLinkResult, LinkScriptError> {
+    if sandbox.is_some() && !SANDBOX_SUPPORTED {
+        return Err(LinkScriptError::SandboxUnsupported);
+    }
+
     let mut env = HashMap::new();
     env.insert(
         "PREFIX".to_string(),
@@ -126,9 +409,41 @@ pub fn run_link_scripts<'a>(
                 prec.name.as_normalized()
             );
 
-            match rattler_shell::run_in_environment(target_prefix, &link_file, shell, &env) {
-                Ok(o) if o.status.success() => {}
-                Ok(o) => {
+            let result = match sandbox {
+                Some(sandbox) => {
+                    run_sandboxed_link_script(sandbox, target_prefix, &link_file, shell, &env)
+                }
+                None => rattler_shell::run_in_environment(target_prefix, &link_file, shell, &env)
+                    .map(|output| (output, Vec::new())),
+            };
+            match result {
+                Ok((o, violations)) if o.status.success() => {
+                    // The script handled the denied accesses itself.
+                    for violation in violations {
+                        tracing::warn!(
+                            "The sandbox denied a {violation} by the {link_script_type} script of {}",
+                            prec.name.as_normalized()
+                        );
+                    }
+                }
+                Ok((o, violations)) => {
+                    match violations.into_iter().next() {
+                        Some(SandboxViolation::Write(path)) => {
+                            return Err(LinkScriptError::SandboxWriteViolation {
+                                package: prec.name.clone(),
+                                script_type: link_script_type,
+                                path,
+                            });
+                        }
+                        Some(SandboxViolation::Network(address)) => {
+                            return Err(LinkScriptError::SandboxNetworkViolation {
+                                package: prec.name.clone(),
+                                script_type: link_script_type,
+                                address,
+                            });
+                        }
+                        None => {}
+                    }
                     failed_packages.push(prec.name.clone());
                     tracing::warn!("Error running post-link script. Status: {:?}", o.status);
                     tracing::warn!("  stdout: {}", String::from_utf8_lossy(&o.stdout));
@@ -180,6 +495,49 @@ pub fn run_link_scripts<'a>(
     })
 }
 
+/// Runs a single link script in the sandbox and returns its output and the
+/// violations of the sandbox.
+fn run_sandboxed_link_script(
+    sandbox: &LinkScriptSandbox,
+    target_prefix: &Path,
+    link_file: &Path,
+    shell: ShellEnum,
+    env: &HashMap<String, String>,
+) -> Result<(Output, Vec<SandboxViolation>), RunError> {
+    // The sandbox matches paths after resolving symlinks, e.g. `/tmp` on
+    // macOS.
+    let target_prefix = fs_err::canonicalize(target_prefix)?;
+
+    let mut report = None;
+    let result = rattler_shell::run_in_environment_with_options(
+        &target_prefix,
+        link_file,
+        shell,
+        RunOptions {
+            env_vars: env.clone(),
+            wrap_command: Some(Box::new(|command| {
+                let (command, violation_report) =
+                    sandboxed_command(sandbox, &command, &target_prefix)?;
+                report = Some(violation_report);
+                Ok(command)
+            })),
+            ..RunOptions::default()
+        },
+    )?;
+    let violations = match report {
+        Some(report) => read_violations(report)?,
+        None => Vec::new(),
+    };
+    Ok((
+        Output {
+            status: result.status,
+            stdout: result.stdout,
+            stderr: result.stderr,
+        },
+        violations,
+    ))
+}
+
 impl InstallDriver {
     /// Run any post-link scripts that are part of the packages that are being
     /// installed.
@@ -203,11 +561,12 @@ impl InstallDriver {
             .filter(|r| to_install.contains(&r.repodata_record.package_record.name))
             .cloned();
 
-        run_link_scripts(
+        run_link_scripts_impl(
             LinkScriptType::PostLink,
             filter_iter,
             target_prefix,
             &transaction.platform,
+            self.link_script_sandbox.as_ref(),
         )
     }
 
@@ -221,17 +580,19 @@ impl InstallDriver {
     where
         Old: Borrow<PrefixRecord>,
     {
-        run_link_scripts(
+        run_link_scripts_impl(
             LinkScriptType::PreUnlink,
             transaction.removed_packages().map(Borrow::borrow),
             target_prefix,
             &transaction.platform,
+            self.link_script_sandbox.as_ref(),
         )
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::LinkScriptSandbox;
     use crate::{
         get_repodata_record, get_test_data_dir,
         install::{
@@ -307,4 +668,22 @@ mod tests {
         // check that the pre-unlink script was run
         assert!(!target_prefix.path().join("i-was-post-linked").exists());
     }
+
+    #[test]
+    fn test_sandbox_unsupported() {
+        if super::SANDBOX_SUPPORTED {
+            return;
+        }
+        let result = super::run_link_scripts_in_sandbox(
+            super::LinkScriptType::PostLink,
+            std::iter::empty(),
+            std::path::Path::new("/"),
+            &Platform::current(),
+            &LinkScriptSandbox::new(),
+        );
+        assert!(matches!(
+            result,
+            Err(super::LinkScriptError::SandboxUnsupported)
+        ));
+    }
 }
@@ -0,0 +1,152 @@
+//! Tests for running link scripts in a sandbox.
+//!
+//! The sandbox runs the current executable again, so this test has its own
+//! `main` that initializes the sandbox before running the tests.
+
+#[cfg(any(
+    all(target_os = "linux", target_arch = "x86_64"),
+    all(target_os = "linux", target_arch = "aarch64"),
+    target_os = "macos",
+))]
+mod tests {
+    use std::path::Path;
+
+    use libtest_mimic::{Failed, Trial};
+    use rattler::install::link_script::{
+        run_link_scripts_in_sandbox, LinkScriptError, LinkScriptSandbox, LinkScriptType,
+    };
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord};
+    use url::Url;
+
+    /// Creates a prefix with a post-link script for the package `test`.
+    fn prefix_with_post_link_script(prefix: &Path, script: &str) -> Vec<PrefixRecord> {
+        fs_err::create_dir_all(prefix.join("bin")).unwrap();
+        fs_err::create_dir_all(prefix.join("conda-meta")).unwrap();
+        fs_err::write(prefix.join("bin/.test-post-link.sh"), script).unwrap();
+
+        let record = RepoDataRecord {
+            package_record: PackageRecord::new(
+                PackageName::new_unchecked("test"),
+                "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+                "0".to_string(),
+            ),
+            file_name: "test-1.0-0.conda".to_string(),
+            url: Url::parse("https://example.com/test-1.0-0.conda").unwrap(),
+            channel: None,
+        };
+        vec![PrefixRecord::from_repodata_record(record, Vec::new())]
+    }
+
+    fn run_post_link_script(
+        script: &str,
+        sandbox: &LinkScriptSandbox,
+    ) -> (tempfile::TempDir, Result<Vec<PackageName>, LinkScriptError>) {
+        let prefix = tempfile::tempdir().unwrap();
+        let records = prefix_with_post_link_script(prefix.path(), script);
+        let result = run_link_scripts_in_sandbox(
+            LinkScriptType::PostLink,
+            records.iter(),
+            prefix.path(),
+            &Platform::current(),
+            sandbox,
+        )
+        .map(|result| result.failed_packages);
+        (prefix, result)
+    }
+
+    fn test_write_to_prefix_and_temp_dir() -> Result<(), Failed> {
+        let (prefix, result) = run_post_link_script(
+            "echo linked > \"$PREFIX/linked\"\n\
+             echo temp > \"$TMPDIR/temp\" && cat \"$TMPDIR/temp\" > \"$PREFIX/temp\"\n",
+            &LinkScriptSandbox::new(),
+        );
+        assert_eq!(result.unwrap(), Vec::<PackageName>::new());
+        assert_eq!(
+            fs_err::read_to_string(prefix.path().join("linked")).unwrap(),
+            "linked\n"
+        );
+        assert_eq!(
+            fs_err::read_to_string(prefix.path().join("temp")).unwrap(),
+            "temp\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn test_write_violation() -> Result<(), Failed> {
+        // Paths outside of the system directories are hidden from the
+        // sandbox, writing to them fails without a violation.
+        let target = Path::new("/dev/rattler-link-script-escaped");
+        // The error output is discarded, the violation is reported by the
+        // sandbox.
+        let (_prefix, result) = run_post_link_script(
+            &format!("echo escaped 2>/dev/null > '{}'\n", target.display()),
+            &LinkScriptSandbox::new(),
+        );
+        match result {
+            Err(LinkScriptError::SandboxWriteViolation { path, .. }) => assert_eq!(path, target),
+            result => panic!("expected a write violation, got {result:?}"),
+        }
+        assert!(!target.exists());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn test_network_violation() -> Result<(), Failed> {
+        let (_prefix, result) =
+            run_post_link_script("exec 3<>/dev/tcp/192.0.2.1/80\n", &LinkScriptSandbox::new());
+        match result {
+            Err(LinkScriptError::SandboxNetworkViolation { address, .. }) => {
+                assert_eq!(address, "192.0.2.1:80");
+            }
+            result => panic!("expected a network violation, got {result:?}"),
+        }
+        Ok(())
+    }
+
+    fn test_failure_is_not_a_violation() -> Result<(), Failed> {
+        let (_prefix, result) = run_post_link_script(
+            "echo 'Read-only file system' >&2\nexit 1\n",
+            &LinkScriptSandbox::new(),
+        );
+        assert_eq!(result.unwrap(), vec![PackageName::new_unchecked("test")]);
+        Ok(())
+    }
+
+    pub fn tests() -> Vec<Trial> {
+        vec![
+            Trial::test(
+                "test_write_to_prefix_and_temp_dir",
+                test_write_to_prefix_and_temp_dir,
+            ),
+            #[cfg(target_os = "linux")]
+            Trial::test("test_write_violation", test_write_violation),
+            #[cfg(target_os = "linux")]
+            Trial::test("test_network_violation", test_network_violation),
+            Trial::test(
+                "test_failure_is_not_a_violation",
+                test_failure_is_not_a_violation,
+            ),
+        ]
+    }
+}
+
+#[cfg(any(
+    all(target_os = "linux", target_arch = "x86_64"),
+    all(target_os = "linux", target_arch = "aarch64"),
+    target_os = "macos",
+))]
+fn main() {
+    rattler::install::link_script::init_sandbox();
+    let args = libtest_mimic::Arguments::from_args();
+    libtest_mimic::run(&args, tests::tests()).exit();
+}
+
+#[cfg(not(any(
+    all(target_os = "linux", target_arch = "x86_64"),
+    all(target_os = "linux", target_arch = "aarch64"),
+    target_os = "macos",
+)))]
+fn main() {
+    eprintln!("This platform is not supported by the sandbox");
+}
@@ -0,0 +1,436 @@
+//! Limits the bandwidth and the number of concurrent downloads that are used
+//! to populate the [`super::PackageCache`]. See [`DownloadScheduler`].
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    num::{NonZeroU64, NonZeroUsize},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use http_body::{Body, Frame, SizeHint};
+use parking_lot::Mutex;
+use reqwest::{Request, Response, ResponseBuilderExt};
+use reqwest_middleware::{ClientWithMiddleware, Middleware, Next};
+use tokio::sync::oneshot;
+use url::Url;
+
+/// Limits that are applied to package downloads.
+///
+/// These limits are independent of any IO concurrency limits. They only apply
+/// to packages that are actually downloaded; cache hits are not affected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadLimits {
+    /// The maximum number of bytes per second that all downloads may use
+    /// together.
+    pub max_bytes_per_second: Option<NonZeroU64>,
+
+    /// The maximum number of packages that are downloaded at the same time.
+    pub max_concurrent_downloads: Option<NonZeroUsize>,
+
+    /// The maximum number of packages that are downloaded from a single host
+    /// at the same time.
+    pub max_downloads_per_host: Option<NonZeroUsize>,
+}
+
+impl DownloadLimits {
+    /// Constructs the limits from the `concurrency` section of a
+    /// configuration.
+    #[cfg(feature = "rattler_config")]
+    pub fn from_config(config: &rattler_config::config::concurrency::ConcurrencyConfig) -> Self {
+        Self {
+            max_bytes_per_second: config.max_bandwidth.and_then(NonZeroU64::new),
+            max_concurrent_downloads: NonZeroUsize::new(config.downloads),
+            max_downloads_per_host: config.downloads_per_host.and_then(NonZeroUsize::new),
+        }
+    }
+}
+
+/// The priority of a download. Downloads that are waiting for a free slot are
+/// started in ascending order of their priority; downloads with the same
+/// priority are started in the order in which they were requested.
+pub type DownloadPriority = u64;
+
+/// Schedules package downloads according to [`DownloadLimits`].
+///
+/// Downloads that exceed the concurrency limits are queued and started by
+/// priority once a slot frees up. The bandwidth limit is shared by all
+/// downloads that are scheduled through the same scheduler (or one of its
+/// clones).
+#[derive(Clone, Default)]
+pub struct DownloadScheduler {
+    inner: Arc<SchedulerInner>,
+}
+
+#[derive(Default)]
+struct SchedulerInner {
+    limits: DownloadLimits,
+    global: Option<Arc<Slots>>,
+    hosts: DashMap<String, Arc<Slots>>,
+    bandwidth: Option<Arc<Bandwidth>>,
+}
+
+impl DownloadScheduler {
+    /// Constructs a new scheduler that enforces the given limits.
+    pub fn new(limits: DownloadLimits) -> Self {
+        Self {
+            inner: Arc::new(SchedulerInner {
+                limits,
+                global: limits
+                    .max_concurrent_downloads
+                    .map(|limit| Arc::new(Slots::new(limit.get()))),
+                hosts: DashMap::default(),
+                bandwidth: limits
+                    .max_bytes_per_second
+                    .map(|rate| Arc::new(Bandwidth::new(rate.get()))),
+            }),
+        }
+    }
+
+    /// Returns the limits that this scheduler enforces.
+    pub fn limits(&self) -> DownloadLimits {
+        self.inner.limits
+    }
+
+    /// Waits until a download from the given url may start. The download
+    /// should be performed while the returned permit is held.
+    pub async fn acquire(&self, url: &Url, priority: DownloadPriority) -> DownloadPermit {
+        // The host slot is acquired before the global slot to ensure that a
+        // download waiting for a busy host does not block downloads from other
+        // hosts.
+        let host = match (self.inner.limits.max_downloads_per_host, url.host_str()) {
+            (Some(limit), Some(host)) => {
+                let slots = self
+                    .inner
+                    .hosts
+                    .entry(host.to_string())
+                    .or_insert_with(|| Arc::new(Slots::new(limit.get())))
+                    .clone();
+                Some(slots.acquire(priority).await)
+            }
+            _ => None,
+        };
+        let global = match &self.inner.global {
+            Some(slots) => Some(slots.clone().acquire(priority).await),
+            None => None,
+        };
+        DownloadPermit {
+            _global: global,
+            _host: host,
+        }
+    }
+
+    /// Returns a client that enforces the bandwidth limit on the bodies of
+    /// all responses.
+    pub fn throttle(&self, client: ClientWithMiddleware) -> ClientWithMiddleware {
+        match &self.inner.bandwidth {
+            Some(bandwidth) => reqwest_middleware::ClientBuilder::from_client(client)
+                .with(BandwidthMiddleware {
+                    bandwidth: bandwidth.clone(),
+                })
+                .build(),
+            None => client,
+        }
+    }
+}
+
+/// Allows a download to proceed. The slots are released when the permit is
+/// dropped.
+pub struct DownloadPermit {
+    _global: Option<SlotPermit>,
+    _host: Option<SlotPermit>,
+}
+
+/// A counting semaphore that hands out free slots by priority.
+struct Slots {
+    state: Mutex<SlotsState>,
+}
+
+struct SlotsState {
+    available: usize,
+    next_ticket: u64,
+    waiting: BTreeMap<(DownloadPriority, u64), oneshot::Sender<()>>,
+}
+
+impl Slots {
+    fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(SlotsState {
+                available: limit,
+                next_ticket: 0,
+                waiting: BTreeMap::new(),
+            }),
+        }
+    }
+
+    async fn acquire(self: Arc<Self>, priority: DownloadPriority) -> SlotPermit {
+        let (key, rx) = {
+            let mut state = self.state.lock();
+            if state.available > 0 && state.waiting.is_empty() {
+                state.available -= 1;
+                drop(state);
+                return SlotPermit { slots: self };
+            }
+            let key = (priority, state.next_ticket);
+            state.next_ticket += 1;
+            let (tx, rx) = oneshot::channel();
+            state.waiting.insert(key, tx);
+            (key, rx)
+        };
+
+        let mut waiter = Waiter {
+            slots: self.clone(),
+            key,
+            rx,
+            acquired: false,
+        };
+        // The sender is only removed from the queue by `release`, which hands
+        // the slot to this waiter while it is alive.
+        waiter.acquired = (&mut waiter.rx).await.is_ok();
+        drop(waiter);
+        SlotPermit { slots: self }
+    }
+
+    /// Hands the slot to the first waiter that is still interested or returns
+    /// it to the pool.
+    fn release(&self) {
+        let mut state = self.state.lock();
+        while let Some((_, tx)) = state.waiting.pop_first() {
+            if tx.send(()).is_ok() {
+                return;
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// Removes a cancelled waiter from the queue, or passes on the slot if it was
+/// already assigned to it.
+struct Waiter {
+    slots: Arc<Slots>,
+    key: (DownloadPriority, u64),
+    rx: oneshot::Receiver<()>,
+    acquired: bool,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if self.acquired {
+            return;
+        }
+        let removed = self.slots.state.lock().waiting.remove(&self.key).is_some();
+        if !removed {
+            // The slot was handed to us after all.
+            self.slots.release();
+        }
+    }
+}
+
+struct SlotPermit {
+    slots: Arc<Slots>,
+}
+
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        self.slots.release();
+    }
+}
+
+/// A token bucket that is shared by all downloads.
+struct Bandwidth {
+    bytes_per_second: u64,
+    state: Mutex<BandwidthState>,
+}
+
+struct BandwidthState {
+    /// The number of bytes that may be received without waiting. Negative if
+    /// more bytes were received than allowed.
+    budget: f64,
+    last_update: Instant,
+}
+
+impl Bandwidth {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            state: Mutex::new(BandwidthState {
+                budget: bytes_per_second as f64,
+                last_update: Instant::now(),
+            }),
+        }
+    }
+
+    /// Records that `bytes` were received and returns how long the receiver
+    /// has to wait before it may receive more data.
+    fn consume(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_second as f64;
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_update).as_secs_f64();
+        // Allow bursts of at most one second worth of data.
+        state.budget = (state.budget + elapsed * rate).min(rate) - bytes as f64;
+        state.last_update = now;
+        if state.budget < 0.0 {
+            Duration::from_secs_f64(-state.budget / rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Middleware that throttles the response bodies.
+struct BandwidthMiddleware {
+    bandwidth: Arc<Bandwidth>,
+}
+
+#[async_trait::async_trait]
+impl Middleware for BandwidthMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let response = next.run(req, extensions).await?;
+        let url = response.url().clone();
+        let (parts, body) = http::Response::<reqwest::Body>::from(response).into_parts();
+        let mut throttled = http::Response::builder()
+            .url(url)
+            .body(reqwest::Body::wrap(ThrottledBody {
+                inner: body,
+                bandwidth: self.bandwidth.clone(),
+                delay: None,
+            }))
+            .expect("a response without headers is always valid");
+        *throttled.status_mut() = parts.status;
+        *throttled.version_mut() = parts.version;
+        *throttled.headers_mut() = parts.headers;
+        throttled.extensions_mut().extend(parts.extensions);
+        Ok(Response::from(throttled))
+    }
+}
+
+/// A response body that waits after each frame until the bandwidth budget
+/// allows more data to be received.
+struct ThrottledBody {
+    inner: reqwest::Body,
+    bandwidth: Arc<Bandwidth>,
+    delay: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl Body for ThrottledBody {
+    type Data = bytes::Bytes;
+    type Error = reqwest::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(delay) = &mut self.delay {
+            std::task::ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+
+        let frame = std::task::ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(data) = frame
+            .as_ref()
+            .and_then(|frame| frame.as_ref().ok()?.data_ref())
+        {
+            let wait = self.bandwidth.consume(data.len());
+            if !wait.is_zero() {
+                self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use parking_lot::Mutex;
+    use url::Url;
+
+    use super::{Bandwidth, DownloadLimits, DownloadScheduler};
+
+    #[tokio::test]
+    async fn test_downloads_per_host() {
+        let scheduler = DownloadScheduler::new(DownloadLimits {
+            max_downloads_per_host: Some(1.try_into().unwrap()),
+            ..DownloadLimits::default()
+        });
+        let a = Url::parse("https://a.example.com/pkg.conda").unwrap();
+        let b = Url::parse("https://b.example.com/pkg.conda").unwrap();
+
+        let permit = scheduler.acquire(&a, 0).await;
+
+        // Another host is not affected by the busy host.
+        let _other = tokio::time::timeout(Duration::from_secs(1), scheduler.acquire(&b, 0))
+            .await
+            .expect("a different host must not wait");
+
+        // The same host has to wait until the permit is released.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), scheduler.acquire(&a, 0))
+                .await
+                .is_err()
+        );
+        drop(permit);
+        let _permit = tokio::time::timeout(Duration::from_secs(1), scheduler.acquire(&a, 0))
+            .await
+            .expect("the slot must be released by the permit and the cancelled waiter");
+    }
+
+    #[tokio::test]
+    async fn test_priority_order() {
+        let scheduler = DownloadScheduler::new(DownloadLimits {
+            max_concurrent_downloads: Some(1.try_into().unwrap()),
+            ..DownloadLimits::default()
+        });
+        let url = Url::parse("https://example.com/pkg.conda").unwrap();
+        let permit = scheduler.acquire(&url, 0).await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for priority in [3, 1, 2, 1] {
+            let scheduler = scheduler.clone();
+            let url = url.clone();
+            let order = order.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(&url, priority).await;
+                order.lock().push(priority);
+            }));
+            // Make sure the tasks are queued in order.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(permit);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock(), vec![1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_bandwidth() {
+        let bandwidth = Bandwidth::new(1000);
+        // The first second worth of data is available immediately.
+        assert_eq!(bandwidth.consume(1000), Duration::ZERO);
+        // Everything after that has to wait.
+        let wait = bandwidth.consume(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+}
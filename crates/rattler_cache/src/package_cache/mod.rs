@@ -14,6 +14,7 @@ pub use cache_key::CacheKey;
 pub use cache_lock::CacheLock;
 use cache_lock::CacheRwLock;
 use dashmap::DashMap;
+pub use download_scheduler::{DownloadLimits, DownloadPermit, DownloadPriority, DownloadScheduler};
 use fs_err::tokio as tokio_fs;
 use futures::TryFutureExt;
 use itertools::Itertools;
@@ -26,6 +27,7 @@ use rattler_networking::{
 };
 use rattler_package_streaming::{DownloadReporter, ExtractError};
 use rattler_redaction::Redact;
+use rattler_signatures::SignatureVerifier;
 pub use reporter::CacheReporter;
 use simple_spawn_blocking::Cancelled;
 use tracing::instrument;
@@ -35,6 +37,7 @@ use crate::validation::{validate_package_directory, ValidationMode};
 
 mod cache_key;
 mod cache_lock;
+mod download_scheduler;
 mod reporter;
 
 /// A [`PackageCache`] manages a cache of extracted Conda packages on disk.
@@ -48,6 +51,9 @@ mod reporter;
 pub struct PackageCache {
     inner: Arc<PackageCacheInner>,
     cache_origin: bool,
+    resumable_downloads: bool,
+    download_scheduler: Option<DownloadScheduler>,
+    signature_verifier: Option<SignatureVerifier>,
 }
 
 #[derive(Default)]
@@ -114,6 +120,9 @@ impl PackageCache {
                 packages: DashMap::default(),
             }),
             cache_origin: false,
+            resumable_downloads: false,
+            download_scheduler: None,
+            signature_verifier: None,
         }
     }
 
@@ -126,6 +135,45 @@ impl PackageCache {
         }
     }
 
+    /// Downloads packages to a partial file next to the cache before they are
+    /// extracted instead of streaming them straight into the extraction.
+    ///
+    /// If the connection drops, the next attempt of the retry policy passed to
+    /// [`Self::get_or_fetch_from_url_with_retry`] continues the download with
+    /// an HTTP range request instead of starting over. This is useful for
+    /// large packages and unreliable connections.
+    pub fn with_resumable_downloads(self) -> Self {
+        Self {
+            resumable_downloads: true,
+            ..self
+        }
+    }
+
+    /// Schedules all downloads that are performed by
+    /// [`Self::get_or_fetch_from_url`] and friends with the given scheduler.
+    /// This makes it possible to limit the bandwidth and the number of
+    /// concurrent downloads. Clones of the scheduler share their limits.
+    pub fn with_download_scheduler(self, scheduler: DownloadScheduler) -> Self {
+        Self {
+            download_scheduler: Some(scheduler),
+            ..self
+        }
+    }
+
+    /// Verifies the signature of every package that is fetched by
+    /// [`Self::get_or_fetch_from_url`] and friends before it is extracted.
+    ///
+    /// Packages are downloaded to a partial file next to the cache, like with
+    /// [`Self::with_resumable_downloads`], and are only extracted if the
+    /// verifier accepts their signature. Otherwise an
+    /// [`ExtractError::SignatureVerificationFailed`] is returned.
+    pub fn with_signature_verifier(self, verifier: SignatureVerifier) -> Self {
+        Self {
+            signature_verifier: Some(verifier),
+            ..self
+        }
+    }
+
     /// Returns the directory that contains the specified package.
     ///
     /// If the package was previously successfully fetched and stored in the
@@ -237,7 +285,6 @@ impl PackageCache {
     /// uses the passed in `retry_policy` if, after the request has been sent
     /// and the response is successful, streaming of the package data fails
     /// and the whole request must be retried.
-    #[instrument(skip_all, fields(url=%url))]
     pub async fn get_or_fetch_from_url_with_retry(
         &self,
         pkg: impl Into<CacheKey>,
@@ -245,6 +292,33 @@ impl PackageCache {
         client: LazyClient,
         retry_policy: impl RetryPolicy + Send + 'static + Clone,
         reporter: Option<Arc<dyn CacheReporter>>,
+    ) -> Result<CacheLock, PackageCacheError> {
+        self.get_or_fetch_from_url_with_priority(
+            pkg,
+            url,
+            client,
+            retry_policy,
+            DownloadPriority::default(),
+            reporter,
+        )
+        .await
+    }
+
+    /// Returns the directory that contains the specified package.
+    ///
+    /// This function is similar to [`Self::get_or_fetch_from_url_with_retry`]
+    /// but if a [`DownloadScheduler`] is configured and the download has to
+    /// wait for a free slot, downloads with a lower `priority` are started
+    /// first.
+    #[instrument(skip_all, fields(url=%url))]
+    pub async fn get_or_fetch_from_url_with_priority(
+        &self,
+        pkg: impl Into<CacheKey>,
+        url: Url,
+        client: LazyClient,
+        retry_policy: impl RetryPolicy + Send + 'static + Clone,
+        priority: DownloadPriority,
+        reporter: Option<Arc<dyn CacheReporter>>,
     ) -> Result<CacheLock, PackageCacheError> {
         let request_start = SystemTime::now();
         // Convert into cache key
@@ -256,30 +330,82 @@ impl PackageCache {
         let sha256 = cache_key.sha256();
         let md5 = cache_key.md5();
         let download_reporter = reporter.clone();
+        let resumable_downloads = self.resumable_downloads;
+        let signature_verifier = self.signature_verifier.clone();
+        let download_scheduler = self
+            .download_scheduler
+            .clone()
+            .filter(|_| url.scheme() != "file");
         // Get or fetch the package, using the specified fetch function
         self.get_or_fetch(cache_key, move |destination| {
             let url = url.clone();
             let client = client.clone();
             let retry_policy = retry_policy.clone();
             let download_reporter = download_reporter.clone();
+            let download_scheduler = download_scheduler.clone();
+            let signature_verifier = signature_verifier.clone();
             async move {
+                // Wait for the scheduler to allow the download. The permit is
+                // held until the package is extracted.
+                let _permit = match &download_scheduler {
+                    Some(scheduler) => Some(scheduler.acquire(&url, priority).await),
+                    None => None,
+                };
+                let client = match &download_scheduler {
+                    Some(scheduler) => scheduler.throttle(client.client().clone()),
+                    None => client.client().clone(),
+                };
+                // Keep the partial download next to the destination so a
+                // retry can continue where the previous attempt stopped. A
+                // partial download that is left over from another process
+                // might be stale, it is never continued.
+                let mut partial_file_name = destination.file_name().unwrap_or_default().to_os_string();
+                partial_file_name.push(".partial");
+                let partial_path = destination.with_file_name(partial_file_name);
+                remove_partial_download(&partial_path).await;
                 let mut current_try = 0;
                 // Retry until the retry policy says to stop
                 loop {
                     current_try += 1;
                     tracing::debug!("downloading {} to {}", &url, destination.display());
+                    let download_reporter = download_reporter.clone().map(|reporter| Arc::new(PassthroughReporter {
+                        reporter,
+                        index: Mutex::new(None),
+                    }) as Arc::<dyn DownloadReporter>);
                     // Extract the package
-                    let result = rattler_package_streaming::reqwest::tokio::extract(
-                        client.client().clone(),
-                        url.clone(),
-                        &destination,
-                        sha256,
-                        download_reporter.clone().map(|reporter| Arc::new(PassthroughReporter {
-                            reporter,
-                            index: Mutex::new(None),
-                        }) as Arc::<dyn DownloadReporter>),
-                    )
-                        .await;
+                    let result = if let Some(verifier) = &signature_verifier {
+                        extract_verified(
+                            verifier,
+                            client.clone(),
+                            url.clone(),
+                            &destination,
+                            &partial_path,
+                            sha256,
+                            md5,
+                            download_reporter,
+                        )
+                            .await
+                    } else if resumable_downloads {
+                        rattler_package_streaming::reqwest::resumable::extract(
+                            client.clone(),
+                            url.clone(),
+                            &destination,
+                            &partial_path,
+                            sha256,
+                            md5,
+                            download_reporter,
+                        )
+                            .await
+                    } else {
+                        rattler_package_streaming::reqwest::tokio::extract(
+                            client.clone(),
+                            url.clone(),
+                            &destination,
+                            sha256,
+                            download_reporter,
+                        )
+                            .await
+                    };
 
                     let err = match result {
                         Ok(result) => {
@@ -325,13 +451,17 @@ impl PackageCache {
                     if !matches!(&err,
                         ExtractError::IoError(_) | ExtractError::CouldNotCreateDestination(_)
                     ) {
+                        remove_partial_download(&partial_path).await;
                         return Err(err);
                     }
 
                     // Determine whether to retry based on the retry policy
                     let execute_after = match retry_policy.should_retry(request_start, current_try) {
                         RetryDecision::Retry { execute_after } => execute_after,
-                        RetryDecision::DoNotRetry => return Err(err),
+                        RetryDecision::DoNotRetry => {
+                            remove_partial_download(&partial_path).await;
+                            return Err(err);
+                        }
                     };
                     let duration = execute_after.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
 
@@ -353,6 +483,99 @@ impl PackageCache {
     }
 }
 
+/// Removes a partial download. A missing file is not an error and other
+/// errors are only logged, a leftover partial download is never continued.
+async fn remove_partial_download(path: &Path) {
+    match tokio_fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => tracing::warn!(
+            "failed to remove the partial download {}: {err}",
+            path.display()
+        ),
+    }
+}
+
+/// Downloads the package at `url` to `partial_path`, verifies its signature and
+/// only then extracts it to `destination`. Local packages are verified in
+/// place.
+#[allow(clippy::too_many_arguments)]
+async fn extract_verified(
+    verifier: &SignatureVerifier,
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+    destination: &Path,
+    partial_path: &Path,
+    sha256: Option<Sha256Hash>,
+    md5: Option<rattler_digest::Md5Hash>,
+    reporter: Option<Arc<dyn DownloadReporter>>,
+) -> Result<rattler_package_streaming::ExtractResult, ExtractError> {
+    let verify = |path: PathBuf| {
+        let client = client.clone();
+        let url = url.clone();
+        async move {
+            verifier
+                .verify_file(&client, &url, &path)
+                .await
+                .map_err(|err| {
+                    ExtractError::SignatureVerificationFailed(
+                        url.redact().to_string(),
+                        Box::new(err),
+                    )
+                })
+        }
+    };
+
+    if url.scheme() == "file" {
+        let path = url.to_file_path().map_err(|_err| {
+            ExtractError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{url}' is not a valid file url"),
+            ))
+        })?;
+        verify(path).await?;
+        return rattler_package_streaming::reqwest::tokio::extract(
+            client,
+            url,
+            destination,
+            sha256,
+            reporter,
+        )
+        .await;
+    }
+
+    if let Some(reporter) = &reporter {
+        reporter.on_download_start();
+    }
+    rattler_package_streaming::reqwest::resumable::download(
+        client.clone(),
+        url.clone(),
+        partial_path,
+        sha256,
+        reporter.clone(),
+    )
+    .await?;
+
+    if let Err(err) = verify(partial_path.to_path_buf()).await {
+        // Never keep an archive around that might have been tampered with.
+        tokio_fs::remove_file(partial_path).await?;
+        return Err(err);
+    }
+
+    let result = rattler_package_streaming::reqwest::resumable::extract_partial(
+        &url,
+        destination,
+        partial_path,
+        sha256,
+        md5,
+    )
+    .await?;
+    if let Some(reporter) = &reporter {
+        reporter.on_download_complete();
+    }
+    Ok(result)
+}
+
 /// Validates that the package that is currently stored is a valid package and
 /// otherwise calls the `fetch` method to populate the cache.
 async fn validate_or_fetch_to_cache<F, Fut, E>(
@@ -572,7 +795,10 @@ mod test {
     use tokio_stream::StreamExt;
     use url::Url;
 
-    use super::PackageCache;
+    use rattler_package_streaming::ExtractError;
+    use rattler_signatures::{IdentityPolicy, SignatureVerifier, TrustRoot};
+
+    use super::{DownloadLimits, DownloadScheduler, PackageCache, PackageCacheError};
     use crate::{
         package_cache::CacheKey,
         validation::{validate_package_directory, ValidationMode},
@@ -954,4 +1180,232 @@ mod test {
             "expected sha256 to be different"
         );
     }
+
+    #[tokio::test]
+    async fn test_download_scheduler() {
+        let router = Router::new()
+            .fallback_service(tower_http::services::ServeDir::new(get_test_data_dir()));
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+
+        let archive_name = "empty-0.1.0-h4616a5c_0.conda";
+        let url = Url::parse(&format!(
+            "http://localhost:{}/packages/{archive_name}",
+            addr.port()
+        ))
+        .unwrap();
+
+        // The package is ~1500 bytes, so it takes at least half a second at
+        // 1000 bytes per second.
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path()).with_download_scheduler(
+            DownloadScheduler::new(DownloadLimits {
+                max_bytes_per_second: Some(1000.try_into().unwrap()),
+                max_downloads_per_host: Some(1.try_into().unwrap()),
+                ..DownloadLimits::default()
+            }),
+        );
+
+        let start = std::time::Instant::now();
+        let cache_lock = cache
+            .get_or_fetch_from_url_with_priority(
+                ArchiveIdentifier::try_from_filename(archive_name).unwrap(),
+                url,
+                ClientBuilder::new(Client::default()).build().into(),
+                DoNotRetryPolicy,
+                0,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+        validate_package_directory(cache_lock.path(), ValidationMode::Full).unwrap();
+    }
+
+    /// Breaks the connection of the first response after 500 bytes and
+    /// records the `Range` header of all requests.
+    async fn interrupt_first_response(
+        State(ranges): State<Arc<Mutex<Vec<Option<String>>>>>,
+        req: Request<Body>,
+        next: Next,
+    ) -> Response {
+        let range = req
+            .headers()
+            .get(reqwest::header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+        let is_first = {
+            let mut ranges = ranges.lock().await;
+            ranges.push(range);
+            ranges.len() == 1
+        };
+
+        let response = next.run(req).await;
+        if !is_first {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        let body = Body::from_stream(stream::once(async move { Ok(bytes.slice(..500)) }).chain(
+            stream::once(async {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                Err(std::io::Error::other("connection reset"))
+            }),
+        ));
+        Response::from_parts(parts, body)
+    }
+
+    #[tokio::test]
+    async fn test_resumable_download() {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new()
+            .fallback_service(tower_http::services::ServeDir::new(get_test_data_dir()))
+            .layer(middleware::from_fn_with_state(
+                ranges.clone(),
+                interrupt_first_response,
+            ));
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+
+        let archive_name = "empty-0.1.0-h4616a5c_0.conda";
+        let url = Url::parse(&format!(
+            "http://localhost:{}/packages/{archive_name}",
+            addr.port()
+        ))
+        .unwrap();
+
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path()).with_resumable_downloads();
+        let fetch = |max_retries| {
+            cache.get_or_fetch_from_url_with_retry(
+                ArchiveIdentifier::try_from_filename(archive_name).unwrap(),
+                url.clone(),
+                ClientBuilder::new(Client::default()).build().into(),
+                ExponentialBackoffBuilder::default().build_with_max_retries(max_retries),
+                None,
+            )
+        };
+        let partial_downloads = || {
+            fs_err::read_dir(packages_dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .filter(|name| name.to_string_lossy().ends_with(".partial"))
+                .collect::<Vec<_>>()
+        };
+
+        // A download that is not retried does not leave a partial file behind.
+        fetch(0).await.unwrap_err();
+        assert_eq!(*ranges.lock().await, vec![None]);
+        assert_eq!(partial_downloads(), Vec::<std::ffi::OsString>::new());
+
+        // A stale partial download of another process is not continued.
+        ranges.lock().await.clear();
+        let cache_key = CacheKey::from(ArchiveIdentifier::try_from_filename(archive_name).unwrap());
+        fs_err::write(
+            packages_dir.path().join(format!("{cache_key}.partial")),
+            b"stale",
+        )
+        .unwrap();
+        let cache_lock = fetch(3).await.unwrap();
+        validate_package_directory(cache_lock.path(), ValidationMode::Full).unwrap();
+
+        // The retry continued the interrupted download.
+        assert_eq!(
+            *ranges.lock().await,
+            vec![None, Some(String::from("bytes=500-"))]
+        );
+        assert!(!packages_dir
+            .path()
+            .join(format!(
+                "{}.partial",
+                cache_lock.path().file_name().unwrap().to_string_lossy()
+            ))
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn test_signature_verification() {
+        let archive_name = "empty-0.1.0-h4616a5c_0.conda";
+        let served_dir = tempdir().unwrap();
+        fs_err::copy(
+            get_test_data_dir().join("packages").join(archive_name),
+            served_dir.path().join(archive_name),
+        )
+        .unwrap();
+        fs_err::copy(
+            get_test_data_dir()
+                .join("signatures")
+                .join(format!("{archive_name}.sigstore.json")),
+            served_dir
+                .path()
+                .join(format!("{archive_name}.sigstore.json")),
+        )
+        .unwrap();
+
+        let router =
+            Router::new().fallback_service(tower_http::services::ServeDir::new(served_dir.path()));
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let url = Url::parse(&format!("http://localhost:{}/{archive_name}", addr.port())).unwrap();
+
+        let mut trust_root =
+            TrustRoot::from_pem_file(get_test_data_dir().join("signatures/ca.pem")).unwrap();
+        trust_root
+            .add_transparency_log_pem(
+                &fs_err::read_to_string(get_test_data_dir().join("signatures/rekor.pem")).unwrap(),
+            )
+            .unwrap();
+        let fetch = |verifier: SignatureVerifier| {
+            let url = url.clone();
+            async move {
+                let packages_dir = tempdir().unwrap();
+                let cache =
+                    PackageCache::new(packages_dir.path()).with_signature_verifier(verifier);
+                let result = cache
+                    .get_or_fetch_from_url(
+                        ArchiveIdentifier::try_from_filename(archive_name).unwrap(),
+                        url,
+                        ClientBuilder::new(Client::default()).build().into(),
+                        None,
+                    )
+                    .await;
+                (packages_dir, result)
+            }
+        };
+
+        let verifier = SignatureVerifier::new(trust_root.clone())
+            .with_identity_policy(IdentityPolicy::new("publisher@example.com"));
+        let (packages_dir, result) = fetch(verifier).await;
+        let cache_lock = result.unwrap();
+        validate_package_directory(cache_lock.path(), ValidationMode::Full).unwrap();
+        assert!(!packages_dir
+            .path()
+            .join(format!(
+                "{}.partial",
+                cache_lock.path().file_name().unwrap().to_string_lossy()
+            ))
+            .exists());
+
+        // A package signed by someone else is not extracted.
+        let verifier = SignatureVerifier::new(trust_root)
+            .with_identity_policy(IdentityPolicy::new("someone-else@example.com"));
+        let (packages_dir, result) = fetch(verifier).await;
+        let Err(PackageCacheError::FetchError(err)) = result else {
+            panic!("expected a fetch error");
+        };
+        assert_matches!(
+            err.downcast_ref::<ExtractError>(),
+            Some(ExtractError::SignatureVerificationFailed(..))
+        );
+        assert!(fs_err::read_dir(packages_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .all(|name| name.to_string_lossy().ends_with(".lock")));
+    }
 }
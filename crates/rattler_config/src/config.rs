@@ -8,17 +8,20 @@ use url::Url;
 
 use crate::config::s3::S3OptionsMap;
 use crate::config::{
-    build::BuildConfig, concurrency::ConcurrencyConfig, proxy::ProxyConfig,
-    repodata_config::RepodataConfig, run_post_link_scripts::RunPostLinkScripts,
+    build::BuildConfig, concurrency::ConcurrencyConfig, credential_helpers::CredentialHelpersMap,
+    proxy::ProxyConfig, repodata_config::RepodataConfig, run_post_link_scripts::RunPostLinkScripts,
+    tls::TlsConfigMap,
 };
 
 pub mod build;
 pub mod channel_config;
 pub mod concurrency;
+pub mod credential_helpers;
 pub mod proxy;
 pub mod repodata_config;
 pub mod run_post_link_scripts;
 pub mod s3;
+pub mod tls;
 use crate::config::channel_config::default_channel_config;
 #[cfg(feature = "edit")]
 use crate::edit::ConfigEditError;
@@ -114,6 +117,16 @@ pub struct ConfigBase<T> {
     #[serde(skip_serializing_if = "S3OptionsMap::is_default")]
     pub s3_options: S3OptionsMap,
 
+    /// External executables that provide credentials for matching hosts.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "CredentialHelpersMap::is_default")]
+    pub credential_helpers: CredentialHelpersMap,
+
+    /// Custom CA bundles and client certificates for matching hosts.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "TlsConfigMap::is_default")]
+    pub tls: TlsConfigMap,
+
     /// Run the post link scripts
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -153,6 +166,8 @@ where
             concurrency: ConcurrencyConfig::default(),
             proxy_config: ProxyConfig::default(),
             s3_options: S3OptionsMap::default(),
+            credential_helpers: CredentialHelpersMap::default(),
+            tls: TlsConfigMap::default(),
             run_post_link_scripts: None,
             extensions: T::default(),
             loaded_from: Vec::new(),
@@ -246,6 +261,10 @@ where
     fn merge_config(self, other: &Self) -> Result<Self, MergeError> {
         Ok(Self {
             s3_options: self.s3_options.merge_config(&other.s3_options)?,
+            credential_helpers: self
+                .credential_helpers
+                .merge_config(&other.credential_helpers)?,
+            tls: self.tls.merge_config(&other.tls)?,
             // Use the other configuration's default channels if available
             default_channels: other
                 .default_channels
@@ -285,7 +304,8 @@ where
     }
 
     fn validate(&self) -> Result<(), ValidationError> {
-        Ok(())
+        self.credential_helpers.validate()?;
+        self.tls.validate()
     }
 
     /// Gather all the keys of the configuration.
@@ -306,6 +326,8 @@ where
         keys.extend(get_keys(&self.proxy_config));
         keys.extend(get_keys(&self.extensions));
         keys.extend(get_keys(&self.s3_options));
+        keys.extend(get_keys(&self.credential_helpers));
+        keys.extend(get_keys(&self.tls));
 
         keys.push("default_channels".to_string());
         keys.push("authentication_override_file".to_string());
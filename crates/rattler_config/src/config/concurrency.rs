@@ -33,6 +33,16 @@ pub struct ConcurrencyConfig {
     // to 0 of partial struct was omitted.
     #[serde(default = "default_max_concurrent_downloads")]
     pub downloads: usize,
+
+    /// The maximum number of concurrent package downloads from a single
+    /// host. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads_per_host: Option<usize>,
+
+    /// The maximum number of bytes per second that package downloads may
+    /// use together. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth: Option<u64>,
 }
 
 impl Default for ConcurrencyConfig {
@@ -40,6 +50,8 @@ impl Default for ConcurrencyConfig {
         Self {
             solves: default_max_concurrent_solves(),
             downloads: default_max_concurrent_downloads(),
+            downloads_per_host: None,
+            max_bandwidth: None,
         }
     }
 }
@@ -67,6 +79,8 @@ impl Config for ConcurrencyConfig {
             } else {
                 other.downloads
             },
+            downloads_per_host: other.downloads_per_host.or(self.downloads_per_host),
+            max_bandwidth: other.max_bandwidth.or(self.max_bandwidth),
         })
     }
 
@@ -85,11 +99,30 @@ impl Config for ConcurrencyConfig {
             ));
         }
 
+        if self.downloads_per_host == Some(0) {
+            return Err(ValidationError::InvalidValue(
+                "downloads-per-host".to_string(),
+                "The number of concurrent downloads per host must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.max_bandwidth == Some(0) {
+            return Err(ValidationError::InvalidValue(
+                "max-bandwidth".to_string(),
+                "The maximum bandwidth must be greater than 0".to_string(),
+            ));
+        }
+
         Ok(())
     }
 
     fn keys(&self) -> Vec<String> {
-        vec!["solves".to_string(), "downloads".to_string()]
+        vec![
+            "solves".to_string(),
+            "downloads".to_string(),
+            "downloads-per-host".to_string(),
+            "max-bandwidth".to_string(),
+        ]
     }
 
     #[cfg(feature = "edit")]
@@ -122,6 +155,23 @@ impl Config for ConcurrencyConfig {
                         source: e,
                     })?;
             }
+            "downloads-per-host" => {
+                self.downloads_per_host =
+                    value.map(|value| value.parse()).transpose().map_err(|e| {
+                        ConfigEditError::NumberParseError {
+                            key: key.to_string(),
+                            source: e,
+                        }
+                    })?;
+            }
+            "max-bandwidth" => {
+                self.max_bandwidth = value.map(|value| value.parse()).transpose().map_err(|e| {
+                    ConfigEditError::NumberParseError {
+                        key: key.to_string(),
+                        source: e,
+                    }
+                })?;
+            }
             _ => {
                 return Err(ConfigEditError::UnknownKeyInner {
                     key: key.to_string(),
@@ -0,0 +1,96 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+#[cfg(feature = "edit")]
+use crate::edit::ConfigEditError;
+
+/// Maps host patterns (e.g. `conda.example.com` or `*.example.com`) to the
+/// credential helper that provides the credentials for matching hosts.
+#[derive(Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CredentialHelpersMap(pub IndexMap<String, CredentialHelperConfig>);
+
+/// An external executable that provides credentials.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CredentialHelperConfig {
+    /// The name of or the path to the executable
+    pub command: String,
+
+    /// Arguments that are passed to the executable before the action
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl Config for CredentialHelpersMap {
+    fn is_default(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn merge_config(self, other: &Self) -> Result<Self, super::MergeError> {
+        // Merge the two maps, overwriting existing patterns
+        let mut merged = self.0.clone();
+        for (key, value) in &other.0 {
+            merged.insert(key.clone(), value.clone());
+        }
+        Ok(CredentialHelpersMap(merged))
+    }
+
+    #[cfg(feature = "edit")]
+    fn set(&mut self, key: &str, value: Option<String>) -> Result<(), ConfigEditError> {
+        fn parse<T: serde::de::DeserializeOwned>(
+            key: &str,
+            value: Option<String>,
+        ) -> Result<T, ConfigEditError> {
+            let value = value.ok_or_else(|| ConfigEditError::MissingValue {
+                key: key.to_string(),
+            })?;
+            serde_json::de::from_str(&value).map_err(|e| ConfigEditError::JsonParseError {
+                key: key.to_string(),
+                source: e,
+            })
+        }
+
+        if key == "credential-helpers" {
+            self.0 = parse(key, value)?;
+            return Ok(());
+        }
+        // Host patterns contain dots themselves, so the remainder of the key
+        // is the pattern.
+        let Some(pattern) = key.strip_prefix("credential-helpers.") else {
+            return Err(ConfigEditError::UnknownKey {
+                key: key.to_string(),
+                supported_keys: "".to_string(),
+            });
+        };
+        match value {
+            Some(_) => {
+                self.0.insert(pattern.to_string(), parse(key, value)?);
+            }
+            None => {
+                self.0.shift_remove(pattern);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_extension_name(&self) -> String {
+        "credential-helpers".to_string()
+    }
+
+    fn validate(&self) -> Result<(), super::ValidationError> {
+        for (pattern, helper) in &self.0 {
+            if helper.command.is_empty() {
+                return Err(super::ValidationError::InvalidValue(
+                    format!("credential-helpers.{pattern}"),
+                    "the command of a credential helper cannot be empty".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.keys().map(ToString::to_string).collect()
+    }
+}
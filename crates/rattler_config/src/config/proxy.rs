@@ -68,7 +68,7 @@ impl Default for ProxyConfig {
 
 impl ProxyConfig {
     pub fn is_default(&self) -> bool {
-        self.https.is_none() && self.https.is_none() && self.non_proxy_hosts.is_empty()
+        self.https.is_none() && self.http.is_none() && self.non_proxy_hosts.is_empty()
     }
 }
 
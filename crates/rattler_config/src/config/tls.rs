@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+#[cfg(feature = "edit")]
+use crate::edit::ConfigEditError;
+
+/// Maps host patterns (e.g. `conda.example.com` or `*.example.com`) to the
+/// TLS settings that are used for connections to matching hosts.
+#[derive(Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TlsConfigMap(pub IndexMap<String, TlsOptions>);
+
+/// TLS settings for connections to a host.
+#[derive(Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsOptions {
+    /// A PEM file with additional root certificates to trust
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// A PEM file with the client certificate to authenticate with (mTLS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<PathBuf>,
+
+    /// A PEM file with the private key of the client certificate. Can be
+    /// omitted if the key is part of the certificate file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+}
+
+impl Config for TlsConfigMap {
+    fn is_default(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn merge_config(self, other: &Self) -> Result<Self, super::MergeError> {
+        // Merge the two maps, overwriting existing patterns
+        let mut merged = self.0.clone();
+        for (key, value) in &other.0 {
+            merged.insert(key.clone(), value.clone());
+        }
+        Ok(TlsConfigMap(merged))
+    }
+
+    #[cfg(feature = "edit")]
+    fn set(&mut self, key: &str, value: Option<String>) -> Result<(), ConfigEditError> {
+        fn parse<T: serde::de::DeserializeOwned>(
+            key: &str,
+            value: Option<String>,
+        ) -> Result<T, ConfigEditError> {
+            let value = value.ok_or_else(|| ConfigEditError::MissingValue {
+                key: key.to_string(),
+            })?;
+            serde_json::de::from_str(&value).map_err(|e| ConfigEditError::JsonParseError {
+                key: key.to_string(),
+                source: e,
+            })
+        }
+
+        if key == "tls" {
+            self.0 = parse(key, value)?;
+            return Ok(());
+        }
+        // Host patterns contain dots themselves, so the remainder of the key
+        // is the pattern.
+        let Some(pattern) = key.strip_prefix("tls.") else {
+            return Err(ConfigEditError::UnknownKey {
+                key: key.to_string(),
+                supported_keys: "".to_string(),
+            });
+        };
+        match value {
+            Some(_) => {
+                self.0.insert(pattern.to_string(), parse(key, value)?);
+            }
+            None => {
+                self.0.shift_remove(pattern);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_extension_name(&self) -> String {
+        "tls".to_string()
+    }
+
+    fn validate(&self) -> Result<(), super::ValidationError> {
+        for (pattern, options) in &self.0 {
+            if options.client_key.is_some() && options.client_certificate.is_none() {
+                return Err(super::ValidationError::InvalidValue(
+                    format!("tls.{pattern}"),
+                    "a client key requires a client certificate".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.keys().map(ToString::to_string).collect()
+    }
+}
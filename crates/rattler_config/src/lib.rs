@@ -145,6 +145,18 @@ mod tests {
             .set("concurrency.downloads", Some("10".to_string()))
             .unwrap();
         assert_eq!(config.concurrency.downloads, 10);
+
+        // Test editing the download limits
+        config
+            .set("concurrency.downloads-per-host", Some("4".to_string()))
+            .unwrap();
+        assert_eq!(config.concurrency.downloads_per_host, Some(4));
+        config
+            .set("concurrency.max-bandwidth", Some("1048576".to_string()))
+            .unwrap();
+        assert_eq!(config.concurrency.max_bandwidth, Some(1_048_576));
+        config.set("concurrency.max-bandwidth", None).unwrap();
+        assert_eq!(config.concurrency.max_bandwidth, None);
     }
 
     #[test]
@@ -261,6 +273,74 @@ mod tests {
         assert!(!config.s3_options.0["mybucket"].force_path_style);
     }
 
+    #[test]
+    fn test_edit_credential_helpers() {
+        let mut config = TestConfig::default();
+
+        config
+            .set(
+                "credential-helpers.*.example.com",
+                Some(r#"{"command": "vault-helper", "args": ["--profile", "conda"]}"#.to_string()),
+            )
+            .unwrap();
+        let helper = &config.credential_helpers.0["*.example.com"];
+        assert_eq!(helper.command, "vault-helper");
+        assert_eq!(helper.args, vec!["--profile", "conda"]);
+        assert!(config.validate().is_ok());
+
+        // The helper survives a roundtrip through the config file format.
+        let parsed: TestConfig = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(parsed.credential_helpers, config.credential_helpers);
+
+        config
+            .set("credential-helpers.*.example.com", None)
+            .unwrap();
+        assert!(config.credential_helpers.0.is_empty());
+    }
+
+    #[test]
+    fn test_edit_tls() {
+        let mut config = TestConfig::default();
+
+        config
+            .set(
+                "tls.conda.corp.example",
+                Some(
+                    r#"{"ca-bundle": "/etc/corp/ca.pem", "client-certificate": "/etc/corp/client.pem"}"#
+                        .to_string(),
+                ),
+            )
+            .unwrap();
+        let tls = &config.tls.0["conda.corp.example"];
+        assert_eq!(tls.ca_bundle, Some(PathBuf::from("/etc/corp/ca.pem")));
+        assert_eq!(
+            tls.client_certificate,
+            Some(PathBuf::from("/etc/corp/client.pem"))
+        );
+        assert!(config.validate().is_ok());
+
+        // `tls-no-verify` is not part of the TLS map.
+        config
+            .set("tls-no-verify", Some("true".to_string()))
+            .unwrap();
+        assert_eq!(config.tls_no_verify, Some(true));
+
+        let parsed: TestConfig = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(parsed.tls, config.tls);
+
+        config
+            .set(
+                "tls.*.corp.example",
+                Some(r#"{"client-key": "/etc/corp/client.key"}"#.to_string()),
+            )
+            .unwrap();
+        assert!(config.validate().is_err());
+
+        config.set("tls.*.corp.example", None).unwrap();
+        config.set("tls.conda.corp.example", None).unwrap();
+        assert!(config.tls.0.is_empty());
+    }
+
     #[test]
     fn test_edit_run_post_link_scripts() {
         let mut config = TestConfig::default();
@@ -751,6 +831,7 @@ mod tests {
             concurrency: crate::config::concurrency::ConcurrencyConfig {
                 solves: 4,
                 downloads: 8,
+                ..Default::default()
             },
             extensions: TestExtension {
                 numeric_field: Some(50),
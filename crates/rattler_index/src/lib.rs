@@ -35,6 +35,7 @@ use rattler_digest::Sha256Hash;
 use rattler_package_streaming::{
     read,
     seek::{self, stream_conda_content},
+    validate::validate_package_reader,
 };
 use rattler_s3::ResolvedS3Credentials;
 use retry_policies::{policies::ExponentialBackoff, Jitter, RetryDecision, RetryPolicy};
@@ -243,11 +244,20 @@ pub fn package_record_from_conda_reader(reader: impl BufRead) -> std::io::Result
 ///
 /// * `buffer` - The file contents to parse
 /// * `filename` - The filename (used to determine archive type)
+/// * `validate_package` - Whether to validate the package and log the problems
+///   that are found
 ///
 /// # Returns
 ///
 /// Returns the parsed `PackageRecord`.
-fn parse_package_buffer(buffer: opendal::Buffer, filename: &str) -> std::io::Result<PackageRecord> {
+fn parse_package_buffer(
+    buffer: opendal::Buffer,
+    filename: &str,
+    validate_package: bool,
+) -> std::io::Result<PackageRecord> {
+    if validate_package {
+        warn_on_invalid_package(&buffer, filename);
+    }
     let reader = buffer.reader();
     let archive_type = ArchiveType::try_from(filename).unwrap();
     match archive_type {
@@ -256,6 +266,19 @@ fn parse_package_buffer(buffer: opendal::Buffer, filename: &str) -> std::io::Res
     }
 }
 
+/// Validate a package that is being ingested and log every problem that is
+/// found. Invalid packages are still indexed.
+fn warn_on_invalid_package(buffer: &opendal::Buffer, filename: &str) {
+    match validate_package_reader(Cursor::new(buffer.to_bytes()), filename) {
+        Ok(report) => {
+            for error in report.errors {
+                tracing::warn!("{filename}: {error}");
+            }
+        }
+        Err(err) => tracing::warn!("Failed to validate {filename}: {err}"),
+    }
+}
+
 /// Read and parse a package file with caching and retry logic.
 ///
 /// This function encapsulates the logic for reading a package file, including:
@@ -270,6 +293,8 @@ fn parse_package_buffer(buffer: opendal::Buffer, filename: &str) -> std::io::Res
 /// * `cache` - The package record cache (scoped to a single subdir)
 /// * `subdir` - The subdirectory (e.g., "noarch", "linux-64")
 /// * `filename` - The package filename (e.g., "package-1.0.0.tar.bz2")
+/// * `validate_package` - Whether to validate the package and log the problems
+///   that are found
 ///
 /// # Returns
 ///
@@ -279,6 +304,7 @@ async fn read_and_parse_package(
     cache: &cache::PackageRecordCache,
     subdir: Platform,
     filename: &str,
+    validate_package: bool,
 ) -> std::io::Result<PackageRecord> {
     let file_path = format!("{subdir}/{filename}");
 
@@ -306,7 +332,7 @@ async fn read_and_parse_package(
             .map_err(|e| std::io::Error::other(e.to_string()))?;
 
             // Parse package
-            let record = parse_package_buffer(buffer, filename)?;
+            let record = parse_package_buffer(buffer, filename, validate_package)?;
 
             // Store in cache using filename as key
             cache
@@ -327,7 +353,7 @@ async fn read_and_parse_package(
                 .read(&file_path)
                 .await
                 .map_err(|e| std::io::Error::other(e.to_string()))?;
-            parse_package_buffer(buffer, filename)
+            parse_package_buffer(buffer, filename, validate_package)
         }
     }
 }
@@ -417,13 +443,14 @@ impl RepodataMetadataCollection {
     }
 }
 
-#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
 async fn index_subdir(
     subdir: Platform,
     op: Operator,
     force: bool,
     write_zst: bool,
     write_shards: bool,
+    validate_packages: bool,
     repodata_patch: Option<PatchInstructions>,
     progress: Option<MultiProgress>,
     semaphore: Arc<Semaphore>,
@@ -443,6 +470,7 @@ async fn index_subdir(
             force,
             write_zst,
             write_shards,
+            validate_packages,
             repodata_patch.clone(),
             progress.clone(),
             semaphore.clone(),
@@ -490,13 +518,14 @@ async fn index_subdir(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
 async fn index_subdir_inner(
     subdir: Platform,
     op: Operator,
     force: bool,
     write_zst: bool,
     write_shards: bool,
+    validate_packages: bool,
     repodata_patch: Option<PatchInstructions>,
     progress: Option<MultiProgress>,
     semaphore: Arc<Semaphore>,
@@ -628,7 +657,9 @@ async fn index_subdir_inner(
                     console::style(&filename).dim()
                 ));
 
-                let record = read_and_parse_package(&op, &cache, subdir, &filename).await?;
+                let record =
+                    read_and_parse_package(&op, &cache, subdir, &filename, validate_packages)
+                        .await?;
 
                 pb.inc(1);
                 Ok::<(String, PackageRecord), std::io::Error>((filename, record))
@@ -904,6 +935,9 @@ pub struct IndexFsConfig {
     pub write_shards: bool,
     /// Whether to force the index to be written.
     pub force: bool,
+    /// Whether to validate the contents of new packages and log the problems
+    /// that are found. This reads every package a second time.
+    pub validate_packages: bool,
     /// The maximum number of parallel tasks to run.
     pub max_parallel: usize,
     /// The multi-progress bar to use for the index.
@@ -920,6 +954,7 @@ pub async fn index_fs(
         write_zst,
         write_shards,
         force,
+        validate_packages,
         max_parallel,
         multi_progress,
     }: IndexFsConfig,
@@ -935,6 +970,7 @@ pub async fn index_fs(
         write_zst,
         write_shards,
         force,
+        validate_packages,
         max_parallel,
         multi_progress,
     )
@@ -948,6 +984,10 @@ pub struct IndexS3Config {
     pub channel: Url,
     /// The resolved credentials to use for S3 access.
     pub credentials: ResolvedS3Credentials,
+    /// The client to send the requests to S3 with, e.g. one that is built
+    /// with `rattler_networking::ClientConfig` to apply the proxy and TLS
+    /// settings. If not set, a client without these settings is used.
+    pub client: Option<reqwest_middleware::ClientWithMiddleware>,
     /// The target platform to index.
     pub target_platform: Option<Platform>,
     /// The path to a repodata patch to apply to the index.
@@ -958,6 +998,9 @@ pub struct IndexS3Config {
     pub write_shards: bool,
     /// Whether to force the index to be written.
     pub force: bool,
+    /// Whether to validate the contents of new packages and log the problems
+    /// that are found. This reads every package a second time.
+    pub validate_packages: bool,
     /// The maximum number of parallel tasks to run.
     pub max_parallel: usize,
     /// The multi-progress bar to use for the index.
@@ -970,11 +1013,13 @@ pub async fn index_s3(
     IndexS3Config {
         channel,
         credentials,
+        client,
         target_platform,
         repodata_patch,
         write_zst,
         write_shards,
         force,
+        validate_packages,
         max_parallel,
         multi_progress,
     }: IndexS3Config,
@@ -996,7 +1041,12 @@ pub async fn index_s3(
         credentials.addressing_style == rattler_s3::S3AddressingStyle::VirtualHost;
 
     let builder = s3_config.into_builder();
-    let op = Operator::new(builder)?.layer(RetryLayer::new()).finish();
+    let op = Operator::new(builder)?
+        .layer(rattler_s3::opendal::http_client_layer(
+            client.unwrap_or_default(),
+        ))
+        .layer(RetryLayer::new())
+        .finish();
 
     index(
         target_platform,
@@ -1005,6 +1055,7 @@ pub async fn index_s3(
         write_zst,
         write_shards,
         force,
+        validate_packages,
         max_parallel,
         multi_progress,
     )
@@ -1028,7 +1079,7 @@ pub async fn index_s3(
 ///
 /// Returns `IndexStats` containing statistics about the indexing operation,
 /// including the number of packages added/removed and retry counts per subdir.
-#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
 pub async fn index(
     target_platform: Option<Platform>,
     op: Operator,
@@ -1036,6 +1087,7 @@ pub async fn index(
     write_zst: bool,
     write_shards: bool,
     force: bool,
+    validate_packages: bool,
     max_parallel: usize,
     multi_progress: Option<MultiProgress>,
 ) -> anyhow::Result<IndexStats> {
@@ -1109,6 +1161,7 @@ pub async fn index(
             force,
             write_zst,
             write_shards,
+            validate_packages,
             repodata_patch
                 .as_ref()
                 .and_then(|p| p.subdirs.get(&subdir.to_string()).cloned()),
@@ -6,7 +6,7 @@ use clap_verbosity_flag::Verbosity;
 use rattler_conda_types::Platform;
 use rattler_config::config::concurrency::default_max_concurrent_solves;
 use rattler_index::{index_fs, index_s3, IndexFsConfig, IndexS3Config};
-use rattler_networking::AuthenticationStorage;
+use rattler_networking::{AuthenticationStorage, ClientConfig};
 use rattler_s3::S3Credentials;
 use url::Url;
 
@@ -45,6 +45,11 @@ struct Cli {
     #[arg(short, long, default_value = "false", global = true)]
     force: bool,
 
+    /// Whether to validate the contents of new packages and log the problems
+    /// that are found. This reads every new package a second time.
+    #[arg(long, default_value = "false", global = true)]
+    validate_packages: bool,
+
     /// The maximum number of packages to process in-memory simultaneously.
     /// This is necessary to limit memory usage when indexing large channels.
     #[arg(long, global = true)]
@@ -124,6 +129,7 @@ async fn main() -> anyhow::Result<()> {
                 write_zst: cli.write_zst.unwrap_or(true),
                 write_shards: cli.write_shards.unwrap_or(true),
                 force: cli.force,
+                validate_packages: cli.validate_packages,
                 max_parallel,
                 multi_progress: Some(multi_progress),
             })
@@ -145,22 +151,32 @@ async fn main() -> anyhow::Result<()> {
                 .or(s3_config.map(|c| c.endpoint_url.clone()));
 
             // Resolve the credentials
+            let auth_storage = AuthenticationStorage::from_env_and_defaults()?;
             let credentials = match Option::<S3Credentials>::from(credentials) {
                 Some(credentials) => {
-                    let auth_storage = AuthenticationStorage::from_env_and_defaults()?;
                     credentials.resolve(&channel, &auth_storage).ok_or_else(|| anyhow::anyhow!("Could not find S3 credentials in the authentication storage, and no credentials were provided via the command line."))?
                 }
                 None => rattler_s3::ResolvedS3Credentials::from_sdk().await?,
             };
 
+            // Send the requests with the proxy and TLS settings of the
+            // configuration.
+            let client_config = config
+                .as_ref()
+                .map(ClientConfig::from_config)
+                .unwrap_or_default();
+            let client = client_config.build(auth_storage)?;
+
             index_s3(IndexS3Config {
                 channel,
                 credentials,
+                client: Some(client),
                 target_platform: cli.target_platform,
                 repodata_patch: cli.repodata_patch,
                 write_zst: cli.write_zst.unwrap_or(true),
                 write_shards: cli.write_shards.unwrap_or(true),
                 force: cli.force,
+                validate_packages: cli.validate_packages,
                 max_parallel,
                 multi_progress: Some(multi_progress),
             })
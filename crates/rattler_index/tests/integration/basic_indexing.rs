@@ -71,6 +71,7 @@ async fn test_index() {
         write_zst: true,
         write_shards: true,
         force: true,
+        validate_packages: false,
         max_parallel: 32,
         multi_progress: None,
     })
@@ -132,6 +133,7 @@ async fn test_index_empty_directory_creates_noarch_repodata() {
         write_zst: true,
         write_shards: true,
         force: true,
+        validate_packages: false,
         max_parallel: 100,
         multi_progress: None,
     })
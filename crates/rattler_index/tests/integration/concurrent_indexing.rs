@@ -108,6 +108,7 @@ async fn test_concurrent_index_with_race_condition_and_retry() {
                 false,
                 false,
                 false,
+                false,
                 1,
                 None,
             )
@@ -127,6 +128,7 @@ async fn test_concurrent_index_with_race_condition_and_retry() {
                 false,
                 false,
                 false,
+                false,
                 1,
                 None,
             )
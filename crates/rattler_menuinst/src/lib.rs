@@ -9,11 +9,16 @@ use rattler_conda_types::{
 mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
+pub mod preview;
 mod render;
 pub mod schema;
+pub mod validate;
 #[cfg(target_os = "windows")]
 mod windows;
 
+pub use preview::preview_menuitems;
+pub use validate::{validate_menu_schema, SchemaError};
+
 use crate::{render::BaseMenuItemPlaceholders, schema::MenuInstSchema};
 
 mod utils;
@@ -32,11 +37,12 @@ pub enum MenuInstError {
     #[error("invalid path: {0}")]
     InvalidPath(PathBuf),
 
-    #[cfg(target_os = "linux")]
+    #[error("invalid menu schema:{}", validate::format_errors(.0))]
+    InvalidSchema(Vec<SchemaError>),
+
     #[error("could not quote command with shlex: {0}")]
     ShlexQuoteError(#[from] shlex::QuoteError),
 
-    #[cfg(target_os = "macos")]
     #[error("failed to create plist: {0}")]
     PlistError(#[from] plist::Error),
 
@@ -87,26 +93,31 @@ pub fn install_menuitems_for_record(
         .filter(|path| is_menu_schema_path(&path.relative_path))
         .collect();
 
+    if menu_files.is_empty() {
+        return Ok(());
+    }
+
+    let mut trackers = Vec::new();
     for menu_file in menu_files {
         let full_path = target_prefix.join(&menu_file.relative_path);
-        let tracker_vec = install_menuitems(
+        trackers.extend(install_menuitems(
             &full_path,
             target_prefix,
             target_prefix,
             platform,
             menu_mode,
-        )?;
+        )?);
+    }
 
-        // Store tracker in the prefix record
-        let mut record = prefix_record.clone();
-        record.installed_system_menus = tracker_vec;
+    // Store the trackers of all menu files in the prefix record
+    let mut record = prefix_record.clone();
+    record.installed_system_menus = trackers;
 
-        // Save the updated prefix record
-        record.write_to_path(
-            target_prefix.join("conda-meta").join(record.file_name()),
-            true,
-        )?;
-    }
+    // Save the updated prefix record
+    record.write_to_path(
+        target_prefix.join("conda-meta").join(record.file_name()),
+        true,
+    )?;
 
     Ok(())
 }
@@ -1,9 +1,6 @@
 use fs_err as fs;
-use fs_err::File;
 use mime_config::MimeConfig;
 use rattler_conda_types::menuinst::{LinuxRegisteredMimeFile, LinuxTracker, MenuMode};
-use std::collections::HashMap;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use tempfile::TempDir;
@@ -14,7 +11,10 @@ use rattler_conda_types::Platform;
 use rattler_shell::activation::{ActivationVariables, Activator, PathModificationBehavior};
 use rattler_shell::shell;
 
-use crate::render::{BaseMenuItemPlaceholders, MenuItemPlaceholders, PlaceholderString};
+use crate::preview::linux::{
+    desktop_entry, exec_command, glob_patterns, mime_type_basename, mime_type_xml, mime_types,
+};
+use crate::render::{BaseMenuItemPlaceholders, MenuItemPlaceholders};
 use crate::utils::{log_output, run_pre_create_command, slugify};
 use crate::{
     schema::{Linux, MenuItemCommand},
@@ -295,157 +295,26 @@ impl LinuxMenu {
                     ..Default::default()
                 };
                 let activation_env = activator.run_activation(activation_variables, None)?;
-
-                let envs = activation_env
-                    .into_iter()
-                    .map(|(k, v)| format!(r#""{k}={v}""#))
-                    .collect();
-                Ok::<Vec<String>, MenuInstError>(envs)
+                Ok::<Vec<(String, String)>, MenuInstError>(activation_env.into_iter().collect())
             })
             .transpose()?
             .unwrap_or_default();
 
-        let main_command = self
-            .command
-            .command
-            .iter()
-            .map(|s| s.resolve(&self.placeholders))
-            .map(|part| {
-                if part.starts_with("%") {
-                    Ok(part)
-                } else {
-                    Ok(shlex::try_quote(&part)?.into_owned())
-                }
-            })
-            .collect::<Result<Vec<_>, MenuInstError>>()?
-            .join(" ");
-
-        let main_with_env = if envs.is_empty() {
-            main_command
-        } else {
-            format!("env {} {}", envs.join(" "), main_command)
-        };
-
-        if let Some(pre_command) = &self.command.precommand {
-            let command = [pre_command.0.clone(), main_with_env].join(" && ");
-            Ok(format!("bash -c {}", shlex::try_quote(&command)?))
-        } else {
-            Ok(main_with_env)
-        }
-    }
-
-    fn resolve_and_join(&self, items: &[PlaceholderString]) -> String {
-        let mut res = String::new();
-        for item in items {
-            res.push_str(&item.resolve(&self.placeholders));
-            res.push(';');
-        }
-        res
+        exec_command(&self.command, &self.placeholders, &envs)
     }
 
     fn create_desktop_entry(&self, tracker: &mut LinuxTracker) -> Result<(), MenuInstError> {
         let file = self.location();
         tracing::info!("Creating desktop entry at {}", file.display());
-        let writer = File::create(&file)?;
-        let mut writer = std::io::BufWriter::new(writer);
-
+        let contents = desktop_entry(
+            &self.name,
+            &self.command()?,
+            &self.command,
+            &self.item,
+            &self.placeholders,
+        );
+        fs::write(&file, contents)?;
         tracker.paths.push(file);
-
-        writeln!(writer, "[Desktop Entry]")?;
-        writeln!(writer, "Type=Application")?;
-        writeln!(writer, "Encoding=UTF-8")?;
-        writeln!(writer, "Name={}", self.name)?;
-        writeln!(writer, "Exec={}", self.command()?)?;
-        writeln!(
-            writer,
-            "Terminal={}",
-            self.command.terminal.unwrap_or(false)
-        )?;
-
-        if let Some(icon) = &self.command.icon {
-            let icon = icon.resolve(&self.placeholders);
-            writeln!(writer, "Icon={icon}")?;
-        }
-
-        let description = self.command.description.resolve(&self.placeholders);
-        if !description.is_empty() {
-            writeln!(writer, "Comment={description}")?;
-        }
-
-        if let Some(working_dir) = &self.command.working_dir {
-            let working_dir = working_dir.resolve(&self.placeholders);
-            writeln!(writer, "Path={working_dir}")?;
-        }
-
-        // resolve categories and join them with a semicolon
-        if let Some(categories) = &self.item.categories {
-            writeln!(writer, "Categories={}", self.resolve_and_join(categories))?;
-        }
-
-        if let Some(dbus_activatable) = &self.item.dbus_activatable {
-            writeln!(writer, "DBusActivatable={dbus_activatable}")?;
-        }
-
-        if let Some(generic_name) = &self.item.generic_name {
-            writeln!(
-                writer,
-                "GenericName={}",
-                generic_name.resolve(&self.placeholders)
-            )?;
-        }
-
-        if let Some(hidden) = &self.item.hidden {
-            writeln!(writer, "Hidden={hidden}")?;
-        }
-
-        if let Some(implements) = &self.item.implements {
-            writeln!(writer, "Implements={}", self.resolve_and_join(implements))?;
-        }
-
-        if let Some(keywords) = &self.item.keywords {
-            writeln!(writer, "Keywords={}", self.resolve_and_join(keywords))?;
-        }
-
-        if let Some(mime_types) = &self.item.mime_type {
-            writeln!(writer, "MimeType={}", self.resolve_and_join(mime_types))?;
-        }
-
-        if let Some(no_display) = &self.item.no_display {
-            writeln!(writer, "NoDisplay={no_display}")?;
-        }
-
-        if let Some(not_show_in) = &self.item.not_show_in {
-            writeln!(writer, "NotShowIn={}", self.resolve_and_join(not_show_in))?;
-        }
-
-        if let Some(only_show_in) = &self.item.only_show_in {
-            writeln!(writer, "OnlyShowIn={}", self.resolve_and_join(only_show_in))?;
-        }
-
-        if let Some(single_main_window) = &self.item.single_main_window {
-            writeln!(writer, "SingleMainWindow={single_main_window}")?;
-        }
-
-        if let Some(prefers_non_default_gpu) = &self.item.prefers_non_default_gpu {
-            writeln!(writer, "PrefersNonDefaultGPU={prefers_non_default_gpu}")?;
-        }
-
-        if let Some(startup_notify) = &self.item.startup_notify {
-            writeln!(writer, "StartupNotify={startup_notify}")?;
-        }
-
-        if let Some(startup_wm_class) = &self.item.startup_wm_class {
-            writeln!(
-                writer,
-                "StartupWMClass={}",
-                startup_wm_class.resolve(&self.placeholders)
-            )?;
-        }
-
-        if let Some(try_exec) = &self.item.try_exec {
-            writeln!(writer, "TryExec={}", try_exec.resolve(&self.placeholders))?;
-        }
-
         Ok(())
     }
 
@@ -492,23 +361,13 @@ impl LinuxMenu {
     }
 
     fn register_mime_types(&self, tracker: &mut LinuxTracker) -> Result<(), MenuInstError> {
-        let Some(mime_types) = self.item.mime_type.as_ref() else {
+        if self.item.mime_type.is_none() {
             return Ok(());
-        };
-
-        let mime_types = mime_types
-            .iter()
-            .map(|s| s.resolve(&self.placeholders))
-            .collect::<Vec<String>>();
+        }
 
+        let mime_types = mime_types(&self.item, &self.placeholders);
         tracing::info!("Registering mime types {:?}", mime_types);
-        let mut resolved_globs = HashMap::<String, String>::new();
-
-        if let Some(globs) = &self.item.glob_patterns {
-            for (k, v) in globs {
-                resolved_globs.insert(k.resolve(&self.placeholders), v.resolve(&self.placeholders));
-            }
-        }
+        let resolved_globs = glob_patterns(&self.item, &self.placeholders);
 
         for mime_type in &mime_types {
             if let Some(glob_pattern) = resolved_globs.get(mime_type) {
@@ -539,7 +398,7 @@ impl LinuxMenu {
     }
 
     fn xml_path_for_mime_type(&self, mime_type: &str) -> Result<(PathBuf, bool), std::io::Error> {
-        let basename = mime_type.replace("/", "-");
+        let basename = mime_type_basename(mime_type);
         let mime_directory = self.directories.data_directory.join("mime/packages");
         if !mime_directory.is_dir() {
             return Ok((mime_directory.join(format!("{basename}.xml")), false));
@@ -584,20 +443,7 @@ impl LinuxMenu {
         }
 
         // Write the XML that binds our current mime type to the glob pattern
-        let xmlns = "http://www.freedesktop.org/standards/shared-mime-info";
-        let description = format!(
-            "Custom MIME type {mime_type} for '{glob_pattern}' files (registered by menuinst)"
-        );
-
-        let xml = format!(
-            r#"<?xml version="1.0" encoding="UTF-8"?>
-<mime-info xmlns="{xmlns}">
-    <mime-type type="{mime_type}">
-        <glob pattern="{glob_pattern}"/>
-        <comment>{description}</comment>
-    </mime-type>
-</mime-info>"#
-        );
+        let xml = mime_type_xml(mime_type, glob_pattern);
 
         // Install the XML file and register it as default for our app
         let file_name = xml_path.file_name().expect("we should have a filename");
@@ -13,29 +13,15 @@ use rattler_shell::{
     activation::{ActivationError, ActivationVariables, Activator, PathModificationBehavior},
     shell,
 };
-use sha2::{Digest as _, Sha256};
 
 use crate::utils::slugify;
 use crate::{
-    render::{BaseMenuItemPlaceholders, MenuItemPlaceholders, PlaceholderString},
-    schema::{
-        CFBundleDocumentTypesModel, CFBundleTypeRole, CFBundleURLTypesModel, LSHandlerRank, MacOS,
-        MacOSVersion, MenuItemCommand, UTTypeDeclarationModel,
-    },
+    preview::macos::{info_plists, launcher_script},
+    render::{BaseMenuItemPlaceholders, MenuItemPlaceholders},
+    schema::{MacOS, MenuItemCommand},
     utils::{log_output, run_pre_create_command},
     MenuInstError, MenuMode,
 };
-use std::collections::HashMap;
-
-pub fn quote_args<I, S>(args: I) -> Vec<String>
-where
-    I: IntoIterator<Item = S>,
-    S: AsRef<str>,
-{
-    args.into_iter()
-        .map(|arg| format!(r#""{}""#, arg.as_ref()))
-        .collect()
-}
 
 #[derive(Debug, Clone)]
 pub struct MacOSMenu {
@@ -99,168 +85,6 @@ impl Directories {
     }
 }
 
-impl UTTypeDeclarationModel {
-    fn to_plist(&self, placeholders: &MenuItemPlaceholders) -> Value {
-        let mut type_dict = plist::Dictionary::new();
-        type_dict.insert(
-            "UTTypeConformsTo".into(),
-            Value::Array(
-                self.ut_type_conforms_to
-                    .iter()
-                    .map(|s| Value::String(s.resolve(placeholders)))
-                    .collect(),
-            ),
-        );
-        if let Some(desc) = &self.ut_type_description {
-            type_dict.insert(
-                "UTTypeDescription".into(),
-                Value::String(desc.resolve(placeholders)),
-            );
-        }
-        if let Some(icon) = &self.ut_type_icon_file {
-            type_dict.insert(
-                "UTTypeIconFile".into(),
-                Value::String(icon.resolve(placeholders)),
-            );
-        }
-        type_dict.insert(
-            "UTTypeIdentifier".into(),
-            Value::String(self.ut_type_identifier.resolve(placeholders)),
-        );
-        if let Some(url) = &self.ut_type_reference_url {
-            type_dict.insert(
-                "UTTypeReferenceURL".into(),
-                Value::String(url.resolve(placeholders)),
-            );
-        }
-
-        let mut tag_spec = plist::Dictionary::new();
-        for (k, v) in &self.ut_type_tag_specification {
-            tag_spec.insert(
-                k.resolve(placeholders),
-                Value::Array(
-                    v.iter()
-                        .map(|s| Value::String(s.resolve(placeholders)))
-                        .collect(),
-                ),
-            );
-        }
-
-        type_dict.insert("UTTypeTagSpecification".into(), Value::Dictionary(tag_spec));
-        Value::Dictionary(type_dict)
-    }
-}
-
-impl CFBundleDocumentTypesModel {
-    fn to_plist(&self, placeholders: &MenuItemPlaceholders) -> Value {
-        let mut type_dict = plist::Dictionary::new();
-        type_dict.insert(
-            "CFBundleTypeName".into(),
-            Value::String(self.cf_bundle_type_name.resolve(placeholders)),
-        );
-
-        if let Some(icon) = &self.cf_bundle_type_icon_file {
-            type_dict.insert(
-                "CFBundleTypeIconFile".into(),
-                Value::String(icon.resolve(placeholders)),
-            );
-        }
-
-        if let Some(role) = &self.cf_bundle_type_role {
-            type_dict.insert("CFBundleTypeRole".into(), role.to_plist());
-        }
-
-        type_dict.insert(
-            "LSItemContentTypes".into(),
-            Value::Array(
-                self.ls_item_content_types
-                    .iter()
-                    .map(|s| s.resolve(placeholders).into())
-                    .collect(),
-            ),
-        );
-
-        type_dict.insert("LSHandlerRank".into(), self.ls_handler_rank.to_plist());
-
-        Value::Dictionary(type_dict)
-    }
-}
-
-impl CFBundleURLTypesModel {
-    fn to_plist(&self, placeholders: &MenuItemPlaceholders) -> Value {
-        let mut type_dict = plist::Dictionary::new();
-
-        if let Some(role) = self.cf_bundle_type_role.clone() {
-            type_dict.insert("CFBundleTypeRole".into(), role.to_plist());
-        }
-
-        type_dict.insert(
-            "CFBundleURLSchemes".into(),
-            Value::Array(
-                self.cf_bundle_url_schemes
-                    .iter()
-                    .map(|s| s.resolve(placeholders).into())
-                    .collect(),
-            ),
-        );
-
-        type_dict.insert(
-            "CFBundleURLName".into(),
-            Value::String(self.cf_bundle_url_name.resolve(placeholders)),
-        );
-
-        if let Some(icon) = &self.cf_bundle_url_icon_file {
-            type_dict.insert(
-                "CFBundleURLIconFile".into(),
-                Value::String(icon.resolve(placeholders)),
-            );
-        }
-
-        Value::Dictionary(type_dict)
-    }
-}
-
-impl MacOSVersion {
-    pub fn to_plist(&self) -> plist::Value {
-        plist::Value::String(
-            self.0
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<String>>()
-                .join("."),
-        )
-    }
-}
-
-impl LSHandlerRank {
-    pub fn to_plist(&self) -> plist::Value {
-        plist::Value::String(
-            match self {
-                LSHandlerRank::Owner => "Owner",
-                LSHandlerRank::Default => "Default",
-                LSHandlerRank::Alternate => "Alternate",
-                LSHandlerRank::None => "None",
-            }
-            .to_string(),
-        )
-    }
-}
-
-impl CFBundleTypeRole {
-    pub fn to_plist(&self) -> plist::Value {
-        plist::Value::String(
-            match self {
-                CFBundleTypeRole::Editor => "Editor",
-                CFBundleTypeRole::Viewer => "Viewer",
-                CFBundleTypeRole::Shell => "Shell",
-                CFBundleTypeRole::QLGenerator => "QLGenerator",
-                CFBundleTypeRole::None => "None",
-            }
-            .to_string(),
-        )
-    }
-}
-
 /// Call `lsregister` with args
 fn lsregister(args: &[&str], directory: &Path) -> Result<(), MenuInstError> {
     let exe = "/System/Library/Frameworks/CoreServices.framework/Frameworks/LaunchServices.framework/Support/lsregister";
@@ -424,168 +248,18 @@ impl MacOSMenu {
     }
 
     fn write_plist_info(&self) -> Result<(), MenuInstError> {
-        let name = self.name.clone();
-        let slugname = slugify(&name);
-        let shortname = if slugname.len() > 16 {
-            let hashed = format!("{:x}", Sha256::digest(slugname.as_bytes()));
-            format!("{}{}", &slugname[..10], &hashed[..6])
-        } else {
-            slugname.clone()
-        };
-
-        let mut pl = plist::Dictionary::new();
-
-        let bundle_name = resolve(&self.item.cf_bundle_name, &self.placeholders, &shortname);
-        pl.insert("CFBundleName".into(), Value::String(bundle_name));
-
-        let display_name = resolve(&self.item.cf_bundle_display_name, &self.placeholders, &name);
-        pl.insert("CFBundleDisplayName".into(), Value::String(display_name));
-
-        // This one is _not_ part of the schema, so we just set it
-        pl.insert("CFBundleExecutable".into(), Value::String(slugname.clone()));
-
-        pl.insert(
-            "CFBundleIdentifier".into(),
-            Value::String(format!("com.{slugname}")),
-        );
-        pl.insert("CFBundlePackageType".into(), Value::String("APPL".into()));
-
-        let cf_bundle_version = resolve(&self.item.cf_bundle_version, &self.placeholders, "1.0.0");
-        pl.insert(
-            "CFBundleVersion".into(),
-            Value::String(cf_bundle_version.clone()),
-        );
-
-        pl.insert(
-            "CFBundleGetInfoString".into(),
-            Value::String(format!("{slugname}-{cf_bundle_version}")),
-        );
-
-        pl.insert(
-            "CFBundleShortVersionString".into(),
-            Value::String(cf_bundle_version),
+        let (pl, nested_pl) = info_plists(
+            &self.name,
+            &self.item,
+            &self.command,
+            &self.placeholders,
+            self.needs_appkit_launcher(),
         );
 
-        if let Some(icon) = &self.command.icon {
-            let resolved_icon = icon.resolve(&self.placeholders);
-            if let Some(icon_name) = Path::new(&resolved_icon)
-                .file_name()
-                .and_then(|name| name.to_str())
-            {
-                pl.insert("CFBundleIconFile".into(), Value::String(icon_name.into()));
-            } else {
-                tracing::warn!("Failed to extract icon name from path: {:?}", resolved_icon);
-            }
-        }
-
-        if let Some(cf_bundle_types_model) = &self.item.cf_bundle_document_types {
-            let mut types_array = Vec::new();
-            for cf_bundle_type in cf_bundle_types_model {
-                types_array.push(cf_bundle_type.to_plist(&self.placeholders));
-            }
-            pl.insert("CFBundleDocumentTypes".into(), Value::Array(types_array));
-        }
-
-        if let Some(cf_bundle_spoken_names) = &self.item.cf_bundle_spoken_name {
-            pl.insert(
-                "CFBundleSpokenName".into(),
-                Value::String(cf_bundle_spoken_names.resolve(&self.placeholders)),
-            );
-        }
-
-        if self.needs_appkit_launcher() {
-            tracing::debug!(
-                "Writing plist to {}",
-                self.directories
-                    .nested_location
-                    .join("Contents/Info.plist")
-                    .display()
-            );
-            plist::to_file_xml(
-                self.directories.nested_location.join("Contents/Info.plist"),
-                &pl,
-            )?;
-            pl.insert("LSBackgroundOnly".into(), Value::Boolean(true));
-            pl.insert(
-                "CFBundleIdentifier".into(),
-                Value::String(format!("com.{slugname}-appkit-launcher")),
-            );
-        }
-
-        if let Some(category) = self.item.ls_application_category_type.as_ref() {
-            pl.insert(
-                "LSApplicationCategoryType".into(),
-                Value::String(category.clone()),
-            );
-        }
-
-        if let Some(background_only) = self.item.ls_background_only {
-            pl.insert("LSBackgroundOnly".into(), Value::Boolean(background_only));
-        }
-
-        if let Some(env) = self.item.ls_environment.as_ref() {
-            let mut env_dict = plist::Dictionary::new();
-            for (k, v) in env {
-                env_dict.insert(k.into(), Value::String(v.resolve(&self.placeholders)));
-            }
-            pl.insert("LSEnvironment".into(), Value::Dictionary(env_dict));
-        }
-
-        if let Some(version) = self.item.ls_minimum_system_version.as_ref() {
-            pl.insert("LSMinimumSystemVersion".into(), version.to_plist());
-        }
-
-        if let Some(prohibited) = self.item.ls_multiple_instances_prohibited {
-            pl.insert(
-                "LSMultipleInstancesProhibited".into(),
-                Value::Boolean(prohibited),
-            );
-        }
-
-        if let Some(ns_supports_automatic_graphics_switching) =
-            self.item.ns_supports_automatic_graphics_switching
-        {
-            pl.insert(
-                "NSSupportsAutomaticGraphicsSwitching".into(),
-                Value::Boolean(ns_supports_automatic_graphics_switching),
-            );
-        }
-
-        if let Some(requires_native) = self.item.ls_requires_native_execution {
-            pl.insert(
-                "LSRequiresNativeExecution".into(),
-                Value::Boolean(requires_native),
-            );
-        }
-
-        if let Some(ut_exported_type_declarations) = &self.item.ut_exported_type_declarations {
-            let mut type_array = Vec::new();
-            for ut_type in ut_exported_type_declarations {
-                type_array.push(ut_type.to_plist(&self.placeholders));
-            }
-            pl.insert(
-                "UTExportedTypeDeclarations".into(),
-                Value::Array(type_array),
-            );
-        }
-
-        if let Some(ut_imported_type_declarations) = &self.item.ut_imported_type_declarations {
-            let mut type_array = Vec::new();
-            for ut_type in ut_imported_type_declarations {
-                type_array.push(ut_type.to_plist(&self.placeholders));
-            }
-            pl.insert(
-                "UTImportedTypeDeclarations".into(),
-                Value::Array(type_array),
-            );
-        }
-
-        if let Some(cf_bundle_url_types) = &self.item.cf_bundle_url_types {
-            let mut url_array = Vec::new();
-            for url_type in cf_bundle_url_types {
-                url_array.push(url_type.to_plist(&self.placeholders));
-            }
-            pl.insert("CFBundleURLTypes".into(), Value::Array(url_array));
+        if let Some(nested_pl) = nested_pl {
+            let nested_target = self.directories.nested_location.join("Contents/Info.plist");
+            tracing::debug!("Writing plist to {}", nested_target.display());
+            plist::to_file_xml(nested_target, &nested_pl)?;
         }
 
         let plist_target = self.directories.location.join("Contents/Info.plist");
@@ -631,50 +305,28 @@ impl MacOSMenu {
     }
 
     fn command(&self) -> Result<String, ActivationError> {
-        let mut lines = vec!["#!/bin/sh".to_string()];
-
-        if self.command.terminal.unwrap_or(false) {
-            lines.extend_from_slice(&[
-                r#"if [ "${__CFBundleIdentifier:-}" != "com.apple.Terminal" ]; then"#.to_string(),
-                r#"    open -b com.apple.terminal "$0""#.to_string(),
-                r#"    exit $?"#.to_string(),
-                "fi".to_string(),
-            ]);
-        }
-
         if let Some(working_dir) = self.command.working_dir.as_ref() {
             let working_dir = working_dir.resolve(&self.placeholders);
             fs::create_dir_all(&working_dir).expect("Failed to create working directory");
-            lines.push(format!("cd \"{working_dir}\""));
-        }
-
-        if let Some(precommand) = &self.command.precommand {
-            lines.push(precommand.resolve(&self.placeholders));
         }
 
         // Run a cached activation
-        if self.command.activate.unwrap_or(false) {
+        let env: Vec<(String, String)> = if self.command.activate.unwrap_or(false) {
             // create a bash activation script and emit it into the script
             let activator = Activator::from_path(&self.prefix, shell::Bash, Platform::current())?;
             let activation_variables = ActivationVariables {
                 path_modification_behavior: PathModificationBehavior::Prepend,
                 ..Default::default()
             };
-            let activation_env = activator.run_activation(activation_variables, None)?;
-
-            for (k, v) in activation_env {
-                lines.push(format!(r#"export {k}="{v}""#));
-            }
-        }
-
-        let command = self
-            .command
-            .command
-            .iter()
-            .map(|s| s.resolve(&self.placeholders));
-        lines.push(quote_args(command).join(" "));
+            activator
+                .run_activation(activation_variables, None)?
+                .into_iter()
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        Ok(lines.join("\n"))
+        Ok(launcher_script(&self.command, &self.placeholders, &env))
     }
 
     fn write_appkit_launcher(&self) -> Result<PathBuf, MenuInstError> {
@@ -829,17 +481,6 @@ pub(crate) fn remove_menu_item(tracker: &MacOsTracker) -> Result<Vec<PathBuf>, M
     Ok(removed)
 }
 
-fn resolve(
-    input: &Option<PlaceholderString>,
-    placeholders: impl AsRef<HashMap<String, String>>,
-    default: &str,
-) -> String {
-    match input {
-        Some(s) => s.resolve(placeholders),
-        None => default.to_string(),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use crate::{schema::MenuInstSchema, test::test_data, MenuMode};
@@ -972,14 +613,14 @@ mod tests {
     /// Test macOS version parsing
     #[test]
     fn test_macos_version() {
-        let version = super::MacOSVersion(vec![10, 15, 0]);
+        let version = crate::schema::MacOSVersion(vec![10, 15, 0]);
         assert_eq!(
-            version.to_plist(),
+            crate::preview::macos::version_plist(&version),
             plist::Value::String("10.15.0".to_string())
         );
 
         // parsing from string
-        let version: super::MacOSVersion = serde_json::from_str("\"10.15.0\"").unwrap();
+        let version: crate::schema::MacOSVersion = serde_json::from_str("\"10.15.0\"").unwrap();
         assert_eq!(version.0, vec![10, 15, 0]);
     }
 }
@@ -0,0 +1,445 @@
+//! A dry run of the installation of menu items.
+//!
+//! [`preview_menuitems`] renders a `Menu/*.json` file for Linux, macOS or
+//! Windows on any host and reports the files, shortcuts and registry keys
+//! that [`crate::install_menuitems`] would create, without touching the
+//! system. This allows checking menu definitions for all platforms in CI.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{menuinst::MenuMode, Platform};
+use rattler_shell::{
+    activation::{ActivationVariables, Activator, PathModificationBehavior},
+    shell::Shell,
+};
+use serde::Serialize;
+
+use crate::{render::BaseMenuItemPlaceholders, validate::validate_menu_schema, MenuInstError};
+
+pub(crate) mod linux;
+pub(crate) mod macos;
+pub(crate) mod windows;
+
+/// The menu items that the installation of a menu schema would create.
+#[derive(Debug, Clone, Serialize)]
+pub struct MenuPreview {
+    /// The name of the menu.
+    pub menu_name: String,
+
+    /// The platform for which the menu was rendered.
+    pub platform: Platform,
+
+    /// The menu items that are defined for the platform.
+    pub items: Vec<MenuItemPreview>,
+}
+
+/// The changes that the installation of a single menu item would make.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MenuItemPreview {
+    /// The resolved name of the menu item.
+    pub name: String,
+
+    /// The location of the menu item: the `.desktop` file on Linux, the `.app`
+    /// bundle on macOS and the start menu shortcut on Windows. This is the
+    /// value of the `MENU_ITEM_LOCATION` placeholder.
+    pub location: PathBuf,
+
+    /// The command that runs before the menu item is created, if any. It is
+    /// not run by the preview.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precreate: Option<String>,
+
+    /// The files that would be written.
+    pub files: Vec<PreviewFile>,
+
+    /// The MIME types that would be associated with the menu item (Linux
+    /// only).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mime_associations: Vec<MimeAssociation>,
+
+    /// The shortcuts that would be created (Windows only).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shortcuts: Vec<WindowsShortcut>,
+
+    /// The registry keys that would be written (Windows only).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub registry_keys: Vec<RegistryKey>,
+
+    /// The Windows Terminal profile that would be added (Windows only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terminal_profile: Option<TerminalProfile>,
+
+    /// Things that the preview could not reproduce exactly.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// A file that would be written by the installation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PreviewFile {
+    /// The path of the file.
+    pub path: PathBuf,
+
+    /// The contents of the file.
+    pub contents: PreviewContents,
+
+    /// Whether the file would be made executable.
+    pub executable: bool,
+}
+
+/// The contents of a [`PreviewFile`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum PreviewContents {
+    /// A rendered text file.
+    Text(String),
+
+    /// A copy of the given file.
+    CopyOf(PathBuf),
+
+    /// A symbolic link to the given path.
+    SymlinkTo(PathBuf),
+
+    /// The launcher binary that is shipped with menuinst for macOS.
+    Launcher,
+
+    /// The launcher binary for macOS that forwards Apple Events to the
+    /// `handle-event` script of the bundle.
+    AppkitLauncher,
+}
+
+/// A MIME type that would be associated with a menu item in `mimeapps.list`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MimeAssociation {
+    /// The MIME type, e.g. `text/x-python`.
+    pub mime_type: String,
+
+    /// The name of the application that handles the MIME type.
+    pub application: String,
+
+    /// The `mimeapps.list` file that would be updated.
+    pub config_file: PathBuf,
+}
+
+/// A Windows shortcut (`.lnk` file).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WindowsShortcut {
+    /// The path of the shortcut.
+    pub path: PathBuf,
+
+    /// The program that the shortcut runs.
+    pub target: String,
+
+    /// The quoted arguments that are passed to the program.
+    pub arguments: String,
+
+    /// The working directory of the program.
+    pub working_dir: String,
+
+    /// The icon of the shortcut.
+    pub icon: Option<String>,
+
+    /// The description of the shortcut.
+    pub description: String,
+
+    /// The application user model id of the shortcut.
+    pub app_user_model_id: String,
+}
+
+/// A key in the Windows registry with the values that would be set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegistryKey {
+    /// The full path of the key including the hive, e.g.
+    /// `HKEY_CURRENT_USER\Software\Classes\.py\OpenWithProgids`.
+    pub path: String,
+
+    /// The values of the key, in the order in which they would be set.
+    pub values: Vec<RegistryValue>,
+}
+
+/// A string value in the Windows registry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegistryValue {
+    /// The name of the value, empty for the default value of the key.
+    pub name: String,
+
+    /// The data of the value.
+    pub data: String,
+}
+
+/// A Windows Terminal profile that would be added to the settings of all
+/// installed Windows Terminals.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TerminalProfile {
+    /// The name of the profile.
+    pub name: String,
+
+    /// The command line that the profile runs.
+    pub commandline: String,
+
+    /// The icon of the profile.
+    pub icon: Option<String>,
+
+    /// The starting directory of the profile.
+    pub starting_directory: Option<String>,
+}
+
+/// Renders the menu items of a `Menu/*.json` file for `platform` without
+/// installing them.
+///
+/// The schema is validated first, see [`crate::validate_menu_schema`]. The
+/// `placeholders` override the values of the placeholders that are derived
+/// from the prefixes, e.g. to render `HOME` or `DISTRIBUTION_NAME`
+/// independently of the machine that runs the preview. Directories of the
+/// user or the system that differ between machines are rendered with the
+/// defaults of the target platform.
+///
+/// Activation scripts of the prefix are not run: the environment of an
+/// activated menu item only contains the environment variables and `PATH`
+/// entries of the prefix, and a warning is added to the items that would run
+/// the scripts.
+pub fn preview_menuitems(
+    file: &Path,
+    prefix: &Path,
+    base_prefix: &Path,
+    platform: Platform,
+    menu_mode: MenuMode,
+    placeholders: &HashMap<String, String>,
+) -> Result<MenuPreview, MenuInstError> {
+    let text = fs_err::read_to_string(file)?;
+    let menu_inst = validate_menu_schema(&text)?;
+    let base_placeholders =
+        BaseMenuItemPlaceholders::new(base_prefix, prefix, platform).with_overrides(placeholders);
+
+    let mut items = Vec::new();
+    for item in menu_inst.menu_items {
+        if platform.is_linux() {
+            if let Some(linux_item) = item.platforms.linux {
+                let command = item.command.merge(linux_item.base);
+                items.push(linux::preview_menu_item(
+                    &menu_inst.menu_name,
+                    prefix,
+                    platform,
+                    &linux_item.specific,
+                    &command,
+                    &base_placeholders,
+                    menu_mode,
+                )?);
+            }
+        } else if platform.is_osx() {
+            if let Some(macos_item) = item.platforms.osx {
+                let command = item.command.merge(macos_item.base);
+                items.push(macos::preview_menu_item(
+                    prefix,
+                    platform,
+                    &macos_item.specific,
+                    &command,
+                    &base_placeholders,
+                    menu_mode,
+                )?);
+            }
+        } else if platform.is_windows() {
+            if let Some(windows_item) = item.platforms.win {
+                let command = item.command.merge(windows_item.base);
+                items.push(windows::preview_menu_item(
+                    &menu_inst.menu_name,
+                    prefix,
+                    platform,
+                    &windows_item.specific,
+                    &command,
+                    &base_placeholders,
+                    menu_mode,
+                )?);
+            }
+        }
+    }
+
+    Ok(MenuPreview {
+        menu_name: menu_inst.menu_name,
+        platform,
+        items,
+    })
+}
+
+/// Computes the environment variables of the activated `prefix` without
+/// running its activation scripts, sorted by name. A warning is added to
+/// `warnings` if the prefix has activation scripts.
+fn activation_env<T: Shell + Clone + 'static>(
+    prefix: &Path,
+    shell: T,
+    platform: Platform,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<(String, String)>, MenuInstError> {
+    let mut activator = Activator::from_path(prefix, shell, platform)?;
+    if !activator.activation_scripts.is_empty() {
+        warnings.push(format!(
+            "the activation scripts of the prefix are not run by the preview: {}",
+            activator
+                .activation_scripts
+                .iter()
+                .map(|script| script.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        activator.activation_scripts.clear();
+    }
+
+    let environment = activator.activated_environment(ActivationVariables {
+        path_modification_behavior: PathModificationBehavior::Prepend,
+        ..Default::default()
+    })?;
+    let mut env = environment.env_vars.into_iter().collect::<Vec<_>>();
+    env.sort();
+    Ok(env)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::Path};
+
+    use rattler_conda_types::{menuinst::MenuMode, Platform};
+
+    use super::{preview_menuitems, MenuPreview, PreviewContents};
+    use crate::{test::test_data, MenuInstError};
+
+    fn preview(path: &str, platform: Platform) -> MenuPreview {
+        let prefix = tempfile::TempDir::new().unwrap();
+        let prefix = prefix.path().join("my-env");
+        let placeholders = HashMap::from([("HOME".to_string(), "/home/user".to_string())]);
+        preview_menuitems(
+            &test_data().join(path),
+            &prefix,
+            &prefix,
+            platform,
+            MenuMode::System,
+            &placeholders,
+        )
+        .unwrap()
+    }
+
+    fn text<'a>(preview: &'a MenuPreview, path: &str) -> &'a str {
+        let file = preview.items[0]
+            .files
+            .iter()
+            .find(|file| file.path == Path::new(path))
+            .unwrap_or_else(|| panic!("{path} is not in the preview"));
+        match &file.contents {
+            PreviewContents::Text(text) => text,
+            contents => panic!("{path} is not a text file: {contents:?}"),
+        }
+    }
+
+    #[test]
+    fn test_preview_linux() {
+        let preview = preview("spyder/menu.json", Platform::Linux64);
+        assert_eq!(preview.items.len(), 1);
+        let item = &preview.items[0];
+        assert_eq!(item.name, "Spyder 6 (my-env)");
+
+        // Like the installation, the menu name is slugified without resolving
+        // its placeholders.
+        let desktop_file =
+            "/usr/share/applications/distribution-name-spyder_spyder-6-my-env.desktop";
+        assert_eq!(item.location, Path::new(desktop_file));
+        let desktop_entry = text(&preview, desktop_file);
+        assert!(desktop_entry.starts_with("[Desktop Entry]\nType=Application\n"));
+        assert!(desktop_entry.contains("Name=Spyder 6 (my-env)\n"));
+        assert!(desktop_entry.contains("/my-env/bin/spyder %F\n"));
+        assert!(desktop_entry.contains("MimeType=text/x-spython;\n"));
+        assert!(desktop_entry.contains("StartupWMClass=Spyder-6.my-env\n"));
+
+        let mime_xml = text(&preview, "/usr/share/mime/packages/text-x-spython.xml");
+        assert!(mime_xml.contains(r#"<glob pattern="*.spy"/>"#));
+        assert_eq!(item.mime_associations.len(), 1);
+        assert_eq!(
+            item.mime_associations[0].config_file,
+            Path::new("/etc/xdg/mimeapps.list")
+        );
+    }
+
+    #[test]
+    fn test_preview_macos() {
+        let preview = preview("spyder/menu.json", Platform::OsxArm64);
+        let item = &preview.items[0];
+        let bundle = "/Applications/Spyder 6 (my-env).app";
+        assert_eq!(item.location, Path::new(bundle));
+
+        let info_plist = text(&preview, &format!("{bundle}/Contents/Info.plist"));
+        assert!(info_plist.contains("<key>CFBundleName</key>\n\t<string>Spyder 6</string>"));
+        assert!(info_plist.contains("<key>CFBundleVersion</key>\n\t<string>6.0.2</string>"));
+        assert!(info_plist.contains("<key>CFBundleDocumentTypes</key>"));
+        assert_eq!(
+            text(&preview, &format!("{bundle}/Contents/PkgInfo")),
+            "APPLspyder-6"
+        );
+
+        let script = text(
+            &preview,
+            &format!("{bundle}/Contents/MacOS/spyder-6-my-env-script"),
+        );
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("pushd \"$(dirname \"$0\")\" &>/dev/null\n"));
+        assert!(script.ends_with("/my-env/bin/spyder\" \"$@\""));
+
+        let symlink = item
+            .files
+            .iter()
+            .find(|file| file.path == Path::new(bundle).join("Contents/MacOS/python"))
+            .unwrap();
+        assert!(
+            matches!(&symlink.contents, PreviewContents::SymlinkTo(src) if src.ends_with("my-env/bin/python"))
+        );
+    }
+
+    #[test]
+    fn test_preview_windows() {
+        let preview = preview("spyder/menu.json", Platform::Win64);
+        let item = &preview.items[0];
+        let start_menu = r"%ProgramData%\Microsoft\Windows\Start Menu\Programs";
+        assert_eq!(
+            item.shortcuts
+                .iter()
+                .map(|shortcut| shortcut.path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>(),
+            [
+                format!(r"{start_menu}\{{{{ DISTRIBUTION_NAME }}}} spyder\Spyder 6 (my-env).lnk"),
+                r"%PUBLIC%\Desktop\Spyder 6 (my-env).lnk".to_string(),
+            ]
+        );
+        assert!(item.shortcuts[0].target.ends_with("/Scripts/spyder.exe"));
+        assert_eq!(item.shortcuts[0].arguments, "%*");
+        assert_eq!(
+            item.shortcuts[0].app_user_model_id,
+            "spyder-ide.Spyder-6.my-env"
+        );
+
+        // Four keys for each of the six file extensions.
+        assert_eq!(item.registry_keys.len(), 6 * 4);
+        let key = &item.registry_keys[0];
+        assert_eq!(
+            key.path,
+            r"HKEY_LOCAL_MACHINE\Software\Classes\.enaml\OpenWithProgids"
+        );
+        assert_eq!(key.values[0].name, "Spyder 6 (my-env).AssocFile.enaml");
+    }
+
+    #[test]
+    fn test_preview_invalid_schema() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("menu.json");
+        fs_err::write(&file, r#"{"menu_name": "Test", "menu_items": []}"#).unwrap();
+        let err = preview_menuitems(
+            &file,
+            dir.path(),
+            dir.path(),
+            Platform::Linux64,
+            MenuMode::User,
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, MenuInstError::InvalidSchema(_)), "{err}");
+    }
+}
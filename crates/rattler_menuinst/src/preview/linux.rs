@@ -0,0 +1,328 @@
+//! Rendering of `.desktop` files and MIME type definitions. The functions are
+//! shared with the installation on Linux.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{menuinst::MenuMode, Platform};
+use rattler_shell::shell;
+
+use super::{activation_env, MenuItemPreview, MimeAssociation, PreviewContents, PreviewFile};
+use crate::{
+    render::{BaseMenuItemPlaceholders, MenuItemPlaceholders, PlaceholderString},
+    schema::{Environment, Linux, MenuItemCommand},
+    utils::slugify,
+    MenuInstError,
+};
+
+/// Returns the value of the `Exec` key of a desktop entry. The command is run
+/// with the given environment variables of the activated prefix.
+pub(crate) fn exec_command(
+    command: &MenuItemCommand,
+    placeholders: &MenuItemPlaceholders,
+    env: &[(String, String)],
+) -> Result<String, MenuInstError> {
+    let main_command = command
+        .command
+        .iter()
+        .map(|s| s.resolve(placeholders))
+        .map(|part| {
+            if part.starts_with("%") {
+                Ok(part)
+            } else {
+                Ok(shlex::try_quote(&part)?.into_owned())
+            }
+        })
+        .collect::<Result<Vec<_>, MenuInstError>>()?
+        .join(" ");
+
+    let main_with_env = if env.is_empty() {
+        main_command
+    } else {
+        let env = env
+            .iter()
+            .map(|(k, v)| format!(r#""{k}={v}""#))
+            .collect::<Vec<_>>();
+        format!("env {} {}", env.join(" "), main_command)
+    };
+
+    if let Some(pre_command) = &command.precommand {
+        let command = [pre_command.0.clone(), main_with_env].join(" && ");
+        Ok(format!("bash -c {}", shlex::try_quote(&command)?))
+    } else {
+        Ok(main_with_env)
+    }
+}
+
+fn resolve_and_join(items: &[PlaceholderString], placeholders: &MenuItemPlaceholders) -> String {
+    let mut res = String::new();
+    for item in items {
+        res.push_str(&item.resolve(placeholders));
+        res.push(';');
+    }
+    res
+}
+
+/// Renders the desktop entry of a menu item with the given `Exec` command.
+pub(crate) fn desktop_entry(
+    name: &str,
+    exec: &str,
+    command: &MenuItemCommand,
+    item: &Linux,
+    placeholders: &MenuItemPlaceholders,
+) -> String {
+    let mut lines = vec![
+        "[Desktop Entry]".to_string(),
+        "Type=Application".to_string(),
+        "Encoding=UTF-8".to_string(),
+        format!("Name={name}"),
+        format!("Exec={exec}"),
+        format!("Terminal={}", command.terminal.unwrap_or(false)),
+    ];
+
+    if let Some(icon) = &command.icon {
+        lines.push(format!("Icon={}", icon.resolve(placeholders)));
+    }
+
+    let description = command.description.resolve(placeholders);
+    if !description.is_empty() {
+        lines.push(format!("Comment={description}"));
+    }
+
+    if let Some(working_dir) = &command.working_dir {
+        lines.push(format!("Path={}", working_dir.resolve(placeholders)));
+    }
+
+    // resolve categories and join them with a semicolon
+    if let Some(categories) = &item.categories {
+        lines.push(format!(
+            "Categories={}",
+            resolve_and_join(categories, placeholders)
+        ));
+    }
+
+    if let Some(dbus_activatable) = &item.dbus_activatable {
+        lines.push(format!("DBusActivatable={dbus_activatable}"));
+    }
+
+    if let Some(generic_name) = &item.generic_name {
+        lines.push(format!(
+            "GenericName={}",
+            generic_name.resolve(placeholders)
+        ));
+    }
+
+    if let Some(hidden) = &item.hidden {
+        lines.push(format!("Hidden={hidden}"));
+    }
+
+    if let Some(implements) = &item.implements {
+        lines.push(format!(
+            "Implements={}",
+            resolve_and_join(implements, placeholders)
+        ));
+    }
+
+    if let Some(keywords) = &item.keywords {
+        lines.push(format!(
+            "Keywords={}",
+            resolve_and_join(keywords, placeholders)
+        ));
+    }
+
+    if let Some(mime_types) = &item.mime_type {
+        lines.push(format!(
+            "MimeType={}",
+            resolve_and_join(mime_types, placeholders)
+        ));
+    }
+
+    if let Some(no_display) = &item.no_display {
+        lines.push(format!("NoDisplay={no_display}"));
+    }
+
+    if let Some(not_show_in) = &item.not_show_in {
+        lines.push(format!(
+            "NotShowIn={}",
+            resolve_and_join(not_show_in, placeholders)
+        ));
+    }
+
+    if let Some(only_show_in) = &item.only_show_in {
+        lines.push(format!(
+            "OnlyShowIn={}",
+            resolve_and_join(only_show_in, placeholders)
+        ));
+    }
+
+    if let Some(single_main_window) = &item.single_main_window {
+        lines.push(format!("SingleMainWindow={single_main_window}"));
+    }
+
+    if let Some(prefers_non_default_gpu) = &item.prefers_non_default_gpu {
+        lines.push(format!("PrefersNonDefaultGPU={prefers_non_default_gpu}"));
+    }
+
+    if let Some(startup_notify) = &item.startup_notify {
+        lines.push(format!("StartupNotify={startup_notify}"));
+    }
+
+    if let Some(startup_wm_class) = &item.startup_wm_class {
+        lines.push(format!(
+            "StartupWMClass={}",
+            startup_wm_class.resolve(placeholders)
+        ));
+    }
+
+    if let Some(try_exec) = &item.try_exec {
+        lines.push(format!("TryExec={}", try_exec.resolve(placeholders)));
+    }
+
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+/// Returns the resolved MIME types of a menu item.
+pub(crate) fn mime_types(item: &Linux, placeholders: &MenuItemPlaceholders) -> Vec<String> {
+    item.mime_type
+        .iter()
+        .flatten()
+        .map(|s| s.resolve(placeholders))
+        .collect()
+}
+
+/// Returns the resolved glob patterns of a menu item by MIME type.
+pub(crate) fn glob_patterns(
+    item: &Linux,
+    placeholders: &MenuItemPlaceholders,
+) -> HashMap<String, String> {
+    item.glob_patterns
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.resolve(placeholders), v.resolve(placeholders)))
+        .collect()
+}
+
+/// Returns the name of the XML file that defines `mime_type`, without the
+/// `.xml` extension.
+pub(crate) fn mime_type_basename(mime_type: &str) -> String {
+    mime_type.replace("/", "-")
+}
+
+/// Renders the shared MIME info XML that binds `mime_type` to
+/// `glob_pattern`.
+pub(crate) fn mime_type_xml(mime_type: &str, glob_pattern: &str) -> String {
+    let xmlns = "http://www.freedesktop.org/standards/shared-mime-info";
+    let description =
+        format!("Custom MIME type {mime_type} for '{glob_pattern}' files (registered by menuinst)");
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<mime-info xmlns="{xmlns}">
+    <mime-type type="{mime_type}">
+        <glob pattern="{glob_pattern}"/>
+        <comment>{description}</comment>
+    </mime-type>
+</mime-info>"#
+    )
+}
+
+/// Returns the configuration and data directories for the given menu mode.
+/// For user installations on a Linux host, the XDG directories of the user
+/// are used, on other hosts their defaults.
+fn directories(menu_mode: MenuMode, home: &Path) -> (PathBuf, PathBuf) {
+    match menu_mode {
+        MenuMode::System => (PathBuf::from("/etc/xdg/"), PathBuf::from("/usr/share")),
+        MenuMode::User => {
+            let xdg_dir = |dir: Option<PathBuf>, default: &str| {
+                dir.filter(|_| cfg!(target_os = "linux"))
+                    .unwrap_or_else(|| home.join(default))
+            };
+            (
+                xdg_dir(dirs::config_dir(), ".config"),
+                xdg_dir(dirs::data_dir(), ".local/share"),
+            )
+        }
+    }
+}
+
+/// Renders a menu item for Linux.
+pub(super) fn preview_menu_item(
+    menu_name: &str,
+    prefix: &Path,
+    platform: Platform,
+    item: &Linux,
+    command: &MenuItemCommand,
+    placeholders: &BaseMenuItemPlaceholders,
+    menu_mode: MenuMode,
+) -> Result<MenuItemPreview, MenuInstError> {
+    let name = command.name.resolve(Environment::Base, placeholders);
+    let home = PathBuf::from(&placeholders.as_ref()["HOME"]);
+    let (config_directory, data_directory) = directories(menu_mode, &home);
+
+    let location = data_directory.join("applications").join(format!(
+        "{}_{}.desktop",
+        slugify(menu_name),
+        slugify(&name)
+    ));
+    let placeholders = placeholders.refine(&location);
+
+    let mut warnings = Vec::new();
+    let env = if command.activate.unwrap_or(true) {
+        activation_env(prefix, shell::Bash, platform, &mut warnings)?
+    } else {
+        Vec::new()
+    };
+    let exec = exec_command(command, &placeholders, &env)?;
+
+    let mut files = vec![PreviewFile {
+        path: location.clone(),
+        contents: PreviewContents::Text(desktop_entry(&name, &exec, command, item, &placeholders)),
+        executable: false,
+    }];
+
+    let mime_types = mime_types(item, &placeholders);
+    let glob_patterns = glob_patterns(item, &placeholders);
+    for mime_type in &mime_types {
+        if let Some(glob_pattern) = glob_patterns.get(mime_type) {
+            files.push(PreviewFile {
+                path: data_directory
+                    .join("mime/packages")
+                    .join(format!("{}.xml", mime_type_basename(mime_type))),
+                contents: PreviewContents::Text(mime_type_xml(mime_type, glob_pattern)),
+                executable: false,
+            });
+        }
+    }
+    if files.len() > 1 {
+        warnings.push(
+            "MIME type definitions are registered with `xdg-mime` if it is available, the \
+             files are only written to the data directory if that fails"
+                .to_string(),
+        );
+    }
+
+    let config_file = config_directory.join("mimeapps.list");
+    let mime_associations = mime_types
+        .into_iter()
+        .map(|mime_type| MimeAssociation {
+            mime_type,
+            application: name.clone(),
+            config_file: config_file.clone(),
+        })
+        .collect();
+
+    Ok(MenuItemPreview {
+        name,
+        location,
+        precreate: command
+            .precreate
+            .as_ref()
+            .map(|precreate| precreate.resolve(&placeholders)),
+        files,
+        mime_associations,
+        warnings,
+        ..MenuItemPreview::default()
+    })
+}
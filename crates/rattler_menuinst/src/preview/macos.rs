@@ -0,0 +1,556 @@
+//! Rendering of `.app` bundles, mirroring the installation on macOS.
+
+use std::path::{Path, PathBuf};
+
+use plist::{Dictionary, Value};
+use rattler_conda_types::{menuinst::MenuMode, Platform};
+use rattler_shell::shell;
+use sha2::{Digest as _, Sha256};
+
+use super::{activation_env, MenuItemPreview, PreviewContents, PreviewFile};
+use crate::{
+    render::{BaseMenuItemPlaceholders, MenuItemPlaceholders, PlaceholderString},
+    schema::{
+        CFBundleDocumentTypesModel, CFBundleTypeRole, CFBundleURLTypesModel, Environment,
+        LSHandlerRank, MacOS, MacOSVersion, MenuItemCommand, UTTypeDeclarationModel,
+    },
+    utils::slugify,
+    MenuInstError,
+};
+
+fn resolve(
+    input: Option<&PlaceholderString>,
+    placeholders: &MenuItemPlaceholders,
+    default: &str,
+) -> String {
+    input.map_or_else(|| default.to_string(), |s| s.resolve(placeholders))
+}
+
+fn resolve_all(items: &[PlaceholderString], placeholders: &MenuItemPlaceholders) -> Value {
+    Value::Array(
+        items
+            .iter()
+            .map(|s| Value::String(s.resolve(placeholders)))
+            .collect(),
+    )
+}
+
+pub(crate) fn version_plist(version: &MacOSVersion) -> Value {
+    Value::String(
+        version
+            .0
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join("."),
+    )
+}
+
+fn handler_rank_plist(rank: &LSHandlerRank) -> Value {
+    Value::String(
+        match rank {
+            LSHandlerRank::Owner => "Owner",
+            LSHandlerRank::Default => "Default",
+            LSHandlerRank::Alternate => "Alternate",
+            LSHandlerRank::None => "None",
+        }
+        .to_string(),
+    )
+}
+
+fn type_role_plist(role: &CFBundleTypeRole) -> Value {
+    Value::String(
+        match role {
+            CFBundleTypeRole::Editor => "Editor",
+            CFBundleTypeRole::Viewer => "Viewer",
+            CFBundleTypeRole::Shell => "Shell",
+            CFBundleTypeRole::QLGenerator => "QLGenerator",
+            CFBundleTypeRole::None => "None",
+        }
+        .to_string(),
+    )
+}
+
+fn type_declaration_plist(
+    declaration: &UTTypeDeclarationModel,
+    placeholders: &MenuItemPlaceholders,
+) -> Value {
+    let mut type_dict = Dictionary::new();
+    type_dict.insert(
+        "UTTypeConformsTo".into(),
+        resolve_all(&declaration.ut_type_conforms_to, placeholders),
+    );
+    if let Some(desc) = &declaration.ut_type_description {
+        type_dict.insert(
+            "UTTypeDescription".into(),
+            Value::String(desc.resolve(placeholders)),
+        );
+    }
+    if let Some(icon) = &declaration.ut_type_icon_file {
+        type_dict.insert(
+            "UTTypeIconFile".into(),
+            Value::String(icon.resolve(placeholders)),
+        );
+    }
+    type_dict.insert(
+        "UTTypeIdentifier".into(),
+        Value::String(declaration.ut_type_identifier.resolve(placeholders)),
+    );
+    if let Some(url) = &declaration.ut_type_reference_url {
+        type_dict.insert(
+            "UTTypeReferenceURL".into(),
+            Value::String(url.resolve(placeholders)),
+        );
+    }
+
+    let mut tag_spec = Dictionary::new();
+    for (k, v) in &declaration.ut_type_tag_specification {
+        tag_spec.insert(k.resolve(placeholders), resolve_all(v, placeholders));
+    }
+    type_dict.insert("UTTypeTagSpecification".into(), Value::Dictionary(tag_spec));
+    Value::Dictionary(type_dict)
+}
+
+fn document_type_plist(
+    document_type: &CFBundleDocumentTypesModel,
+    placeholders: &MenuItemPlaceholders,
+) -> Value {
+    let mut type_dict = Dictionary::new();
+    type_dict.insert(
+        "CFBundleTypeName".into(),
+        Value::String(document_type.cf_bundle_type_name.resolve(placeholders)),
+    );
+    if let Some(icon) = &document_type.cf_bundle_type_icon_file {
+        type_dict.insert(
+            "CFBundleTypeIconFile".into(),
+            Value::String(icon.resolve(placeholders)),
+        );
+    }
+    if let Some(role) = &document_type.cf_bundle_type_role {
+        type_dict.insert("CFBundleTypeRole".into(), type_role_plist(role));
+    }
+    type_dict.insert(
+        "LSItemContentTypes".into(),
+        resolve_all(&document_type.ls_item_content_types, placeholders),
+    );
+    type_dict.insert(
+        "LSHandlerRank".into(),
+        handler_rank_plist(&document_type.ls_handler_rank),
+    );
+    Value::Dictionary(type_dict)
+}
+
+fn url_type_plist(url_type: &CFBundleURLTypesModel, placeholders: &MenuItemPlaceholders) -> Value {
+    let mut type_dict = Dictionary::new();
+    if let Some(role) = &url_type.cf_bundle_type_role {
+        type_dict.insert("CFBundleTypeRole".into(), type_role_plist(role));
+    }
+    type_dict.insert(
+        "CFBundleURLSchemes".into(),
+        resolve_all(&url_type.cf_bundle_url_schemes, placeholders),
+    );
+    type_dict.insert(
+        "CFBundleURLName".into(),
+        Value::String(url_type.cf_bundle_url_name.resolve(placeholders)),
+    );
+    if let Some(icon) = &url_type.cf_bundle_url_icon_file {
+        type_dict.insert(
+            "CFBundleURLIconFile".into(),
+            Value::String(icon.resolve(placeholders)),
+        );
+    }
+    Value::Dictionary(type_dict)
+}
+
+fn to_xml(dictionary: &Dictionary) -> Result<String, MenuInstError> {
+    let mut buffer = Vec::new();
+    plist::to_writer_xml(&mut buffer, dictionary)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Renders the `Info.plist` of the bundle and, if the bundle needs the appkit
+/// launcher, the `Info.plist` of the nested bundle.
+pub(crate) fn info_plists(
+    name: &str,
+    item: &MacOS,
+    command: &MenuItemCommand,
+    placeholders: &MenuItemPlaceholders,
+    needs_appkit_launcher: bool,
+) -> (Dictionary, Option<Dictionary>) {
+    let slugname = slugify(name);
+    let shortname = if slugname.len() > 16 {
+        let hashed = format!("{:x}", Sha256::digest(slugname.as_bytes()));
+        format!("{}{}", &slugname[..10], &hashed[..6])
+    } else {
+        slugname.clone()
+    };
+
+    let mut pl = Dictionary::new();
+    pl.insert(
+        "CFBundleName".into(),
+        Value::String(resolve(
+            item.cf_bundle_name.as_ref(),
+            placeholders,
+            &shortname,
+        )),
+    );
+    pl.insert(
+        "CFBundleDisplayName".into(),
+        Value::String(resolve(
+            item.cf_bundle_display_name.as_ref(),
+            placeholders,
+            name,
+        )),
+    );
+    pl.insert("CFBundleExecutable".into(), Value::String(slugname.clone()));
+    pl.insert(
+        "CFBundleIdentifier".into(),
+        Value::String(format!("com.{slugname}")),
+    );
+    pl.insert("CFBundlePackageType".into(), Value::String("APPL".into()));
+
+    let cf_bundle_version = resolve(item.cf_bundle_version.as_ref(), placeholders, "1.0.0");
+    pl.insert(
+        "CFBundleVersion".into(),
+        Value::String(cf_bundle_version.clone()),
+    );
+    pl.insert(
+        "CFBundleGetInfoString".into(),
+        Value::String(format!("{slugname}-{cf_bundle_version}")),
+    );
+    pl.insert(
+        "CFBundleShortVersionString".into(),
+        Value::String(cf_bundle_version),
+    );
+
+    if let Some(icon) = &command.icon {
+        let resolved_icon = icon.resolve(placeholders);
+        if let Some(icon_name) = Path::new(&resolved_icon)
+            .file_name()
+            .and_then(|name| name.to_str())
+        {
+            pl.insert("CFBundleIconFile".into(), Value::String(icon_name.into()));
+        }
+    }
+
+    if let Some(document_types) = &item.cf_bundle_document_types {
+        pl.insert(
+            "CFBundleDocumentTypes".into(),
+            Value::Array(
+                document_types
+                    .iter()
+                    .map(|document_type| document_type_plist(document_type, placeholders))
+                    .collect(),
+            ),
+        );
+    }
+
+    if let Some(spoken_name) = &item.cf_bundle_spoken_name {
+        pl.insert(
+            "CFBundleSpokenName".into(),
+            Value::String(spoken_name.resolve(placeholders)),
+        );
+    }
+
+    // The nested bundle runs the command, the outer bundle only forwards the
+    // events to it.
+    let nested = needs_appkit_launcher.then(|| pl.clone());
+    if needs_appkit_launcher {
+        pl.insert("LSBackgroundOnly".into(), Value::Boolean(true));
+        pl.insert(
+            "CFBundleIdentifier".into(),
+            Value::String(format!("com.{slugname}-appkit-launcher")),
+        );
+    }
+
+    if let Some(category) = &item.ls_application_category_type {
+        pl.insert(
+            "LSApplicationCategoryType".into(),
+            Value::String(category.clone()),
+        );
+    }
+
+    if let Some(background_only) = item.ls_background_only {
+        pl.insert("LSBackgroundOnly".into(), Value::Boolean(background_only));
+    }
+
+    if let Some(env) = &item.ls_environment {
+        let mut env_dict = Dictionary::new();
+        for (k, v) in env {
+            env_dict.insert(k.into(), Value::String(v.resolve(placeholders)));
+        }
+        pl.insert("LSEnvironment".into(), Value::Dictionary(env_dict));
+    }
+
+    if let Some(version) = &item.ls_minimum_system_version {
+        pl.insert("LSMinimumSystemVersion".into(), version_plist(version));
+    }
+
+    if let Some(prohibited) = item.ls_multiple_instances_prohibited {
+        pl.insert(
+            "LSMultipleInstancesProhibited".into(),
+            Value::Boolean(prohibited),
+        );
+    }
+
+    if let Some(switching) = item.ns_supports_automatic_graphics_switching {
+        pl.insert(
+            "NSSupportsAutomaticGraphicsSwitching".into(),
+            Value::Boolean(switching),
+        );
+    }
+
+    if let Some(requires_native) = item.ls_requires_native_execution {
+        pl.insert(
+            "LSRequiresNativeExecution".into(),
+            Value::Boolean(requires_native),
+        );
+    }
+
+    let declarations = [
+        (
+            "UTExportedTypeDeclarations",
+            &item.ut_exported_type_declarations,
+        ),
+        (
+            "UTImportedTypeDeclarations",
+            &item.ut_imported_type_declarations,
+        ),
+    ];
+    for (key, declarations) in declarations {
+        if let Some(declarations) = declarations {
+            pl.insert(
+                key.into(),
+                Value::Array(
+                    declarations
+                        .iter()
+                        .map(|declaration| type_declaration_plist(declaration, placeholders))
+                        .collect(),
+                ),
+            );
+        }
+    }
+
+    if let Some(url_types) = &item.cf_bundle_url_types {
+        pl.insert(
+            "CFBundleURLTypes".into(),
+            Value::Array(
+                url_types
+                    .iter()
+                    .map(|url_type| url_type_plist(url_type, placeholders))
+                    .collect(),
+            ),
+        );
+    }
+
+    (pl, nested)
+}
+
+/// Renders the script that the launcher of the bundle runs.
+pub(crate) fn launcher_script(
+    command: &MenuItemCommand,
+    placeholders: &MenuItemPlaceholders,
+    env: &[(String, String)],
+) -> String {
+    let mut lines = vec!["#!/bin/sh".to_string()];
+
+    if command.terminal.unwrap_or(false) {
+        lines.extend_from_slice(&[
+            r#"if [ "${__CFBundleIdentifier:-}" != "com.apple.Terminal" ]; then"#.to_string(),
+            r#"    open -b com.apple.terminal "$0""#.to_string(),
+            r#"    exit $?"#.to_string(),
+            "fi".to_string(),
+        ]);
+    }
+
+    if let Some(working_dir) = &command.working_dir {
+        lines.push(format!("cd \"{}\"", working_dir.resolve(placeholders)));
+    }
+
+    if let Some(precommand) = &command.precommand {
+        lines.push(precommand.resolve(placeholders));
+    }
+
+    for (k, v) in env {
+        lines.push(format!(r#"export {k}="{v}""#));
+    }
+
+    lines.push(
+        command
+            .command
+            .iter()
+            .map(|s| format!(r#""{}""#, s.resolve(placeholders)))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+
+    lines.join("\n")
+}
+
+/// Renders a menu item for macOS.
+pub(super) fn preview_menu_item(
+    prefix: &Path,
+    platform: Platform,
+    item: &MacOS,
+    command: &MenuItemCommand,
+    placeholders: &BaseMenuItemPlaceholders,
+    menu_mode: MenuMode,
+) -> Result<MenuItemPreview, MenuInstError> {
+    let name = command.name.resolve(Environment::Base, placeholders);
+    let bundle_name = format!("{name}.app");
+    let location = match menu_mode {
+        MenuMode::System => PathBuf::from("/"),
+        MenuMode::User => PathBuf::from(&placeholders.as_ref()["HOME"]),
+    }
+    .join("Applications")
+    .join(&bundle_name);
+    let nested_location = location.join("Contents/Resources").join(&bundle_name);
+    let placeholders = placeholders.refine(&location);
+    let needs_appkit_launcher = item.event_handler.is_some();
+
+    let mut files = Vec::new();
+    let mut text_file = |path: PathBuf, contents: String, executable: bool| {
+        files.push(PreviewFile {
+            path,
+            contents: PreviewContents::Text(contents),
+            executable,
+        });
+    };
+
+    let mut warnings = Vec::new();
+    if let Some(working_dir) = &command.working_dir {
+        warnings.push(format!(
+            "the working directory {} would be created",
+            working_dir.resolve(&placeholders)
+        ));
+    }
+
+    let short_name = slugify(&name.chars().take(8).collect::<String>());
+    text_file(
+        location.join("Contents/PkgInfo"),
+        format!("APPL{short_name}"),
+        false,
+    );
+    if needs_appkit_launcher {
+        text_file(
+            nested_location.join("Contents/PkgInfo"),
+            format!("APPL{short_name}"),
+            false,
+        );
+    }
+
+    let (info_plist, nested_info_plist) =
+        info_plists(&name, item, command, &placeholders, needs_appkit_launcher);
+    if let Some(nested_info_plist) = nested_info_plist {
+        text_file(
+            nested_location.join("Contents/Info.plist"),
+            to_xml(&nested_info_plist)?,
+            false,
+        );
+    }
+    text_file(
+        location.join("Contents/Info.plist"),
+        to_xml(&info_plist)?,
+        false,
+    );
+
+    let env = if command.activate.unwrap_or(false) {
+        activation_env(prefix, shell::Bash, platform, &mut warnings)?
+    } else {
+        Vec::new()
+    };
+    let launcher_path = if needs_appkit_launcher {
+        nested_location.join("Contents/MacOS").join(slugify(&name))
+    } else {
+        location.join("Contents/MacOS").join(slugify(&name))
+    };
+    text_file(
+        PathBuf::from(format!("{}-script", launcher_path.display())),
+        launcher_script(command, &placeholders, &env),
+        true,
+    );
+
+    if let Some(event_handler) = item
+        .event_handler
+        .as_ref()
+        .filter(|_| needs_appkit_launcher)
+    {
+        text_file(
+            location.join("Contents/Resources/handle-event"),
+            format!("#!/bin/bash\n{}\n", event_handler.resolve(&placeholders)),
+            true,
+        );
+    }
+
+    if let Some(entitlements) = &item.entitlements {
+        let entitlements = entitlements
+            .iter()
+            .map(|e| (e.clone(), Value::Boolean(true)))
+            .collect::<Dictionary>();
+        text_file(
+            location.join("Contents/Entitlements.plist"),
+            to_xml(&entitlements)?,
+            false,
+        );
+        warnings.push("the bundle would be signed with `codesign`".to_string());
+    }
+
+    if needs_appkit_launcher {
+        files.push(PreviewFile {
+            path: location.join("Contents/MacOS").join(slugify(&name)),
+            contents: PreviewContents::AppkitLauncher,
+            executable: true,
+        });
+    }
+    files.push(PreviewFile {
+        path: launcher_path,
+        contents: PreviewContents::Launcher,
+        executable: true,
+    });
+
+    if let Some(icon) = &command.icon {
+        let icon = PathBuf::from(icon.resolve(&placeholders));
+        if let Some(icon_name) = icon.file_name() {
+            let mut resources = vec![location.join("Contents/Resources")];
+            if needs_appkit_launcher {
+                resources.push(nested_location.join("Contents/Resources"));
+            }
+            for resources in resources {
+                files.push(PreviewFile {
+                    path: resources.join(icon_name),
+                    contents: PreviewContents::CopyOf(icon.clone()),
+                    executable: false,
+                });
+            }
+        }
+    }
+
+    for (src, dest) in item.link_in_bundle.iter().flatten() {
+        let src = src.resolve(&placeholders);
+        let dest = location.join(dest.resolve(&placeholders));
+        if !dest.starts_with(&location) {
+            return Err(MenuInstError::InstallError(format!(
+                "'link_in_bundle' destinations MUST be created inside the .app bundle ({}), but it points to '{}'.",
+                location.display(),
+                dest.display()
+            )));
+        }
+        files.push(PreviewFile {
+            path: dest,
+            contents: PreviewContents::SymlinkTo(PathBuf::from(src)),
+            executable: false,
+        });
+    }
+
+    Ok(MenuItemPreview {
+        name,
+        location,
+        precreate: command
+            .precreate
+            .as_ref()
+            .map(|precreate| precreate.resolve(&placeholders)),
+        files,
+        warnings,
+        ..MenuItemPreview::default()
+    })
+}
@@ -0,0 +1,497 @@
+//! Rendering of shortcuts and registry keys, mirroring the installation on
+//! Windows.
+//!
+//! The known folders of Windows are rendered with the environment variables
+//! that point to them, e.g. `%APPDATA%`, because they can only be looked up on
+//! Windows.
+
+use std::path::{Path, PathBuf};
+
+use rattler_conda_types::{menuinst::MenuMode, Platform};
+use rattler_shell::shell;
+
+use super::{
+    activation_env, MenuItemPreview, PreviewContents, PreviewFile, RegistryKey, RegistryValue,
+    TerminalProfile, WindowsShortcut,
+};
+use crate::{
+    render::{BaseMenuItemPlaceholders, MenuItemPlaceholders},
+    schema::{Environment, MenuItemCommand, Windows},
+    utils::{lex, slugify},
+    MenuInstError,
+};
+
+/// Joins Windows path components with a backslash.
+fn join(base: impl AsRef<Path>, path: &str) -> String {
+    format!("{}\\{path}", base.as_ref().display())
+}
+
+/// A registry key under construction.
+struct Key(RegistryKey);
+
+impl Key {
+    fn new(path: String) -> Self {
+        Self(RegistryKey {
+            path,
+            values: Vec::new(),
+        })
+    }
+
+    fn set(mut self, name: &str, data: &str) -> Self {
+        self.0.values.push(RegistryValue {
+            name: name.to_string(),
+            data: data.to_string(),
+        });
+        self
+    }
+
+    fn set_optional(self, name: &str, data: Option<&str>) -> Self {
+        match data {
+            Some(data) => self.set(name, data),
+            None => self,
+        }
+    }
+}
+
+/// The registration of a handler for a file extension.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FileExtension<'a> {
+    pub extension: &'a str,
+    pub identifier: &'a str,
+    pub command: &'a str,
+    pub icon: Option<&'a str>,
+    pub app_name: Option<&'a str>,
+    pub app_user_model_id: Option<&'a str>,
+    pub friendly_type_name: Option<&'a str>,
+}
+
+/// Renders the registry keys that associate a file extension with a handler,
+/// under `HKEY_LOCAL_MACHINE` or `HKEY_CURRENT_USER` depending on `mode`.
+pub(crate) fn file_extension_keys(
+    file_extension: &FileExtension<'_>,
+    mode: MenuMode,
+) -> Vec<RegistryKey> {
+    let FileExtension {
+        extension,
+        identifier,
+        command,
+        icon,
+        app_name,
+        app_user_model_id,
+        friendly_type_name,
+    } = *file_extension;
+    let hive = match mode {
+        MenuMode::System => "HKEY_LOCAL_MACHINE",
+        MenuMode::User => "HKEY_CURRENT_USER",
+    };
+    let classes = format!(r"{hive}\Software\Classes");
+
+    // NOTE: Windows <10 requires the friendly type name in a PE file, but we
+    // just set the raw string
+    [
+        Key::new(format!(r"{classes}\{extension}\OpenWithProgids")).set(identifier, ""),
+        Key::new(format!(r"{classes}\{identifier}"))
+            .set("", &format!("{extension} {identifier} file"))
+            .set_optional("FriendlyAppName", app_name)
+            .set_optional("AppUserModelID", app_user_model_id)
+            .set_optional("DefaultIcon", icon)
+            .set_optional("FriendlyTypeName", friendly_type_name),
+        Key::new(format!(r"{classes}\{identifier}\shell\open\command")).set("", command),
+        Key::new(format!(r"{classes}\{identifier}\shell\open"))
+            .set_optional("", app_name)
+            .set_optional("FriendlyAppName", app_name)
+            .set_optional("Icon", icon),
+    ]
+    .into_iter()
+    .map(|key| key.0)
+    .filter(|key| !key.values.is_empty())
+    .collect()
+}
+
+/// The registration of a handler for a URL protocol.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UrlProtocol<'a> {
+    /// The URL protocol name (e.g., `rattlertest`).
+    pub protocol: &'a str,
+    /// The command to execute when the protocol is invoked.
+    pub command: &'a str,
+    /// The identifier for the protocol handler.
+    pub identifier: &'a str,
+    /// The path to an icon file to associate with the protocol.
+    pub icon: Option<&'a str>,
+    /// The friendly name of the application.
+    pub app_name: Option<&'a str>,
+    /// The application user model ID (AUMI) for the protocol handler.
+    pub app_user_model_id: Option<&'a str>,
+}
+
+/// Renders the registry keys that register a URL protocol handler, under
+/// `HKEY_CLASSES_ROOT` or `HKEY_CURRENT_USER` depending on `mode`.
+pub(crate) fn url_protocol_keys(
+    url_protocol: &UrlProtocol<'_>,
+    mode: MenuMode,
+) -> Vec<RegistryKey> {
+    let UrlProtocol {
+        protocol,
+        command,
+        identifier,
+        icon,
+        app_name,
+        app_user_model_id,
+    } = *url_protocol;
+    let base = match mode {
+        MenuMode::System => format!(r"HKEY_CLASSES_ROOT\{protocol}"),
+        MenuMode::User => format!(r"HKEY_CURRENT_USER\Software\Classes\{protocol}"),
+    };
+
+    [
+        Key::new(base.clone())
+            .set("", &format!("URL:{}", lex::title_case(protocol)))
+            .set("URL Protocol", "")
+            .set_optional("FriendlyAppName", app_name)
+            .set_optional("DefaultIcon", icon)
+            .set_optional("AppUserModelId", app_user_model_id)
+            .set("_menuinst", identifier),
+        Key::new(format!(r"{base}\shell\open\command")).set("", command),
+        Key::new(format!(r"{base}\shell\open"))
+            .set_optional("", app_name)
+            .set_optional("FriendlyAppName", app_name)
+            .set_optional("Icon", icon),
+    ]
+    .into_iter()
+    .map(|key| key.0)
+    .filter(|key| !key.values.is_empty())
+    .collect()
+}
+
+/// Renders the script, shortcuts and registry keys of a menu item. The
+/// installer uses the same rendering to create them.
+pub(crate) struct WindowsMenu<'a> {
+    pub menu_name: &'a str,
+    pub prefix: &'a Path,
+    pub name: &'a str,
+    pub item: &'a Windows,
+    pub command: &'a MenuItemCommand,
+    pub placeholders: &'a MenuItemPlaceholders,
+    pub menu_mode: MenuMode,
+}
+
+impl WindowsMenu<'_> {
+    pub(crate) fn path_for_script(&self) -> String {
+        join(self.prefix, &format!("Menu\\{}.bat", self.name))
+    }
+
+    pub(crate) fn script_content(&self, env: &[(String, String)]) -> String {
+        let mut lines = vec![
+            "@echo off".to_string(),
+            ":: Script generated by conda/menuinst".to_string(),
+        ];
+
+        if let Some(pre_command_code) = &self.command.precommand {
+            lines.push(pre_command_code.resolve(self.placeholders));
+        }
+
+        for (k, v) in env {
+            lines.push(format!(r#"set "{k}={v}""#));
+        }
+
+        let args: Vec<String> = self
+            .command
+            .command
+            .iter()
+            .map(|elem| elem.resolve(self.placeholders))
+            .collect();
+        lines.push(lex::quote_args(&args).join(" "));
+
+        lines.join("\n")
+    }
+
+    pub(crate) fn build_command(&self, with_arg1: bool) -> Vec<String> {
+        if !self.command.activate.unwrap_or(false) {
+            let mut command = self
+                .command
+                .command
+                .iter()
+                .map(|elem| elem.resolve(self.placeholders))
+                .collect::<Vec<_>>();
+            if with_arg1 && !command.iter().any(|s| s.contains("%1")) {
+                command.push("%1".to_string());
+            }
+            return command;
+        }
+
+        let script_path = self.path_for_script();
+        let system_root = std::env::var("SystemRoot").unwrap_or("C:\\Windows".to_string());
+        let system32 = join(system_root, "system32");
+        let cmd_exe = join(&system32, "cmd.exe");
+
+        if self.command.terminal.unwrap_or(false) {
+            let mut command = vec![
+                cmd_exe,
+                "/D".to_string(),
+                "/K".to_string(),
+                format!("\"{script_path}\""),
+            ];
+            if with_arg1 {
+                command.push("%1".to_string());
+            }
+            command
+        } else {
+            let arg1 = if with_arg1 { "%1 " } else { "" };
+            let powershell = join(&system32, "WindowsPowerShell\\v1.0\\powershell.exe");
+            [
+                cmd_exe.as_str(),
+                "/D",
+                "/C",
+                "START",
+                "/MIN",
+                "\"\"",
+                &powershell,
+                "-WindowStyle",
+                "hidden",
+                &format!("\"start '{script_path}' {arg1}-WindowStyle hidden\""),
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+        }
+    }
+
+    pub(crate) fn app_id(&self) -> String {
+        match &self.item.app_user_model_id {
+            Some(aumi) => aumi.resolve(self.placeholders),
+            None => format!(
+                "Menuinst.{}",
+                slugify(self.name)
+                    .replace(".", "")
+                    .chars()
+                    .take(128)
+                    .collect::<String>()
+            ),
+        }
+    }
+
+    pub(crate) fn icon(&self) -> Option<String> {
+        self.command
+            .icon
+            .as_ref()
+            .map(|s| s.resolve(self.placeholders))
+    }
+
+    pub(crate) fn shortcuts(&self, directories: &Directories) -> Vec<WindowsShortcut> {
+        let args = self.build_command(false);
+        let Some((target, args)) = args.split_first() else {
+            return Vec::new();
+        };
+
+        let link_name = format!("{}.lnk", self.name);
+        let mut paths = vec![join(
+            &directories.start_menu,
+            &format!("{}\\{link_name}", self.menu_name),
+        )];
+        if self.item.desktop.unwrap_or(true) {
+            paths.push(join(&directories.desktop, &link_name));
+        }
+        if let Some(quick_launch) = &directories.quick_launch {
+            if self.item.quicklaunch.unwrap_or(false) {
+                paths.push(join(quick_launch, &link_name));
+            }
+        }
+
+        let shortcut = WindowsShortcut {
+            path: PathBuf::new(),
+            target: target.clone(),
+            arguments: lex::quote_args(args).join(" "),
+            working_dir: self.command.working_dir.as_ref().map_or_else(
+                || "%HOMEPATH%".to_string(),
+                |working_dir| working_dir.resolve(self.placeholders),
+            ),
+            icon: self.icon(),
+            description: self.command.description.resolve(self.placeholders),
+            app_user_model_id: self.app_id(),
+        };
+        paths
+            .into_iter()
+            .map(|path| WindowsShortcut {
+                path: PathBuf::from(path),
+                ..shortcut.clone()
+            })
+            .collect()
+    }
+
+    /// The file extensions of the menu item and the identifiers of their
+    /// handlers.
+    pub(crate) fn file_extensions(&self) -> Vec<(String, String)> {
+        self.item
+            .file_extensions
+            .iter()
+            .flatten()
+            .map(|extension| {
+                let extension = extension.resolve(self.placeholders);
+                let identifier = format!("{}.AssocFile{extension}", self.name);
+                (extension, identifier)
+            })
+            .collect()
+    }
+
+    /// The URL protocols of the menu item and the identifiers of their
+    /// handlers.
+    pub(crate) fn url_protocols(&self) -> Vec<(String, String)> {
+        self.item
+            .url_protocols
+            .iter()
+            .flatten()
+            .map(|protocol| {
+                let protocol = protocol.resolve(self.placeholders);
+                let identifier = format!("{}.Protocol{protocol}", self.name);
+                (protocol, identifier)
+            })
+            .collect()
+    }
+
+    /// The application user model ID of the URL protocol handlers.
+    pub(crate) fn url_protocol_app_id(&self) -> String {
+        format!("{}.Protocol", self.name)
+    }
+
+    fn file_extension_keys(&self) -> Vec<RegistryKey> {
+        let icon = self.icon();
+        let command = self.build_command(true).join(" ");
+        let app_user_model_id = self.app_id();
+        self.file_extensions()
+            .iter()
+            .flat_map(|(extension, identifier)| {
+                let file_extension = FileExtension {
+                    extension,
+                    identifier,
+                    command: &command,
+                    icon: icon.as_deref(),
+                    app_name: Some(self.name),
+                    app_user_model_id: Some(&app_user_model_id),
+                    friendly_type_name: None,
+                };
+                file_extension_keys(&file_extension, self.menu_mode)
+            })
+            .collect()
+    }
+
+    fn url_protocol_keys(&self) -> Vec<RegistryKey> {
+        let icon = self.icon();
+        let command = self.build_command(true).join(" ");
+        let app_user_model_id = self.url_protocol_app_id();
+        self.url_protocols()
+            .iter()
+            .flat_map(|(protocol, identifier)| {
+                let url_protocol = UrlProtocol {
+                    protocol,
+                    command: &command,
+                    identifier,
+                    icon: icon.as_deref(),
+                    app_name: Some(self.name),
+                    app_user_model_id: Some(&app_user_model_id),
+                };
+                url_protocol_keys(&url_protocol, self.menu_mode)
+            })
+            .collect()
+    }
+
+    fn terminal_profile(&self) -> Option<TerminalProfile> {
+        // Terminal profiles are only registered for the current user.
+        if self.menu_mode != MenuMode::User {
+            return None;
+        }
+        let name = self.item.terminal_profile.as_ref()?;
+        Some(TerminalProfile {
+            name: name.resolve(self.placeholders),
+            commandline: self.build_command(true).join(" "),
+            icon: self.icon(),
+            starting_directory: self
+                .command
+                .working_dir
+                .as_ref()
+                .map(|s| s.resolve(self.placeholders)),
+        })
+    }
+}
+
+/// The folders in which shortcuts are created.
+pub(crate) struct Directories {
+    pub start_menu: String,
+    pub desktop: String,
+    pub quick_launch: Option<String>,
+}
+
+impl Directories {
+    fn new(menu_mode: MenuMode) -> Self {
+        match menu_mode {
+            MenuMode::System => Self {
+                start_menu: r"%ProgramData%\Microsoft\Windows\Start Menu\Programs".to_string(),
+                desktop: r"%PUBLIC%\Desktop".to_string(),
+                quick_launch: None,
+            },
+            MenuMode::User => Self {
+                start_menu: r"%APPDATA%\Microsoft\Windows\Start Menu\Programs".to_string(),
+                desktop: r"%USERPROFILE%\Desktop".to_string(),
+                quick_launch: Some(
+                    r"%APPDATA%\Microsoft\Internet Explorer\Quick Launch".to_string(),
+                ),
+            },
+        }
+    }
+}
+
+/// Renders a menu item for Windows.
+pub(super) fn preview_menu_item(
+    menu_name: &str,
+    prefix: &Path,
+    platform: Platform,
+    item: &Windows,
+    command: &MenuItemCommand,
+    placeholders: &BaseMenuItemPlaceholders,
+    menu_mode: MenuMode,
+) -> Result<MenuItemPreview, MenuInstError> {
+    let name = command.name.resolve(Environment::Base, placeholders);
+    let directories = Directories::new(menu_mode);
+    let location = PathBuf::from(join(&directories.start_menu, &format!("{name}.lnk")));
+
+    let placeholders = placeholders.refine(&location);
+    let menu = WindowsMenu {
+        menu_name,
+        prefix,
+        name: &name,
+        item,
+        command,
+        placeholders: &placeholders,
+        menu_mode,
+    };
+
+    let mut warnings = Vec::new();
+    let mut files = Vec::new();
+    if command.activate.unwrap_or(false) {
+        let env = activation_env(prefix, shell::CmdExe, platform, &mut warnings)?;
+        files.push(PreviewFile {
+            path: PathBuf::from(menu.path_for_script()),
+            contents: PreviewContents::Text(menu.script_content(&env)),
+            executable: false,
+        });
+    }
+
+    let mut registry_keys = menu.file_extension_keys();
+    registry_keys.extend(menu.url_protocol_keys());
+
+    Ok(MenuItemPreview {
+        location,
+        precreate: command
+            .precreate
+            .as_ref()
+            .map(|precreate| precreate.resolve(&placeholders))
+            .filter(|precreate| !precreate.is_empty()),
+        files,
+        shortcuts: menu.shortcuts(&directories),
+        registry_keys,
+        terminal_profile: menu.terminal_profile(),
+        warnings,
+        name,
+        ..MenuItemPreview::default()
+    })
+}
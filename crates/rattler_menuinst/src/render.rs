@@ -73,6 +73,17 @@ impl BaseMenuItemPlaceholders {
         BaseMenuItemPlaceholders { placeholders: vars }
     }
 
+    /// Overrides the values of the given placeholders, or adds them if they
+    /// are not defined yet.
+    pub fn with_overrides(mut self, overrides: &HashMap<String, String>) -> Self {
+        self.placeholders.extend(
+            overrides
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        self
+    }
+
     /// Insert the menu item location into the placeholders
     ///
     /// - On Linux, this is the path to the `.desktop` file
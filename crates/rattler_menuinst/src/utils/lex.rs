@@ -37,9 +37,44 @@ pub fn ensure_pad(name: &str, pad: char) -> String {
     }
 }
 
+/// Converts a string to title case, capitalizing the first letter of each word.
+///
+/// Words are separated by whitespace, hyphens, or underscores. This is used internally
+/// to format URL protocol names.
+///
+/// # Arguments
+/// * `s` - The input string to convert.
+///
+/// # Returns
+/// A new `String` in title case.
+///
+/// # Examples
+/// ```ignore
+/// assert_eq!(title_case("hello-world"), "Hello-World");
+/// assert_eq!(title_case("my_url"), "My_Url");
+/// ```
+pub fn title_case(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut capitalize_next = true;
+
+    for c in s.chars() {
+        if c.is_whitespace() || c == '-' || c == '_' {
+            capitalize_next = true;
+            result.push(c);
+        } else if capitalize_next {
+            result.extend(c.to_uppercase());
+            capitalize_next = false;
+        } else {
+            result.extend(c.to_lowercase());
+        }
+    }
+
+    result
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{ensure_pad, quote_args, quote_string};
+    use super::{ensure_pad, quote_args, quote_string, title_case};
 
     #[test]
     fn test_quote_args() {
@@ -72,4 +107,19 @@ mod tests {
         assert_eq!(ensure_pad("_conda_", '_'), "_conda_");
         assert_eq!(ensure_pad("", '_'), "");
     }
+
+    #[test]
+    fn test_title_case() {
+        let inputs = [
+            ("hello-world", "Hello-World"),
+            ("my_url", "My_Url"),
+            ("my_url_protocol", "My_Url_Protocol"),
+            ("my_url_protocol_test", "My_Url_Protocol_Test"),
+            ("my_url_protocol_test_2", "My_Url_Protocol_Test_2"),
+        ];
+
+        for (input, expected) in inputs.iter() {
+            assert_eq!(title_case(input), *expected);
+        }
+    }
 }
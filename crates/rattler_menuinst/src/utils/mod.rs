@@ -1,3 +1,4 @@
+pub mod lex;
 pub mod slugify;
 pub use slugify::slugify;
 pub mod terminal;
@@ -0,0 +1,355 @@
+//! Validation of `Menu/*.json` files.
+//!
+//! [`validate_menu_schema`] deserializes a menu schema and checks the values
+//! that the installation relies on, without rendering or installing
+//! anything. Every problem is reported as a [`SchemaError`] with the JSON path
+//! of the offending value, so that menu definitions can be checked in CI.
+
+use std::fmt::{self, Display, Formatter};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    render::PlaceholderString,
+    schema::{MenuInstSchema, MenuItem, NameField},
+    MenuInstError,
+};
+
+/// The maximum length of a Windows application user model id.
+const MAX_APP_USER_MODEL_ID_LENGTH: usize = 128;
+
+/// Matches placeholders like `{{ PREFIX }}`, which may contain whitespace.
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{\s*\w+\s*\}\}").unwrap());
+
+/// An error in a menu schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaError {
+    /// The JSON path of the offending value, e.g.
+    /// `$.menu_items[0].platforms.win.file_extensions[1]`.
+    pub path: String,
+
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Formats a list of errors with one error per line, used for
+/// [`MenuInstError::InvalidSchema`].
+pub(crate) fn format_errors(errors: &[SchemaError]) -> String {
+    errors.iter().map(|error| format!("\n  {error}")).collect()
+}
+
+/// Deserializes and validates the contents of a `Menu/*.json` file.
+///
+/// Returns [`MenuInstError::InvalidSchema`] with all problems that were found.
+/// If the file cannot be deserialized, only the first deserialization error
+/// is reported.
+pub fn validate_menu_schema(text: &str) -> Result<MenuInstSchema, MenuInstError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(text);
+    let schema: MenuInstSchema = serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        MenuInstError::InvalidSchema(vec![SchemaError {
+            path: json_path(&err.path().to_string()),
+            message: err.inner().to_string(),
+        }])
+    })?;
+
+    let mut validator = Validator::default();
+    validator.validate(&schema);
+    if validator.errors.is_empty() {
+        Ok(schema)
+    } else {
+        Err(MenuInstError::InvalidSchema(validator.errors))
+    }
+}
+
+/// Converts a path as displayed by `serde_path_to_error` to a JSON path.
+fn json_path(path: &str) -> String {
+    match path {
+        "" | "." => "$".to_string(),
+        path if path.starts_with('[') => format!("${path}"),
+        path => format!("$.{path}"),
+    }
+}
+
+/// Returns the string with all placeholders replaced by a single word, so that
+/// the whitespace inside of placeholders is not mistaken for whitespace in
+/// the value.
+fn without_placeholders(value: &PlaceholderString) -> String {
+    PLACEHOLDER
+        .replace_all(&value.0, "PLACEHOLDER")
+        .into_owned()
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<SchemaError>,
+}
+
+impl Validator {
+    fn error(&mut self, path: String, message: impl Into<String>) {
+        self.errors.push(SchemaError {
+            path,
+            message: message.into(),
+        });
+    }
+
+    fn validate(&mut self, schema: &MenuInstSchema) {
+        if schema.menu_name.trim().is_empty() {
+            self.error("$.menu_name".to_string(), "must not be empty");
+        }
+        if schema.menu_items.is_empty() {
+            self.error(
+                "$.menu_items".to_string(),
+                "must contain at least one menu item",
+            );
+        }
+        for (index, item) in schema.menu_items.iter().enumerate() {
+            self.validate_item(&format!("$.menu_items[{index}]"), item);
+        }
+    }
+
+    fn validate_item(&mut self, path: &str, item: &MenuItem) {
+        let names = match &item.command.name {
+            NameField::Simple(name) => vec![(format!("{path}.name"), name)],
+            NameField::Complex(name) => vec![
+                (
+                    format!("{path}.name.target_environment_is_base"),
+                    &name.target_environment_is_base,
+                ),
+                (
+                    format!("{path}.name.target_environment_is_not_base"),
+                    &name.target_environment_is_not_base,
+                ),
+            ],
+        };
+        for (path, name) in names {
+            if name.0.trim().is_empty() {
+                self.error(path, "must not be empty");
+            }
+        }
+
+        let platforms = &item.platforms;
+        if platforms.linux.is_none() && platforms.osx.is_none() && platforms.win.is_none() {
+            self.error(
+                format!("{path}.platforms"),
+                "must define the menu item for at least one platform",
+            );
+        }
+
+        // The command of a platform replaces the command of the menu item, so
+        // the command is checked for every platform that the item is defined
+        // for.
+        let platform_commands = [
+            ("linux", platforms.linux.as_ref().map(|p| &p.base.command)),
+            ("osx", platforms.osx.as_ref().map(|p| &p.base.command)),
+            ("win", platforms.win.as_ref().map(|p| &p.base.command)),
+        ];
+        for (platform, command) in platform_commands {
+            let (path, command) = match command {
+                None => continue,
+                Some(Some(command)) => (format!("{path}.platforms.{platform}.command"), command),
+                Some(None) => (format!("{path}.command"), &item.command.command),
+            };
+            if command
+                .first()
+                .is_none_or(|program| program.0.trim().is_empty())
+            {
+                self.error(path, format!("must name the program to run on {platform}"));
+            }
+        }
+
+        if let Some(linux) = &platforms.linux {
+            let path = format!("{path}.platforms.linux");
+            let mime_types = linux.specific.mime_type.as_deref().unwrap_or_default();
+            for (index, mime_type) in mime_types.iter().enumerate() {
+                if !mime_type.0.contains('/') {
+                    self.error(
+                        format!("{path}.MimeType[{index}]"),
+                        format!("'{}' is not a MIME type like 'text/plain'", mime_type.0),
+                    );
+                }
+            }
+            for mime_type in linux.specific.glob_patterns.iter().flat_map(|g| g.keys()) {
+                if !mime_types.contains(mime_type) {
+                    self.error(
+                        format!("{path}.glob_patterns.{}", mime_type.0),
+                        "the MIME type must also be listed in 'MimeType'",
+                    );
+                }
+            }
+        }
+
+        if let Some(osx) = &platforms.osx {
+            let path = format!("{path}.platforms.osx");
+            for destination in osx.specific.link_in_bundle.iter().flat_map(|l| l.values()) {
+                let is_absolute = destination.0.starts_with('/');
+                if is_absolute && !destination.0.starts_with("{{ MENU_ITEM_LOCATION }}") {
+                    self.error(
+                        format!("{path}.link_in_bundle"),
+                        format!(
+                            "the destination '{}' must be inside the .app bundle, use a relative \
+                             path or start it with '{{{{ MENU_ITEM_LOCATION }}}}'",
+                            destination.0
+                        ),
+                    );
+                }
+            }
+        }
+
+        if let Some(win) = &platforms.win {
+            let path = format!("{path}.platforms.win");
+            let extensions = win.specific.file_extensions.as_deref().unwrap_or_default();
+            for (index, extension) in extensions.iter().enumerate() {
+                let value = without_placeholders(extension);
+                if !value.starts_with('.') || value.len() < 2 || value.contains(char::is_whitespace)
+                {
+                    self.error(
+                        format!("{path}.file_extensions[{index}]"),
+                        format!("'{}' is not a file extension like '.txt'", extension.0),
+                    );
+                }
+            }
+
+            let protocols = win.specific.url_protocols.as_deref().unwrap_or_default();
+            for (index, protocol) in protocols.iter().enumerate() {
+                let value = without_placeholders(protocol);
+                if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ':') {
+                    self.error(
+                        format!("{path}.url_protocols[{index}]"),
+                        format!(
+                            "'{}' is not a URL protocol, it must not be empty or contain \
+                             whitespace or ':'",
+                            protocol.0
+                        ),
+                    );
+                }
+            }
+
+            if let Some(app_user_model_id) = &win.specific.app_user_model_id {
+                let value = without_placeholders(app_user_model_id);
+                let has_two_parts = value
+                    .split_once('.')
+                    .is_some_and(|(company, product)| !company.is_empty() && !product.is_empty());
+                if !has_two_parts
+                    || value.contains(char::is_whitespace)
+                    || value.len() > MAX_APP_USER_MODEL_ID_LENGTH
+                {
+                    self.error(
+                        format!("{path}.app_user_model_id"),
+                        format!(
+                            "'{}' must have the form 'Company.Product', contain no whitespace \
+                             and be at most {MAX_APP_USER_MODEL_ID_LENGTH} characters long",
+                            app_user_model_id.0
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fs_err as fs;
+
+    use super::validate_menu_schema;
+    use crate::{test::test_data, MenuInstError};
+
+    fn errors(text: &str) -> Vec<(String, String)> {
+        match validate_menu_schema(text) {
+            Err(MenuInstError::InvalidSchema(errors)) => errors
+                .into_iter()
+                .map(|error| (error.path, error.message))
+                .collect(),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_valid_schemas() {
+        for path in [
+            "spyder/menu.json",
+            "gnuradio/gnuradio-grc.json",
+            "gqrx/gqrx-menu.json",
+            "mne/menu.json",
+            "defaults/defaults.json",
+        ] {
+            let text = fs::read_to_string(test_data().join(path)).unwrap();
+            assert_eq!(errors(&text), Vec::new(), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_deserialization_error_path() {
+        let errors = errors(
+            r#"{
+                "$schema": "https://json-schema.org/draft-07/schema",
+                "menu_name": "Test",
+                "menu_items": [{
+                    "name": "Test",
+                    "description": "",
+                    "command": ["test"],
+                    "platforms": {"linux": {"Terminal": true}}
+                }]
+            }"#,
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "$.menu_items[0].platforms.linux");
+        assert!(errors[0].1.contains("Terminal"), "{}", errors[0].1);
+    }
+
+    #[test]
+    fn test_semantic_errors() {
+        let errors = errors(
+            r#"{
+                "$schema": "https://json-schema.org/draft-07/schema",
+                "menu_name": "",
+                "menu_items": [{
+                    "name": "Test",
+                    "description": "",
+                    "command": [""],
+                    "platforms": {
+                        "linux": {
+                            "MimeType": ["text/x-test"],
+                            "glob_patterns": {"text/x-other": "*.other"}
+                        },
+                        "osx": {
+                            "command": ["test"],
+                            "link_in_bundle": {"{{ PREFIX }}/bin/test": "/usr/local/bin/test"}
+                        },
+                        "win": {
+                            "command": ["test.exe"],
+                            "file_extensions": [".{{ ENV_NAME }}", "txt"],
+                            "url_protocols": ["test:"],
+                            "app_user_model_id": "Test App"
+                        }
+                    }
+                }]
+            }"#,
+        );
+        let paths = errors
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "$.menu_name",
+                "$.menu_items[0].command",
+                "$.menu_items[0].platforms.linux.glob_patterns.text/x-other",
+                "$.menu_items[0].platforms.osx.link_in_bundle",
+                "$.menu_items[0].platforms.win.file_extensions[1]",
+                "$.menu_items[0].platforms.win.url_protocols[0]",
+                "$.menu_items[0].platforms.win.app_user_model_id",
+            ]
+        );
+    }
+}
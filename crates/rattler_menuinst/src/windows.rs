@@ -8,7 +8,7 @@ use rattler_shell::{
     activation::{ActivationVariables, Activator, PathModificationBehavior},
     shell,
 };
-use registry::{notify_shell_changes, FileExtension, UrlProtocol};
+use registry::notify_shell_changes;
 use std::{
     io::Write as _,
     path::{Path, PathBuf},
@@ -17,15 +17,15 @@ use terminal::TerminalProfile;
 pub use terminal::TerminalUpdateError;
 
 use crate::{
+    preview::windows::{self as rendering, FileExtension, UrlProtocol},
     render::{BaseMenuItemPlaceholders, MenuItemPlaceholders},
     schema::{Environment, MenuItemCommand, Windows},
-    utils::{log_output, slugify},
+    utils::log_output,
     MenuInstError, MenuMode,
 };
 
 mod create_shortcut;
 mod knownfolders;
-mod lex;
 mod registry;
 mod terminal;
 
@@ -148,16 +148,21 @@ impl WindowsMenu {
         }
     }
 
-    fn script_content(&self) -> Result<String, MenuInstError> {
-        let mut lines = vec![
-            "@echo off".to_string(),
-            ":: Script generated by conda/menuinst".to_string(),
-        ];
-
-        if let Some(pre_command_code) = self.command.precommand.as_ref() {
-            lines.push(pre_command_code.resolve(&self.placeholders));
+    /// The renderer that is shared with the preview of the menu item.
+    fn renderer(&self) -> rendering::WindowsMenu<'_> {
+        rendering::WindowsMenu {
+            menu_name: &self.menu_name,
+            prefix: &self.prefix,
+            name: &self.name,
+            item: &self.item,
+            command: &self.command,
+            placeholders: &self.placeholders,
+            menu_mode: self.menu_mode,
         }
+    }
 
+    fn script_content(&self) -> Result<String, MenuInstError> {
+        let mut env: Vec<(String, String)> = Vec::new();
         if self.command.activate.unwrap_or_default() {
             // create a bash activation script and emit it into the script
             let activator =
@@ -166,23 +171,10 @@ impl WindowsMenu {
                 path_modification_behavior: PathModificationBehavior::Prepend,
                 ..Default::default()
             };
-            let activation_env = activator.run_activation(activation_variables, None)?;
-
-            for (k, v) in activation_env {
-                lines.push(format!(r#"set "{k}={v}""#));
-            }
+            env.extend(activator.run_activation(activation_variables, None)?);
         }
 
-        let args: Vec<String> = self
-            .command
-            .command
-            .iter()
-            .map(|elem| elem.resolve(&self.placeholders))
-            .collect();
-
-        lines.push(lex::quote_args(&args).join(" "));
-
-        Ok(lines.join("\n"))
+        Ok(self.renderer().script_content(&env))
     }
 
     fn write_script(&self, path: &Path) -> Result<(), MenuInstError> {
@@ -197,78 +189,7 @@ impl WindowsMenu {
     }
 
     fn path_for_script(&self) -> PathBuf {
-        self.prefix.join("Menu").join(format!("{}.bat", &self.name))
-    }
-
-    fn build_command(&self, with_arg1: bool) -> Result<Vec<String>, MenuInstError> {
-        if self.command.activate.unwrap_or(false) {
-            let script_path = self.path_for_script();
-            self.write_script(&script_path)?;
-
-            let system_root = std::env::var("SystemRoot").unwrap_or("C:\\Windows".to_string());
-            let system32 = Path::new(&system_root).join("system32");
-            let cmd_exe = system32.join("cmd.exe").to_string_lossy().to_string();
-
-            if self.command.terminal.unwrap_or(false) {
-                let mut command = [&cmd_exe, "/D", "/K"]
-                    .iter()
-                    .map(|s| (*s).to_string())
-                    .collect::<Vec<String>>();
-
-                // add script path with quotes
-                command.push(format!("\"{}\"", script_path.to_string_lossy()));
-
-                if with_arg1 {
-                    command.push("%1".to_string());
-                }
-                Ok(command)
-            } else {
-                let script_path = self.path_for_script();
-                self.write_script(&script_path)?;
-
-                let arg1 = if with_arg1 { "%1 " } else { "" };
-                let powershell = system32
-                    .join("WindowsPowerShell")
-                    .join("v1.0")
-                    .join("powershell.exe")
-                    .to_string_lossy()
-                    .to_string();
-
-                let mut command = [
-                    &cmd_exe,
-                    "/D",
-                    "/C",
-                    "START",
-                    "/MIN",
-                    "\"\"",
-                    &powershell,
-                    "-WindowStyle",
-                    "hidden",
-                ]
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<String>>();
-
-                command.push(format!(
-                    "\"start '{}' {}-WindowStyle hidden\"",
-                    script_path.to_string_lossy(),
-                    arg1
-                ));
-
-                Ok(command)
-            }
-        } else {
-            let mut command = Vec::new();
-            for elem in self.command.command.iter() {
-                command.push(elem.resolve(&self.placeholders));
-            }
-
-            if with_arg1 && !command.iter().any(|s| s.contains("%1")) {
-                command.push("%1".to_string());
-            }
-
-            Ok(command)
-        }
+        PathBuf::from(self.renderer().path_for_script())
     }
 
     fn precreate(&self) -> Result<(), MenuInstError> {
@@ -295,138 +216,69 @@ impl WindowsMenu {
         Ok(())
     }
 
-    fn app_id(&self) -> String {
-        match self.item.app_user_model_id.as_ref() {
-            Some(aumi) => aumi.resolve(&self.placeholders),
-            None => format!(
-                "Menuinst.{}",
-                slugify(&self.name)
-                    .replace(".", "")
-                    .chars()
-                    .take(128)
-                    .collect::<String>()
-            ),
-        }
-    }
-
-    fn create_shortcut(
-        &self,
-        args: &[String],
-        tracker: &mut WindowsTracker,
-    ) -> Result<(), MenuInstError> {
-        let icon = self
-            .command
-            .icon
-            .as_ref()
-            .map(|s| s.resolve(&self.placeholders));
-
-        let workdir = if let Some(workdir) = &self.command.working_dir {
-            workdir.resolve(&self.placeholders)
-        } else {
-            "%HOMEPATH%".to_string()
+    fn create_shortcuts(&self, tracker: &mut WindowsTracker) -> Result<(), MenuInstError> {
+        let directories = rendering::Directories {
+            start_menu: self.directories.start_menu.display().to_string(),
+            desktop: self.directories.desktop.display().to_string(),
+            quick_launch: self
+                .directories
+                .quick_launch
+                .as_ref()
+                .map(|path| path.display().to_string()),
         };
-
-        if workdir != "%HOMEPATH%" {
-            fs::create_dir_all(&workdir)?;
-        }
-
-        let app_id = self.app_id();
-
-        // split args into command and arguments
-        let Some((command, args)) = args.split_first() else {
+        let shortcuts = self.renderer().shortcuts(&directories);
+        let Some(first) = shortcuts.first() else {
             return Ok(());
         };
-        let args = lex::quote_args(args).join(" ");
 
-        let link_name = format!("{}.lnk", self.name);
+        if first.working_dir != "%HOMEPATH%" {
+            fs::create_dir_all(&first.working_dir)?;
+        }
 
-        // install start menu shortcut
         let start_menu_subdir_path = self.directories.start_menu.join(&self.menu_name);
         if !start_menu_subdir_path.exists() {
             fs::create_dir_all(&start_menu_subdir_path)?;
             tracker.start_menu_subdir_path = Some(start_menu_subdir_path.clone());
         }
 
-        let start_menu_link_path = start_menu_subdir_path.join(&link_name);
-        let shortcut = Shortcut {
-            path: command,
-            description: &self.command.description.resolve(&self.placeholders),
-            filename: &start_menu_link_path,
-            arguments: Some(&args),
-            workdir: Some(&workdir),
-            iconpath: icon.as_deref(),
-            iconindex: Some(0),
-            app_id: Some(&app_id),
-        };
-        create_shortcut::create_shortcut(shortcut)?;
-        tracker.shortcuts.push(start_menu_link_path.clone());
-
-        // install desktop shortcut
-        if self.item.desktop.unwrap_or(true) {
-            let desktop_link_path = self.directories.desktop.join(&link_name);
-            let shortcut = Shortcut {
-                path: command,
-                description: &self.command.description.resolve(&self.placeholders),
-                filename: &desktop_link_path,
-                arguments: Some(&args),
-                workdir: Some(&workdir),
-                iconpath: icon.as_deref(),
+        for shortcut in &shortcuts {
+            create_shortcut::create_shortcut(Shortcut {
+                path: &shortcut.target,
+                description: &shortcut.description,
+                filename: &shortcut.path,
+                arguments: Some(&shortcut.arguments),
+                workdir: Some(&shortcut.working_dir),
+                iconpath: shortcut.icon.as_deref(),
                 iconindex: Some(0),
-                app_id: Some(&app_id),
-            };
-
-            create_shortcut::create_shortcut(shortcut)?;
-            tracker.shortcuts.push(desktop_link_path.clone());
-        }
-
-        // install quicklaunch shortcut
-        if let Some(quick_launch_dir) = self.directories.quick_launch.as_ref() {
-            if self.item.quicklaunch.unwrap_or(false) {
-                let quicklaunch_link_path = quick_launch_dir.join(link_name);
-                let shortcut = Shortcut {
-                    path: command,
-                    description: &self.command.description.resolve(&self.placeholders),
-                    filename: &quicklaunch_link_path,
-                    arguments: Some(&args),
-                    workdir: Some(&workdir),
-                    iconpath: icon.as_deref(),
-                    iconindex: Some(0),
-                    app_id: Some(&app_id),
-                };
-
-                create_shortcut::create_shortcut(shortcut)?;
-                tracker.shortcuts.push(quicklaunch_link_path.clone());
-            }
+                app_id: Some(&shortcut.app_user_model_id),
+            })?;
+            tracker.shortcuts.push(shortcut.path.clone());
         }
         Ok(())
     }
 
     fn icon(&self) -> Option<String> {
-        self.command
-            .icon
-            .as_ref()
-            .map(|s| s.resolve(&self.placeholders))
+        self.renderer().icon()
     }
 
     fn register_file_extensions(&self, tracker: &mut WindowsTracker) -> Result<(), MenuInstError> {
-        let Some(extensions) = &self.item.file_extensions else {
+        let renderer = self.renderer();
+        let extensions = renderer.file_extensions();
+        if extensions.is_empty() {
             return Ok(());
-        };
+        }
 
         let icon = self.icon();
-        let command = self.build_command(true)?.join(" ");
-        let name = &self.name;
-        let app_user_model_id = self.app_id();
+        let command = renderer.build_command(true).join(" ");
+        let app_user_model_id = renderer.app_id();
 
-        for extension in extensions {
-            let extension = extension.resolve(&self.placeholders);
-            let identifier = format!("{name}.AssocFile{extension}");
+        for (extension, identifier) in extensions {
             let file_extension = FileExtension {
                 extension: &extension,
                 identifier: &identifier,
                 command: &command,
                 icon: icon.as_deref(),
-                app_name: Some(name),
+                app_name: Some(&self.name),
                 app_user_model_id: Some(&app_user_model_id),
                 friendly_type_name: None,
             };
@@ -434,8 +286,8 @@ impl WindowsMenu {
             registry::register_file_extension(file_extension, self.menu_mode)?;
 
             tracker.file_extensions.push(WindowsFileExtension {
-                extension: extension.clone(),
-                identifier: identifier.clone(),
+                extension,
+                identifier,
             });
         }
 
@@ -443,33 +295,30 @@ impl WindowsMenu {
     }
 
     fn register_url_protocols(&self, tracker: &mut WindowsTracker) -> Result<bool, MenuInstError> {
-        let protocols = match &self.item.url_protocols {
-            Some(protocols) if !protocols.is_empty() => protocols,
-            _ => return Ok(false),
-        };
+        let renderer = self.renderer();
+        let protocols = renderer.url_protocols();
+        if protocols.is_empty() {
+            return Ok(false);
+        }
 
-        let command = self.build_command(true)?.join(" ");
+        let command = renderer.build_command(true).join(" ");
         let icon = self.icon();
-        let name = &self.name;
-        let app_user_model_id = format!("{name}.Protocol");
-
-        for protocol in protocols {
-            let protocol = protocol.resolve(&self.placeholders);
-            let identifier = format!("{name}.Protocol{protocol}");
+        let app_user_model_id = renderer.url_protocol_app_id();
 
+        for (protocol, identifier) in protocols {
             let url_protocol = UrlProtocol {
                 protocol: &protocol,
                 command: &command,
                 identifier: &identifier,
                 icon: icon.as_deref(),
-                app_name: Some(name),
+                app_name: Some(&self.name),
                 app_user_model_id: Some(&app_user_model_id),
             };
 
             registry::register_url_protocol(url_protocol, self.menu_mode)?;
             tracker.url_protocols.push(WindowsUrlProtocol {
-                protocol: protocol.clone(),
-                identifier: identifier.clone(),
+                protocol,
+                identifier,
             });
         }
 
@@ -486,7 +335,7 @@ impl WindowsMenu {
         let profile = TerminalProfile {
             name: terminal_profile,
             icon: self.icon(),
-            commandline: self.build_command(true)?.join(" "),
+            commandline: self.renderer().build_command(true).join(" "),
             starting_directory: self
                 .command
                 .working_dir
@@ -506,9 +355,11 @@ impl WindowsMenu {
     }
 
     pub fn install(&self, tracker: &mut WindowsTracker) -> Result<(), MenuInstError> {
-        let args = self.build_command(false)?;
+        if self.command.activate.unwrap_or(false) {
+            self.write_script(&self.path_for_script())?;
+        }
         self.precreate()?;
-        self.create_shortcut(&args, tracker)?;
+        self.create_shortcuts(tracker)?;
         self.register_file_extensions(tracker)?;
         self.register_url_protocols(tracker)?;
         self.register_windows_terminal(tracker)?;
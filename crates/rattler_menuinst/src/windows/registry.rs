@@ -1,15 +1,36 @@
-use crate::MenuMode;
+use crate::{
+    preview::{
+        windows::{file_extension_keys, url_protocol_keys, FileExtension, UrlProtocol},
+        RegistryKey,
+    },
+    MenuMode,
+};
 use windows::Win32::UI::Shell::{SHChangeNotify, SHCNE_ASSOCCHANGED, SHCNF_IDLIST};
 
-#[derive(Debug, Clone, Copy)]
-pub struct FileExtension<'a> {
-    pub extension: &'a str,
-    pub identifier: &'a str,
-    pub command: &'a str,
-    pub icon: Option<&'a str>,
-    pub app_name: Option<&'a str>,
-    pub app_user_model_id: Option<&'a str>,
-    pub friendly_type_name: Option<&'a str>,
+/// Writes registry keys that were rendered by [`file_extension_keys`] or
+/// [`url_protocol_keys`]. The path of each key starts with the name of its
+/// hive.
+fn write_keys(keys: &[RegistryKey]) -> Result<(), std::io::Error> {
+    for key in keys {
+        let (hive, path) = key.path.split_once('\\').unwrap_or((key.path.as_str(), ""));
+        let hkey = match hive {
+            "HKEY_CLASSES_ROOT" => windows_registry::CLASSES_ROOT,
+            "HKEY_CURRENT_USER" => windows_registry::CURRENT_USER,
+            "HKEY_LOCAL_MACHINE" => windows_registry::LOCAL_MACHINE,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unknown registry hive in '{}'", key.path),
+                ))
+            }
+        };
+
+        let registry_key = hkey.create(path)?;
+        for value in &key.values {
+            registry_key.set_string(&value.name, &value.data)?;
+        }
+    }
+    Ok(())
 }
 
 /// Registers a file extension handler in the Windows Registry.
@@ -42,73 +63,7 @@ pub fn register_file_extension(
     file_extension: FileExtension<'_>,
     mode: MenuMode,
 ) -> Result<(), std::io::Error> {
-    let FileExtension {
-        extension,
-        identifier,
-        command,
-        icon,
-        app_name,
-        app_user_model_id,
-        friendly_type_name,
-    } = file_extension;
-    let hkey = if mode == MenuMode::System {
-        windows_registry::LOCAL_MACHINE
-    } else {
-        windows_registry::CURRENT_USER
-    };
-
-    let classes = hkey.create(r"Software\Classes")?;
-
-    // Associate extension with handler
-    let ext_key = classes.create(format!(r"{extension}\OpenWithProgids"))?;
-    ext_key.set_string(identifier, "")?;
-
-    // Register the handler
-    let handler_desc = format!("{extension} {identifier} file");
-    classes.create(identifier)?.set_string("", &handler_desc)?;
-
-    // Set the 'open' command
-    classes
-        .create(format!(r"{identifier}\shell\open\command"))?
-        .set_string("", command)?;
-
-    // Set app name related values if provided
-    if let Some(name) = app_name {
-        let open_key = classes.create(format!(r"{identifier}\shell\open"))?;
-        open_key.set_string("", name)?;
-        open_key.set_string("FriendlyAppName", name)?;
-        classes
-            .create(identifier)?
-            .set_string("FriendlyAppName", name)?;
-    }
-
-    // Set app user model ID if provided
-    if let Some(id) = app_user_model_id {
-        classes
-            .create(identifier)?
-            .set_string("AppUserModelID", id)?;
-    }
-
-    // Set icon if provided
-    if let Some(icon_path) = icon {
-        // Set default icon and shell open icon
-        classes
-            .create(identifier)?
-            .set_string("DefaultIcon", icon_path)?;
-        classes
-            .create(format!(r"{identifier}\shell\open"))?
-            .set_string("Icon", icon_path)?;
-    }
-
-    // Set friendly type name if provided
-    // NOTE: Windows <10 requires the string in a PE file, but we just set the raw string
-    if let Some(friendly_name) = friendly_type_name {
-        classes
-            .create(identifier)?
-            .set_string("FriendlyTypeName", friendly_name)?;
-    }
-
-    Ok(())
+    write_keys(&file_extension_keys(&file_extension, mode))
 }
 
 /// Unregisters a file extension handler from the Windows Registry.
@@ -168,61 +123,6 @@ pub fn unregister_file_extension(
     Ok(())
 }
 
-/// Converts a string to title case, capitalizing the first letter of each word.
-///
-/// Words are separated by whitespace, hyphens, or underscores. This is used internally
-/// to format URL protocol names.
-///
-/// # Arguments
-/// * `s` - The input string to convert.
-///
-/// # Returns
-/// A new `String` in title case.
-///
-/// # Examples
-/// ```ignore
-/// assert_eq!(title_case("hello-world"), "Hello-World");
-/// assert_eq!(title_case("my_url"), "My_Url");
-/// ```
-fn title_case(s: &str) -> String {
-    let mut result = String::with_capacity(s.len());
-    let mut capitalize_next = true;
-
-    for c in s.chars() {
-        if c.is_whitespace() || c == '-' || c == '_' {
-            capitalize_next = true;
-            result.push(c);
-        } else if capitalize_next {
-            result.extend(c.to_uppercase());
-            capitalize_next = false;
-        } else {
-            result.extend(c.to_lowercase());
-        }
-    }
-
-    result
-}
-
-/// Represents a URL protocol registration with associated metadata.
-///
-/// This struct defines the properties needed to register a custom URL protocol handler in the Windows Registry,
-/// such as a command to execute and optional application details.
-#[derive(Debug, Clone, Copy)]
-pub struct UrlProtocol<'a> {
-    /// The URL protocol name (e.g., `rattlertest`).
-    pub protocol: &'a str,
-    /// The command to execute when the protocol is invoked.
-    pub command: &'a str,
-    /// The identifier for the protocol handler.
-    pub identifier: &'a str,
-    /// The path to an icon file to associate with the protocol.
-    pub icon: Option<&'a str>,
-    /// The friendly name of the application.
-    pub app_name: Option<&'a str>,
-    /// The application user model ID (AUMI) for the protocol handler.
-    pub app_user_model_id: Option<&'a str>,
-}
-
 /// Registers a custom URL protocol handler in the Windows Registry.
 ///
 /// Creates registry entries for a URL protocol (e.g., `myapp://`) with a command and optional metadata,
@@ -251,57 +151,7 @@ pub fn register_url_protocol(
     url_protocol: UrlProtocol<'_>,
     mode: MenuMode,
 ) -> Result<(), std::io::Error> {
-    let UrlProtocol {
-        protocol,
-        command,
-        identifier,
-        icon,
-        app_name,
-        app_user_model_id,
-    } = url_protocol;
-
-    let hkey = if mode == MenuMode::System {
-        windows_registry::CLASSES_ROOT
-    } else {
-        windows_registry::CURRENT_USER
-    };
-
-    let base_path = if mode == MenuMode::System {
-        protocol.to_string()
-    } else {
-        format!(r"Software\Classes\{protocol}")
-    };
-
-    let protocol_key = hkey.create(base_path)?;
-
-    protocol_key.set_string("", format!("URL:{}", title_case(protocol)))?;
-    protocol_key.set_string("URL Protocol", "")?;
-
-    protocol_key
-        .create(r"shell\open\command")?
-        .set_string("", command)?;
-
-    if let Some(name) = app_name {
-        // let open_key = command_key.create(r"shell\open")?;
-        let open_key = protocol_key.create(r"shell\open")?;
-        open_key.set_string("", name)?;
-        open_key.set_string("FriendlyAppName", name)?;
-        protocol_key.set_string("FriendlyAppName", name)?;
-    }
-
-    if let Some(icon_path) = icon {
-        protocol_key.set_string("DefaultIcon", icon_path)?;
-        let open_key = protocol_key.create(r"shell\open")?;
-        open_key.set_string("Icon", icon_path)?;
-    }
-
-    if let Some(aumi) = app_user_model_id {
-        protocol_key.set_string("AppUserModelId", aumi)?;
-    }
-
-    protocol_key.set_string("_menuinst", identifier)?;
-
-    Ok(())
+    write_keys(&url_protocol_keys(&url_protocol, mode))
 }
 
 /// Unregisters a URL protocol handler from the Windows Registry.
@@ -382,21 +232,6 @@ mod tests {
         let _ = unregister_url_protocol(protocol, identifier, mode);
     }
 
-    #[test]
-    fn test_title_case() {
-        let inputs = [
-            ("hello-world", "Hello-World"),
-            ("my_url", "My_Url"),
-            ("my_url_protocol", "My_Url_Protocol"),
-            ("my_url_protocol_test", "My_Url_Protocol_Test"),
-            ("my_url_protocol_test_2", "My_Url_Protocol_Test_2"),
-        ];
-
-        for (input, expected) in inputs.iter() {
-            assert_eq!(title_case(input), *expected);
-        }
-    }
-
     #[test]
     fn test_register_file_extension_user() -> std::io::Result<()> {
         let extension = ".rattlertest";
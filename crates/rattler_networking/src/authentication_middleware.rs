@@ -1,18 +1,29 @@
 //! `reqwest` middleware that authenticates requests with data from the `AuthenticationStorage`
 use crate::authentication_storage::AuthenticationStorageError;
-use crate::{Authentication, AuthenticationStorage};
+use crate::{oauth, Authentication, AuthenticationStorage};
+use async_once_cell::OnceCell;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
-use reqwest::{Request, Response};
+use reqwest::{Request, Response, StatusCode};
 use reqwest_middleware::{Middleware, Next};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
 use url::Url;
 
+/// The outcome of exchanging a refresh token, shared by all requests that
+/// tried to refresh the same token concurrently.
+type RefreshCell = Arc<OnceCell<Option<Authentication>>>;
+
 /// `reqwest` middleware to authenticate requests
 #[derive(Clone)]
 pub struct AuthenticationMiddleware {
     auth_storage: AuthenticationStorage,
+
+    /// Refreshes of OAuth tokens keyed by the refresh token that is used.
+    /// Identity providers may rotate refresh tokens, so every refresh token
+    /// is only exchanged once.
+    refreshes: Arc<Mutex<HashMap<String, RefreshCell>>>,
 }
 
 #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
@@ -30,19 +41,58 @@ impl Middleware for AuthenticationMiddleware {
         }
 
         let url = req.url().clone();
-        match self.auth_storage.get_by_url(url) {
+        match self.lookup(url).await {
             Err(_) => {
                 // Forward error to caller (invalid URL)
                 next.run(req, extensions).await
             }
-            Ok((url, auth)) => {
+            Ok((url, found)) => {
+                // Refreshed credentials are written back under the key they
+                // were found under.
+                let (key, mut auth) = match found {
+                    Some((key, auth)) => (key, Some(auth)),
+                    None => (String::new(), None),
+                };
+                if auth.as_ref().is_some_and(Authentication::needs_refresh) {
+                    if let Some(refreshed) = self.refresh(&key, auth.as_ref(), &next).await {
+                        auth = Some(refreshed);
+                    }
+                }
+
+                let lookup_url = url.clone();
                 let url = Self::authenticate_url(url, &auth);
 
                 let mut req = req;
                 *req.url_mut() = url;
 
+                // Keep a copy of the request to retry it with a refreshed
+                // token if the server rejects the current one.
+                let retry = match &auth {
+                    Some(Authentication::OAuth {
+                        refresh_token: Some(_),
+                        ..
+                    }) => req.try_clone(),
+                    _ => None,
+                };
+
                 let req = Self::authenticate_request(req, &auth).await?;
-                next.run(req, extensions).await
+                let response = next.clone().run(req, extensions).await?;
+                if response.status() != StatusCode::UNAUTHORIZED {
+                    return Ok(response);
+                }
+
+                if let Some(retry) = retry {
+                    if let Some(refreshed) = self.refresh(&key, auth.as_ref(), &next).await {
+                        let req = Self::authenticate_request(retry, &Some(refreshed)).await?;
+                        return next.run(req, extensions).await;
+                    }
+                }
+
+                // The credentials were rejected or are missing, so they are
+                // looked up again for the next request instead of using the
+                // cached result.
+                self.auth_storage.forget_url(&lookup_url);
+                Ok(response)
             }
         }
     }
@@ -51,14 +101,124 @@ impl Middleware for AuthenticationMiddleware {
 impl AuthenticationMiddleware {
     /// Create a new authentication middleware with the given authentication storage
     pub fn from_auth_storage(auth_storage: AuthenticationStorage) -> Self {
-        Self { auth_storage }
+        Self {
+            auth_storage,
+            refreshes: Arc::default(),
+        }
     }
 
     /// Create a new authentication middleware with the default authentication storage
     pub fn from_env_and_defaults() -> Result<Self, AuthenticationStorageError> {
-        Ok(Self {
-            auth_storage: AuthenticationStorage::from_env_and_defaults()?,
-        })
+        Ok(Self::from_auth_storage(
+            AuthenticationStorage::from_env_and_defaults()?,
+        ))
+    }
+
+    /// Create a new authentication middleware with the default authentication
+    /// storage and the credential helpers from the given configuration, see
+    /// [`AuthenticationStorage::from_config`].
+    #[cfg(all(feature = "rattler_config", not(target_arch = "wasm32")))]
+    pub fn from_config<T>(
+        config: &rattler_config::config::ConfigBase<T>,
+    ) -> Result<Self, AuthenticationStorageError> {
+        Ok(Self::from_auth_storage(AuthenticationStorage::from_config(
+            config,
+        )?))
+    }
+
+    /// Looks up the credentials for `url` and the key they are stored under.
+    /// Backends like credential helpers may block, so the lookup runs on the
+    /// blocking thread pool of the tokio runtime if there is one.
+    async fn lookup(
+        &self,
+        url: Url,
+    ) -> Result<(Url, Option<(String, Authentication)>), reqwest::Error> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let auth_storage = self.auth_storage.clone();
+            return match handle
+                .spawn_blocking(move || auth_storage.get_with_key_by_url(url))
+                .await
+            {
+                Ok(result) => result,
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            };
+        }
+        self.auth_storage.get_with_key_by_url(url)
+    }
+
+    /// Exchanges the refresh token of OAuth credentials for a new access
+    /// token and writes the new tokens back to the storage under `key`.
+    /// Returns `None` if the credentials could not be refreshed.
+    async fn refresh(
+        &self,
+        key: &str,
+        auth: Option<&Authentication>,
+        next: &Next<'_>,
+    ) -> Option<Authentication> {
+        let Some(
+            auth @ Authentication::OAuth {
+                refresh_token: Some(refresh_token),
+                ..
+            },
+        ) = auth
+        else {
+            return None;
+        };
+
+        let cell = self
+            .refreshes
+            .lock()
+            .unwrap()
+            .entry(refresh_token.clone())
+            .or_insert_with(|| Arc::new(OnceCell::new()))
+            .clone();
+
+        let refreshed = cell
+            .get_or_init(async {
+                // Another request may already have refreshed the credentials.
+                if let Ok(Some(stored)) = self.auth_storage.get(key) {
+                    if &stored != auth && !stored.needs_refresh() {
+                        return Some(stored);
+                    }
+                }
+
+                let refreshed = async {
+                    let request = oauth::refresh_request(auth)?;
+                    let response = next
+                        .clone()
+                        .run(request, &mut http::Extensions::new())
+                        .await?;
+                    oauth::refresh_response(response, auth).await
+                }
+                .await;
+
+                match refreshed {
+                    Ok(refreshed) => {
+                        if let Err(e) = self.auth_storage.store(key, &refreshed) {
+                            tracing::warn!("failed to store refreshed credentials for {key}: {e}");
+                        }
+                        Some(refreshed)
+                    }
+                    Err(e) => {
+                        tracing::warn!("failed to refresh the credentials for {key}: {e}");
+                        None
+                    }
+                }
+            })
+            .await
+            .clone();
+
+        // Later refreshes that reuse the same refresh token must not see this
+        // result once the new access token expires.
+        let mut refreshes = self.refreshes.lock().unwrap();
+        if refreshes
+            .get(refresh_token)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            refreshes.remove(refresh_token);
+        }
+        refreshed
     }
 
     /// Authenticate the given URL with the given authentication information
@@ -113,6 +273,16 @@ impl AuthenticationMiddleware {
                         .insert(reqwest::header::AUTHORIZATION, header_value);
                     Ok(req)
                 }
+                Authentication::OAuth { access_token, .. } => {
+                    let mut header_value =
+                        reqwest::header::HeaderValue::from_str(&format!("Bearer {access_token}"))
+                            .map_err(reqwest_middleware::Error::middleware)?;
+                    header_value.set_sensitive(true);
+
+                    req.headers_mut()
+                        .insert(reqwest::header::AUTHORIZATION, header_value);
+                    Ok(req)
+                }
                 Authentication::CondaToken(_) | Authentication::S3Credentials { .. } => Ok(req),
             }
         } else {
@@ -456,4 +626,171 @@ mod tests {
 
         Ok(())
     }
+
+    /// Starts a server that serves `/data` for the access token `access-2`
+    /// and hands out that token for the refresh token `refresh-1`.
+    async fn oauth_server(refreshes: Arc<std::sync::atomic::AtomicUsize>) -> Url {
+        use axum::{
+            body::Bytes,
+            http::{header, HeaderMap, StatusCode},
+            response::IntoResponse,
+            routing::{get, post},
+        };
+        use std::future::IntoFuture;
+
+        let router = axum::Router::new()
+            .route(
+                "/token",
+                post(move |body: Bytes| async move {
+                    refreshes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    assert!(url::form_urlencoded::parse(&body)
+                        .any(|(key, value)| key == "refresh_token" && value == "refresh-1"));
+                    (
+                        [(header::CONTENT_TYPE, "application/json")],
+                        r#"{"access_token": "access-2", "refresh_token": "refresh-2", "expires_in": 3600}"#,
+                    )
+                }),
+            )
+            .route(
+                "/data",
+                get(|headers: HeaderMap| async move {
+                    if headers.get(header::AUTHORIZATION).unwrap() == "Bearer access-2" {
+                        "data".into_response()
+                    } else {
+                        StatusCode::UNAUTHORIZED.into_response()
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        format!("http://localhost:{}/", addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[rstest::rstest]
+    #[case::expired(Some(0))]
+    #[case::rejected(None)]
+    #[tokio::test]
+    async fn test_oauth_refresh(#[case] expires_at: Option<i64>) -> anyhow::Result<()> {
+        let refreshes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = oauth_server(refreshes.clone()).await;
+
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(Arc::new(
+            crate::authentication_storage::backends::memory::MemoryStorage::new(),
+        ));
+        storage.store(
+            "localhost",
+            &Authentication::OAuth {
+                access_token: String::from("access-1"),
+                refresh_token: Some(String::from("refresh-1")),
+                expires_at,
+                token_endpoint: server.join("token")?.to_string(),
+                client_id: String::from("rattler"),
+            },
+        )?;
+
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::default())
+            .with(AuthenticationMiddleware::from_auth_storage(storage.clone()))
+            .build();
+
+        for _ in 0..2 {
+            let response = client.get(server.join("data")?).send().await?;
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.text().await?, "data");
+        }
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // The refreshed tokens are written back to the storage.
+        let Some(Authentication::OAuth {
+            access_token,
+            refresh_token,
+            expires_at,
+            ..
+        }) = storage.get("localhost")?
+        else {
+            panic!("expected OAuth credentials");
+        };
+        assert_eq!(access_token, "access-2");
+        assert_eq!(refresh_token.as_deref(), Some("refresh-2"));
+        assert!(expires_at.unwrap() > crate::oauth::now());
+
+        Ok(())
+    }
+
+    /// A backend that hands out a new token on every lookup, like a
+    /// credential helper that renews its tokens.
+    #[derive(Debug, Default)]
+    struct RenewingStorage {
+        gets: std::sync::atomic::AtomicUsize,
+    }
+
+    impl crate::authentication_storage::StorageBackend for RenewingStorage {
+        fn store(
+            &self,
+            _host: &str,
+            _authentication: &Authentication,
+        ) -> Result<(), AuthenticationStorageError> {
+            unimplemented!()
+        }
+
+        fn get(&self, host: &str) -> Result<Option<Authentication>, AuthenticationStorageError> {
+            if host != "localhost" {
+                return Ok(None);
+            }
+            let gets = self.gets.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            Ok(Some(Authentication::BearerToken(format!("token-{gets}"))))
+        }
+
+        fn delete(&self, _host: &str) -> Result<(), AuthenticationStorageError> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_credentials_are_looked_up_again() -> anyhow::Result<()> {
+        use axum::{
+            http::{header, HeaderMap, StatusCode},
+            response::IntoResponse,
+            routing::get,
+        };
+        use std::future::IntoFuture;
+
+        let router = axum::Router::new().route(
+            "/data",
+            get(|headers: HeaderMap| async move {
+                if headers.get(header::AUTHORIZATION).unwrap() == "Bearer token-2" {
+                    "data".into_response()
+                } else {
+                    StatusCode::UNAUTHORIZED.into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let url: Url = format!("http://localhost:{port}/data").parse()?;
+
+        let backend = Arc::new(RenewingStorage::default());
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(backend.clone());
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::default())
+            .with(AuthenticationMiddleware::from_auth_storage(storage))
+            .build();
+
+        let response = client.get(url.clone()).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // The rejected token is not used again, and the renewed token is
+        // cached.
+        for _ in 0..2 {
+            let response = client.get(url.clone()).send().await?;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(backend.gets.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        Ok(())
+    }
 }
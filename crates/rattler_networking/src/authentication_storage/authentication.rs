@@ -28,6 +28,21 @@ pub enum Authentication {
         /// The session token to use for S3 authentication
         session_token: Option<String>,
     },
+    /// OAuth 2.0 / OIDC tokens. The access token is sent as a bearer
+    /// token and renewed with the refresh token when it expires.
+    OAuth {
+        /// The access token that is sent with requests
+        access_token: String,
+        /// The token to obtain a new access token with
+        refresh_token: Option<String>,
+        /// The time at which the access token expires, in seconds since the
+        /// unix epoch
+        expires_at: Option<i64>,
+        /// The token endpoint of the identity provider
+        token_endpoint: String,
+        /// The client id the tokens were issued to
+        client_id: String,
+    },
 }
 
 /// An error that can occur when parsing an authentication string
@@ -56,6 +71,20 @@ impl Authentication {
             Authentication::BasicHTTP { .. } => "BasicHTTP",
             Authentication::CondaToken(_) => "CondaToken",
             Authentication::S3Credentials { .. } => "S3",
+            Authentication::OAuth { .. } => "OAuth",
+        }
+    }
+
+    /// Returns true if these are OAuth credentials whose access token expires
+    /// within [`crate::oauth::EXPIRY_MARGIN`] and that can be refreshed.
+    pub fn needs_refresh(&self) -> bool {
+        match self {
+            Authentication::OAuth {
+                refresh_token: Some(_),
+                expires_at: Some(expires_at),
+                ..
+            } => *expires_at - crate::oauth::EXPIRY_MARGIN.as_secs() as i64 <= crate::oauth::now(),
+            _ => false,
         }
     }
 }
@@ -0,0 +1,447 @@
+//! Retrieve credentials from external credential helper executables.
+//!
+//! Like git and docker credential helpers, a helper is invoked with the
+//! action (`get`, `store` or `erase`) as its last argument and communicates
+//! through JSON on stdin and stdout:
+//!
+//! - `get` receives `{"host": "<host>"}` and prints the credentials in the
+//!   same format as the authentication file (e.g. `{"BearerToken": "..."}`),
+//!   or nothing if it has no credentials for the host.
+//! - `store` receives `{"host": "<host>", "authentication": {...}}`.
+//! - `erase` receives `{"host": "<host>"}`.
+//!
+//! A non-zero exit code is reported as an error, as is a helper that does not
+//! exit within the timeout of the storage, in which case it is killed.
+//!
+//! Helpers are only consulted for plain hosts, credentials that are scoped to
+//! a path are left to the other backends. Storing or deleting credentials for
+//! a host without a helper fails with [`CredentialHelperError::NotHandled`] so
+//! that the next backend is used instead.
+//!
+//! The results of `get` are cached until they are forgotten with
+//! [`StorageBackend::forget`], which the authentication middleware does when
+//! a server rejects a request.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    authentication_storage::{AuthenticationStorageError, StorageBackend},
+    host_pattern::matches_pattern,
+    Authentication,
+};
+
+/// An external executable that provides credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialHelper {
+    /// The name of or the path to the executable
+    pub command: String,
+
+    /// Arguments that are passed to the executable before the action
+    pub args: Vec<String>,
+}
+
+/// The default time a credential helper may take before it is killed.
+pub const DEFAULT_CREDENTIAL_HELPER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A struct that implements storage and access of authentication information
+/// by delegating to the credential helper that is configured for a host.
+#[derive(Debug, Clone)]
+pub struct CredentialHelperStorage {
+    /// Host patterns and their helpers, in the order they are matched
+    helpers: Vec<(String, CredentialHelper)>,
+
+    /// The time a helper may take before it is killed
+    timeout: Duration,
+
+    /// The results of previous `get` requests by host, including hosts for
+    /// which no credentials were found, so a helper only runs again for a
+    /// host once the result is forgotten
+    cache: Arc<Mutex<HashMap<String, Option<Authentication>>>>,
+}
+
+impl Default for CredentialHelperStorage {
+    fn default() -> Self {
+        Self {
+            helpers: Vec::new(),
+            timeout: DEFAULT_CREDENTIAL_HELPER_TIMEOUT,
+            cache: Arc::default(),
+        }
+    }
+}
+
+/// An error that can occur when invoking a credential helper
+#[derive(thiserror::Error, Debug)]
+pub enum CredentialHelperError {
+    /// The helper could not be started or communicated with
+    #[error("failed to run credential helper `{0}`: {1}")]
+    IOError(String, #[source] std::io::Error),
+
+    /// The helper exited with a non-zero exit code
+    #[error("credential helper `{command}` failed ({status}): {stderr}")]
+    Failed {
+        /// The command that failed
+        command: String,
+        /// The exit status of the helper
+        status: std::process::ExitStatus,
+        /// What the helper wrote to stderr
+        stderr: String,
+    },
+
+    /// The helper printed something that is not valid credentials
+    #[error("credential helper `{0}` returned invalid credentials: {1}")]
+    InvalidResponse(String, #[source] serde_json::Error),
+
+    /// The helper did not exit in time and was killed
+    #[error("credential helper `{0}` did not exit within {1:?}")]
+    Timeout(String, Duration),
+
+    /// No credential helper is configured for the host
+    #[error("no credential helper is configured for `{0}`")]
+    NotHandled(String),
+}
+
+#[derive(Serialize)]
+struct HelperRequest<'a> {
+    host: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authentication: Option<&'a Authentication>,
+}
+
+impl CredentialHelper {
+    /// Runs the helper with the given action and request and returns what it
+    /// printed to stdout. The helper is killed if it does not exit within
+    /// `timeout`.
+    fn run(
+        &self,
+        action: &str,
+        request: &HelperRequest<'_>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, CredentialHelperError> {
+        let io_error = |e| CredentialHelperError::IOError(self.command.clone(), e);
+
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .arg(action)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(io_error)?;
+
+        let input = serde_json::to_vec(request).expect("the request can always be serialized");
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&input)
+            .map_err(io_error)?;
+
+        // Read the output on separate threads so the helper cannot block on a
+        // full pipe while we wait for it to exit.
+        let stdout = read_to_end(child.stdout.take().expect("stdout is piped"));
+        let stderr = read_to_end(child.stderr.take().expect("stderr is piped"));
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(io_error)? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // The helper may already have exited in the meantime.
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(CredentialHelperError::Timeout(
+                    self.command.clone(),
+                    timeout,
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        let stdout = stdout
+            .join()
+            .expect("the reader thread does not panic")
+            .map_err(io_error)?;
+        if !status.success() {
+            let stderr = stderr
+                .join()
+                .expect("the reader thread does not panic")
+                .unwrap_or_default();
+            return Err(CredentialHelperError::Failed {
+                command: self.command.clone(),
+                status,
+                stderr: String::from_utf8_lossy(&stderr).trim().to_string(),
+            });
+        }
+        Ok(stdout)
+    }
+}
+
+/// Reads everything from `reader` on a new thread.
+fn read_to_end(
+    mut reader: impl Read + Send + 'static,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+impl CredentialHelperStorage {
+    /// Create a new storage without any helpers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `helper` for all hosts that match `pattern`. Patterns are matched
+    /// in the order they are added.
+    pub fn with_helper(mut self, pattern: impl Into<String>, helper: CredentialHelper) -> Self {
+        self.helpers.push((pattern.into(), helper));
+        self
+    }
+
+    /// Kill helpers that do not exit within `timeout`, defaults to
+    /// [`DEFAULT_CREDENTIAL_HELPER_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns true if no helpers are configured
+    pub fn is_empty(&self) -> bool {
+        self.helpers.is_empty()
+    }
+
+    /// Create a storage from the `credential-helpers` section of a
+    /// configuration
+    #[cfg(feature = "rattler_config")]
+    pub fn from_config(
+        config: &rattler_config::config::credential_helpers::CredentialHelpersMap,
+    ) -> Self {
+        config
+            .0
+            .iter()
+            .fold(Self::new(), |storage, (pattern, helper)| {
+                storage.with_helper(
+                    pattern,
+                    CredentialHelper {
+                        command: helper.command.clone(),
+                        args: helper.args.clone(),
+                    },
+                )
+            })
+    }
+
+    /// Returns the helper for `host`. Wildcard hosts and keys that are
+    /// scoped to a path are never handled by a helper.
+    fn helper_for(&self, host: &str) -> Option<&CredentialHelper> {
+        if host.starts_with("*.") || host.contains('/') {
+            return None;
+        }
+        self.helpers
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, host))
+            .map(|(_, helper)| helper)
+    }
+
+    /// Returns the helper for `host` or a [`CredentialHelperError::NotHandled`]
+    /// error.
+    fn required_helper_for(&self, host: &str) -> Result<&CredentialHelper, CredentialHelperError> {
+        self.helper_for(host)
+            .ok_or_else(|| CredentialHelperError::NotHandled(host.to_string()))
+    }
+}
+
+impl StorageBackend for CredentialHelperStorage {
+    fn store(
+        &self,
+        host: &str,
+        authentication: &Authentication,
+    ) -> Result<(), AuthenticationStorageError> {
+        let helper = self.required_helper_for(host)?;
+        self.cache.lock().unwrap().remove(host);
+        helper.run(
+            "store",
+            &HelperRequest {
+                host,
+                authentication: Some(authentication),
+            },
+            self.timeout,
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, host: &str) -> Result<Option<Authentication>, AuthenticationStorageError> {
+        let Some(helper) = self.helper_for(host) else {
+            return Ok(None);
+        };
+        if let Some(authentication) = self.cache.lock().unwrap().get(host) {
+            return Ok(authentication.clone());
+        }
+        let output = helper.run(
+            "get",
+            &HelperRequest {
+                host,
+                authentication: None,
+            },
+            self.timeout,
+        )?;
+        let authentication =
+            if output.iter().all(u8::is_ascii_whitespace) {
+                None
+            } else {
+                Some(serde_json::from_slice(&output).map_err(|e| {
+                    CredentialHelperError::InvalidResponse(helper.command.clone(), e)
+                })?)
+            };
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), authentication.clone());
+        Ok(authentication)
+    }
+
+    fn delete(&self, host: &str) -> Result<(), AuthenticationStorageError> {
+        let helper = self.required_helper_for(host)?;
+        self.cache.lock().unwrap().remove(host);
+        helper.run(
+            "erase",
+            &HelperRequest {
+                host,
+                authentication: None,
+            },
+            self.timeout,
+        )?;
+        Ok(())
+    }
+
+    fn forget(&self, host: &str) {
+        self.cache.lock().unwrap().remove(host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credential_helper() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state");
+        let script = dir.path().join("helper.sh");
+        fs_err::write(
+            &script,
+            r#"#!/bin/sh
+input=$(cat)
+case "$2" in
+    get)
+        echo "$input" >> "$1.log"
+        case "$input" in
+            *'"host":"conda.example.com"'*) echo '{"BearerToken": "secret"}' ;;
+            *'"host":"broken.example.com"'*) echo 'not json' ;;
+        esac
+        ;;
+    store) echo "$input" > "$1" ;;
+    erase) rm "$1" ;;
+    *) echo "unknown action $2" >&2; exit 1 ;;
+esac
+"#,
+        )
+        .unwrap();
+        fs_err::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let storage = CredentialHelperStorage::new().with_helper(
+            "*.example.com",
+            CredentialHelper {
+                command: script.to_string_lossy().into_owned(),
+                args: vec![state.to_string_lossy().into_owned()],
+            },
+        );
+
+        assert_eq!(
+            storage.get("conda.example.com").unwrap(),
+            Some(Authentication::BearerToken(String::from("secret")))
+        );
+        assert_eq!(storage.get("other.example.com").unwrap(), None);
+        assert_eq!(storage.get("prefix.dev").unwrap(), None);
+        assert!(storage.get("broken.example.com").is_err());
+
+        // Results are cached, also if the helper has no credentials, and
+        // scoped keys are never passed to the helper.
+        assert_eq!(storage.get("other.example.com").unwrap(), None);
+        assert_eq!(storage.get("conda.example.com/channel/*").unwrap(), None);
+        let log = state.with_extension("log");
+        assert_eq!(fs_err::read_to_string(&log).unwrap().lines().count(), 3);
+
+        // Forgotten results are requested again.
+        storage.forget("other.example.com");
+        assert_eq!(storage.get("other.example.com").unwrap(), None);
+        assert_eq!(fs_err::read_to_string(&log).unwrap().lines().count(), 4);
+
+        let auth = Authentication::BasicHTTP {
+            username: String::from("user"),
+            password: String::from("password"),
+        };
+        storage.store("conda.example.com", &auth).unwrap();
+        assert_eq!(
+            fs_err::read_to_string(&state).unwrap().trim(),
+            r#"{"host":"conda.example.com","authentication":{"BasicHTTP":{"username":"user","password":"password"}}}"#
+        );
+
+        storage.delete("conda.example.com").unwrap();
+        assert!(!state.exists());
+
+        // Hosts without a helper are left to the other backends.
+        assert!(matches!(
+            storage.store("prefix.dev", &auth),
+            Err(AuthenticationStorageError::CredentialHelperError(
+                CredentialHelperError::NotHandled(_)
+            ))
+        ));
+        assert!(storage.delete("prefix.dev").is_err());
+        assert!(storage.store("conda.example.com/channel", &auth).is_err());
+        assert!(!state.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credential_helper_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("helper.sh");
+        fs_err::write(&script, "#!/bin/sh\nexec sleep 60\n").unwrap();
+        fs_err::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let storage = CredentialHelperStorage::new()
+            .with_timeout(Duration::from_millis(200))
+            .with_helper(
+                "*",
+                CredentialHelper {
+                    command: script.to_string_lossy().into_owned(),
+                    args: Vec::new(),
+                },
+            );
+
+        let start = Instant::now();
+        assert!(matches!(
+            storage.get("conda.example.com"),
+            Err(AuthenticationStorageError::CredentialHelperError(
+                CredentialHelperError::Timeout(..)
+            ))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -151,6 +151,14 @@ impl StorageBackend for FileStorage {
             Ok(())
         }
     }
+
+    fn list(&self) -> Result<Vec<String>, AuthenticationStorageError> {
+        Ok(self.read_json()?.into_keys().collect())
+    }
+
+    fn can_list(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
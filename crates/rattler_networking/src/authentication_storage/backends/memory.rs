@@ -66,6 +66,18 @@ impl StorageBackend for MemoryStorage {
         store.remove(host);
         Ok(())
     }
+
+    fn list(&self) -> Result<Vec<String>, AuthenticationStorageError> {
+        let store = self
+            .store
+            .lock()
+            .map_err(|_err| MemoryStorageError::LockError)?;
+        Ok(store.keys().cloned().collect())
+    }
+
+    fn can_list(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -1,5 +1,7 @@
 //! Multiple backends for storing authentication data.
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod credential_helper;
 pub mod file;
 #[cfg(feature = "keyring")]
 pub mod keyring;
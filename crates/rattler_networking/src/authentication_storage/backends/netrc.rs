@@ -115,6 +115,14 @@ impl StorageBackend for NetRcStorage {
             Err(err) => Err(err.into()),
         }
     }
+
+    fn list(&self) -> Result<Vec<String>, AuthenticationStorageError> {
+        Ok(self.machines.keys().cloned().collect())
+    }
+
+    fn can_list(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -24,6 +24,12 @@ pub enum AuthenticationStorageError {
     /// An error occurred when accessing the memory storage
     #[error("MemoryStorageError")]
     MemoryStorageError(#[from] crate::authentication_storage::backends::memory::MemoryStorageError),
+    /// An error occurred when invoking a credential helper
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error(transparent)]
+    CredentialHelperError(
+        #[from] crate::authentication_storage::backends::credential_helper::CredentialHelperError,
+    ),
 }
 
 /// A trait that defines the interface for authentication storage backends
@@ -40,4 +46,23 @@ pub trait StorageBackend: std::fmt::Debug {
 
     /// Delete the authentication information for the given host
     fn delete(&self, host: &str) -> Result<(), AuthenticationStorageError>;
+
+    /// List the hosts (or scopes) for which authentication information is
+    /// stored. Backends that cannot enumerate their entries, like the
+    /// keyring, return an empty list.
+    fn list(&self) -> Result<Vec<String>, AuthenticationStorageError> {
+        Ok(Vec::new())
+    }
+
+    /// Returns true if [`Self::list`] returns all keys that are stored in
+    /// the backend. Credentials that are scoped to a path are only stored in
+    /// and looked up from backends that can list their keys.
+    fn can_list(&self) -> bool {
+        false
+    }
+
+    /// Forget cached information about the given host so the next
+    /// [`Self::get`] consults the source of the credentials again. Backends
+    /// without a cache don't need to implement this.
+    fn forget(&self, _host: &str) {}
 }
@@ -3,7 +3,7 @@
 use anyhow::{anyhow, Result};
 use reqwest::IntoUrl;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     sync::{Arc, Mutex},
 };
 use url::Url;
@@ -30,14 +30,20 @@ pub struct AuthenticationStorage {
     /// Authentication backends
     pub backends: Vec<Arc<dyn StorageBackend + Send + Sync>>,
     cache: Arc<Mutex<HashMap<String, Option<Authentication>>>>,
+    index: Arc<Mutex<Option<Arc<KeyIndex>>>>,
 }
 
+/// The keys that are stored in each backend, `None` for backends that cannot
+/// list their keys. Lookups only query a backend for keys that it lists.
+type KeyIndex = Vec<Option<HashSet<String>>>;
+
 impl AuthenticationStorage {
     /// Create a new authentication storage with no backends
     pub fn empty() -> Self {
         Self {
             backends: vec![],
             cache: Arc::new(Mutex::new(HashMap::new())),
+            index: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -73,10 +79,63 @@ impl AuthenticationStorage {
         Ok(storage)
     }
 
+    /// Create a new authentication storage with the default backends,
+    /// preceded by the credential helpers that are configured in the given
+    /// configuration.
+    #[cfg(all(feature = "rattler_config", not(target_arch = "wasm32")))]
+    pub fn from_config<T>(
+        config: &rattler_config::config::ConfigBase<T>,
+    ) -> Result<Self, AuthenticationStorageError> {
+        use super::backends::credential_helper::CredentialHelperStorage;
+
+        let mut storage = Self::from_env_and_defaults()?;
+        let helpers = CredentialHelperStorage::from_config(&config.credential_helpers);
+        if !helpers.is_empty() {
+            storage.backends.insert(0, Arc::new(helpers));
+        }
+        Ok(storage)
+    }
+
     /// Add a new storage backend to the authentication storage
     /// (backends are tried in the order they are added)
     pub fn add_backend(&mut self, backend: Arc<dyn StorageBackend + Send + Sync>) {
         self.backends.push(backend);
+        self.invalidate_index();
+    }
+
+    /// Returns the keys that are stored in each backend, listing them if
+    /// that did not happen yet.
+    fn index(&self) -> Arc<KeyIndex> {
+        let mut index = self.index.lock().unwrap();
+        match index.as_ref() {
+            Some(index) if index.len() == self.backends.len() => index.clone(),
+            _ => {
+                let keys: Arc<KeyIndex> = Arc::new(
+                    self.backends
+                        .iter()
+                        .map(|backend| {
+                            if !backend.can_list() {
+                                return None;
+                            }
+                            match backend.list() {
+                                Ok(keys) => Some(keys.into_iter().collect()),
+                                Err(e) => {
+                                    // Fall back to querying the backend.
+                                    tracing::warn!("Error listing credentials from backend: {}", e);
+                                    None
+                                }
+                            }
+                        })
+                        .collect(),
+                );
+                *index = Some(keys.clone());
+                keys
+            }
+        }
+    }
+
+    fn invalidate_index(&self) {
+        *self.index.lock().unwrap() = None;
     }
 
     /// Store the given authentication information for the given host
@@ -85,12 +144,18 @@ impl AuthenticationStorage {
             let mut cache = self.cache.lock().unwrap();
             cache.insert(host.to_string(), Some(authentication.clone()));
         }
+        self.invalidate_index();
 
         for backend in &self.backends {
+            if is_scoped(host) && !backend.can_list() {
+                continue;
+            }
             #[allow(unused_variables)]
             if let Err(error) = backend.store(host, authentication) {
                 #[cfg(feature = "keyring")]
-                if let AuthenticationStorageError::KeyringStorageError(
+                if is_not_handled(&error) {
+                    // The backend is not responsible for this host.
+                } else if let AuthenticationStorageError::KeyringStorageError(
                     KeyringAuthenticationStorageError::StorageError(_),
                 ) = error
                 {
@@ -118,7 +183,16 @@ impl AuthenticationStorage {
             }
         }
 
-        for backend in &self.backends {
+        let index = self.index();
+        let mut any_failed = false;
+        for (backend, keys) in self.backends.iter().zip(index.iter()) {
+            let stored = match keys {
+                Some(keys) => keys.contains(host),
+                None => !is_scoped(host),
+            };
+            if !stored {
+                continue;
+            }
             match backend.get(host) {
                 Ok(Some(auth)) => {
                     let mut cache = self.cache.lock().unwrap();
@@ -127,6 +201,7 @@ impl AuthenticationStorage {
                 }
                 Ok(None) => {}
                 Err(_e) => {
+                    any_failed = true;
                     #[cfg(feature = "keyring")]
                     if let AuthenticationStorageError::KeyringStorageError(
                         KeyringAuthenticationStorageError::StorageError(_),
@@ -140,6 +215,14 @@ impl AuthenticationStorage {
             }
         }
 
+        // Remember misses as well, backends that cannot list their keys are
+        // queried for the host and its wildcards on every lookup. Failures
+        // might be transient so those are retried on the next lookup.
+        if !any_failed {
+            let mut cache = self.cache.lock().unwrap();
+            cache.insert(host.to_string(), None);
+        }
+
         Ok(None)
     }
 
@@ -150,72 +233,101 @@ impl AuthenticationStorage {
     /// E.g. if credentials are stored for `*.prefix.dev` and the
     /// given URL is `https://repo.prefix.dev`, the credentials
     /// for `*.prefix.dev` will be returned.
+    ///
+    /// Credentials can also be scoped to a path, see
+    /// [`Self::get_with_key_by_url`].
     pub fn get_by_url<U: IntoUrl>(
         &self,
         url: U,
     ) -> Result<(Url, Option<Authentication>), reqwest::Error> {
-        let url = url.into_url()?;
-        let Some(host) = url.host_str() else {
-            return Ok((url, None));
-        };
-
-        match self.get(host) {
-            Ok(None) => {}
-            Err(_) => return Ok((url, None)),
-            Ok(Some(credentials)) => return Ok((url, Some(credentials))),
-        };
+        let (url, found) = self.get_with_key_by_url(url)?;
+        Ok((url, found.map(|(_, auth)| auth)))
+    }
 
-        // S3 protocol URLs need to be treated separately since they follow a different schema
-        if url.scheme() == "s3" {
-            let mut current_url = url.clone();
-            loop {
-                match self.get(current_url.as_str()) {
-                    Ok(None) => {
-                        let possible_rest =
-                            current_url.as_str().rsplit_once('/').map(|(rest, _)| rest);
-
-                        match possible_rest {
-                            Some(rest) => {
-                                if let Ok(new_url) = Url::parse(rest) {
-                                    current_url = new_url;
-                                } else {
-                                    return Ok((url, None));
-                                }
-                            }
-                            _ => return Ok((url, None)), // No more subpaths to check
-                        }
-                    }
-                    Ok(Some(credentials)) => return Ok((url, Some(credentials))),
-                    Err(_) => return Ok((url, None)),
-                }
+    /// Retrieve the authentication information for the given URL together
+    /// with the key under which it is stored.
+    ///
+    /// Credentials are looked up with longest-match precedence, i.e. for
+    /// `https://host/conda/team-a/noarch/repodata.json` the following keys
+    /// are tried in order:
+    ///
+    /// - path scopes from the longest to the shortest path, both with and
+    ///   without the scheme and optionally ending in `/*`, e.g.
+    ///   `https://host/conda/team-a/*` or `host/conda/team-a`
+    /// - the host itself (`host`)
+    /// - wildcard hosts from the longest to the shortest domain (`*.host`)
+    ///
+    /// The keys are matched against the keys that the backends list, so a
+    /// backend is only queried for keys that it stores. Backends that cannot
+    /// list their keys (like the keyring) only store plain and wildcard
+    /// hosts and are only queried for those.
+    pub fn get_with_key_by_url<U: IntoUrl>(
+        &self,
+        url: U,
+    ) -> Result<(Url, Option<(String, Authentication)>), reqwest::Error> {
+        let url = url.into_url()?;
+        let index = self.index();
+        let any_unlisted = index.iter().any(Option::is_none);
+        for key in lookup_keys(&url) {
+            let stored = (any_unlisted && !is_scoped(&key))
+                || index.iter().flatten().any(|keys| keys.contains(&key));
+            if !stored {
+                continue;
+            }
+            match self.get(&key) {
+                Ok(Some(credentials)) => return Ok((url, Some((key, credentials)))),
+                Ok(None) => {}
+                Err(_) => return Ok((url, None)),
             }
         }
+        Ok((url, None))
+    }
 
-        // Check for credentials under e.g. `*.prefix.dev`
-        let Some(mut domain) = url.domain() else {
-            return Ok((url, None));
-        };
-
-        loop {
-            let wildcard_host = format!("*.{domain}");
-
-            let Ok(credentials) = self.get(&wildcard_host) else {
-                return Ok((url, None));
-            };
-
-            if let Some(credentials) = credentials {
-                return Ok((url, Some(credentials)));
+    /// Forget the cached authentication information for all keys that
+    /// [`Self::get_with_key_by_url`] tries for `url`, so the next lookup
+    /// queries the backends again. This is used when a server rejects the
+    /// credentials (or the lack thereof) for a request.
+    pub fn forget_url(&self, url: &Url) {
+        let keys = lookup_keys(url);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for key in &keys {
+                cache.remove(key);
             }
+        }
+        for backend in &self.backends {
+            for key in &keys {
+                backend.forget(key);
+            }
+        }
+    }
 
-            let possible_rest = domain.split_once('.').map(|(_, rest)| rest);
-
-            match possible_rest {
-                Some(rest) => {
-                    domain = rest;
+    /// List all keys for which authentication information is stored, with
+    /// the stored authentication information. If a key is stored in
+    /// multiple backends the first backend wins.
+    ///
+    /// Entries in backends that cannot be enumerated (like the keyring)
+    /// are not included.
+    pub fn list(&self) -> Result<BTreeMap<String, Authentication>> {
+        let mut entries = BTreeMap::new();
+        for backend in &self.backends {
+            let keys = match backend.list() {
+                Ok(keys) => keys,
+                Err(e) => {
+                    tracing::warn!("Error listing credentials from backend: {}", e);
+                    continue;
+                }
+            };
+            for key in keys {
+                if entries.contains_key(&key) {
+                    continue;
+                }
+                if let Ok(Some(auth)) = backend.get(&key) {
+                    entries.insert(key, auth);
                 }
-                _ => return Ok((url, None)), // No more subdomains to check
             }
         }
+        Ok(entries)
     }
 
     /// Delete the authentication information for the given host
@@ -224,14 +336,20 @@ impl AuthenticationStorage {
             let mut cache = self.cache.lock().unwrap();
             cache.insert(host.to_string(), None);
         }
+        self.invalidate_index();
 
         let mut all_failed = true;
 
         for backend in &self.backends {
+            if is_scoped(host) && !backend.can_list() {
+                continue;
+            }
             #[allow(unused_variables)]
             if let Err(error) = backend.delete(host) {
                 #[cfg(feature = "keyring")]
-                if let AuthenticationStorageError::KeyringStorageError(
+                if is_not_handled(&error) {
+                    // The backend is not responsible for this host.
+                } else if let AuthenticationStorageError::KeyringStorageError(
                     KeyringAuthenticationStorageError::StorageError(_),
                 ) = error
                 {
@@ -251,3 +369,260 @@ impl AuthenticationStorage {
         }
     }
 }
+
+/// Returns the keys under which credentials for `url` may be stored, from the
+/// most to the least specific.
+fn lookup_keys(url: &Url) -> Vec<String> {
+    let Some(host) = url.host_str() else {
+        return Vec::new();
+    };
+    let origin = match url.port() {
+        Some(port) => format!("{}://{host}:{port}", url.scheme()),
+        None => format!("{}://{host}", url.scheme()),
+    };
+
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    let mut keys = Vec::new();
+    for len in (0..=segments.len()).rev() {
+        let path: String = segments[..len].iter().map(|s| format!("/{s}")).collect();
+        for base in [&origin, host] {
+            keys.push(format!("{base}{path}/*"));
+            keys.push(format!("{base}{path}/"));
+            if !path.is_empty() || base != host {
+                keys.push(format!("{base}{path}"));
+            }
+        }
+    }
+    keys.push(host.to_string());
+
+    // Check for credentials under e.g. `*.prefix.dev`
+    if let Some(mut domain) = url.domain() {
+        loop {
+            keys.push(format!("*.{domain}"));
+            match domain.split_once('.') {
+                Some((_, rest)) => domain = rest,
+                None => break,
+            }
+        }
+    }
+
+    keys
+}
+
+/// Returns true if `key` is scoped to a path.
+fn is_scoped(key: &str) -> bool {
+    key.contains('/')
+}
+
+/// Returns true if a backend declined a request because it is not responsible
+/// for the host, which is not worth reporting.
+#[cfg(feature = "keyring")]
+fn is_not_handled(error: &AuthenticationStorageError) -> bool {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        matches!(
+            error,
+            AuthenticationStorageError::CredentialHelperError(
+                super::backends::credential_helper::CredentialHelperError::NotHandled(_)
+            )
+        )
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = error;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::authentication_storage::backends::memory::MemoryStorage;
+
+    #[test]
+    fn test_lookup_keys() {
+        let url = Url::parse("https://artifacts.corp.example/conda/team-a/noarch").unwrap();
+        insta::assert_debug_snapshot!(lookup_keys(&url));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_credential_helper_falls_through() {
+        use crate::authentication_storage::backends::credential_helper::{
+            CredentialHelper, CredentialHelperStorage,
+        };
+
+        let memory = Arc::new(MemoryStorage::new());
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(Arc::new(CredentialHelperStorage::new().with_helper(
+            "conda.example.com",
+            CredentialHelper {
+                command: String::from("rattler-test-missing-helper"),
+                args: Vec::new(),
+            },
+        )));
+        storage.add_backend(memory.clone());
+
+        // Credentials for hosts without a helper end up in the next backend.
+        let token = Authentication::BearerToken(String::from("token"));
+        storage.store("prefix.dev", &token).unwrap();
+        assert_eq!(memory.get("prefix.dev").unwrap(), Some(token));
+        storage.delete("prefix.dev").unwrap();
+        assert_eq!(memory.get("prefix.dev").unwrap(), None);
+    }
+
+    /// A backend that counts how often it is queried.
+    #[derive(Debug, Default)]
+    struct CountingStorage {
+        inner: MemoryStorage,
+        can_list: bool,
+        gets: std::sync::atomic::AtomicUsize,
+    }
+
+    impl CountingStorage {
+        fn gets(&self) -> usize {
+            self.gets.load(std::sync::atomic::Ordering::Relaxed)
+        }
+    }
+
+    impl StorageBackend for CountingStorage {
+        fn store(
+            &self,
+            host: &str,
+            authentication: &Authentication,
+        ) -> Result<(), AuthenticationStorageError> {
+            self.inner.store(host, authentication)
+        }
+
+        fn get(&self, host: &str) -> Result<Option<Authentication>, AuthenticationStorageError> {
+            self.gets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.inner.get(host)
+        }
+
+        fn delete(&self, host: &str) -> Result<(), AuthenticationStorageError> {
+            self.inner.delete(host)
+        }
+
+        fn list(&self) -> Result<Vec<String>, AuthenticationStorageError> {
+            self.inner.list()
+        }
+
+        fn can_list(&self) -> bool {
+            self.can_list
+        }
+    }
+
+    #[test]
+    fn test_only_stored_keys_are_queried() {
+        let unlisted = Arc::new(CountingStorage::default());
+        let listed = Arc::new(CountingStorage {
+            can_list: true,
+            ..CountingStorage::default()
+        });
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(unlisted.clone());
+        storage.add_backend(listed.clone());
+
+        // The backend that cannot list its keys is only queried for the host
+        // and its wildcards, the other one is not queried at all.
+        let url = "https://artifacts.corp.example/conda/team-a/noarch/repodata.json";
+        assert_eq!(storage.get_by_url(url).unwrap().1, None);
+        assert_eq!(unlisted.gets(), 4);
+        assert_eq!(listed.gets(), 0);
+
+        // A second lookup is answered from the cache.
+        assert_eq!(storage.get_by_url(url).unwrap().1, None);
+        assert_eq!(unlisted.gets(), 4);
+
+        // Scoped credentials are stored in the backend that lists its keys
+        // and are the only key it is queried for.
+        let token = Authentication::BearerToken(String::from("token"));
+        storage
+            .store("artifacts.corp.example/conda/team-a", &token)
+            .unwrap();
+        assert_eq!(unlisted.inner.list().unwrap(), Vec::<String>::new());
+        storage.cache.lock().unwrap().clear();
+        assert_eq!(storage.get_by_url(url).unwrap().1, Some(token.clone()));
+        assert_eq!(listed.gets(), 1);
+        assert_eq!(unlisted.gets(), 4);
+
+        // Storing credentials replaces the cached miss.
+        storage.store("*.corp.example", &token).unwrap();
+        assert_eq!(
+            storage.get_by_url("https://repo.corp.example").unwrap().1,
+            Some(token)
+        );
+    }
+
+    #[test]
+    fn test_scoped_credentials() {
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(Arc::new(MemoryStorage::new()));
+
+        let token = |t: &str| Authentication::BearerToken(t.to_string());
+        storage.store("*.corp.example", &token("corp")).unwrap();
+        storage
+            .store("artifacts.corp.example/conda/team-a", &token("team-a"))
+            .unwrap();
+        storage
+            .store(
+                "https://artifacts.corp.example/conda/team-b/*",
+                &token("team-b"),
+            )
+            .unwrap();
+        storage
+            .store(
+                "https://artifacts.corp.example/conda/team-b/private/*",
+                &token("team-b-private"),
+            )
+            .unwrap();
+
+        let get = |url: &str| storage.get_with_key_by_url(url).unwrap().1;
+
+        assert_eq!(
+            get("https://artifacts.corp.example/conda/team-a/noarch/repodata.json"),
+            Some((
+                "artifacts.corp.example/conda/team-a".to_string(),
+                token("team-a")
+            ))
+        );
+        assert_eq!(
+            get("https://artifacts.corp.example/conda/team-b/noarch/repodata.json")
+                .map(|(_, auth)| auth),
+            Some(token("team-b"))
+        );
+        assert_eq!(
+            get("https://artifacts.corp.example/conda/team-b/private/noarch/repodata.json")
+                .map(|(_, auth)| auth),
+            Some(token("team-b-private"))
+        );
+        // The scheme is part of the scope.
+        assert_eq!(
+            get("http://artifacts.corp.example/conda/team-b/noarch/repodata.json")
+                .map(|(_, auth)| auth),
+            Some(token("corp"))
+        );
+        assert_eq!(
+            get("https://artifacts.corp.example/conda/team-c/noarch/repodata.json")
+                .map(|(key, _)| key),
+            Some("*.corp.example".to_string())
+        );
+        assert_eq!(get("https://prefix.dev/conda-forge"), None);
+
+        assert_eq!(
+            storage.list().unwrap().keys().collect::<Vec<_>>(),
+            [
+                "*.corp.example",
+                "artifacts.corp.example/conda/team-a",
+                "https://artifacts.corp.example/conda/team-b/*",
+                "https://artifacts.corp.example/conda/team-b/private/*",
+            ]
+        );
+    }
+}
@@ -0,0 +1,741 @@
+//! Build HTTP clients from proxy, TLS and mirror settings.
+//!
+//! A [`ClientConfig`] collects all settings that influence how requests are
+//! sent and turns them into a single [`ClientWithMiddleware`] that can be
+//! shared by the repodata gateway, package downloads and the S3 and OCI
+//! middlewares:
+//!
+//! - Proxies are selected per URL scheme and bypassed for hosts that match a
+//!   [`NoProxy`] rule. Credentials for a proxy are taken from the proxy URL or
+//!   looked up in the [`AuthenticationStorage`].
+//! - Custom CA bundles and client certificates (mTLS) are configured per host
+//!   pattern. Requests to matching hosts are sent with a dedicated client.
+//!
+//! Proxy auto-configuration (PAC) scripts are not supported, evaluating them
+//! requires a JavaScript engine. The proxies have to be configured
+//! explicitly, a proxy URL that points to a PAC file is ignored with a
+//! warning.
+
+use std::{collections::HashMap, net::IpAddr, path::PathBuf, sync::Arc};
+
+use reqwest::{Request, Response};
+use reqwest_middleware::{ClientWithMiddleware, Middleware, Next};
+use url::Url;
+
+use crate::{
+    host_pattern::matches_pattern, mirror_middleware::Mirror, Authentication,
+    AuthenticationMiddleware, AuthenticationStorage, MirrorMiddleware, OciMiddleware,
+};
+
+/// Hosts for which no proxy should be used, in the format of the `no_proxy`
+/// environment variable. Every rule is one of:
+///
+/// - `*` to bypass the proxy for all hosts
+/// - a host name like `example.com` (or `.example.com`) that matches the host
+///   and all of its subdomains
+/// - `*.example.com` that only matches subdomains of `example.com`
+/// - an IP address like `10.1.2.3` or `::1`
+/// - a CIDR block like `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoProxy {
+    rules: Vec<NoProxyRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NoProxyRule {
+    All,
+    Domain(String),
+    Subdomains(String),
+    Ip(IpAddr),
+    Cidr(IpAddr, u8),
+}
+
+impl NoProxy {
+    /// Parses the given rules. Invalid rules are ignored with a warning.
+    pub fn parse<I, S>(rules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let rules = rules
+            .into_iter()
+            .filter_map(|rule| {
+                let rule = rule.as_ref().trim().to_lowercase();
+                let parsed = NoProxyRule::parse(&rule);
+                if parsed.is_none() && !rule.is_empty() {
+                    tracing::warn!("ignoring invalid no-proxy rule '{rule}'");
+                }
+                parsed
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Returns true if requests to `host` should bypass the proxy.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = host.to_lowercase();
+        let ip = host.parse::<IpAddr>().ok();
+        self.rules.iter().any(|rule| match rule {
+            NoProxyRule::All => true,
+            NoProxyRule::Domain(domain) => {
+                host == *domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }
+            NoProxyRule::Subdomains(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            NoProxyRule::Ip(addr) => ip == Some(*addr),
+            NoProxyRule::Cidr(network, prefix) => {
+                ip.is_some_and(|ip| cidr_contains(*network, *prefix, ip))
+            }
+        })
+    }
+}
+
+impl NoProxyRule {
+    fn parse(rule: &str) -> Option<Self> {
+        if rule.is_empty() {
+            return None;
+        }
+        if rule == "*" {
+            return Some(Self::All);
+        }
+        if let Some((network, prefix)) = rule.split_once('/') {
+            let network = network.parse().ok()?;
+            let prefix: u8 = prefix.parse().ok()?;
+            let max = if matches!(network, IpAddr::V4(_)) {
+                32
+            } else {
+                128
+            };
+            return (prefix <= max).then_some(Self::Cidr(network, prefix));
+        }
+        let unbracketed = rule.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = unbracketed.parse() {
+            return Some(Self::Ip(ip));
+        }
+        if let Some(domain) = rule.strip_prefix("*.") {
+            return Some(Self::Subdomains(domain.to_string()));
+        }
+        Some(Self::Domain(rule.trim_start_matches('.').to_string()))
+    }
+}
+
+fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// The proxies to use per URL scheme.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxySettings {
+    /// The proxy for `http` URLs
+    pub http: Option<Url>,
+
+    /// The proxy for `https` URLs
+    pub https: Option<Url>,
+
+    /// Hosts that are accessed without a proxy
+    pub no_proxy: NoProxy,
+}
+
+impl ProxySettings {
+    /// Returns the proxy to use for the given URL, if any.
+    pub fn proxy_for(&self, url: &Url) -> Option<&Url> {
+        let host = url.host_str()?;
+        if self.no_proxy.matches(host) {
+            return None;
+        }
+        match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if no proxy is configured.
+    pub fn is_empty(&self) -> bool {
+        self.http.is_none() && self.https.is_none()
+    }
+}
+
+/// TLS settings for the connections to a host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsSettings {
+    /// A PEM file with additional root certificates to trust
+    pub ca_bundle: Option<PathBuf>,
+
+    /// A PEM file with the client certificate to authenticate with
+    pub client_certificate: Option<PathBuf>,
+
+    /// A PEM file with the private key of the client certificate. If not set
+    /// the key is read from the certificate file.
+    pub client_key: Option<PathBuf>,
+}
+
+/// An error that can occur when building a client from a [`ClientConfig`].
+#[derive(Debug, thiserror::Error)]
+pub enum ClientConfigError {
+    /// A certificate or key file could not be read
+    #[error("failed to read {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    /// A CA bundle contains an invalid certificate
+    #[error("invalid certificate in {0}")]
+    InvalidCertificate(PathBuf, #[source] reqwest::Error),
+
+    /// The client certificate or its key is invalid
+    #[error("invalid client certificate or key in {0}")]
+    InvalidIdentity(PathBuf, #[source] reqwest::Error),
+
+    /// The TLS backend needs the client key in a separate file
+    #[error("the client certificate for {0} requires a separate client key")]
+    MissingClientKey(String),
+
+    /// Custom TLS settings require a TLS backend
+    #[error("custom TLS settings require the `rustls-tls` or `native-tls` feature")]
+    TlsNotSupported,
+
+    /// The client could not be built
+    #[error("failed to build the HTTP client")]
+    Client(#[source] reqwest::Error),
+}
+
+/// The settings to build an HTTP client from.
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// The proxies to use
+    pub proxy: ProxySettings,
+
+    /// TLS settings per host pattern (e.g. `conda.example.com` or
+    /// `*.example.com`)
+    pub tls: Vec<(String, TlsSettings)>,
+
+    /// Whether to skip the verification of TLS certificates
+    pub tls_no_verify: bool,
+
+    /// Mirrors per channel URL
+    pub mirrors: HashMap<Url, Vec<Mirror>>,
+
+    /// The configuration of S3 buckets
+    #[cfg(feature = "s3")]
+    pub s3: HashMap<String, crate::s3_middleware::S3Config>,
+}
+
+impl ClientConfig {
+    /// Creates the client settings from a configuration.
+    #[cfg(feature = "rattler_config")]
+    pub fn from_config<T>(config: &rattler_config::config::ConfigBase<T>) -> Self {
+        let proxy = &config.proxy_config;
+        Self {
+            proxy: ProxySettings {
+                http: proxy.http.clone(),
+                https: proxy.https.clone(),
+                no_proxy: NoProxy::parse(&proxy.non_proxy_hosts),
+            },
+            tls: config
+                .tls
+                .0
+                .iter()
+                .map(|(pattern, options)| {
+                    (
+                        pattern.clone(),
+                        TlsSettings {
+                            ca_bundle: options.ca_bundle.clone(),
+                            client_certificate: options.client_certificate.clone(),
+                            client_key: options.client_key.clone(),
+                        },
+                    )
+                })
+                .collect(),
+            tls_no_verify: config.tls_no_verify.unwrap_or(false),
+            mirrors: config
+                .mirrors
+                .iter()
+                .map(|(url, mirrors)| {
+                    let mirrors = mirrors
+                        .iter()
+                        .map(|mirror| Mirror {
+                            url: mirror.clone(),
+                            no_zstd: false,
+                            no_bz2: false,
+                            no_jlap: false,
+                            max_failures: None,
+                        })
+                        .collect();
+                    (url.clone(), mirrors)
+                })
+                .collect(),
+            #[cfg(feature = "s3")]
+            s3: crate::s3_middleware::compute_s3_config(&config.s3_options.0),
+        }
+    }
+
+    /// Returns a client builder with the proxy and the global TLS settings
+    /// applied. Credentials for proxies that do not contain credentials in
+    /// their URL are looked up in `auth_storage`.
+    ///
+    /// Responses are not decompressed automatically, the repodata gateway
+    /// decodes compressed responses itself.
+    pub fn client_builder(
+        &self,
+        auth_storage: &AuthenticationStorage,
+    ) -> Result<reqwest::ClientBuilder, ClientConfigError> {
+        let mut builder = reqwest::Client::builder().no_gzip();
+        if !self.proxy.is_empty() {
+            let mut proxies = self.proxy.clone();
+            proxies.http = proxies
+                .http
+                .and_then(reject_pac)
+                .map(|url| authenticate_proxy(url, auth_storage));
+            proxies.https = proxies
+                .https
+                .and_then(reject_pac)
+                .map(|url| authenticate_proxy(url, auth_storage));
+            builder = builder.proxy(reqwest::Proxy::custom(move |url| {
+                proxies.proxy_for(url).cloned()
+            }));
+        }
+        if self.tls_no_verify {
+            builder = tls::accept_invalid_certs(builder)?;
+        }
+        Ok(builder)
+    }
+
+    /// Builds a client with the proxy, TLS and mirror settings and the
+    /// authentication, OCI, S3 and GCS middlewares.
+    pub fn build(
+        &self,
+        auth_storage: AuthenticationStorage,
+    ) -> Result<ClientWithMiddleware, ClientConfigError> {
+        self.build_with_middleware(auth_storage, Vec::new())
+    }
+
+    /// Like [`Self::build`] but the given middlewares run before all other
+    /// middlewares, e.g. a
+    /// [`RecordingMiddleware`](crate::RecordingMiddleware).
+    pub fn build_with_middleware(
+        &self,
+        auth_storage: AuthenticationStorage,
+        middleware: Vec<Arc<dyn Middleware>>,
+    ) -> Result<ClientWithMiddleware, ClientConfigError> {
+        let client = self
+            .client_builder(&auth_storage)?
+            .build()
+            .map_err(ClientConfigError::Client)?;
+
+        let host_clients = self
+            .tls
+            .iter()
+            .map(|(pattern, settings)| {
+                let builder = tls::apply(self.client_builder(&auth_storage)?, pattern, settings)?;
+                let client = builder.build().map_err(ClientConfigError::Client)?;
+                Ok((pattern.clone(), client))
+            })
+            .collect::<Result<Vec<_>, ClientConfigError>>()?;
+
+        let mut builder = reqwest_middleware::ClientBuilder::new(client);
+        for middleware in middleware {
+            builder = builder.with_arc(middleware);
+        }
+        if !self.mirrors.is_empty() {
+            builder = builder.with(MirrorMiddleware::from_map(self.mirrors.clone()));
+        }
+        builder = builder
+            .with_arc(Arc::new(AuthenticationMiddleware::from_auth_storage(
+                auth_storage.clone(),
+            )))
+            .with(OciMiddleware::new(auth_storage.clone()));
+        #[cfg(feature = "s3")]
+        {
+            builder = builder.with(crate::S3Middleware::new(self.s3.clone(), auth_storage));
+        }
+        #[cfg(feature = "gcs")]
+        {
+            builder = builder.with(crate::GCSMiddleware);
+        }
+        if !host_clients.is_empty() {
+            builder = builder.with(HostClientMiddleware::new(host_clients));
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Returns `None` with a warning if `url` points to a proxy auto-configuration
+/// script, which is not supported.
+fn reject_pac(url: Url) -> Option<Url> {
+    if url.path().to_lowercase().ends_with(".pac") {
+        tracing::warn!(
+            "ignoring the proxy {url}, proxy auto-configuration scripts are not supported"
+        );
+        return None;
+    }
+    Some(url)
+}
+
+/// Adds the credentials from the authentication storage to a proxy URL that
+/// does not contain credentials.
+fn authenticate_proxy(mut url: Url, auth_storage: &AuthenticationStorage) -> Url {
+    if !url.username().is_empty() {
+        return url;
+    }
+    match auth_storage.get_by_url(url.clone()) {
+        Ok((_, Some(Authentication::BasicHTTP { username, password }))) => {
+            if url.set_username(&username).is_err() || url.set_password(Some(&password)).is_err() {
+                tracing::warn!("cannot add credentials to the proxy URL {url}");
+            }
+        }
+        Ok((_, Some(auth))) => {
+            tracing::warn!(
+                "ignoring {} credentials for proxy {url}, only basic authentication is supported",
+                auth.method()
+            );
+        }
+        Ok((_, None)) | Err(_) => {}
+    }
+    url
+}
+
+/// A [`Middleware`] that sends requests to matching hosts with a dedicated
+/// client, e.g. one that presents a client certificate. Requests to other
+/// hosts are passed on. This middleware must be the last middleware of a
+/// client because it does not call the remaining middlewares for matching
+/// hosts.
+#[derive(Debug, Clone)]
+pub struct HostClientMiddleware {
+    clients: Vec<(String, reqwest::Client)>,
+}
+
+impl HostClientMiddleware {
+    /// Creates a new middleware from host patterns (e.g. `conda.example.com`
+    /// or `*.example.com`) and their clients. If multiple patterns match a
+    /// host the most specific one is used.
+    pub fn new(clients: Vec<(String, reqwest::Client)>) -> Self {
+        Self { clients }
+    }
+
+    fn client_for(&self, host: &str) -> Option<&reqwest::Client> {
+        self.clients
+            .iter()
+            .filter(|(pattern, _)| matches_pattern(pattern, host))
+            .max_by_key(|(pattern, _)| {
+                if pattern == host {
+                    usize::MAX
+                } else {
+                    pattern.len()
+                }
+            })
+            .map(|(_, client)| client)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for HostClientMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        match req.url().host_str().and_then(|host| self.client_for(host)) {
+            Some(client) => client
+                .execute(req)
+                .await
+                .map_err(reqwest_middleware::Error::Reqwest),
+            None => next.run(req, extensions).await,
+        }
+    }
+}
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+mod tls {
+    use std::path::Path;
+
+    use reqwest::{Certificate, ClientBuilder, Identity};
+
+    use super::{ClientConfigError, TlsSettings};
+
+    fn read(path: &Path) -> Result<Vec<u8>, ClientConfigError> {
+        fs_err::read(path).map_err(|e| ClientConfigError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn accept_invalid_certs(
+        builder: ClientBuilder,
+    ) -> Result<ClientBuilder, ClientConfigError> {
+        Ok(builder.danger_accept_invalid_certs(true))
+    }
+
+    pub fn apply(
+        mut builder: ClientBuilder,
+        pattern: &str,
+        settings: &TlsSettings,
+    ) -> Result<ClientBuilder, ClientConfigError> {
+        if let Some(path) = &settings.ca_bundle {
+            let certificates = Certificate::from_pem_bundle(&read(path)?)
+                .map_err(|e| ClientConfigError::InvalidCertificate(path.clone(), e))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(path) = &settings.client_certificate {
+            let certificate = read(path)?;
+            let key = settings.client_key.as_deref().map(read).transpose()?;
+            builder = builder.identity(identity(certificate, key).map_err(|e| match e {
+                IdentityError::Invalid(e) => ClientConfigError::InvalidIdentity(path.clone(), e),
+                IdentityError::MissingKey => {
+                    ClientConfigError::MissingClientKey(pattern.to_string())
+                }
+            })?);
+            #[cfg(feature = "rustls-tls")]
+            {
+                builder = builder.use_rustls_tls();
+            }
+        }
+        Ok(builder)
+    }
+
+    enum IdentityError {
+        Invalid(reqwest::Error),
+        #[cfg_attr(feature = "rustls-tls", allow(dead_code))]
+        MissingKey,
+    }
+
+    #[cfg(feature = "rustls-tls")]
+    fn identity(mut certificate: Vec<u8>, key: Option<Vec<u8>>) -> Result<Identity, IdentityError> {
+        if let Some(key) = key {
+            certificate.push(b'\n');
+            certificate.extend(key);
+        }
+        Identity::from_pem(&certificate).map_err(IdentityError::Invalid)
+    }
+
+    #[cfg(not(feature = "rustls-tls"))]
+    fn identity(certificate: Vec<u8>, key: Option<Vec<u8>>) -> Result<Identity, IdentityError> {
+        let key = key.ok_or(IdentityError::MissingKey)?;
+        Identity::from_pkcs8_pem(&certificate, &key).map_err(IdentityError::Invalid)
+    }
+}
+
+#[cfg(not(any(feature = "rustls-tls", feature = "native-tls")))]
+mod tls {
+    use reqwest::ClientBuilder;
+
+    use super::{ClientConfigError, TlsSettings};
+
+    pub fn accept_invalid_certs(
+        _builder: ClientBuilder,
+    ) -> Result<ClientBuilder, ClientConfigError> {
+        Err(ClientConfigError::TlsNotSupported)
+    }
+
+    pub fn apply(
+        _builder: ClientBuilder,
+        _pattern: &str,
+        _settings: &TlsSettings,
+    ) -> Result<ClientBuilder, ClientConfigError> {
+        Err(ClientConfigError::TlsNotSupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::IntoFuture,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::State, http::HeaderMap, http::Uri, routing::get, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::authentication_storage::backends::memory::MemoryStorage;
+
+    #[test]
+    fn test_no_proxy() {
+        let no_proxy = NoProxy::parse([
+            "localhost",
+            ".internal.example",
+            "*.corp.example",
+            "10.0.0.0/8",
+            "192.168.1.1",
+            "fd00::/8",
+            "not a cidr/99",
+        ]);
+
+        assert!(no_proxy.matches("localhost"));
+        assert!(no_proxy.matches("LOCALHOST"));
+        assert!(no_proxy.matches("internal.example"));
+        assert!(no_proxy.matches("conda.internal.example"));
+        assert!(!no_proxy.matches("corp.example"));
+        assert!(no_proxy.matches("conda.corp.example"));
+        assert!(!no_proxy.matches("evilcorp.example"));
+        assert!(no_proxy.matches("10.1.2.3"));
+        assert!(!no_proxy.matches("11.1.2.3"));
+        assert!(no_proxy.matches("192.168.1.1"));
+        assert!(!no_proxy.matches("192.168.1.2"));
+        assert!(no_proxy.matches("[fd12::1]"));
+        assert!(!no_proxy.matches("[fe80::1]"));
+        assert!(!no_proxy.matches("conda.anaconda.org"));
+
+        assert!(NoProxy::parse(["*"]).matches("conda.anaconda.org"));
+        assert!(NoProxy::parse(["0.0.0.0/0"]).matches("1.2.3.4"));
+    }
+
+    #[test]
+    fn test_proxy_for() {
+        let proxy = ProxySettings {
+            http: Some(Url::parse("http://http-proxy:3128").unwrap()),
+            https: Some(Url::parse("http://https-proxy:3128").unwrap()),
+            no_proxy: NoProxy::parse(["*.corp.example"]),
+        };
+        let proxy_for = |url: &str| {
+            proxy
+                .proxy_for(&Url::parse(url).unwrap())
+                .map(|url| url.host_str().unwrap().to_string())
+        };
+        assert_eq!(
+            proxy_for("http://conda.anaconda.org"),
+            Some("http-proxy".to_string())
+        );
+        assert_eq!(
+            proxy_for("https://conda.anaconda.org"),
+            Some("https-proxy".to_string())
+        );
+        assert_eq!(proxy_for("https://conda.corp.example"), None);
+        assert_eq!(proxy_for("s3://bucket"), None);
+    }
+
+    #[test]
+    fn test_reject_pac() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert_eq!(reject_pac(url("http://proxy.corp.example/proxy.PAC")), None);
+        assert_eq!(
+            reject_pac(url("http://proxy.corp.example:3128")),
+            Some(url("http://proxy.corp.example:3128"))
+        );
+    }
+
+    #[test]
+    fn test_host_client_selection() {
+        let client = |_| reqwest::Client::new();
+        let middleware = HostClientMiddleware::new(vec![
+            ("*".to_string(), client(0)),
+            ("*.corp.example".to_string(), client(1)),
+            ("conda.corp.example".to_string(), client(2)),
+        ]);
+        let index = |host: &str| {
+            let selected = middleware.client_for(host).unwrap();
+            middleware
+                .clients
+                .iter()
+                .position(|(_, client)| std::ptr::eq(client, selected))
+                .unwrap()
+        };
+        assert_eq!(index("conda.corp.example"), 2);
+        assert_eq!(index("other.corp.example"), 1);
+        assert_eq!(index("prefix.dev"), 0);
+    }
+
+    #[test]
+    fn test_missing_ca_bundle() {
+        let config = ClientConfig {
+            tls: vec![(
+                "conda.corp.example".to_string(),
+                TlsSettings {
+                    ca_bundle: Some(PathBuf::from("/does/not/exist.pem")),
+                    ..TlsSettings::default()
+                },
+            )],
+            ..ClientConfig::default()
+        };
+        let err = config.build(AuthenticationStorage::empty()).unwrap_err();
+        assert!(matches!(err, ClientConfigError::Io(..)), "{err:?}");
+    }
+
+    type Seen = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    async fn proxy(State(seen): State<Seen>, uri: Uri, headers: HeaderMap) -> &'static str {
+        let auth = headers
+            .get("proxy-authorization")
+            .map(|value| value.to_str().unwrap().to_string());
+        seen.lock().unwrap().push((uri.to_string(), auth));
+        "proxied"
+    }
+
+    async fn serve(router: Router) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        Url::parse(&format!("http://{address}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_with_credentials_from_storage() {
+        let seen = Seen::default();
+        let proxy_url = serve(Router::new().fallback(get(proxy)).with_state(seen.clone())).await;
+        let direct_url = serve(Router::new().route("/direct", get(|| async { "direct" }))).await;
+
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(Arc::new(MemoryStorage::new()));
+        storage
+            .store(
+                "127.0.0.1",
+                &Authentication::BasicHTTP {
+                    username: "user".to_string(),
+                    password: "secret".to_string(),
+                },
+            )
+            .unwrap();
+
+        let config = ClientConfig {
+            proxy: ProxySettings {
+                http: Some(proxy_url),
+                https: None,
+                no_proxy: NoProxy::parse(["localhost"]),
+            },
+            ..ClientConfig::default()
+        };
+        let client = config.build(storage).unwrap();
+
+        let body = client
+            .get("http://conda.corp.example/conda-forge/noarch/repodata.json")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "proxied");
+        assert_eq!(
+            seen.lock().unwrap().as_slice(),
+            [(
+                "http://conda.corp.example/conda-forge/noarch/repodata.json".to_string(),
+                Some("Basic dXNlcjpzZWNyZXQ=".to_string())
+            )]
+        );
+
+        let direct = format!("http://localhost:{}/direct", direct_url.port().unwrap());
+        let body = client
+            .get(direct)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "direct");
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,32 @@
+//! Matching of host names against the host patterns that are used in the
+//! configuration, e.g. for credential helpers and per-host TLS settings.
+
+/// Returns true if `host` matches `pattern`. A pattern is either a host name,
+/// `*.domain` to match all subdomains of `domain`, or `*` to match any host.
+pub(crate) fn matches_pattern(pattern: &str, host: &str) -> bool {
+    if pattern == "*" || pattern == host {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*", "conda.example.com"));
+        assert!(matches_pattern("conda.example.com", "conda.example.com"));
+        assert!(matches_pattern("*.example.com", "conda.example.com"));
+        assert!(matches_pattern("*.example.com", "a.b.example.com"));
+        assert!(!matches_pattern("*.example.com", "example.com"));
+        assert!(!matches_pattern("*.example.com", "badexample.com"));
+        assert!(!matches_pattern("conda.example.com", "example.com"));
+    }
+}
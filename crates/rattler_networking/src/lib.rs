@@ -3,9 +3,13 @@
 //! Networking utilities for Rattler, specifically authenticating requests
 pub use authentication_middleware::AuthenticationMiddleware;
 pub use authentication_storage::{authentication::Authentication, storage::AuthenticationStorage};
+#[cfg(not(target_arch = "wasm32"))]
+pub use client_config::ClientConfig;
 pub use lazy_client::LazyClient;
 pub use mirror_middleware::MirrorMiddleware;
 pub use oci_middleware::OciMiddleware;
+#[cfg(not(target_arch = "wasm32"))]
+pub use recording_middleware::RecordingMiddleware;
 
 #[cfg(feature = "gcs")]
 pub mod gcs_middleware;
@@ -19,8 +23,15 @@ pub use s3_middleware::S3Middleware;
 
 pub mod authentication_middleware;
 pub mod authentication_storage;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client_config;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod host_pattern;
 mod lazy_client;
 pub mod mirror_middleware;
+pub mod oauth;
 pub mod oci_middleware;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recording_middleware;
 pub mod retry_policies;
@@ -1,7 +1,15 @@
 //! Middleware to handle mirrors
+//!
+//! Requests to a url that has mirrors configured are redirected to one of the
+//! mirrors. If a mirror fails with a connection error, a timeout or a server
+//! error, the same request is retried against the next mirror.
 use std::{
     collections::HashMap,
-    sync::atomic::{self, AtomicUsize},
+    sync::{
+        atomic::{self, AtomicBool, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 use http::Extensions;
@@ -10,6 +18,11 @@ use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next, Result};
 use url::Url;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use wasmtimer::std::Instant;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Settings for the specific mirror (e.g. no zstd or bz2 support)
 pub struct Mirror {
@@ -25,35 +38,157 @@ pub struct Mirror {
     pub max_failures: Option<usize>,
 }
 
-#[allow(dead_code)]
+impl Mirror {
+    /// Returns false if the mirror is configured to not serve the given path.
+    fn supports(&self, path: &str) -> bool {
+        !(path.ends_with(".json.zst") && self.no_zstd
+            || path.ends_with(".json.bz2") && self.no_bz2
+            || path.ends_with(".jlap") && self.no_jlap)
+    }
+}
+
+/// A trait to get notified about which mirrors are used to serve requests.
+pub trait MirrorReporter: Send + Sync {
+    /// Called when a response for `request_url` was received from `mirror`.
+    fn on_mirror_served(&self, _request_url: &Url, _mirror: &Url) {}
+
+    /// Called when the request for `request_url` to `mirror` failed.
+    fn on_mirror_failed(&self, _request_url: &Url, _mirror: &Url) {}
+
+    /// Called when a mirror that was considered dead passed a health probe.
+    fn on_mirror_recovered(&self, _mirror: &Url) {}
+}
+
+/// Added to the extensions of a [`Response`] that was served by a mirror.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedByMirror(pub Url);
+
+/// Smoothing factor of the exponentially weighted moving averages.
+const EWMA_ALPHA: f64 = 0.3;
+
+/// The time after which a health probe of a dead mirror is given up.
+#[cfg(not(target_arch = "wasm32"))]
+const DEFAULT_HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Responses smaller than this are ignored when measuring throughput because
+/// they are dominated by latency.
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_THROUGHPUT_SAMPLE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct MirrorStats {
+    /// Average time until the response headers are received in seconds.
+    latency: Option<f64>,
+    /// Average throughput of response bodies in bytes per second.
+    throughput: Option<f64>,
+    /// The last time the mirror failed or was probed.
+    last_checked: Option<Instant>,
+}
+
+fn ewma(average: Option<f64>, sample: f64) -> f64 {
+    average.map_or(sample, |average| {
+        EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * average
+    })
+}
+
 struct MirrorState {
     failures: AtomicUsize,
+    stats: Mutex<MirrorStats>,
+    /// Whether a health probe of the mirror is running.
+    probing: AtomicBool,
     mirror: Mirror,
 }
 
 impl MirrorState {
     pub fn add_failure(&self) {
         self.failures.fetch_add(1, atomic::Ordering::Relaxed);
+        self.stats.lock().unwrap().last_checked = Some(Instant::now());
+    }
+
+    fn failures(&self) -> usize {
+        self.failures.load(atomic::Ordering::Relaxed)
+    }
+
+    fn is_dead(&self) -> bool {
+        self.mirror
+            .max_failures
+            .is_some_and(|max| self.failures() >= max)
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.latency = Some(ewma(stats.latency, latency.as_secs_f64()));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn record_throughput(&self, bytes: usize, elapsed: Duration) {
+        if bytes < MIN_THROUGHPUT_SAMPLE_BYTES || elapsed.is_zero() {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        stats.throughput = Some(ewma(stats.throughput, bytes as f64 / elapsed.as_secs_f64()));
+    }
+
+    /// The expected time in seconds to download a 1 MiB file from this
+    /// mirror. Mirrors without measurements are assumed to be instant so
+    /// that they are tried at least once.
+    fn expected_cost(&self) -> f64 {
+        let stats = self.stats.lock().unwrap();
+        stats.latency.unwrap_or(0.0)
+            + stats
+                .throughput
+                .map_or(0.0, |throughput| 1024.0 * 1024.0 / throughput)
     }
+
+    /// Returns true if the mirror is dead, it has been at least `interval`
+    /// since it last failed or was probed and no probe is running.
+    fn needs_probe(&self, interval: Duration) -> bool {
+        self.is_dead()
+            && !self.probing.load(atomic::Ordering::Acquire)
+            && self
+                .stats
+                .lock()
+                .unwrap()
+                .last_checked
+                .is_none_or(|last| last.elapsed() >= interval)
+    }
+}
+
+/// Settings of the health probes of dead mirrors.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+struct HealthProbe {
+    interval: Duration,
+    timeout: Duration,
+    client: reqwest::Client,
 }
 
 /// Middleware to handle mirrors
 pub struct MirrorMiddleware {
-    mirror_map: HashMap<Url, Vec<MirrorState>>,
+    mirror_map: HashMap<Url, Vec<Arc<MirrorState>>>,
     sorted_keys: Vec<(String, Url)>,
+    failover: bool,
+    track_performance: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    health_probe: Option<HealthProbe>,
+    reporter: Option<Arc<dyn MirrorReporter>>,
 }
 
 impl MirrorMiddleware {
     /// Create a new `MirrorMiddleware` from a map of mirrors
     pub fn from_map(mirror_map: HashMap<Url, Vec<Mirror>>) -> Self {
-        let mirror_map: HashMap<Url, Vec<MirrorState>> = mirror_map
+        let mirror_map: HashMap<Url, Vec<Arc<MirrorState>>> = mirror_map
             .into_iter()
             .map(|(url, mirrors)| {
                 let mirrors = mirrors
                     .into_iter()
-                    .map(|mirror| MirrorState {
-                        failures: AtomicUsize::new(0),
-                        mirror,
+                    .map(|mirror| {
+                        Arc::new(MirrorState {
+                            failures: AtomicUsize::new(0),
+                            stats: Mutex::default(),
+                            probing: AtomicBool::new(false),
+                            mirror,
+                        })
                     })
                     .collect();
                 (url, mirrors)
@@ -70,6 +205,78 @@ impl MirrorMiddleware {
         Self {
             mirror_map,
             sorted_keys,
+            failover: true,
+            track_performance: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            health_probe: None,
+            reporter: None,
+        }
+    }
+
+    /// Sets whether a request that fails with a connection error, a timeout or
+    /// a server error is retried against the next mirror. Enabled by default.
+    pub fn with_failover(self, failover: bool) -> Self {
+        Self { failover, ..self }
+    }
+
+    /// Sets whether the latency and throughput of the mirrors is measured.
+    /// If enabled, faster mirrors are preferred over slower ones. Disabled by
+    /// default, in which case mirrors are tried in the order they are
+    /// configured.
+    pub fn with_performance_tracking(self, track_performance: bool) -> Self {
+        Self {
+            track_performance,
+            ..self
+        }
+    }
+
+    /// Sets the interval after which a mirror that is considered dead is
+    /// probed again. If the probe succeeds the mirror is used again.
+    ///
+    /// Probes are `HEAD` requests to the mirror that run in the background
+    /// on the current tokio runtime, requests are never delayed by them.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_health_probe_interval(self, interval: Duration) -> Self {
+        let probe = self.health_probe.unwrap_or_else(|| HealthProbe {
+            interval,
+            timeout: DEFAULT_HEALTH_PROBE_TIMEOUT,
+            client: reqwest::Client::new(),
+        });
+        Self {
+            health_probe: Some(HealthProbe { interval, ..probe }),
+            ..self
+        }
+    }
+
+    /// Sets the time after which a health probe is given up, 10 seconds by
+    /// default. Only used if a health probe interval is set.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_health_probe_timeout(self, timeout: Duration) -> Self {
+        Self {
+            health_probe: self
+                .health_probe
+                .map(|probe| HealthProbe { timeout, ..probe }),
+            ..self
+        }
+    }
+
+    /// Sets the client that sends the health probes. Only used if a health
+    /// probe interval is set.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_health_probe_client(self, client: reqwest::Client) -> Self {
+        Self {
+            health_probe: self
+                .health_probe
+                .map(|probe| HealthProbe { client, ..probe }),
+            ..self
+        }
+    }
+
+    /// Sets a reporter that is notified about which mirror served a request.
+    pub fn with_reporter(self, reporter: Arc<dyn MirrorReporter>) -> Self {
+        Self {
+            reporter: Some(reporter),
+            ..self
         }
     }
 
@@ -78,23 +285,73 @@ impl MirrorMiddleware {
     pub fn keys(&self) -> &[(String, Url)] {
         &self.sorted_keys
     }
-}
 
-fn select_mirror(mirrors: &[MirrorState]) -> Option<&MirrorState> {
-    let mut min_failures = usize::MAX;
-    let mut min_failures_index = usize::MAX;
+    /// Returns the mirrors that are alive in the order they should be tried.
+    fn order_mirrors<'m>(&self, mirrors: &'m [Arc<MirrorState>]) -> Vec<&'m Arc<MirrorState>> {
+        let alive = mirrors.iter().filter(|mirror| !mirror.is_dead());
+        if self.track_performance {
+            alive
+                .sorted_by(|a, b| {
+                    a.expected_cost()
+                        .total_cmp(&b.expected_cost())
+                        .then(a.failures().cmp(&b.failures()))
+                })
+                .collect()
+        } else {
+            alive.sorted_by_key(|mirror| mirror.failures()).collect()
+        }
+    }
 
-    for (i, mirror) in mirrors.iter().enumerate() {
-        let failures = mirror.failures.load(atomic::Ordering::Relaxed);
-        if failures < min_failures && mirror.mirror.max_failures.is_none_or(|max| failures < max) {
-            min_failures = failures;
-            min_failures_index = i;
+    /// Starts a health probe in the background for every dead mirror that is
+    /// due for one. Mirrors that respond are revived.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn probe_dead_mirrors(&self, mirrors: &[Arc<MirrorState>]) {
+        let Some(probe) = &self.health_probe else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        for mirror in mirrors
+            .iter()
+            .filter(|mirror| mirror.needs_probe(probe.interval))
+        {
+            if mirror.probing.swap(true, atomic::Ordering::AcqRel) {
+                continue;
+            }
+            let mirror = Arc::clone(mirror);
+            let probe = probe.clone();
+            let reporter = self.reporter.clone();
+            runtime.spawn(async move {
+                let url = &mirror.mirror.url;
+                let request = probe.client.head(url.clone()).send();
+                match tokio::time::timeout(probe.timeout, request).await {
+                    Ok(Ok(response)) if !response.status().is_server_error() => {
+                        tracing::info!("mirror {url} is reachable again");
+                        mirror.failures.store(0, atomic::Ordering::Relaxed);
+                        if let Some(reporter) = &reporter {
+                            reporter.on_mirror_recovered(url);
+                        }
+                    }
+                    _ => tracing::debug!("mirror {url} is still unreachable"),
+                }
+                mirror.stats.lock().unwrap().last_checked = Some(Instant::now());
+                mirror.probing.store(false, atomic::Ordering::Release);
+            });
         }
     }
-    if min_failures_index == usize::MAX {
-        return None;
+}
+
+/// Returns true if the request should be retried against another mirror.
+fn should_failover(res: &Result<Response>) -> bool {
+    match res {
+        Ok(res) => res.status().is_server_error(),
+        #[cfg(not(target_arch = "wasm32"))]
+        Err(reqwest_middleware::Error::Reqwest(err)) => err.is_connect() || err.is_timeout(),
+        #[cfg(target_arch = "wasm32")]
+        Err(reqwest_middleware::Error::Reqwest(err)) => err.is_request() || err.is_timeout(),
+        Err(reqwest_middleware::Error::Middleware(_)) => false,
     }
-    Some(&mirrors[min_failures_index])
 }
 
 #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
@@ -102,58 +359,97 @@ fn select_mirror(mirrors: &[MirrorState]) -> Option<&MirrorState> {
 impl Middleware for MirrorMiddleware {
     async fn handle(
         &self,
-        mut req: Request,
+        req: Request,
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> Result<Response> {
         let url_str = req.url().to_string();
 
         for (key, url) in self.keys() {
-            if let Some(url_rest) = url_str.strip_prefix(key) {
-                let url_rest = url_rest.trim_start_matches('/');
-                // replace the key with the mirror
-                let mirrors = self.mirror_map.get(url).unwrap();
-                let selected_mirror = select_mirror(mirrors);
-
-                let Some(selected_mirror) = selected_mirror else {
-                    return Ok(create_404_response(req.url(), "All mirrors are dead"));
-                };
+            let Some(url_rest) = url_str.strip_prefix(key) else {
+                continue;
+            };
+            let url_rest = url_rest.trim_start_matches('/');
+            let mirrors = self.mirror_map.get(url).unwrap();
+
+            #[cfg(not(target_arch = "wasm32"))]
+            self.probe_dead_mirrors(mirrors);
+            let candidates = self.order_mirrors(mirrors);
+            let Some(first) = candidates.first().copied() else {
+                return Ok(create_404_response(req.url(), "All mirrors are dead"));
+            };
+
+            // Skip the mirrors that do not support the file type.
+            let candidates = candidates
+                .into_iter()
+                .filter(|mirror| mirror.mirror.supports(url_rest))
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                return Ok(create_404_response(
+                    &first.mirror.url.join(url_rest).unwrap(),
+                    "Mirror does not support the requested file type",
+                ));
+            }
 
-                let mirror = &selected_mirror.mirror;
-                let selected_url = mirror.url.join(url_rest).unwrap();
+            let request_url = req.url().clone();
+            let mut req = Some(req);
+            for (idx, mirror) in candidates.iter().enumerate() {
+                let Some(current) = req.take() else {
+                    break;
+                };
 
-                // Short-circuit if the mirror does not support the file type
-                if url_rest.ends_with(".json.zst") && mirror.no_zstd {
-                    return Ok(create_404_response(
-                        &selected_url,
-                        "Mirror does not support zstd",
-                    ));
-                }
-                if url_rest.ends_with(".json.bz2") && mirror.no_bz2 {
-                    return Ok(create_404_response(
-                        &selected_url,
-                        "Mirror does not support bz2",
-                    ));
-                }
-                if url_rest.ends_with(".jlap") && mirror.no_jlap {
-                    return Ok(create_404_response(
-                        &selected_url,
-                        "Mirror does not support jlap",
-                    ));
+                // Keep a copy of the request around in case we need to fail over.
+                // Requests with a streaming body cannot be cloned.
+                let is_last = idx + 1 == candidates.len();
+                if self.failover && !is_last {
+                    req = current.try_clone();
                 }
 
-                *req.url_mut() = selected_url;
-                let res = next.run(req, extensions).await;
+                let mut current = current;
+                let mirror_url = mirror.mirror.url.join(url_rest).unwrap();
+                *current.url_mut() = mirror_url;
 
-                // record a failure if the request failed so we can avoid the mirror in the future
-                match res.as_ref() {
-                    Ok(res) if res.status().is_server_error() => selected_mirror.add_failure(),
-                    Err(_) => selected_mirror.add_failure(),
-                    _ => {}
+                let start = Instant::now();
+                let res = next.clone().run(current, extensions).await;
+
+                // Record a failure if the request failed so we can avoid the
+                // mirror in the future.
+                let failed = match res.as_ref() {
+                    Ok(res) => res.status().is_server_error(),
+                    Err(_) => true,
+                };
+                if failed {
+                    mirror.add_failure();
+                    if let Some(reporter) = &self.reporter {
+                        reporter.on_mirror_failed(&request_url, &mirror.mirror.url);
+                    }
+                    if req.is_some() && should_failover(&res) {
+                        tracing::debug!(
+                            "request to mirror {} failed, trying the next mirror",
+                            mirror.mirror.url
+                        );
+                        continue;
+                    }
+                    return res;
                 }
 
-                return res;
+                let mut res = res?;
+                if self.track_performance {
+                    mirror.record_latency(start.elapsed());
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        res = track_throughput(res, Arc::clone(mirror));
+                    }
+                }
+                if let Some(reporter) = &self.reporter {
+                    reporter.on_mirror_served(&request_url, &mirror.mirror.url);
+                }
+                res.extensions_mut()
+                    .insert(ServedByMirror(mirror.mirror.url.clone()));
+                return Ok(res);
             }
+
+            unreachable!("at least one mirror is always tried");
         }
 
         // if we don't have a mirror, we don't need to do anything
@@ -161,6 +457,77 @@ impl Middleware for MirrorMiddleware {
     }
 }
 
+/// Wraps the body of the response to measure the throughput of the mirror.
+#[cfg(not(target_arch = "wasm32"))]
+fn track_throughput(res: Response, mirror: Arc<MirrorState>) -> Response {
+    use reqwest::ResponseBuilderExt;
+
+    let url = res.url().clone();
+    let (mut parts, body) = http::Response::<reqwest::Body>::from(res).into_parts();
+
+    // Converting the response loses the url, restore it.
+    let (url_parts, ()) = http::Response::builder()
+        .url(url)
+        .body(())
+        .expect("a response without a body is always valid")
+        .into_parts();
+    parts.extensions.extend(url_parts.extensions);
+
+    let body = ThroughputBody {
+        inner: body,
+        mirror,
+        bytes: 0,
+        start: Instant::now(),
+    };
+    Response::from(http::Response::from_parts(parts, reqwest::Body::wrap(body)))
+}
+
+/// A response body that records the throughput of a mirror once it has been
+/// read completely.
+#[cfg(not(target_arch = "wasm32"))]
+struct ThroughputBody {
+    inner: reqwest::Body,
+    mirror: Arc<MirrorState>,
+    bytes: usize,
+    start: Instant,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl http_body::Body for ThroughputBody {
+    type Data = <reqwest::Body as http_body::Body>::Data;
+    type Error = <reqwest::Body as http_body::Body>::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<std::result::Result<http_body::Frame<Self::Data>, Self::Error>>>
+    {
+        let this = &mut *self;
+        let poll = std::pin::Pin::new(&mut this.inner).poll_frame(cx);
+        match &poll {
+            std::task::Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.bytes += data.len();
+                }
+            }
+            std::task::Poll::Ready(None) => {
+                this.mirror
+                    .record_throughput(this.bytes, this.start.elapsed());
+            }
+            _ => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub(crate) fn create_404_response(url: &Url, body: &str) -> Response {
     use reqwest::ResponseBuilderExt;
@@ -180,7 +547,15 @@ pub(crate) fn create_404_response(_url: &Url, _body: &str) -> Response {
 
 #[cfg(test)]
 mod test {
-    use std::{future::IntoFuture, net::SocketAddr};
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
 
     use axum::{extract::State, http::StatusCode, routing::get, Router};
     use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
@@ -188,7 +563,7 @@ mod test {
 
     use crate::MirrorMiddleware;
 
-    use super::Mirror;
+    use super::{Mirror, MirrorReporter, ServedByMirror};
 
     async fn count(State(name): State<String>) -> String {
         format!("Hi from counter: {name}")
@@ -266,7 +641,7 @@ mod test {
             vec![mirror_setting(addr_1), mirror_setting(addr_2)],
         );
 
-        let middleware = MirrorMiddleware::from_map(mirror_map.clone());
+        let middleware = MirrorMiddleware::from_map(mirror_map.clone()).with_failover(false);
         let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
             .with(middleware)
             .build();
@@ -293,6 +668,232 @@ mod test {
         assert!(res.text().await.unwrap() == "Hi from counter: server 2");
     }
 
+    #[derive(Default)]
+    struct RecordingReporter {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl MirrorReporter for RecordingReporter {
+        fn on_mirror_served(&self, _request_url: &Url, mirror: &Url) {
+            self.events.lock().unwrap().push(format!("served {mirror}"));
+        }
+
+        fn on_mirror_failed(&self, _request_url: &Url, mirror: &Url) {
+            self.events.lock().unwrap().push(format!("failed {mirror}"));
+        }
+
+        fn on_mirror_recovered(&self, mirror: &Url) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("recovered {mirror}"));
+        }
+    }
+
+    /// Returns the url of a port that nothing is listening on.
+    async fn closed_port() -> Url {
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        format!("http://{}:{}", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mirror_failover() {
+        let addr_1 = test_server("server 1", true).await;
+        let addr_2 = closed_port().await;
+        let addr_3 = test_server("server 3", false).await;
+
+        let mut mirror_map = std::collections::HashMap::new();
+        mirror_map.insert(
+            "http://bla.com".parse().unwrap(),
+            vec![
+                mirror_setting(addr_1.clone()),
+                mirror_setting(addr_2.clone()),
+                mirror_setting(addr_3.clone()),
+            ],
+        );
+
+        let reporter = Arc::new(RecordingReporter::default());
+        let middleware = MirrorMiddleware::from_map(mirror_map).with_reporter(reporter.clone());
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build();
+
+        // The server error and the connection error are retried on the next mirror
+        // within the same request.
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(
+            res.extensions().get::<ServedByMirror>(),
+            Some(&ServedByMirror(addr_3.clone()))
+        );
+        assert_eq!(res.text().await.unwrap(), "Hi from counter: server 3");
+        assert_eq!(
+            *reporter.events.lock().unwrap(),
+            vec![
+                format!("failed {addr_1}"),
+                format!("failed {addr_2}"),
+                format!("served {addr_3}"),
+            ]
+        );
+    }
+
+    async fn toggle_server(broken: Arc<AtomicBool>) -> Url {
+        let router = Router::new()
+            .route(
+                "/count",
+                get(|State(broken): State<Arc<AtomicBool>>| async move {
+                    if broken.load(Ordering::SeqCst) {
+                        Err(StatusCode::INTERNAL_SERVER_ERROR)
+                    } else {
+                        Ok("Hi from toggle server")
+                    }
+                }),
+            )
+            .route(
+                "/",
+                get(|State(broken): State<Arc<AtomicBool>>| async move {
+                    if broken.load(Ordering::SeqCst) {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .with_state(broken);
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        format!("http://{}:{}", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mirror_health_probe() {
+        let broken = Arc::new(AtomicBool::new(true));
+        let addr_1 = toggle_server(broken.clone()).await;
+        let addr_2 = test_server("server 2", false).await;
+
+        let mut mirror_map = std::collections::HashMap::new();
+        mirror_map.insert(
+            "http://bla.com".parse().unwrap(),
+            vec![
+                Mirror {
+                    max_failures: Some(1),
+                    ..mirror_setting(addr_1.clone())
+                },
+                mirror_setting(addr_2),
+            ],
+        );
+
+        let reporter = Arc::new(RecordingReporter::default());
+        let middleware = MirrorMiddleware::from_map(mirror_map)
+            .with_health_probe_interval(Duration::ZERO)
+            .with_reporter(reporter.clone());
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build();
+
+        // The first mirror fails and is considered dead.
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "Hi from counter: server 2");
+
+        // The probe still fails, so the request goes to the second mirror.
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "Hi from counter: server 2");
+
+        // Once the first mirror is healthy again a probe in the background
+        // revives it.
+        broken.store(false, Ordering::SeqCst);
+        let recovered = format!("recovered {addr_1}");
+        while !reporter.events.lock().unwrap().contains(&recovered) {
+            let res = client.get("http://bla.com/count").send().await.unwrap();
+            assert!(res.status().is_success());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "Hi from toggle server");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_health_probe_does_not_block_requests() {
+        // A mirror that is dead and does not answer health probes.
+        let router = Router::new()
+            .route(
+                "/count",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .route("/", get(std::future::pending::<()>));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr_1: Url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let addr_2 = test_server("server 2", false).await;
+
+        let mut mirror_map = std::collections::HashMap::new();
+        mirror_map.insert(
+            "http://bla.com".parse().unwrap(),
+            vec![
+                Mirror {
+                    max_failures: Some(1),
+                    ..mirror_setting(addr_1)
+                },
+                mirror_setting(addr_2),
+            ],
+        );
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(
+                MirrorMiddleware::from_map(mirror_map)
+                    .with_health_probe_interval(Duration::ZERO)
+                    .with_health_probe_timeout(Duration::from_millis(100)),
+            )
+            .build();
+
+        for _ in 0..3 {
+            let res = tokio::time::timeout(
+                Duration::from_secs(5),
+                client.get("http://bla.com/count").send(),
+            )
+            .await
+            .expect("the request is not delayed by the probe")
+            .unwrap();
+            assert_eq!(res.text().await.unwrap(), "Hi from counter: server 2");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirror_performance_tracking() {
+        let addr_1 = test_server("server 1", false).await;
+        let addr_2 = test_server("server 2", false).await;
+
+        let mut mirror_map = std::collections::HashMap::new();
+        mirror_map.insert(
+            "http://bla.com".parse().unwrap(),
+            vec![mirror_setting(addr_1), mirror_setting(addr_2.clone())],
+        );
+
+        let middleware = MirrorMiddleware::from_map(mirror_map).with_performance_tracking(true);
+
+        // Pretend the first mirror is very slow.
+        let mirrors = middleware.mirror_map.values().next().unwrap();
+        mirrors[0].record_latency(Duration::from_secs(10));
+
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build();
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert_eq!(res.url(), &addr_2.join("count").unwrap());
+        assert_eq!(res.text().await.unwrap(), "Hi from counter: server 2");
+    }
+
     #[test]
     fn test_mirror_sort() {
         let keys: Vec<Url> = vec![
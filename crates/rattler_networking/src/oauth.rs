@@ -0,0 +1,485 @@
+//! OAuth 2.0 / OIDC support.
+//!
+//! Credentials are obtained with the device authorization grant (RFC 8628),
+//! which works for command line tools that cannot receive a redirect. The
+//! resulting [`Authentication::OAuth`] credentials carry a refresh token that
+//! [`crate::AuthenticationMiddleware`] uses to renew the access token before
+//! it expires.
+
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::{header::CONTENT_TYPE, HeaderValue, Method};
+use reqwest::{Request, Response};
+use serde::Deserialize;
+use url::Url;
+#[cfg(target_arch = "wasm32")]
+use wasmtimer::std::{SystemTime, UNIX_EPOCH};
+
+use crate::Authentication;
+
+/// The grant type of the device authorization grant.
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Access tokens are refreshed when they expire within this margin.
+pub const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// An error that can occur while talking to an identity provider.
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    /// The request could not be sent or the server returned an error.
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+
+    /// The request failed in another middleware.
+    #[error(transparent)]
+    Middleware(#[from] reqwest_middleware::Error),
+
+    /// An endpoint of the identity provider is not a valid URL.
+    #[error(transparent)]
+    ParseError(#[from] url::ParseError),
+
+    /// The identity provider rejected the request.
+    #[error("the identity provider returned `{error}`{}", .description.as_ref().map(|d| format!(": {d}")).unwrap_or_default())]
+    Provider {
+        /// The error code, e.g. `invalid_grant`
+        error: String,
+        /// A human readable description of the error
+        description: Option<String>,
+    },
+
+    /// The identity provider does not advertise a device authorization
+    /// endpoint.
+    #[error("the identity provider does not support the device authorization grant")]
+    DeviceFlowUnsupported,
+
+    /// The credentials do not contain a refresh token.
+    #[error("the credentials cannot be refreshed")]
+    NotRefreshable,
+}
+
+/// The subset of the OIDC provider metadata that is needed to log in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    /// The endpoint to exchange grants for tokens
+    pub token_endpoint: Url,
+
+    /// The endpoint to start the device authorization grant
+    pub device_authorization_endpoint: Option<Url>,
+}
+
+/// Fetches the metadata of an OIDC provider from
+/// `<issuer>/.well-known/openid-configuration`.
+pub async fn discover(
+    client: &reqwest::Client,
+    issuer: &Url,
+) -> Result<ProviderMetadata, OAuthError> {
+    let mut url = issuer.clone();
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    let url = url.join(".well-known/openid-configuration")?;
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// The response of the device authorization endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceAuthorization {
+    /// The code that is used to poll for the tokens
+    pub device_code: String,
+
+    /// The code the user has to enter at the verification URI
+    pub user_code: String,
+
+    /// The URI the user has to visit
+    pub verification_uri: String,
+
+    /// The verification URI that already contains the user code
+    pub verification_uri_complete: Option<String>,
+
+    /// The number of seconds after which the device code expires
+    pub expires_in: u64,
+
+    /// The minimum number of seconds between polling requests
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+/// Starts the device authorization grant.
+pub async fn request_device_authorization(
+    client: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    scopes: &[String],
+) -> Result<DeviceAuthorization, OAuthError> {
+    let endpoint = metadata
+        .device_authorization_endpoint
+        .clone()
+        .ok_or(OAuthError::DeviceFlowUnsupported)?;
+    let mut params = vec![("client_id", client_id.to_string())];
+    if !scopes.is_empty() {
+        params.push(("scope", scopes.join(" ")));
+    }
+    let response = client.execute(form_request(endpoint, &params)).await?;
+    if !response.status().is_success() {
+        return Err(provider_error(response).await);
+    }
+    Ok(response.json().await?)
+}
+
+/// The result of polling the token endpoint during the device authorization
+/// grant.
+#[derive(Debug)]
+pub enum DevicePoll {
+    /// The user has not completed the authorization yet.
+    Pending,
+
+    /// The client is polling too fast and should increase the interval.
+    SlowDown,
+
+    /// The user authorized the device.
+    Complete(Authentication),
+}
+
+/// Polls the token endpoint once to check whether the user completed the
+/// device authorization.
+pub async fn poll_device_token(
+    client: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    device: &DeviceAuthorization,
+) -> Result<DevicePoll, OAuthError> {
+    let request = form_request(
+        metadata.token_endpoint.clone(),
+        &[
+            ("grant_type", DEVICE_CODE_GRANT_TYPE.to_string()),
+            ("device_code", device.device_code.clone()),
+            ("client_id", client_id.to_string()),
+        ],
+    );
+    let response = client.execute(request).await?;
+    match token_response(response, &metadata.token_endpoint, client_id, None).await {
+        Ok(auth) => Ok(DevicePoll::Complete(auth)),
+        Err(OAuthError::Provider { error, .. }) if error == "authorization_pending" => {
+            Ok(DevicePoll::Pending)
+        }
+        Err(OAuthError::Provider { error, .. }) if error == "slow_down" => Ok(DevicePoll::SlowDown),
+        Err(e) => Err(e),
+    }
+}
+
+/// Builds the request that exchanges the refresh token of `auth` for a new
+/// access token.
+pub fn refresh_request(auth: &Authentication) -> Result<Request, OAuthError> {
+    let Authentication::OAuth {
+        refresh_token: Some(refresh_token),
+        token_endpoint,
+        client_id,
+        ..
+    } = auth
+    else {
+        return Err(OAuthError::NotRefreshable);
+    };
+    Ok(form_request(
+        Url::parse(token_endpoint)?,
+        &[
+            ("grant_type", String::from("refresh_token")),
+            ("refresh_token", refresh_token.clone()),
+            ("client_id", client_id.clone()),
+        ],
+    ))
+}
+
+/// Parses the response to a [`refresh_request`]. Identity providers may omit
+/// the refresh token when it is not rotated, in which case the previous one is
+/// kept.
+pub async fn refresh_response(
+    response: Response,
+    previous: &Authentication,
+) -> Result<Authentication, OAuthError> {
+    let Authentication::OAuth {
+        refresh_token,
+        token_endpoint,
+        client_id,
+        ..
+    } = previous
+    else {
+        return Err(OAuthError::NotRefreshable);
+    };
+    token_response(
+        response,
+        &Url::parse(token_endpoint)?,
+        client_id,
+        refresh_token.clone(),
+    )
+    .await
+}
+
+/// Exchanges the refresh token of `auth` for a new access token.
+pub async fn refresh(
+    client: &reqwest::Client,
+    auth: &Authentication,
+) -> Result<Authentication, OAuthError> {
+    let response = client.execute(refresh_request(auth)?).await?;
+    refresh_response(response, auth).await
+}
+
+/// Returns the current time as seconds since the unix epoch.
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn form_request(url: Url, params: &[(&str, String)]) -> Request {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    let mut request = Request::new(Method::POST, url);
+    request.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
+    );
+    *request.body_mut() = Some(body.into());
+    request
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+async fn provider_error(response: Response) -> OAuthError {
+    let status = response.status();
+    match response.json::<ErrorResponse>().await {
+        Ok(error) => OAuthError::Provider {
+            error: error.error,
+            description: error.error_description,
+        },
+        Err(_) => OAuthError::Provider {
+            error: status.to_string(),
+            description: None,
+        },
+    }
+}
+
+async fn token_response(
+    response: Response,
+    token_endpoint: &Url,
+    client_id: &str,
+    previous_refresh_token: Option<String>,
+) -> Result<Authentication, OAuthError> {
+    if !response.status().is_success() {
+        return Err(provider_error(response).await);
+    }
+    let token: TokenResponse = response.json().await?;
+    Ok(Authentication::OAuth {
+        access_token: token.access_token,
+        refresh_token: token.refresh_token.or(previous_refresh_token),
+        expires_at: token.expires_in.map(|expires_in| now() + expires_in as i64),
+        token_endpoint: token_endpoint.to_string(),
+        client_id: client_id.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{future::IntoFuture, net::SocketAddr, sync::Arc, sync::Mutex};
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{header::CONTENT_TYPE, StatusCode},
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Router,
+    };
+    use serde_json::json;
+    use url::Url;
+
+    use super::{discover, poll_device_token, refresh, request_device_authorization, DevicePoll};
+    use crate::Authentication;
+
+    /// Number of times the token endpoint answered `authorization_pending`
+    /// before completing the device flow.
+    type PendingPolls = Arc<Mutex<usize>>;
+
+    fn json_response(status: StatusCode, body: serde_json::Value) -> Response {
+        (
+            status,
+            [(CONTENT_TYPE, "application/json")],
+            body.to_string(),
+        )
+            .into_response()
+    }
+
+    async fn token(State(pending): State<PendingPolls>, body: Bytes) -> Response {
+        let param = |name: &str| {
+            url::form_urlencoded::parse(&body)
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        match param("grant_type").as_deref() {
+            Some("urn:ietf:params:oauth:grant-type:device_code") => {
+                let mut pending = pending.lock().unwrap();
+                if *pending > 0 {
+                    *pending -= 1;
+                    return json_response(
+                        StatusCode::BAD_REQUEST,
+                        json!({"error": "authorization_pending"}),
+                    );
+                }
+                assert_eq!(param("device_code").as_deref(), Some("device-code"));
+                json_response(
+                    StatusCode::OK,
+                    json!({
+                        "access_token": "access-1",
+                        "refresh_token": "refresh-1",
+                        "expires_in": 3600,
+                        "token_type": "Bearer"
+                    }),
+                )
+            }
+            Some("refresh_token") if param("refresh_token").as_deref() == Some("refresh-1") => {
+                json_response(
+                    StatusCode::OK,
+                    json!({"access_token": "access-2", "expires_in": 3600}),
+                )
+            }
+            _ => json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "invalid_grant", "error_description": "unknown grant"}),
+            ),
+        }
+    }
+
+    async fn mock_idp() -> Url {
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let issuer = format!("http://{}:{}/", addr.ip(), addr.port());
+
+        let configuration = json!({
+            "issuer": issuer,
+            "token_endpoint": format!("{issuer}token"),
+            "device_authorization_endpoint": format!("{issuer}device"),
+        });
+        let router = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { json_response(StatusCode::OK, configuration) }),
+            )
+            .route(
+                "/device",
+                post(|| async {
+                    json_response(
+                        StatusCode::OK,
+                        json!({
+                            "device_code": "device-code",
+                            "user_code": "ABCD-EFGH",
+                            "verification_uri": "https://idp.example.com/device",
+                            "expires_in": 600,
+                            "interval": 1
+                        }),
+                    )
+                }),
+            )
+            .route("/token", post(token))
+            .with_state(Arc::new(Mutex::new(2)));
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        issuer.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_device_flow_and_refresh() {
+        let issuer = mock_idp().await;
+        let client = reqwest::Client::new();
+
+        let metadata = discover(&client, &issuer).await.unwrap();
+        let device = request_device_authorization(&client, &metadata, "rattler", &[])
+            .await
+            .unwrap();
+        assert_eq!(device.user_code, "ABCD-EFGH");
+        assert_eq!(device.interval, 1);
+
+        let mut pending = 0;
+        let auth = loop {
+            match poll_device_token(&client, &metadata, "rattler", &device)
+                .await
+                .unwrap()
+            {
+                DevicePoll::Complete(auth) => break auth,
+                DevicePoll::Pending => pending += 1,
+                DevicePoll::SlowDown => unreachable!(),
+            }
+        };
+        assert_eq!(pending, 2);
+        let Authentication::OAuth {
+            access_token,
+            refresh_token,
+            expires_at,
+            ..
+        } = &auth
+        else {
+            panic!("expected OAuth credentials, got {auth:?}");
+        };
+        assert_eq!(access_token, "access-1");
+        assert_eq!(refresh_token.as_deref(), Some("refresh-1"));
+        assert!(expires_at.is_some());
+
+        // The refresh token is kept when the provider does not rotate it.
+        let refreshed = refresh(&client, &auth).await.unwrap();
+        let Authentication::OAuth {
+            access_token,
+            refresh_token,
+            ..
+        } = &refreshed
+        else {
+            panic!("expected OAuth credentials, got {refreshed:?}");
+        };
+        assert_eq!(access_token, "access-2");
+        assert_eq!(refresh_token.as_deref(), Some("refresh-1"));
+
+        // A refresh with an unknown refresh token is rejected.
+        let Authentication::OAuth {
+            token_endpoint,
+            client_id,
+            ..
+        } = auth
+        else {
+            unreachable!()
+        };
+        let invalid = Authentication::OAuth {
+            access_token: String::from("access-1"),
+            refresh_token: Some(String::from("unknown")),
+            expires_at: None,
+            token_endpoint,
+            client_id,
+        };
+        let err = refresh(&client, &invalid).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the identity provider returned `invalid_grant`: unknown grant"
+        );
+    }
+}
@@ -1,38 +1,86 @@
 //! Middleware to handle `oci://` URLs to pull artifacts from an OCI registry
+//!
+//! Registries announce how clients have to authenticate with a
+//! `WWW-Authenticate` challenge on the `/v2/` endpoint. The middleware
+//! answers these challenges with the credentials from an
+//! [`AuthenticationStorage`]: basic credentials are exchanged for a bearer
+//! token at the advertised realm, and bearer tokens stored for a registry are
+//! used as-is.
 use std::{
     collections::HashMap,
     fmt::{Display, Formatter},
+    sync::{Arc, Mutex},
 };
 
+use base64::{prelude::BASE64_STANDARD, Engine};
 use http::{
-    header::{ACCEPT, AUTHORIZATION},
-    Extensions,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE},
+    Extensions, HeaderValue, Method, StatusCode,
 };
 use reqwest::{Request, Response};
-use reqwest_middleware::{Middleware, Next};
-use serde::Deserialize;
+use reqwest_middleware::{ClientWithMiddleware, Middleware, Next};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use url::{ParseError, Url};
 
-use crate::mirror_middleware::create_404_response;
+use crate::{mirror_middleware::create_404_response, Authentication, AuthenticationStorage};
 
+/// The media type of an OCI image manifest.
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// The media type of the (empty) config blob that is attached to conda
+/// artifacts.
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.unknown.config.v1+json";
+
+/// An error that can occur while talking to an OCI registry.
 #[derive(thiserror::Error, Debug)]
-enum OciMiddlewareError {
+pub enum OciMiddlewareError {
+    /// The request could not be sent or the server returned an error.
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    /// The request failed in another middleware.
+    #[error(transparent)]
+    Middleware(#[from] reqwest_middleware::Error),
+
+    /// The URL is not a valid OCI URL.
     #[error("URL parse error: {0}")]
     ParseError(#[from] ParseError),
 
+    /// A header could not be constructed.
     #[error("Invalid header value: {0}")]
     InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
 
+    /// The manifest does not contain a layer for the requested media type.
     #[error("Layer not found")]
     LayerNotFound,
+
+    /// The registry does not contain a manifest for the requested tag.
+    #[error("Manifest not found")]
+    ManifestNotFound,
+
+    /// The registry rejected an upload.
+    #[error("the registry rejected the upload of {0}: {1}")]
+    UploadRejected(String, StatusCode),
+
+    /// The registry did not return the location to upload a blob to.
+    #[error("the registry did not return an upload location")]
+    MissingUploadLocation,
+
+    /// The manifest could not be serialized.
+    #[error("failed to serialize the manifest: {0}")]
+    Serialize(#[from] serde_json::Error),
 }
 
 /// Middleware to handle `oci://` URLs
 #[derive(Default, Debug, Clone)]
-pub struct OciMiddleware;
+pub struct OciMiddleware {
+    auth_storage: Option<AuthenticationStorage>,
+
+    /// The challenges that registries responded with, keyed by host. `None`
+    /// means the registry can be accessed without authentication.
+    challenges: Arc<Mutex<HashMap<String, Option<AuthChallenge>>>>,
+}
 
 /// The action to perform on the OCI registry
 pub enum OciAction {
@@ -46,7 +94,8 @@ pub enum OciAction {
 
 #[derive(Clone, Debug, Deserialize)]
 struct OCIToken {
-    token: String,
+    token: Option<String>,
+    access_token: Option<String>,
 }
 
 impl Display for OciAction {
@@ -59,27 +108,353 @@ impl Display for OciAction {
     }
 }
 
-// [oci://ghcr.io/channel-mirrors/conda-forge]/[osx-arm64/xtensor]
-async fn get_token(url: &OCIUrl, action: OciAction) -> Result<String, OciMiddlewareError> {
-    let token_url = url.token_url(action)?;
+/// A parsed `WWW-Authenticate` challenge of an OCI registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthChallenge {
+    /// The registry accepts basic credentials directly.
+    Basic {
+        /// The protection space of the registry
+        realm: Option<String>,
+    },
+
+    /// The registry requires a bearer token that is obtained from `realm`.
+    Bearer {
+        /// The URL of the token endpoint
+        realm: String,
+        /// The name of the service the token is requested for
+        service: Option<String>,
+        /// The scope that was requested by the registry
+        scope: Option<String>,
+    },
+}
+
+impl AuthChallenge {
+    /// Parses the value of a `WWW-Authenticate` header, e.g.
+    /// `Bearer realm="https://ghcr.io/token",service="ghcr.io"`.
+    pub fn parse(header: &str) -> Option<Self> {
+        let header = header.trim();
+        let (scheme, params) = header.split_once(' ').unwrap_or((header, ""));
+        let params = parse_auth_params(params);
+        if scheme.eq_ignore_ascii_case("basic") {
+            Some(AuthChallenge::Basic {
+                realm: params.get("realm").cloned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Some(AuthChallenge::Bearer {
+                realm: params.get("realm")?.clone(),
+                service: params.get("service").cloned(),
+                scope: params.get("scope").cloned(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses comma separated `key=value` pairs where values may be quoted. Quoted
+/// values can contain commas (e.g. `scope="repository:foo:push,pull"`).
+fn parse_auth_params(params: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let value = value.trim_start();
+        let (value, remainder) = if let Some(quoted) = value.strip_prefix('"') {
+            let mut parsed = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            parsed.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = idx + 1;
+                        break;
+                    }
+                    c => parsed.push(c),
+                }
+            }
+            (parsed, &quoted[end..])
+        } else {
+            let (value, remainder) = value.split_once(',').unwrap_or((value, ""));
+            (value.trim().to_string(), remainder)
+        };
+        result.insert(key, value);
+        rest = remainder.trim_start().trim_start_matches(',');
+    }
+    result
+}
+
+/// How requests to the registry are sent: either through the remaining
+/// middleware of a client or through a complete client.
+enum Transport<'a> {
+    Next(Next<'a>),
+    Client(&'a ClientWithMiddleware),
+}
 
-    let response = reqwest::get(token_url.clone()).await?;
+impl Transport<'_> {
+    async fn send(&self, req: Request) -> Result<Response, OciMiddlewareError> {
+        Ok(match self {
+            Transport::Next(next) => next.clone().run(req, &mut Extensions::new()).await?,
+            Transport::Client(client) => client.execute(req).await?,
+        })
+    }
+}
 
-    match response.error_for_status() {
-        Ok(response) => {
-            let token = response.json::<OCIToken>().await?;
-            Ok(token.token)
+fn new_request(method: Method, url: Url, authorization: Option<&HeaderValue>) -> Request {
+    let mut req = Request::new(method, url);
+    if let Some(authorization) = authorization {
+        req.headers_mut()
+            .insert(AUTHORIZATION, authorization.clone());
+    }
+    req
+}
+
+fn bearer_header(token: &str) -> Result<HeaderValue, OciMiddlewareError> {
+    let mut header = HeaderValue::from_str(&format!("Bearer {token}"))?;
+    header.set_sensitive(true);
+    Ok(header)
+}
+
+fn basic_header(username: &str, password: &str) -> Result<HeaderValue, OciMiddlewareError> {
+    let credentials = BASE64_STANDARD.encode(format!("{username}:{password}"));
+    let mut header = HeaderValue::from_str(&format!("Basic {credentials}"))?;
+    header.set_sensitive(true);
+    Ok(header)
+}
+
+impl OciMiddleware {
+    /// Creates a new middleware that authenticates with registries using the
+    /// credentials from the given storage.
+    pub fn new(auth_storage: AuthenticationStorage) -> Self {
+        Self {
+            auth_storage: Some(auth_storage),
+            challenges: Arc::default(),
         }
-        Err(e) => {
-            tracing::error!("OCI Mirror: failed to get token with URL: {}", token_url);
-            Err(OciMiddlewareError::Reqwest(e))
+    }
+
+    fn credentials(&self, url: &OCIUrl) -> Option<Authentication> {
+        let storage = self.auth_storage.as_ref()?;
+        match storage.get_by_url(url.registry_url().ok()?) {
+            Ok((_, credentials)) => credentials,
+            Err(e) => {
+                tracing::warn!("failed to retrieve credentials for {}: {e}", url.host);
+                None
+            }
         }
     }
+
+    /// Returns the challenge of the registry, probing the `/v2/` endpoint if
+    /// the registry has not been contacted before.
+    async fn challenge(
+        &self,
+        transport: &Transport<'_>,
+        url: &OCIUrl,
+    ) -> Result<Option<AuthChallenge>, OciMiddlewareError> {
+        if let Some(challenge) = self.challenges.lock().unwrap().get(&url.host) {
+            return Ok(challenge.clone());
+        }
+
+        let response = transport
+            .send(new_request(
+                Method::GET,
+                url.registry_url()?.join("v2/")?,
+                None,
+            ))
+            .await?;
+        let challenge = if response.status().is_success() {
+            None
+        } else {
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(AuthChallenge::parse);
+
+            // Fall back to the token endpoint that most registries expose.
+            Some(challenge.unwrap_or_else(|| AuthChallenge::Bearer {
+                realm: format!("{}/token", url.base),
+                service: None,
+                scope: None,
+            }))
+        };
+
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(url.host.clone(), challenge.clone());
+        Ok(challenge)
+    }
+
+    /// Returns the `Authorization` header to use for requests to the
+    /// repository of the given URL.
+    async fn authorization(
+        &self,
+        transport: &Transport<'_>,
+        url: &OCIUrl,
+        action: OciAction,
+    ) -> Result<Option<HeaderValue>, OciMiddlewareError> {
+        let credentials = self.credentials(url);
+
+        // A bearer token stored for the registry is used without negotiation.
+        if let Some(Authentication::BearerToken(token)) = &credentials {
+            return bearer_header(token).map(Some);
+        }
+        let basic = match &credentials {
+            Some(Authentication::BasicHTTP { username, password }) => {
+                Some(basic_header(username, password)?)
+            }
+            _ => None,
+        };
+
+        match self.challenge(transport, url).await? {
+            None => Ok(None),
+            Some(AuthChallenge::Basic { .. }) => Ok(basic),
+            Some(AuthChallenge::Bearer { realm, service, .. }) => {
+                let mut token_url = Url::parse(&realm)?;
+                {
+                    let mut query = token_url.query_pairs_mut();
+                    if let Some(service) = &service {
+                        query.append_pair("service", service);
+                    }
+                    query.append_pair("scope", &format!("repository:{}:{action}", url.path));
+                }
+
+                let response = transport
+                    .send(new_request(Method::GET, token_url.clone(), basic.as_ref()))
+                    .await?;
+                match response.error_for_status() {
+                    Ok(response) => {
+                        let token = response.json::<OCIToken>().await?;
+                        match token.token.or(token.access_token) {
+                            Some(token) => bearer_header(&token).map(Some),
+                            None => Ok(None),
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("OCI: failed to get token with URL: {}", token_url);
+                        Err(OciMiddlewareError::Reqwest(e))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rewrites the request to point to the blob that contains the artifact.
+    async fn resolve_blob_url(
+        &self,
+        transport: &Transport<'_>,
+        req: &mut Request,
+    ) -> Result<(), OciMiddlewareError> {
+        let oci_url = OCIUrl::new(req.url())?;
+        let authorization = self
+            .authorization(transport, &oci_url, OciAction::Pull)
+            .await?;
+
+        if let Some(authorization) = &authorization {
+            req.headers_mut()
+                .insert(AUTHORIZATION, authorization.clone());
+        }
+
+        // if we know the hash, we can pull the artifact directly
+        // if we don't, we need to pull the manifest and then pull the artifact
+        if let Some(expected_sha_hash) = req
+            .headers()
+            .get("X-Expected-Sha256")
+            .and_then(|s| s.to_str().ok())
+        {
+            *req.url_mut() = oci_url.blob_url(&format!("sha256:{expected_sha_hash}"))?;
+        } else {
+            let manifest = oci_url
+                .fetch_manifest(transport, authorization.as_ref())
+                .await?;
+
+            let Some(layer) = manifest
+                .layers
+                .iter()
+                .find(|l| l.media_type == oci_url.media_type)
+            else {
+                return Err(OciMiddlewareError::LayerNotFound);
+            };
+
+            *req.url_mut() = oci_url.blob_url(&layer.digest)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes an artifact to the location in the registry that the `oci://`
+    /// `url` is read from by this middleware, e.g.
+    /// `oci://ghcr.io/my-org/my-channel/noarch/foo-1.0-0.conda`.
+    pub async fn push(
+        &self,
+        client: &ClientWithMiddleware,
+        url: &Url,
+        data: Vec<u8>,
+    ) -> Result<(), OciMiddlewareError> {
+        let transport = Transport::Client(client);
+        let oci_url = OCIUrl::new(url)?;
+        let authorization = self
+            .authorization(&transport, &oci_url, OciAction::PushPull)
+            .await?;
+
+        let config = oci_url
+            .push_blob(
+                &transport,
+                authorization.as_ref(),
+                CONFIG_MEDIA_TYPE,
+                b"{}".to_vec(),
+                None,
+            )
+            .await?;
+        let file_name = url.path_segments().and_then(|mut s| s.next_back());
+        let layer = oci_url
+            .push_blob(
+                &transport,
+                authorization.as_ref(),
+                &oci_url.media_type,
+                data,
+                file_name,
+            )
+            .await?;
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+            layers: vec![layer],
+            config,
+            annotations: None,
+        };
+
+        let mut req = new_request(Method::PUT, oci_url.manifest_url()?, authorization.as_ref());
+        req.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(MANIFEST_MEDIA_TYPE));
+        *req.body_mut() = Some(serde_json::to_vec(&manifest)?.into());
+        let response = transport.send(req).await?;
+        if !response.status().is_success() {
+            return Err(OciMiddlewareError::UploadRejected(
+                format!("{}:{}", oci_url.path, oci_url.tag),
+                response.status(),
+            ));
+        }
+        Ok(())
+    }
 }
 
+/// An `oci://` URL that is mapped onto a repository and tag of an OCI
+/// registry.
 #[derive(Debug)]
 struct OCIUrl {
     url: Url,
+    /// The scheme and host (including the port) of the registry
+    base: String,
     host: String,
     path: String,
     tag: String,
@@ -103,39 +478,55 @@ fn version_build_tag(tag: &str) -> String {
         .replace('=', "__eq__")
 }
 
+/// Registries on the local machine are accessed over plain HTTP, like docker
+/// does.
+fn registry_scheme(host: &str) -> &'static str {
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if !port.contains(']') => hostname,
+        _ => host,
+    };
+    if matches!(hostname, "localhost" | "127.0.0.1" | "[::1]") {
+        "http"
+    } else {
+        "https"
+    }
+}
+
 impl OCIUrl {
-    pub fn manifest_url(&self) -> Result<Url, ParseError> {
-        format!(
-            "https://{}/v2/{}/manifests/{}",
-            self.host, self.path, self.tag
-        )
-        .parse()
+    pub fn registry_url(&self) -> Result<Url, ParseError> {
+        format!("{}/", self.base).parse()
     }
 
-    pub fn token_url(&self, action: OciAction) -> Result<Url, ParseError> {
-        format!(
-            "https://{}/token?scope=repository:{}:{}",
-            self.host, self.path, action
-        )
-        .parse()
+    pub fn manifest_url(&self) -> Result<Url, ParseError> {
+        format!("{}/v2/{}/manifests/{}", self.base, self.path, self.tag).parse()
     }
 
     pub fn blob_url(&self, sha256: &str) -> Result<Url, ParseError> {
-        format!("https://{}/v2/{}/blobs/{}", self.host, self.path, sha256).parse()
+        format!("{}/v2/{}/blobs/{}", self.base, self.path, sha256).parse()
+    }
+
+    pub fn upload_url(&self) -> Result<Url, ParseError> {
+        format!("{}/v2/{}/blobs/uploads/", self.base, self.path).parse()
     }
 
     pub fn new(url: &Url) -> Result<Self, ParseError> {
         // get filename (last segment of path)
         let filename = url.path_segments().unwrap().next_back().unwrap();
 
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => String::new(),
+        };
+
         let mut res = OCIUrl {
             url: url.clone(),
             tag: "latest".to_string(),
             media_type: "".to_string(),
-            host: url.host_str().unwrap_or("").to_string(),
+            base: format!("{}://{host}", registry_scheme(&host)),
+            host,
             path: url.path().trim_start_matches('/').to_string(),
         };
-
         let mut computed_filename = filename.to_string();
 
         // We reimplement some archive name splitting logic from rattler here
@@ -175,70 +566,112 @@ impl OCIUrl {
         Ok(res)
     }
 
-    pub async fn get_blob_url(req: &mut Request) -> Result<(), OciMiddlewareError> {
-        let oci_url = OCIUrl::new(req.url())?;
-        let token = get_token(&oci_url, OciAction::Pull).await?;
+    async fn fetch_manifest(
+        &self,
+        transport: &Transport<'_>,
+        authorization: Option<&HeaderValue>,
+    ) -> Result<Manifest, OciMiddlewareError> {
+        let mut req = new_request(Method::GET, self.manifest_url()?, authorization);
+        req.headers_mut()
+            .insert(ACCEPT, HeaderValue::from_static(MANIFEST_MEDIA_TYPE));
+        let response = transport.send(req).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(OciMiddlewareError::ManifestNotFound);
+        }
+        Ok(response.error_for_status()?.json().await?)
+    }
 
-        let mut header = reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))?;
-        header.set_sensitive(true);
+    /// Uploads a blob to the repository unless the registry already has it
+    /// and returns the descriptor of the blob.
+    async fn push_blob(
+        &self,
+        transport: &Transport<'_>,
+        authorization: Option<&HeaderValue>,
+        media_type: &str,
+        data: Vec<u8>,
+        title: Option<&str>,
+    ) -> Result<Layer, OciMiddlewareError> {
+        let digest = format!("sha256:{:x}", Sha256::digest(&data));
+        let layer = Layer {
+            size: data.len() as u64,
+            media_type: media_type.to_string(),
+            annotations: title.map(|title| {
+                HashMap::from([(
+                    "org.opencontainers.image.title".to_string(),
+                    title.to_string(),
+                )])
+            }),
+            digest,
+        };
 
-        req.headers_mut().insert(AUTHORIZATION, header);
+        let exists = transport
+            .send(new_request(
+                Method::HEAD,
+                self.blob_url(&layer.digest)?,
+                authorization,
+            ))
+            .await?;
+        if exists.status().is_success() {
+            return Ok(layer);
+        }
 
-        // if we know the hash, we can pull the artifact directly
-        // if we don't, we need to pull the manifest and then pull the artifact
-        if let Some(expected_sha_hash) = req
+        let upload_url = self.upload_url()?;
+        let response = transport
+            .send(new_request(Method::POST, upload_url.clone(), authorization))
+            .await?;
+        if !response.status().is_success() {
+            return Err(OciMiddlewareError::UploadRejected(
+                layer.digest,
+                response.status(),
+            ));
+        }
+        let location = response
             .headers()
-            .get("X-Expected-Sha256")
-            .and_then(|s| s.to_str().ok())
-        {
-            *req.url_mut() = oci_url.blob_url(&format!("sha256:{expected_sha_hash}"))?;
-        } else {
-            // get the tag from the URL retrieve the manifest
-            let manifest_url = oci_url.manifest_url()?; // TODO: handle error
-
-            let manifest = reqwest::Client::new()
-                .get(manifest_url)
-                .bearer_auth(&token)
-                .header(ACCEPT, "application/vnd.oci.image.manifest.v1+json")
-                .send()
-                .await?;
-
-            let manifest: Manifest = manifest.json().await?;
-
-            let layer = if let Some(layer) = manifest
-                .layers
-                .iter()
-                .find(|l| l.media_type == oci_url.media_type)
-            {
-                layer
-            } else {
-                return Err(OciMiddlewareError::LayerNotFound);
-            };
-
-            *req.url_mut() = oci_url.blob_url(&layer.digest)?;
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or(OciMiddlewareError::MissingUploadLocation)?;
+        let mut location = upload_url.join(location)?;
+        location
+            .query_pairs_mut()
+            .append_pair("digest", &layer.digest);
+
+        let mut req = new_request(Method::PUT, location, authorization);
+        req.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        *req.body_mut() = Some(data.into());
+        let response = transport.send(req).await?;
+        if !response.status().is_success() {
+            return Err(OciMiddlewareError::UploadRejected(
+                layer.digest,
+                response.status(),
+            ));
         }
 
-        Ok(())
+        Ok(layer)
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct Layer {
     digest: String,
     #[serde(rename = "mediaType")]
     media_type: String,
     size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     annotations: Option<HashMap<String, String>>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Manifest {
     schema_version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
     layers: Vec<Layer>,
     config: Layer,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     annotations: Option<HashMap<String, String>>,
 }
 
@@ -264,36 +697,256 @@ impl Middleware for OciMiddleware {
             ));
         }
 
-        let res = OCIUrl::get_blob_url(&mut req).await;
+        let res = self
+            .resolve_blob_url(&Transport::Next(next.clone()), &mut req)
+            .await;
 
         match res {
             Ok(_) => next.run(req, extensions).await,
-            Err(e) => match e {
-                OciMiddlewareError::LayerNotFound => {
-                    return Ok(create_404_response(
-                        req.url(),
-                        "No layer available for media type",
-                    ));
-                }
-                _ => {
-                    return Err(reqwest_middleware::Error::Middleware(e.into()));
-                }
-            },
+            Err(OciMiddlewareError::LayerNotFound) => Ok(create_404_response(
+                req.url(),
+                "No layer available for media type",
+            )),
+            Err(OciMiddlewareError::ManifestNotFound) => Ok(create_404_response(
+                req.url(),
+                "No manifest available for tag",
+            )),
+            Err(e) => Err(reqwest_middleware::Error::Middleware(e.into())),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::HashMap,
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{header, HeaderMap, Method, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        Router,
+    };
     use sha2::{Digest, Sha256};
+    use url::Url;
+
+    use super::AuthChallenge;
+    use crate::{
+        authentication_storage::backends::memory::MemoryStorage, Authentication,
+        AuthenticationStorage, OciMiddleware,
+    };
+
+    #[test]
+    fn test_parse_challenge() {
+        assert_eq!(
+            AuthChallenge::parse(
+                r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:foo/bar:push,pull""#
+            ),
+            Some(AuthChallenge::Bearer {
+                realm: String::from("https://ghcr.io/token"),
+                service: Some(String::from("ghcr.io")),
+                scope: Some(String::from("repository:foo/bar:push,pull")),
+            })
+        );
+        assert_eq!(
+            AuthChallenge::parse(r#"basic realm="Registry Realm""#),
+            Some(AuthChallenge::Basic {
+                realm: Some(String::from("Registry Realm")),
+            })
+        );
+        assert_eq!(
+            AuthChallenge::parse("Bearer service=registry, realm=http://localhost/auth"),
+            Some(AuthChallenge::Bearer {
+                realm: String::from("http://localhost/auth"),
+                service: Some(String::from("registry")),
+                scope: None,
+            })
+        );
+        assert_eq!(
+            AuthChallenge::parse("Bearer service=\"missing-realm\""),
+            None
+        );
+        assert_eq!(AuthChallenge::parse("Negotiate"), None);
+    }
+
+    /// A minimal in-memory registry that hands out a token for the basic
+    /// credentials `user:secret`.
+    #[derive(Default)]
+    struct Registry {
+        realm: String,
+        blobs: Mutex<HashMap<String, Vec<u8>>>,
+        manifests: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    async fn handle_registry(
+        State(registry): State<Arc<Registry>>,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        let path = uri.path();
+
+        if path == "/token" {
+            // base64 of `user:secret`
+            return if authorization == Some("Basic dXNlcjpzZWNyZXQ=")
+                && uri.query().is_some_and(|q| q.contains("service=test"))
+            {
+                r#"{"token": "registry-token"}"#.into_response()
+            } else {
+                StatusCode::UNAUTHORIZED.into_response()
+            };
+        }
+
+        if authorization != Some("Bearer registry-token") {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(
+                    header::WWW_AUTHENTICATE,
+                    format!(r#"Bearer realm="{}",service="test""#, registry.realm),
+                )],
+            )
+                .into_response();
+        }
+
+        let path = path.strip_prefix("/v2/").unwrap();
+        if let Some((_, digest)) = path.split_once("/blobs/") {
+            match method {
+                Method::POST => {
+                    (StatusCode::ACCEPTED, [(header::LOCATION, "/upload/1")]).into_response()
+                }
+                _ => match registry.blobs.lock().unwrap().get(digest) {
+                    Some(blob) => blob.clone().into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                },
+            }
+        } else if let Some((name, tag)) = path.split_once("/manifests/") {
+            let key = format!("{name}:{tag}");
+            if method == Method::PUT {
+                registry
+                    .manifests
+                    .lock()
+                    .unwrap()
+                    .insert(key, body.to_vec());
+                StatusCode::CREATED.into_response()
+            } else {
+                match registry.manifests.lock().unwrap().get(&key) {
+                    Some(manifest) => manifest.clone().into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
+            }
+        } else {
+            StatusCode::NOT_FOUND.into_response()
+        }
+    }
+
+    async fn upload(State(registry): State<Arc<Registry>>, uri: Uri, body: Bytes) -> StatusCode {
+        let digest = uri.query().unwrap().strip_prefix("digest=").unwrap();
+        let digest = digest.replace("%3A", ":");
+        assert_eq!(digest, format!("sha256:{:x}", Sha256::digest(&body)));
+        registry.blobs.lock().unwrap().insert(digest, body.to_vec());
+        StatusCode::CREATED
+    }
+
+    async fn test_registry() -> (Arc<Registry>, String) {
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = format!("{}:{}", addr.ip(), addr.port());
+
+        let registry = Arc::new(Registry {
+            realm: format!("http://{host}/token"),
+            ..Registry::default()
+        });
+        let router = Router::new()
+            .route("/upload/1", axum::routing::put(upload))
+            .fallback(handle_registry)
+            .with_state(registry.clone());
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        (registry, host)
+    }
 
-    use crate::OciMiddleware;
+    fn storage_with_credentials() -> AuthenticationStorage {
+        let mut storage = AuthenticationStorage::empty();
+        let backend = MemoryStorage::default();
+        storage.add_backend(Arc::new(backend));
+        storage
+            .store(
+                "127.0.0.1",
+                &Authentication::BasicHTTP {
+                    username: String::from("user"),
+                    password: String::from("secret"),
+                },
+            )
+            .unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn test_oci_push_and_pull() {
+        let (registry, host) = test_registry().await;
+        let middleware = OciMiddleware::new(storage_with_credentials());
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware.clone())
+            .build();
+
+        let url: Url = format!("oci://{host}/channel/noarch/_foo-1.0-py_0.conda")
+            .parse()
+            .unwrap();
+        middleware
+            .push(&client, &url, b"package contents".to_vec())
+            .await
+            .unwrap();
+        assert!(registry
+            .manifests
+            .lock()
+            .unwrap()
+            .contains_key("channel/noarch/zzz_foo:1.0-py_0"));
+
+        let response = client.get(url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.bytes().await.unwrap().as_ref(),
+            b"package contents"
+        );
+
+        // A tag that was never pushed results in a 404.
+        let response = client
+            .get(format!("oci://{host}/channel/noarch/repodata.json"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_oci_push_without_credentials() {
+        let (_registry, host) = test_registry().await;
+        let middleware = OciMiddleware::default();
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+
+        let url: Url = format!("oci://{host}/channel/noarch/foo-1.0-py_0.conda")
+            .parse()
+            .unwrap();
+        assert!(middleware
+            .push(&client, &url, b"package contents".to_vec())
+            .await
+            .is_err());
+    }
 
     // test pulling an image from OCI registry
     #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
     #[tokio::test]
     async fn test_oci_middleware() {
-        let middleware = OciMiddleware;
+        let middleware = OciMiddleware::default();
 
         let client = reqwest::Client::new();
         let client_with_middleware = reqwest_middleware::ClientBuilder::new(client)
@@ -326,7 +979,7 @@ mod tests {
     #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
     #[tokio::test]
     async fn test_oci_middleware_repodata() {
-        let middleware = OciMiddleware;
+        let middleware = OciMiddleware::default();
 
         let client = reqwest::Client::new();
         let client_with_middleware = reqwest_middleware::ClientBuilder::new(client)
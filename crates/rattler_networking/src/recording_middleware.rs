@@ -0,0 +1,514 @@
+//! Middleware to record HTTP interactions to a cassette directory and to
+//! replay them later without network access.
+//!
+//! Every interaction is stored as two files in the cassette directory: a JSON
+//! file with the request and the response metadata and a file with the raw
+//! response body. Credentials are redacted from URLs and headers before they
+//! are written to disk. Interactions are identified by the request method, the
+//! redacted URL and the `Range` header, so a cassette that was recorded with
+//! one set of credentials can be replayed with another (or without any).
+//! Response bodies are streamed to disk while they are read, so an interaction
+//! is only recorded once its body has been read completely.
+//!
+//! Conditional request headers (e.g. `If-None-Match`) are removed in both
+//! modes so that a cassette always contains full responses and replaying does
+//! not depend on the state of the local cache.
+//!
+//! The middleware should be the first middleware of a client. When replaying
+//! it answers every request itself, so none of the other middlewares run.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use http::{Extensions, HeaderMap, HeaderName, HeaderValue, Method};
+use rattler_redaction::Redact;
+use reqwest::{header, Request, ResponseBuilderExt};
+use reqwest_middleware::{Middleware, Next, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Request headers that are removed before a request is recorded or replayed.
+const CONDITIONAL_HEADERS: [HeaderName; 5] = [
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+    header::IF_MATCH,
+    header::IF_UNMODIFIED_SINCE,
+    header::IF_RANGE,
+];
+
+/// Whether the [`RecordingMiddleware`] records or replays interactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingMode {
+    /// Send requests to the network and store the interactions in the
+    /// cassette, overwriting previous recordings of the same request.
+    Record,
+
+    /// Answer requests from the cassette without accessing the network.
+    /// Requests that were not recorded fail.
+    Replay,
+}
+
+/// Errors that can occur when recording or replaying interactions.
+#[derive(Debug, thiserror::Error)]
+pub enum RecordingError {
+    /// Reading from or writing to the cassette failed
+    #[error("failed to access the cassette at {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    /// The cassette contains an invalid interaction
+    #[error("failed to parse the recorded interaction {0}")]
+    InvalidInteraction(PathBuf, #[source] serde_json::Error),
+
+    /// The request was not recorded in the cassette
+    #[error("no recorded response for {method} {url}")]
+    NotRecorded {
+        /// The method of the request
+        method: Method,
+        /// The redacted URL of the request
+        url: Url,
+    },
+}
+
+/// A [`Middleware`] that records HTTP interactions to a cassette directory or
+/// replays them from it.
+#[derive(Debug, Clone)]
+pub struct RecordingMiddleware {
+    cassette: PathBuf,
+    mode: RecordingMode,
+}
+
+/// The metadata of a recorded interaction.
+#[derive(Debug, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    url: Url,
+    request_headers: BTreeMap<String, String>,
+    status: u16,
+    response_headers: Vec<(String, String)>,
+}
+
+impl RecordingMiddleware {
+    /// Create a new middleware that uses the given cassette directory.
+    pub fn new(cassette: impl Into<PathBuf>, mode: RecordingMode) -> Self {
+        Self {
+            cassette: cassette.into(),
+            mode,
+        }
+    }
+
+    /// Create a new middleware that records interactions to the given
+    /// directory.
+    pub fn record(cassette: impl Into<PathBuf>) -> Self {
+        Self::new(cassette, RecordingMode::Record)
+    }
+
+    /// Create a new middleware that replays interactions from the given
+    /// directory.
+    pub fn replay(cassette: impl Into<PathBuf>) -> Self {
+        Self::new(cassette, RecordingMode::Replay)
+    }
+
+    /// The directory that interactions are stored in.
+    pub fn cassette(&self) -> &Path {
+        &self.cassette
+    }
+
+    /// The mode of this middleware.
+    pub fn mode(&self) -> RecordingMode {
+        self.mode
+    }
+
+    /// Returns the name under which the interaction for a request is stored.
+    fn interaction_key(method: &Method, url: &Url, headers: &HeaderMap) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(method.as_str());
+        hasher.update(b" ");
+        hasher.update(url.as_str());
+        if let Some(range) = headers.get(header::RANGE) {
+            hasher.update(b"\n");
+            hasher.update(range.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn io_error(&self, err: std::io::Error) -> RecordingError {
+        RecordingError::Io(self.cassette.clone(), err)
+    }
+
+    /// Atomically writes `contents` to `name` in the cassette directory.
+    fn write(&self, name: &str, contents: &[u8]) -> std::result::Result<(), RecordingError> {
+        fs_err::create_dir_all(&self.cassette).map_err(|e| self.io_error(e))?;
+        let mut file =
+            tempfile::NamedTempFile::new_in(&self.cassette).map_err(|e| self.io_error(e))?;
+        file.write_all(contents).map_err(|e| self.io_error(e))?;
+        file.persist(self.cassette.join(name))
+            .map_err(|e| self.io_error(e.error))?;
+        Ok(())
+    }
+
+    async fn record_interaction(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<reqwest::Response> {
+        let method = req.method().clone();
+        let url = req.url().clone().redact();
+        let key = Self::interaction_key(&method, &url, req.headers());
+        let request_headers = header_pairs(&req.headers().clone().redact())
+            .into_iter()
+            .collect();
+
+        let response = next.run(req, extensions).await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let response_url = response.url().clone();
+
+        let interaction = Interaction {
+            method: method.to_string(),
+            url,
+            request_headers,
+            status: status.as_u16(),
+            response_headers: header_pairs(&headers.clone().redact()),
+        };
+        let metadata =
+            serde_json::to_vec_pretty(&interaction).expect("interactions can always be serialized");
+
+        // The body is written to the cassette while the caller reads it.
+        fs_err::create_dir_all(&self.cassette)
+            .map_err(|e| reqwest_middleware::Error::middleware(self.io_error(e)))?;
+        let file = tempfile::NamedTempFile::new_in(&self.cassette)
+            .map_err(|e| reqwest_middleware::Error::middleware(self.io_error(e)))?;
+        let mut body = RecordingBody {
+            inner: http::Response::<reqwest::Body>::from(response).into_body(),
+            recorder: Some(BodyRecorder {
+                middleware: self.clone(),
+                key,
+                metadata,
+                file,
+            }),
+        };
+        if http_body::Body::is_end_stream(&body.inner) {
+            body.finish()
+                .map_err(reqwest_middleware::Error::middleware)?;
+        }
+
+        Ok(build_response(
+            status,
+            headers,
+            response_url,
+            reqwest::Body::wrap(body),
+        ))
+    }
+
+    fn replay_interaction(
+        &self,
+        req: &Request,
+    ) -> std::result::Result<reqwest::Response, RecordingError> {
+        let method = req.method().clone();
+        let url = req.url().clone().redact();
+        let key = Self::interaction_key(&method, &url, req.headers());
+
+        let metadata_path = self.cassette.join(format!("{key}.json"));
+        let metadata = match fs_err::read(&metadata_path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(RecordingError::NotRecorded { method, url });
+            }
+            Err(e) => return Err(self.io_error(e)),
+        };
+        let interaction: Interaction = serde_json::from_slice(&metadata)
+            .map_err(|e| RecordingError::InvalidInteraction(metadata_path, e))?;
+        let body = fs_err::read(self.cassette.join(format!("{key}.body")))
+            .map_err(|e| self.io_error(e))?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in interaction.response_headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::try_from(value.as_str()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        let status = http::StatusCode::from_u16(interaction.status)
+            .unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        Ok(build_response(status, headers, req.url().clone(), body))
+    }
+}
+
+/// Writes the body of a recorded response to a temporary file in the cassette
+/// and stores the interaction once the body is complete.
+struct BodyRecorder {
+    middleware: RecordingMiddleware,
+    key: String,
+    metadata: Vec<u8>,
+    file: tempfile::NamedTempFile,
+}
+
+impl BodyRecorder {
+    fn finish(mut self) -> std::result::Result<(), RecordingError> {
+        let middleware = self.middleware;
+        self.file.flush().map_err(|e| middleware.io_error(e))?;
+        self.file
+            .persist(middleware.cassette.join(format!("{}.body", self.key)))
+            .map_err(|e| middleware.io_error(e.error))?;
+
+        // The metadata is written last, an interaction without it is not
+        // replayed.
+        middleware.write(&format!("{}.json", self.key), &self.metadata)
+    }
+}
+
+/// A response body that records the data that is read from it.
+///
+/// The interaction is only stored once the body has been read to the end. If
+/// the body is dropped early or fails, the temporary file is removed and
+/// nothing is recorded.
+struct RecordingBody {
+    inner: reqwest::Body,
+    recorder: Option<BodyRecorder>,
+}
+
+impl RecordingBody {
+    fn finish(&mut self) -> std::result::Result<(), RecordingError> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl http_body::Body for RecordingBody {
+    type Data = <reqwest::Body as http_body::Body>::Data;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<std::result::Result<http_body::Frame<Self::Data>, Self::Error>>>
+    {
+        let this = &mut *self;
+        let poll = std::pin::Pin::new(&mut this.inner).poll_frame(cx);
+        let result = match &poll {
+            std::task::Poll::Ready(Some(Ok(frame))) => {
+                match (frame.data_ref(), this.recorder.as_mut()) {
+                    (Some(data), Some(recorder)) => recorder
+                        .file
+                        .write_all(data)
+                        .map_err(|e| recorder.middleware.io_error(e)),
+                    _ => Ok(()),
+                }
+            }
+            std::task::Poll::Ready(Some(Err(_))) => {
+                this.recorder = None;
+                Ok(())
+            }
+            std::task::Poll::Ready(None) => this.finish(),
+            std::task::Poll::Pending => Ok(()),
+        };
+        match result {
+            Ok(()) => poll.map(|frame| frame.map(|frame| frame.map_err(Into::into))),
+            Err(err) => {
+                this.recorder = None;
+                std::task::Poll::Ready(Some(Err(Box::new(err))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.recorder.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Returns the headers as pairs of strings, keeping the order and duplicates.
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn build_response(
+    status: http::StatusCode,
+    headers: HeaderMap,
+    url: Url,
+    body: impl Into<reqwest::Body>,
+) -> reqwest::Response {
+    let mut builder = http::Response::builder().status(status).url(url);
+    if let Some(response_headers) = builder.headers_mut() {
+        *response_headers = headers;
+    }
+    reqwest::Response::from(
+        builder
+            .body(body.into())
+            .expect("status and headers were already validated"),
+    )
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl Middleware for RecordingMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<reqwest::Response> {
+        for name in &CONDITIONAL_HEADERS {
+            req.headers_mut().remove(name);
+        }
+
+        match self.mode {
+            RecordingMode::Record => self.record_interaction(req, extensions, next).await,
+            RecordingMode::Replay => self
+                .replay_interaction(&req)
+                .map_err(reqwest_middleware::Error::middleware),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::IntoFuture,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use reqwest::Client;
+    use reqwest_middleware::ClientBuilder;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::LazyClient;
+
+    async fn repodata(
+        State(requests): State<Arc<AtomicUsize>>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        requests.fetch_add(1, Ordering::SeqCst);
+        if headers.contains_key("if-none-match") {
+            return (
+                StatusCode::NOT_MODIFIED,
+                [("etag", "\"abc\"")],
+                String::new(),
+            );
+        }
+        (
+            StatusCode::OK,
+            [("etag", "\"abc\"")],
+            String::from(r#"{"packages": {}}"#),
+        )
+    }
+
+    async fn serve(requests: Arc<AtomicUsize>) -> Url {
+        let router = Router::new()
+            .route("/t/{token}/conda-forge/noarch/repodata.json", get(repodata))
+            .with_state(requests);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        Url::parse(&format!("http://{address}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let cassette = tempfile::tempdir().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server = serve(requests.clone()).await;
+        let url = server
+            .join("t/secret-token/conda-forge/noarch/repodata.json")
+            .unwrap();
+
+        let recording = ClientBuilder::new(Client::new())
+            .with(RecordingMiddleware::record(cassette.path()))
+            .build();
+        let response = recording
+            .get(url.clone())
+            .header("authorization", "Bearer secret")
+            .header("if-none-match", "\"abc\"")
+            .send()
+            .await
+            .unwrap();
+        // Nothing is stored before the body has been read.
+        let interactions = || {
+            fs_err::read_dir(cassette.path())
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("json".as_ref()))
+                .count()
+        };
+        assert_eq!(interactions(), 0);
+
+        // The conditional header is not sent, so the full response is
+        // recorded.
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), r#"{"packages": {}}"#);
+        assert_eq!(interactions(), 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Credentials are not written to the cassette.
+        for entry in fs_err::read_dir(cassette.path()).unwrap() {
+            let content = fs_err::read_to_string(entry.unwrap().path()).unwrap();
+            assert!(!content.contains("secret"), "{content}");
+        }
+
+        // Replay with a different token and without a server.
+        let replaying = LazyClient::from(
+            ClientBuilder::new(Client::new())
+                .with(RecordingMiddleware::replay(cassette.path()))
+                .build(),
+        );
+        let other_token = server
+            .join("t/other-token/conda-forge/noarch/repodata.json")
+            .unwrap();
+        let response = replaying
+            .client()
+            .get(other_token.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.url(), &other_token);
+        assert_eq!(response.headers()["etag"], "\"abc\"");
+        assert_eq!(response.text().await.unwrap(), r#"{"packages": {}}"#);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Requests that were not recorded fail.
+        let err = replaying
+            .client()
+            .get(
+                server
+                    .join("t/secret-token/conda-forge/linux-64/repodata.json")
+                    .unwrap(),
+            )
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no recorded response"), "{err}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
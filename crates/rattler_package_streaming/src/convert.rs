@@ -0,0 +1,385 @@
+//! Functionality to convert package archives between the `.tar.bz2` and the
+//! `.conda` format.
+//!
+//! The conversion streams the entries from one archive into the other without
+//! extracting them to disk. The original tar headers are preserved, which
+//! means file order, permissions, symlinks and timestamps are retained.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{
+    compression_level::CompressionLevel,
+    package::{ArchiveType, PackageMetadata, PathType, PathsJson},
+};
+use rattler_digest::{HashingReader, Sha256, Sha256Hash};
+
+use crate::{read::stream_tar_bz2, seek, ExtractError};
+
+/// Options that control how the converted archive is compressed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConvertOptions {
+    /// The compression level to use for the inner archives of a `.conda`
+    /// package or the bzip2 stream of a `.tar.bz2` package.
+    pub compression_level: CompressionLevel,
+
+    /// The base 2 logarithm of the zstd window size. Larger windows can improve
+    /// the compression ratio of large packages. Note that decoders by default
+    /// refuse windows larger than `2^27` bytes. Only used when converting to
+    /// `.conda`.
+    pub zstd_window_log: Option<u32>,
+
+    /// The number of threads to use for zstd compression (defaults to the
+    /// number of CPU cores if `None`). Only used when converting to `.conda`.
+    pub compression_num_threads: Option<u32>,
+}
+
+/// An error that can occur when converting a package archive.
+#[derive(thiserror::Error, Debug)]
+#[allow(missing_docs)]
+pub enum ConvertError {
+    #[error(transparent)]
+    Extract(#[from] ExtractError),
+
+    #[error("an io error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("the source and destination must be different package formats")]
+    SameArchiveType,
+
+    #[error("the source and destination are the same file")]
+    SameFile,
+
+    #[error("{} is missing from the converted package", .0.display())]
+    MissingPath(PathBuf),
+
+    #[error("{} in the converted package does not match paths.json: {}", .0.display(), .1)]
+    PathMismatch(PathBuf, String),
+}
+
+impl From<zip::result::ZipError> for ConvertError {
+    fn from(value: zip::result::ZipError) -> Self {
+        ConvertError::Extract(value.into())
+    }
+}
+
+/// Converts a package archive to a different format. The archive types of the
+/// `source` and `destination` are determined from their file extensions.
+///
+/// After conversion the entries of the new archive are verified against the
+/// `info/paths.json` of the source package, if it has one. The package is
+/// written to a temporary file next to `destination`, which only replaces
+/// `destination` once the conversion and the verification succeeded.
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// use rattler_package_streaming::convert::{convert_package, ConvertOptions};
+/// convert_package(
+///     Path::new("conda-forge/win-64/python-3.11.0-hcf16a7b_0_cpython.tar.bz2"),
+///     Path::new("conda-forge/win-64/python-3.11.0-hcf16a7b_0_cpython.conda"),
+///     &ConvertOptions::default(),
+/// )
+/// .unwrap();
+/// ```
+pub fn convert_package(
+    source: &Path,
+    destination: &Path,
+    options: &ConvertOptions,
+) -> Result<(), ConvertError> {
+    let source_type = ArchiveType::try_from(source).ok_or(ExtractError::UnsupportedArchiveType)?;
+    let destination_type =
+        ArchiveType::try_from(destination).ok_or(ExtractError::UnsupportedArchiveType)?;
+    if source_type == destination_type {
+        return Err(ConvertError::SameArchiveType);
+    }
+    if destination.exists() && fs_err::canonicalize(source)? == fs_err::canonicalize(destination)? {
+        return Err(ConvertError::SameFile);
+    }
+
+    let paths_json = match seek::read_package_file::<PathsJson>(source) {
+        Ok(paths_json) => Some(paths_json),
+        Err(ExtractError::MissingComponent) => {
+            tracing::warn!(
+                "{} does not contain info/paths.json, skipping verification",
+                source.display()
+            );
+            None
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let destination_dir = match destination.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp_file = tempfile::NamedTempFile::new_in(destination_dir)?;
+
+    let reader = BufReader::new(File::open(source)?);
+    let mut writer = BufWriter::new(temp_file.as_file_mut());
+    if destination_type == ArchiveType::Conda {
+        let file_name = destination
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let (out_name, _) =
+            ArchiveType::split_str(&file_name).ok_or(ExtractError::UnsupportedArchiveType)?;
+        convert_tar_bz2_to_conda(reader, &mut writer, out_name, options)?;
+    } else {
+        convert_conda_to_tar_bz2(reader, &mut writer, options)?;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    if let Some(paths_json) = paths_json {
+        temp_file.rewind()?;
+        verify_archive(temp_file.as_file(), destination_type, &paths_json)?;
+    }
+
+    temp_file.persist(destination).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Converts a `.tar.bz2` package read from `reader` to a `.conda` package.
+///
+/// The entries of the `info/` directory are written to the
+/// `info-<out_name>.tar.zst` member, all other entries to the
+/// `pkg-<out_name>.tar.zst` member. Both inner archives are compressed to
+/// temporary files before being added to the outer zip archive.
+pub fn convert_tar_bz2_to_conda<R: Read, W: Write + Seek>(
+    reader: R,
+    writer: W,
+    out_name: &str,
+    options: &ConvertOptions,
+) -> Result<(), ConvertError> {
+    let mut pkg_archive = tar::Builder::new(zstd_encoder(tempfile::tempfile()?, options)?);
+    let mut info_archive = tar::Builder::new(zstd_encoder(tempfile::tempfile()?, options)?);
+
+    let mut first_mtime = None;
+    let mut source = stream_tar_bz2(reader);
+    for entry in source.entries()? {
+        let entry = entry?;
+        if first_mtime.is_none() {
+            first_mtime = entry.header().mtime().ok();
+        }
+        let is_info = entry.path()?.starts_with("info/");
+        if is_info {
+            copy_entry(&mut info_archive, entry)?;
+        } else {
+            copy_entry(&mut pkg_archive, entry)?;
+        }
+    }
+
+    let mut pkg_file = pkg_archive.into_inner()?.finish()?;
+    let mut info_file = info_archive.into_inner()?.finish()?;
+
+    // Use the timestamp of the original entries for the members of the zip
+    // archive.
+    let last_modified_time = first_mtime
+        .and_then(|mtime| chrono::DateTime::from_timestamp(mtime as i64, 0))
+        .map(|time| crate::write::zip_date_time(&time))
+        .unwrap_or_default();
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .last_modified_time(last_modified_time)
+        .large_file(true);
+
+    let mut outer_archive = zip::ZipWriter::new(writer);
+    let package_metadata = serde_json::to_string(&PackageMetadata::default())
+        .expect("package metadata can always be serialized");
+    outer_archive.start_file("metadata.json", options)?;
+    outer_archive.write_all(package_metadata.as_bytes())?;
+
+    outer_archive.start_file(format!("pkg-{out_name}.tar.zst"), options)?;
+    pkg_file.rewind()?;
+    io::copy(&mut pkg_file, &mut outer_archive)?;
+
+    outer_archive.start_file(format!("info-{out_name}.tar.zst"), options)?;
+    info_file.rewind()?;
+    io::copy(&mut info_file, &mut outer_archive)?;
+
+    outer_archive.finish()?;
+    Ok(())
+}
+
+/// Converts a `.conda` package read from `reader` to a `.tar.bz2` package.
+///
+/// The entries of the info section are written first, followed by the entries
+/// of the content section, which matches the layout produced by
+/// [`crate::write::write_tar_bz2_package`].
+pub fn convert_conda_to_tar_bz2<R: Read + Seek, W: Write>(
+    mut reader: R,
+    writer: W,
+    options: &ConvertOptions,
+) -> Result<(), ConvertError> {
+    let mut archive = tar::Builder::new(bzip2::write::BzEncoder::new(
+        writer,
+        bzip2::Compression::new(options.compression_level.to_bzip2_level()?),
+    ));
+
+    for entry in seek::stream_conda_info(&mut reader)?.entries()? {
+        copy_entry(&mut archive, entry?)?;
+    }
+    reader.rewind()?;
+    for entry in seek::stream_conda_content(&mut reader)?.entries()? {
+        copy_entry(&mut archive, entry?)?;
+    }
+
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Verifies that every entry in `paths_json` is present in the package at
+/// `package` and that the sizes and hashes of regular files match.
+pub fn verify_against_paths_json(
+    package: &Path,
+    paths_json: &PathsJson,
+) -> Result<(), ConvertError> {
+    let archive_type =
+        ArchiveType::try_from(package).ok_or(ExtractError::UnsupportedArchiveType)?;
+    verify_archive(&File::open(package)?, archive_type, paths_json)
+}
+
+/// Verifies the package of the given type read from `file` against
+/// `paths_json`, see [`verify_against_paths_json`].
+fn verify_archive(
+    file: &File,
+    archive_type: ArchiveType,
+    paths_json: &PathsJson,
+) -> Result<(), ConvertError> {
+    // Compute the size and hash of every regular file in the package.
+    let mut files = HashMap::new();
+    let mut hard_links = Vec::new();
+    match archive_type {
+        ArchiveType::TarBz2 => {
+            collect_files(
+                &mut stream_tar_bz2(BufReader::new(file)),
+                &mut files,
+                &mut hard_links,
+            )?;
+        }
+        ArchiveType::Conda => {
+            let mut file = BufReader::new(file);
+            collect_files(
+                &mut seek::stream_conda_info(&mut file)?,
+                &mut files,
+                &mut hard_links,
+            )?;
+            file.rewind()?;
+            collect_files(
+                &mut seek::stream_conda_content(&mut file)?,
+                &mut files,
+                &mut hard_links,
+            )?;
+        }
+    }
+
+    // Hard links have no data of their own, they have the size and hash of
+    // the file they point to.
+    for (path, target) in hard_links {
+        let target = files.get(&target).cloned().flatten();
+        files.insert(path, target);
+    }
+
+    for entry in &paths_json.paths {
+        let Some(file) = files.get(&entry.relative_path) else {
+            return Err(ConvertError::MissingPath(entry.relative_path.clone()));
+        };
+        if entry.path_type != PathType::HardLink {
+            continue;
+        }
+        let Some((size, hash)) = file else {
+            return Err(ConvertError::PathMismatch(
+                entry.relative_path.clone(),
+                String::from("expected a regular file"),
+            ));
+        };
+        if let Some(expected_size) = entry.size_in_bytes {
+            if expected_size != *size {
+                return Err(ConvertError::PathMismatch(
+                    entry.relative_path.clone(),
+                    format!("expected {expected_size} bytes, got {size} bytes"),
+                ));
+            }
+        }
+        if let Some(expected_hash) = entry.sha256 {
+            if expected_hash != *hash {
+                return Err(ConvertError::PathMismatch(
+                    entry.relative_path.clone(),
+                    format!("expected sha256 {expected_hash:x}, got {hash:x}"),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the size and hash of every regular file in a tar archive. Hard
+/// links are recorded with their target in `hard_links`, other entries are
+/// recorded without a size and hash.
+fn collect_files(
+    archive: &mut tar::Archive<impl Read>,
+    files: &mut HashMap<PathBuf, Option<(u64, Sha256Hash)>>,
+    hard_links: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<(), io::Error> {
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_hard_link() {
+            let target = entry.link_name()?.unwrap_or_default().into_owned();
+            hard_links.push((path.clone(), target));
+            files.insert(path, None);
+        } else if entry_type.is_file() {
+            let mut reader = HashingReader::<_, Sha256>::new(entry);
+            let size = io::copy(&mut reader, &mut io::sink())?;
+            let (_, hash) = reader.finalize();
+            files.insert(path, Some((size, hash)));
+        } else {
+            files.insert(path, None);
+        }
+    }
+    Ok(())
+}
+
+/// Creates a zstd encoder configured with the given options.
+fn zstd_encoder<W: Write>(
+    writer: W,
+    options: &ConvertOptions,
+) -> Result<zstd::Encoder<'static, W>, io::Error> {
+    let mut encoder = zstd::Encoder::new(writer, options.compression_level.to_zstd_level()?)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    encoder.multithread(
+        options
+            .compression_num_threads
+            .unwrap_or_else(|| num_cpus::get() as u32),
+    )?;
+    if let Some(window_log) = options.zstd_window_log {
+        encoder.window_log(window_log)?;
+    }
+    Ok(encoder)
+}
+
+/// Appends an entry from one tar archive to another, preserving the original
+/// header.
+fn copy_entry<W: Write>(
+    archive: &mut tar::Builder<W>,
+    mut entry: tar::Entry<'_, impl Read>,
+) -> Result<(), io::Error> {
+    let mut header = entry.header().clone();
+    let path = entry.path()?.into_owned();
+    let entry_type = header.entry_type();
+    if entry_type.is_symlink() || entry_type.is_hard_link() {
+        let target = entry
+            .link_name()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "link without target"))?
+            .into_owned();
+        archive.append_link(&mut header, path, target)
+    } else {
+        archive.append_data(&mut header, path, &mut entry)
+    }
+}
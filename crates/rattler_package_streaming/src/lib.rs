@@ -11,6 +11,7 @@ use rattler_digest::{Md5Hash, Sha256Hash};
 #[cfg(feature = "reqwest")]
 use rattler_redaction::Redact;
 
+pub mod convert;
 pub mod read;
 pub mod seek;
 
@@ -19,6 +20,7 @@ pub mod reqwest;
 
 pub mod fs;
 pub mod tokio;
+pub mod validate;
 pub mod write;
 
 /// An error that can occur when extracting a package archive.
@@ -63,6 +65,9 @@ pub enum ExtractError {
 
     #[error("could not parse archive member {0}: {1}")]
     ArchiveMemberParseError(PathBuf, #[source] std::io::Error),
+
+    #[error("the signature of {0} could not be verified")]
+    SignatureVerificationFailed(String, #[source] Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl From<ZipError> for ExtractError {
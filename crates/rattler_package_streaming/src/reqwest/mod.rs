@@ -1,2 +1,4 @@
 //! Functionality to stream and extract packages directly from a [`reqwest::Url`].
+pub mod range;
+pub mod resumable;
 pub mod tokio;
@@ -0,0 +1,584 @@
+//! Functionality to read individual members of a remote `.conda` archive using
+//! HTTP range requests.
+//!
+//! A `.conda` archive is an uncompressed zip file that contains (among others)
+//! an `info-*.tar.zst` and a `pkg-*.tar.zst` member. To read metadata from the
+//! package only the zip central directory and the `info-*.tar.zst` member are
+//! required, which for large packages is only a tiny fraction of the total
+//! archive size.
+//!
+//! All requests are sent through the provided
+//! [`reqwest_middleware::ClientWithMiddleware`] so authentication, mirrors and
+//! other middleware keep working. If the server does not support range
+//! requests the whole archive is downloaded instead.
+
+use std::{io::Cursor, ops::Range, path::Path};
+
+use bytes::Bytes;
+use rattler_conda_types::package::{ArchiveType, PackageFile};
+use reqwest::{header, Response, StatusCode};
+use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
+use zip::result::ZipError;
+
+use crate::ExtractError;
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const EOCD_SIZE: usize = 22;
+const ZIP64_EOCD_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP64_EOCD_LOCATOR_SIZE: usize = 20;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_EOCD_SIZE: usize = 56;
+const MAX_COMMENT_SIZE: u64 = u16::MAX as u64;
+
+/// The number of bytes requested from the end of the archive in the first
+/// request. This is exactly the size of the (zip64) end of central directory
+/// records of an archive without a comment, which `.conda` archives never
+/// have. Requesting more could overlap with the `pkg-*.tar.zst` member if the
+/// `info-*.tar.zst` member is small.
+const TAIL_SIZE: u64 = (EOCD_SIZE + ZIP64_EOCD_LOCATOR_SIZE + ZIP64_EOCD_SIZE) as u64;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const LOCAL_HEADER_SIZE: usize = 30;
+const ZIP64_EXTRA_FIELD_TAG: u16 = 0x0001;
+const COMPRESSION_METHOD_STORED: u16 = 0;
+
+/// A single member of the zip central directory.
+#[derive(Debug, Clone)]
+struct CentralDirectoryEntry {
+    name: String,
+    compression_method: u16,
+    compressed_size: u64,
+    header_offset: u64,
+}
+
+/// The bytes of the remote archive that have been fetched so far.
+#[derive(Debug)]
+enum ArchiveBytes {
+    /// The server supports range requests, only parts of the archive are
+    /// available.
+    Partial { total_size: u64 },
+
+    /// The server ignored the range request and sent the entire archive.
+    Full(Bytes),
+}
+
+/// A remote `.conda` archive whose members can be read individually through
+/// HTTP range requests.
+///
+/// Opening the archive fetches the zip central directory, after which
+/// individual members can be read with [`RemoteCondaArchive::read_member`].
+///
+/// ```rust,no_run
+/// # #[tokio::main]
+/// # async fn main() {
+/// use rattler_conda_types::package::IndexJson;
+/// use rattler_package_streaming::reqwest::range::RemoteCondaArchive;
+/// use reqwest::Client;
+/// use reqwest_middleware::ClientWithMiddleware;
+/// use url::Url;
+///
+/// let archive = RemoteCondaArchive::open(
+///     ClientWithMiddleware::from(Client::new()),
+///     Url::parse("https://conda.anaconda.org/conda-forge/linux-64/python-3.10.8-h4a9ceb5_0_cpython.conda").unwrap(),
+/// )
+/// .await
+/// .unwrap();
+/// let index_json: IndexJson = archive.read_package_file().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RemoteCondaArchive {
+    client: ClientWithMiddleware,
+    url: Url,
+    bytes: ArchiveBytes,
+    entries: Vec<CentralDirectoryEntry>,
+    central_directory_offset: u64,
+}
+
+impl RemoteCondaArchive {
+    /// Opens the remote archive at the given url by fetching its central
+    /// directory.
+    pub async fn open(client: ClientWithMiddleware, url: Url) -> Result<Self, ExtractError> {
+        if ArchiveType::try_from(Path::new(url.path())) != Some(ArchiveType::Conda) {
+            return Err(ExtractError::UnsupportedArchiveType);
+        }
+
+        let (tail_range, tail, bytes) = match fetch_tail(&client, &url).await? {
+            RangeResponse::Partial {
+                range,
+                total_size,
+                bytes,
+            } => (range, bytes, ArchiveBytes::Partial { total_size }),
+            RangeResponse::Full(bytes) => (
+                0..bytes.len() as u64,
+                bytes.clone(),
+                ArchiveBytes::Full(bytes),
+            ),
+        };
+
+        let mut archive = Self {
+            client,
+            url,
+            bytes,
+            entries: Vec::new(),
+            central_directory_offset: 0,
+        };
+
+        // If the archive has a comment the end of central directory record may
+        // not be part of the tail, in that case request the largest possible
+        // comment in front of it.
+        let (tail_range, tail) = if find_eocd(&tail).is_none() && tail_range.start > 0 {
+            let start = tail_range.start.saturating_sub(MAX_COMMENT_SIZE);
+            let head = archive.fetch(start..tail_range.start).await?;
+            let mut bytes = Vec::with_capacity(head.len() + tail.len());
+            bytes.extend_from_slice(&head);
+            bytes.extend_from_slice(&tail);
+            (start..tail_range.end, Bytes::from(bytes))
+        } else {
+            (tail_range, tail)
+        };
+
+        // Locate the central directory from the end of central directory record.
+        let (cd_offset, cd_size) = find_central_directory(&archive, &tail_range, &tail).await?;
+
+        // Reuse the tail if it already contains the whole central directory.
+        let cd_range = cd_offset..cd_offset + cd_size;
+        if cd_range.end > tail_range.end {
+            return Err(invalid_archive("central directory exceeds archive bounds"));
+        }
+        let central_directory = if cd_range.start >= tail_range.start {
+            let start = (cd_range.start - tail_range.start) as usize;
+            tail.slice(start..start + cd_size as usize)
+        } else {
+            archive.fetch(cd_range).await?
+        };
+
+        archive.entries = parse_central_directory(&central_directory)?;
+        archive.central_directory_offset = cd_offset;
+        Ok(archive)
+    }
+
+    /// Returns the url of the archive.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the names of all members in the archive.
+    pub fn file_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    /// Returns the name of the `info-*.tar.zst` member in the archive.
+    fn info_member_name(&self) -> Result<&str, ExtractError> {
+        self.file_names()
+            .find(|file_name| file_name.starts_with("info-") && file_name.ends_with(".tar.zst"))
+            .ok_or(ExtractError::MissingComponent)
+    }
+
+    /// Reads the raw bytes of a single member of the archive. Only the bytes
+    /// of the member itself are requested from the server.
+    pub async fn read_member(&self, name: &str) -> Result<Bytes, ExtractError> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or(ExtractError::MissingComponent)?;
+
+        // Conda archives only store uncompressed members.
+        if entry.compression_method != COMPRESSION_METHOD_STORED {
+            return Err(ExtractError::UnsupportedCompressionMethod);
+        }
+
+        // The local header is followed by a name and extra field whose sizes may
+        // differ from those in the central directory. Members are stored
+        // contiguously so we request everything up to the next member which
+        // contains both the local header and the data in a single request.
+        let end = self
+            .entries
+            .iter()
+            .map(|other| other.header_offset)
+            .filter(|&offset| offset > entry.header_offset)
+            .min()
+            .unwrap_or(self.central_directory_offset);
+        let member = self.fetch(entry.header_offset..end).await?;
+
+        if member.len() < LOCAL_HEADER_SIZE || read_u32(&member, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid_archive("invalid local file header"));
+        }
+        let name_len = read_u16(&member, 26) as usize;
+        let extra_len = read_u16(&member, 28) as usize;
+        let data_start = LOCAL_HEADER_SIZE + name_len + extra_len;
+        let data_end = data_start as u64 + entry.compressed_size;
+        if data_end > member.len() as u64 {
+            return Err(invalid_archive("member data exceeds archive bounds"));
+        }
+
+        Ok(member.slice(data_start..data_end as usize))
+    }
+
+    /// Reads the raw bytes of the `info-*.tar.zst` member of the archive.
+    pub async fn read_info_section(&self) -> Result<Bytes, ExtractError> {
+        let name = self.info_member_name()?.to_owned();
+        self.read_member(&name).await
+    }
+
+    /// Reads the contents of a file from the info section of the archive.
+    pub async fn read_package_file_content(
+        &self,
+        package_path: impl AsRef<Path>,
+    ) -> Result<Vec<u8>, ExtractError> {
+        let info_section = self.read_info_section().await?;
+        let package_path = package_path.as_ref().to_path_buf();
+        simple_spawn_blocking::tokio::run_blocking_task(move || {
+            let mut archive = crate::read::stream_tar_zst(Cursor::new(info_section))?;
+            crate::seek::get_file_from_archive(&mut archive, &package_path)
+        })
+        .await
+    }
+
+    /// Reads and parses a [`PackageFile`] from the info section of the archive.
+    pub async fn read_package_file<P: PackageFile>(&self) -> Result<P, ExtractError> {
+        let content = self.read_package_file_content(P::package_path()).await?;
+        P::from_str(&String::from_utf8_lossy(&content))
+            .map_err(|e| ExtractError::ArchiveMemberParseError(P::package_path().to_owned(), e))
+    }
+
+    /// Returns the bytes in the given range of the archive.
+    async fn fetch(&self, range: Range<u64>) -> Result<Bytes, ExtractError> {
+        match &self.bytes {
+            ArchiveBytes::Full(bytes) => {
+                if range.end > bytes.len() as u64 {
+                    return Err(invalid_archive("range exceeds archive bounds"));
+                }
+                Ok(bytes.slice(range.start as usize..range.end as usize))
+            }
+            ArchiveBytes::Partial { total_size } => {
+                if range.end > *total_size {
+                    return Err(invalid_archive("range exceeds archive bounds"));
+                }
+                if range.is_empty() {
+                    return Ok(Bytes::new());
+                }
+                let header = format!("bytes={}-{}", range.start, range.end - 1);
+                match send_range_request(&self.client, &self.url, header).await? {
+                    RangeResponse::Partial {
+                        range: received,
+                        bytes,
+                        ..
+                    } if received == range => Ok(bytes),
+                    RangeResponse::Partial { .. } => Err(ExtractError::IoError(
+                        std::io::Error::other("server responded with an unexpected byte range"),
+                    )),
+                    RangeResponse::Full(bytes) => {
+                        if range.end > bytes.len() as u64 {
+                            return Err(invalid_archive("range exceeds archive bounds"));
+                        }
+                        Ok(bytes.slice(range.start as usize..range.end as usize))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads the contents of a file from the info section of a remote `.conda`
+/// archive without downloading the entire archive.
+pub async fn fetch_package_file_content(
+    client: ClientWithMiddleware,
+    url: Url,
+    package_path: impl AsRef<Path>,
+) -> Result<Vec<u8>, ExtractError> {
+    RemoteCondaArchive::open(client, url)
+        .await?
+        .read_package_file_content(package_path)
+        .await
+}
+
+/// Reads a [`PackageFile`] from a remote `.conda` archive without downloading
+/// the entire archive.
+///
+/// ```rust,no_run
+/// # #[tokio::main]
+/// # async fn main() {
+/// use rattler_conda_types::package::RunExportsJson;
+/// use rattler_package_streaming::reqwest::range::fetch_package_file;
+/// use reqwest::Client;
+/// use reqwest_middleware::ClientWithMiddleware;
+/// use url::Url;
+///
+/// let run_exports: RunExportsJson = fetch_package_file(
+///     ClientWithMiddleware::from(Client::new()),
+///     Url::parse("https://conda.anaconda.org/conda-forge/linux-64/python-3.10.8-h4a9ceb5_0_cpython.conda").unwrap(),
+/// )
+/// .await
+/// .unwrap();
+/// # }
+/// ```
+pub async fn fetch_package_file<P: PackageFile>(
+    client: ClientWithMiddleware,
+    url: Url,
+) -> Result<P, ExtractError> {
+    RemoteCondaArchive::open(client, url)
+        .await?
+        .read_package_file()
+        .await
+}
+
+/// The response to a range request.
+enum RangeResponse {
+    /// The server returned the requested range.
+    Partial {
+        range: Range<u64>,
+        total_size: u64,
+        bytes: Bytes,
+    },
+
+    /// The server ignored the range and returned the entire body.
+    Full(Bytes),
+}
+
+/// Requests the last [`TAIL_SIZE`] bytes of the archive.
+async fn fetch_tail(
+    client: &ClientWithMiddleware,
+    url: &Url,
+) -> Result<RangeResponse, ExtractError> {
+    let response = send_request(client, url, Some(format!("bytes=-{TAIL_SIZE}"))).await?;
+
+    // Some servers reject suffix ranges that are larger than the file itself
+    // instead of returning the whole file. They do report the size of the file
+    // though, so we can request the entire file as a regular range instead.
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        let total_size = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("bytes */"))
+            .and_then(|total| total.trim().parse::<u64>().ok())
+            .filter(|&total| total > 0);
+        let range = total_size.map(|total| format!("bytes=0-{}", total - 1));
+        let response = send_request(client, url, range).await?;
+        return into_range_response(url, response).await;
+    }
+
+    into_range_response(url, response).await
+}
+
+/// Sends a GET request for the given url with an optional `Range` header.
+async fn send_request(
+    client: &ClientWithMiddleware,
+    url: &Url,
+    range: Option<String>,
+) -> Result<Response, ExtractError> {
+    let mut request = client.get(url.clone());
+    if let Some(range) = range {
+        request = request.header(header::RANGE, range);
+    }
+    Ok(request.send().await?)
+}
+
+/// Sends a range request for the given url.
+async fn send_range_request(
+    client: &ClientWithMiddleware,
+    url: &Url,
+    range: String,
+) -> Result<RangeResponse, ExtractError> {
+    let response = send_request(client, url, Some(range)).await?;
+    into_range_response(url, response).await
+}
+
+/// Reads the body of a response to a range request.
+async fn into_range_response(url: &Url, response: Response) -> Result<RangeResponse, ExtractError> {
+    let response = response
+        .error_for_status()
+        .map_err(reqwest_middleware::Error::Reqwest)?;
+
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        tracing::debug!(
+            "server does not support range requests for '{}', downloading the entire archive",
+            url
+        );
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(reqwest_middleware::Error::Reqwest)?;
+        return Ok(RangeResponse::Full(bytes));
+    }
+
+    let (range, total_size) = response
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_content_range)
+        .ok_or_else(|| {
+            ExtractError::IoError(std::io::Error::other(
+                "missing or invalid Content-Range header in response",
+            ))
+        })?;
+
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(reqwest_middleware::Error::Reqwest)?;
+    if bytes.len() as u64 != range.end - range.start {
+        return Err(ExtractError::IoError(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "received fewer bytes than requested",
+        )));
+    }
+
+    Ok(RangeResponse::Partial {
+        range,
+        total_size,
+        bytes,
+    })
+}
+
+/// Parses a `Content-Range` header of the form `bytes <start>-<end>/<total>`
+/// into an exclusive range and the total size of the resource.
+fn parse_content_range(value: &str) -> Option<(Range<u64>, u64)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    let total: u64 = total.trim().parse().ok()?;
+    (start <= end && end < total).then_some((start..end + 1, total))
+}
+
+/// Locates the central directory using the end of central directory record
+/// that is stored at the end of the archive. Returns the offset and size of the
+/// central directory.
+async fn find_central_directory(
+    archive: &RemoteCondaArchive,
+    tail_range: &Range<u64>,
+    tail: &Bytes,
+) -> Result<(u64, u64), ExtractError> {
+    let eocd_pos = find_eocd(tail)
+        .ok_or_else(|| invalid_archive("could not find end of central directory record"))?;
+
+    let cd_size = u64::from(read_u32(tail, eocd_pos + 12));
+    let cd_offset = u64::from(read_u32(tail, eocd_pos + 16));
+    if cd_size != u64::from(u32::MAX) && cd_offset != u64::from(u32::MAX) {
+        return Ok((cd_offset, cd_size));
+    }
+
+    // Large archives store the actual values in the zip64 end of central
+    // directory record which is referenced by the locator preceding the end of
+    // central directory record.
+    let locator_pos = eocd_pos
+        .checked_sub(ZIP64_EOCD_LOCATOR_SIZE)
+        .filter(|&pos| read_u32(tail, pos) == ZIP64_EOCD_LOCATOR_SIGNATURE)
+        .ok_or_else(|| invalid_archive("could not find zip64 end of central directory locator"))?;
+    let zip64_eocd_offset = read_u64(tail, locator_pos + 8);
+    let zip64_eocd_range = zip64_eocd_offset..zip64_eocd_offset + ZIP64_EOCD_SIZE as u64;
+    if zip64_eocd_range.end > tail_range.end {
+        return Err(invalid_archive(
+            "zip64 end of central directory record exceeds archive bounds",
+        ));
+    }
+    let zip64_eocd = if zip64_eocd_range.start >= tail_range.start {
+        let start = (zip64_eocd_range.start - tail_range.start) as usize;
+        tail.slice(start..start + ZIP64_EOCD_SIZE)
+    } else {
+        archive.fetch(zip64_eocd_range).await?
+    };
+    if zip64_eocd.len() < ZIP64_EOCD_SIZE || read_u32(&zip64_eocd, 0) != ZIP64_EOCD_SIGNATURE {
+        return Err(invalid_archive(
+            "invalid zip64 end of central directory record",
+        ));
+    }
+
+    Ok((read_u64(&zip64_eocd, 48), read_u64(&zip64_eocd, 40)))
+}
+
+/// Returns the position of the end of central directory record in the tail of
+/// an archive. The record is followed by a variable length comment so we have
+/// to search backwards for its signature.
+fn find_eocd(tail: &[u8]) -> Option<usize> {
+    if tail.len() < EOCD_SIZE {
+        return None;
+    }
+    (0..=tail.len() - EOCD_SIZE)
+        .rev()
+        .find(|&pos| read_u32(tail, pos) == EOCD_SIGNATURE)
+}
+
+/// Parses all the entries in the central directory.
+fn parse_central_directory(bytes: &[u8]) -> Result<Vec<CentralDirectoryEntry>, ExtractError> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes.len() - pos < CENTRAL_HEADER_SIZE
+            || read_u32(bytes, pos) != CENTRAL_HEADER_SIGNATURE
+        {
+            return Err(invalid_archive("invalid central directory file header"));
+        }
+
+        let compression_method = read_u16(bytes, pos + 10);
+        let mut compressed_size = u64::from(read_u32(bytes, pos + 20));
+        let mut uncompressed_size = u64::from(read_u32(bytes, pos + 24));
+        let name_len = read_u16(bytes, pos + 28) as usize;
+        let extra_len = read_u16(bytes, pos + 30) as usize;
+        let comment_len = read_u16(bytes, pos + 32) as usize;
+        let mut header_offset = u64::from(read_u32(bytes, pos + 42));
+
+        let name_start = pos + CENTRAL_HEADER_SIZE;
+        let extra_start = name_start + name_len;
+        let next = extra_start + extra_len + comment_len;
+        if next > bytes.len() {
+            return Err(invalid_archive(
+                "central directory file header out of bounds",
+            ));
+        }
+        let name = String::from_utf8_lossy(&bytes[name_start..extra_start]).into_owned();
+
+        // Values that do not fit in 32 bits are stored in the zip64 extra field in
+        // a fixed order, but only if the corresponding header field is saturated.
+        let mut extra = &bytes[extra_start..extra_start + extra_len];
+        while extra.len() >= 4 {
+            let tag = read_u16(extra, 0);
+            let len = (read_u16(extra, 2) as usize).min(extra.len() - 4);
+            if tag == ZIP64_EXTRA_FIELD_TAG {
+                let mut field = &extra[4..4 + len];
+                for value in [
+                    &mut uncompressed_size,
+                    &mut compressed_size,
+                    &mut header_offset,
+                ] {
+                    if *value == u64::from(u32::MAX) && field.len() >= 8 {
+                        *value = read_u64(field, 0);
+                        field = &field[8..];
+                    }
+                }
+            }
+            extra = &extra[4 + len..];
+        }
+
+        entries.push(CentralDirectoryEntry {
+            name,
+            compression_method,
+            compressed_size,
+            header_offset,
+        });
+        pos = next;
+    }
+
+    Ok(entries)
+}
+
+fn invalid_archive(message: &'static str) -> ExtractError {
+    ExtractError::ZipError(ZipError::InvalidArchive(message.into()))
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(bytes[pos..pos + 2].try_into().expect("slice has length 2"))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().expect("slice has length 4"))
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(bytes[pos..pos + 8].try_into().expect("slice has length 8"))
+}
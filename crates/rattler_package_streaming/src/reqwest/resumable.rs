@@ -0,0 +1,271 @@
+//! Functionality to download a package archive to a file before extracting
+//! it, resuming interrupted downloads with HTTP range requests.
+//!
+//! The functions in [`super::tokio`] stream the archive straight into the
+//! extraction. If the connection drops halfway through, the download has to
+//! start over from the beginning. For large archives it is often better to
+//! keep the bytes that were already received: [`extract`] writes the archive
+//! to a partial file first and, if the download is interrupted, the next call
+//! with the same partial file continues where the previous one stopped. The
+//! archive is only extracted once the complete file matches the expected
+//! hash.
+
+use std::{io::ErrorKind, path::Path, sync::Arc};
+
+use fs_err::tokio as tokio_fs;
+use futures_util::StreamExt;
+use rattler_conda_types::package::ArchiveType;
+use rattler_digest::{HashingReader, Md5, Md5Hash, Sha256, Sha256Hash};
+use rattler_redaction::Redact;
+use reqwest::{header, StatusCode};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::{DownloadReporter, ExtractError, ExtractResult};
+
+/// Downloads the archive at `url` to `partial_path`.
+///
+/// If `partial_path` already contains the first part of the archive (e.g.
+/// from a previous interrupted call) only the remaining bytes are requested.
+/// If the server does not support range requests the download starts over.
+///
+/// If the connection is interrupted an [`ExtractError::IoError`] is returned
+/// and the bytes that were received so far are kept in `partial_path`.
+///
+/// Returns the total size of the archive.
+pub async fn download(
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+    partial_path: &Path,
+    expected_sha256: Option<Sha256Hash>,
+    reporter: Option<Arc<dyn DownloadReporter>>,
+) -> Result<u64, ExtractError> {
+    if let Some(parent) = partial_path.parent() {
+        tokio_fs::create_dir_all(parent)
+            .await
+            .map_err(ExtractError::CouldNotCreateDestination)?;
+    }
+    let file = tokio_fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(partial_path)
+        .await
+        .map_err(ExtractError::CouldNotCreateDestination)?;
+    let offset = file.metadata().await?.len();
+
+    let mut request = client.get(url.clone());
+    if let Some(sha256) = expected_sha256 {
+        // This is used by the OCI registry middleware to verify the sha256 of the
+        // response
+        request = request.header("X-Expected-Sha256", format!("{sha256:x}"));
+    }
+    if offset > 0 {
+        request = request.header(header::RANGE, format!("bytes={offset}-"));
+    }
+    let response = request.send().await?;
+
+    let (response, offset, total_bytes) = match response.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let (start, total) = parse_content_range(response.headers());
+            if start != Some(offset) {
+                file.set_len(0).await?;
+                return Err(ExtractError::IoError(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "the server returned a different range than requested",
+                )));
+            }
+            (response, offset, total)
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            // Either the partial file already contains the entire archive or
+            // the archive changed on the server.
+            let (_, total) = parse_content_range(response.headers());
+            if total == Some(offset) {
+                return Ok(offset);
+            }
+            file.set_len(0).await?;
+            return Err(ExtractError::IoError(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "the partial download is larger than the remote archive",
+            )));
+        }
+        _ => {
+            // The server ignored the range request, start from scratch.
+            let response = response
+                .error_for_status()
+                .map_err(reqwest_middleware::Error::Reqwest)?;
+            file.set_len(0).await?;
+            let total_bytes = response.content_length();
+            (response, 0, total_bytes)
+        }
+    };
+
+    write_body(response, file, offset, total_bytes, reporter).await
+}
+
+/// Appends the body of the response to the file.
+async fn write_body(
+    response: reqwest::Response,
+    mut file: tokio_fs::File,
+    mut offset: u64,
+    total_bytes: Option<u64>,
+    reporter: Option<Arc<dyn DownloadReporter>>,
+) -> Result<u64, ExtractError> {
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|err| std::io::Error::new(ErrorKind::Interrupted, err))?;
+        file.write_all(&chunk).await?;
+        offset += chunk.len() as u64;
+        if let Some(reporter) = &reporter {
+            reporter.on_download_progress(offset, total_bytes);
+        }
+    }
+    file.flush().await?;
+    Ok(offset)
+}
+
+/// Parses the start and the total size from a `Content-Range` header of the
+/// form `bytes <start>-<end>/<total>` or `bytes */<total>`.
+fn parse_content_range(headers: &header::HeaderMap) -> (Option<u64>, Option<u64>) {
+    let Some(range) = headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes "))
+    else {
+        return (None, None);
+    };
+    let (range, total) = range.split_once('/').unwrap_or((range, "*"));
+    let start = range
+        .split_once('-')
+        .and_then(|(start, _)| start.trim().parse().ok());
+    (start, total.trim().parse().ok())
+}
+
+/// Downloads the archive at `url` to `partial_path`, verifies its hash and
+/// extracts it to `destination`.
+///
+/// If the download is interrupted an [`ExtractError::IoError`] is returned
+/// and calling this function again with the same `partial_path` resumes the
+/// download. Only one of the hashes is checked: the sha256 hash if it is
+/// known, otherwise the md5 hash. If the hash does not match the partial file
+/// is removed so that the next attempt starts from scratch.
+///
+/// ```rust,no_run
+/// # #[tokio::main]
+/// # async fn main() {
+/// # use std::path::Path;
+/// use rattler_package_streaming::reqwest::resumable::extract;
+/// use reqwest::Client;
+/// use reqwest_middleware::ClientWithMiddleware;
+/// use url::Url;
+/// let _ = extract(
+///     ClientWithMiddleware::from(Client::new()),
+///     Url::parse("https://conda.anaconda.org/conda-forge/linux-64/python-3.10.8-h4a9ceb5_0_cpython.conda").unwrap(),
+///     Path::new("/tmp/python"),
+///     Path::new("/tmp/python.partial"),
+///     None,
+///     None,
+///     None)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub async fn extract(
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+    destination: &Path,
+    partial_path: &Path,
+    expected_sha256: Option<Sha256Hash>,
+    expected_md5: Option<Md5Hash>,
+    reporter: Option<Arc<dyn DownloadReporter>>,
+) -> Result<ExtractResult, ExtractError> {
+    // Don't download archives that cannot be extracted.
+    ArchiveType::try_from(Path::new(url.path())).ok_or(ExtractError::UnsupportedArchiveType)?;
+
+    if let Some(reporter) = &reporter {
+        reporter.on_download_start();
+    }
+    download(
+        client,
+        url.clone(),
+        partial_path,
+        expected_sha256,
+        reporter.clone(),
+    )
+    .await?;
+
+    let result = extract_partial(
+        &url,
+        destination,
+        partial_path,
+        expected_sha256,
+        expected_md5,
+    )
+    .await?;
+    if let Some(reporter) = &reporter {
+        reporter.on_download_complete();
+    }
+    Ok(result)
+}
+
+/// Verifies the hash of an archive that was completely downloaded to
+/// `partial_path` with [`download`] and extracts it to `destination`.
+///
+/// Together with [`download`] this makes it possible to inspect the archive,
+/// e.g. to verify its signature, before it is extracted. Like with
+/// [`extract`] the partial file is removed if the hash does not match or once
+/// the archive has been extracted.
+pub async fn extract_partial(
+    url: &Url,
+    destination: &Path,
+    partial_path: &Path,
+    expected_sha256: Option<Sha256Hash>,
+    expected_md5: Option<Md5Hash>,
+) -> Result<ExtractResult, ExtractError> {
+    let archive_type =
+        ArchiveType::try_from(Path::new(url.path())).ok_or(ExtractError::UnsupportedArchiveType)?;
+
+    // Verify the archive before extracting it.
+    let archive = partial_path.to_path_buf();
+    let (sha256, md5, total_size) = simple_spawn_blocking::tokio::run_blocking_task(move || {
+        let mut reader = HashingReader::<_, Md5>::new(HashingReader::<_, Sha256>::new(
+            fs_err::File::open(&archive)?,
+        ));
+        let total_size = std::io::copy(&mut reader, &mut std::io::sink())?;
+        let (reader, md5) = reader.finalize();
+        let (_, sha256) = reader.finalize();
+        Ok::<_, ExtractError>((sha256, md5, total_size))
+    })
+    .await?;
+
+    let mismatch = match (expected_sha256, expected_md5) {
+        (Some(expected), _) if expected != sha256 => {
+            Some((format!("{expected:x}"), format!("{sha256:x}")))
+        }
+        (None, Some(expected)) if expected != md5 => {
+            Some((format!("{expected:x}"), format!("{md5:x}")))
+        }
+        _ => None,
+    };
+    if let Some((expected, actual)) = mismatch {
+        tokio_fs::remove_file(partial_path).await?;
+        return Err(ExtractError::HashMismatch {
+            url: url.clone().redact().to_string(),
+            destination: destination.display().to_string(),
+            expected,
+            actual,
+            total_size,
+        });
+    }
+
+    let result = match archive_type {
+        ArchiveType::TarBz2 => crate::tokio::fs::extract_tar_bz2(partial_path, destination).await,
+        ArchiveType::Conda => crate::tokio::fs::extract_conda(partial_path, destination).await,
+    };
+
+    // The partial file is either extracted or corrupt, in both cases it is no
+    // longer needed.
+    tokio_fs::remove_file(partial_path).await?;
+
+    result
+}
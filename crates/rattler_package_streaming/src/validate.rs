@@ -0,0 +1,556 @@
+//! Functionality to validate the consistency of a package archive.
+//!
+//! The validator reads a `.conda` or `.tar.bz2` archive and checks that the
+//! metadata in the `info/` directory agrees with the filename and with the
+//! actual contents of the package. All problems that are found are collected
+//! in a [`ValidationReport`] instead of stopping at the first one.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+
+use rattler_conda_types::package::{
+    ArchiveIdentifier, ArchiveType, IndexJson, PackageFile, PathType, PathsJson, RunExportsJson,
+};
+use rattler_digest::{HashingReader, Sha256, Sha256Hash};
+
+use crate::{read::stream_tar_bz2, seek, ExtractError};
+
+/// A problem found while validating a package.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum ValidationError {
+    #[error("the package does not contain info/index.json")]
+    MissingIndexJson,
+
+    #[error("failed to parse info/index.json: {0}")]
+    InvalidIndexJson(String),
+
+    #[error("the package does not contain info/paths.json")]
+    MissingPathsJson,
+
+    #[error("failed to parse info/paths.json: {0}")]
+    InvalidPathsJson(String),
+
+    #[error("failed to parse info/run_exports.json: {0}")]
+    InvalidRunExportsJson(String),
+
+    #[error("'{0}' is not a valid package filename")]
+    InvalidFileName(String),
+
+    #[error(
+        "the {field} in the filename ('{file_name}') does not match index.json ('{index_json}')"
+    )]
+    FileNameMismatch {
+        field: &'static str,
+        file_name: String,
+        index_json: String,
+    },
+
+    #[error("{} is listed in paths.json but is missing from the package", .0.display())]
+    MissingFile(PathBuf),
+
+    #[error("{} is part of the package but is not listed in paths.json", .0.display())]
+    UnlistedFile(PathBuf),
+
+    #[error("{} is listed in paths.json as {expected:?} but the package contains a different kind of entry", path.display())]
+    PathTypeMismatch { path: PathBuf, expected: PathType },
+
+    #[error("{} is {actual} bytes but paths.json lists {expected} bytes", path.display())]
+    SizeMismatch {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("{} has sha256 {actual:x} but paths.json lists {expected:x}", path.display())]
+    Sha256Mismatch {
+        path: PathBuf,
+        expected: Sha256Hash,
+        actual: Sha256Hash,
+    },
+
+    #[error("{} does not contain the prefix placeholder '{placeholder}'", path.display())]
+    MissingPrefixPlaceholder { path: PathBuf, placeholder: String },
+
+    #[error("the symlink {} points to {} which is outside of the prefix", path.display(), target.display())]
+    SymlinkEscapesPrefix { path: PathBuf, target: PathBuf },
+
+    #[error("{} is a compiled python file in a noarch: python package", .0.display())]
+    CompiledFileInNoarchPython(PathBuf),
+}
+
+/// The result of validating a package.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// All problems that were found in the package.
+    pub errors: Vec<ValidationError>,
+}
+
+impl ValidationReport {
+    /// Returns true if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Validates the package archive at the given path. The archive type and the
+/// expected name, version and build string are derived from the filename.
+///
+/// An `Err` is only returned if the archive itself could not be read. Problems
+/// with the contents of the package are reported in the [`ValidationReport`].
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// use rattler_package_streaming::validate::validate_package;
+/// let report = validate_package(Path::new("conda-forge/win-64/python-3.11.0-hcf16a7b_0_cpython.conda")).unwrap();
+/// for error in &report.errors {
+///     eprintln!("{error}");
+/// }
+/// ```
+pub fn validate_package(path: &Path) -> Result<ValidationReport, ExtractError> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    validate_package_reader(BufReader::new(File::open(path)?), &file_name)
+}
+
+/// Validates a package archive read from `reader`. `file_name` is the filename
+/// of the package which is used to determine the archive type and is checked
+/// against `info/index.json`.
+pub fn validate_package_reader(
+    mut reader: impl Read + Seek,
+    file_name: &str,
+) -> Result<ValidationReport, ExtractError> {
+    let archive_type =
+        ArchiveType::try_from(file_name).ok_or(ExtractError::UnsupportedArchiveType)?;
+
+    let info = match archive_type {
+        ArchiveType::TarBz2 => read_info_files(&mut stream_tar_bz2(&mut reader))?,
+        ArchiveType::Conda => read_info_files(&mut seek::stream_conda_info(&mut reader)?)?,
+    };
+
+    let mut errors = Vec::new();
+    let index_json = match info.index_json.as_deref().map(IndexJson::from_str) {
+        Some(Ok(index_json)) => Some(index_json),
+        Some(Err(e)) => {
+            errors.push(ValidationError::InvalidIndexJson(e.to_string()));
+            None
+        }
+        None => {
+            errors.push(ValidationError::MissingIndexJson);
+            None
+        }
+    };
+    let paths_json = match info.paths_json.as_deref().map(PathsJson::from_str) {
+        Some(Ok(paths_json)) => Some(paths_json),
+        Some(Err(e)) => {
+            errors.push(ValidationError::InvalidPathsJson(e.to_string()));
+            None
+        }
+        None => {
+            errors.push(ValidationError::MissingPathsJson);
+            None
+        }
+    };
+    if let Some(Err(e)) = info
+        .run_exports_json
+        .as_deref()
+        .map(RunExportsJson::from_str)
+    {
+        errors.push(ValidationError::InvalidRunExportsJson(e.to_string()));
+    }
+
+    if let Some(index_json) = &index_json {
+        validate_file_name(file_name, index_json, &mut errors);
+    }
+
+    // Files with a prefix placeholder are read into memory so we can search for
+    // the placeholder.
+    let placeholders: HashMap<&Path, &str> = paths_json
+        .iter()
+        .flat_map(|paths_json| &paths_json.paths)
+        .filter_map(|entry| {
+            entry
+                .prefix_placeholder
+                .as_ref()
+                .map(|p| (entry.relative_path.as_path(), p.placeholder.as_str()))
+        })
+        .collect();
+
+    reader.rewind()?;
+    let mut contents = match archive_type {
+        ArchiveType::TarBz2 => read_contents(&mut stream_tar_bz2(&mut reader), &placeholders)?,
+        ArchiveType::Conda => {
+            read_contents(&mut seek::stream_conda_content(&mut reader)?, &placeholders)?
+        }
+    };
+
+    // Hard links have no data of their own. If a hard link has a different
+    // placeholder than its target, the target has to be read again to search
+    // for it.
+    let unscanned = resolve_hard_links(&mut contents, &placeholders);
+    if !unscanned.is_empty() {
+        reader.rewind()?;
+        let found = match archive_type {
+            ArchiveType::TarBz2 => find_placeholders(&mut stream_tar_bz2(&mut reader), &unscanned)?,
+            ArchiveType::Conda => {
+                find_placeholders(&mut seek::stream_conda_content(&mut reader)?, &unscanned)?
+            }
+        };
+        for link in found {
+            if let Some(ContentEntry::File {
+                contains_placeholder,
+                ..
+            }) = contents.get_mut(&link)
+            {
+                *contains_placeholder = true;
+            }
+        }
+    }
+
+    if let Some(paths_json) = &paths_json {
+        validate_paths(paths_json, &contents, &mut errors);
+    }
+
+    for (path, entry) in &contents {
+        if let ContentEntry::Symlink(target) = entry {
+            if escapes_prefix(path, target) {
+                errors.push(ValidationError::SymlinkEscapesPrefix {
+                    path: path.clone(),
+                    target: target.clone(),
+                });
+            }
+        }
+    }
+
+    if index_json
+        .as_ref()
+        .is_some_and(|index_json| index_json.noarch.is_python())
+    {
+        for path in contents.keys().filter(|path| is_compiled_python_file(path)) {
+            errors.push(ValidationError::CompiledFileInNoarchPython(path.clone()));
+        }
+    }
+
+    Ok(ValidationReport { errors })
+}
+
+/// The raw contents of the info files that are validated.
+#[derive(Default)]
+struct InfoFiles {
+    index_json: Option<String>,
+    paths_json: Option<String>,
+    run_exports_json: Option<String>,
+}
+
+/// An entry in the content section of a package.
+enum ContentEntry {
+    File {
+        size: u64,
+        sha256: Sha256Hash,
+        contains_placeholder: bool,
+    },
+    Symlink(PathBuf),
+    HardLink(PathBuf),
+    Directory,
+    Other,
+}
+
+/// Reads the info files that are validated from a tar archive.
+fn read_info_files(archive: &mut tar::Archive<impl Read>) -> Result<InfoFiles, io::Error> {
+    let mut info = InfoFiles::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let slot = if path == Path::new("info/index.json") {
+            &mut info.index_json
+        } else if path == Path::new("info/paths.json") {
+            &mut info.paths_json
+        } else if path == Path::new("info/run_exports.json") {
+            &mut info.run_exports_json
+        } else {
+            continue;
+        };
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        *slot = Some(content);
+    }
+    Ok(info)
+}
+
+/// Records every entry outside of the `info/` directory of a tar archive.
+fn read_contents(
+    archive: &mut tar::Archive<impl Read>,
+    placeholders: &HashMap<&Path, &str>,
+) -> Result<BTreeMap<PathBuf, ContentEntry>, io::Error> {
+    let mut contents = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.starts_with("info/") {
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+        let content = if entry_type.is_hard_link() {
+            let target = entry.link_name()?.unwrap_or_default().into_owned();
+            ContentEntry::HardLink(target)
+        } else if entry_type.is_file() {
+            let mut reader = HashingReader::<_, Sha256>::new(&mut entry);
+            let (size, contains_placeholder) = match placeholders.get(path.as_path()) {
+                Some(placeholder) => {
+                    let mut bytes = Vec::new();
+                    reader.read_to_end(&mut bytes)?;
+                    let found = memchr::memmem::find(&bytes, placeholder.as_bytes()).is_some();
+                    (bytes.len() as u64, found)
+                }
+                None => (io::copy(&mut reader, &mut io::sink())?, false),
+            };
+            let (_, sha256) = reader.finalize();
+            ContentEntry::File {
+                size,
+                sha256,
+                contains_placeholder,
+            }
+        } else if entry_type.is_symlink() {
+            let target = entry.link_name()?.unwrap_or_default().into_owned();
+            ContentEntry::Symlink(target)
+        } else if entry_type.is_dir() {
+            ContentEntry::Directory
+        } else {
+            ContentEntry::Other
+        };
+        contents.insert(path, content);
+    }
+    Ok(contents)
+}
+
+/// Replaces the hard links in `contents` with the file they point to. Hard
+/// links to entries that are not regular files are left as they are.
+///
+/// Returns the hard links whose placeholder has not been searched for in their
+/// target, grouped by target.
+fn resolve_hard_links<'a>(
+    contents: &mut BTreeMap<PathBuf, ContentEntry>,
+    placeholders: &HashMap<&Path, &'a str>,
+) -> HashMap<PathBuf, Vec<(PathBuf, &'a str)>> {
+    let mut unscanned: HashMap<PathBuf, Vec<(PathBuf, &str)>> = HashMap::new();
+    let links: Vec<(PathBuf, PathBuf)> = contents
+        .iter()
+        .filter_map(|(path, entry)| match entry {
+            ContentEntry::HardLink(target) => Some((path.clone(), target.clone())),
+            _ => None,
+        })
+        .collect();
+    for (path, target) in links {
+        let Some(&ContentEntry::File {
+            size,
+            sha256,
+            contains_placeholder,
+        }) = contents.get(&target)
+        else {
+            continue;
+        };
+        let contains_placeholder = match placeholders.get(path.as_path()) {
+            None => false,
+            Some(placeholder) if placeholders.get(target.as_path()) == Some(placeholder) => {
+                contains_placeholder
+            }
+            Some(placeholder) => {
+                unscanned
+                    .entry(target)
+                    .or_default()
+                    .push((path.clone(), placeholder));
+                false
+            }
+        };
+        contents.insert(
+            path,
+            ContentEntry::File {
+                size,
+                sha256,
+                contains_placeholder,
+            },
+        );
+    }
+    unscanned
+}
+
+/// Searches the targets of hard links for the placeholders of the links.
+/// Returns the links whose placeholder was found.
+fn find_placeholders(
+    archive: &mut tar::Archive<impl Read>,
+    unscanned: &HashMap<PathBuf, Vec<(PathBuf, &str)>>,
+) -> Result<Vec<PathBuf>, io::Error> {
+    let mut found = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some(links) = unscanned.get(&path) else {
+            continue;
+        };
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        for (link, placeholder) in links {
+            if memchr::memmem::find(&bytes, placeholder.as_bytes()).is_some() {
+                found.push(link.clone());
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Checks that the name, version and build string in the filename match
+/// `index.json`.
+fn validate_file_name(file_name: &str, index_json: &IndexJson, errors: &mut Vec<ValidationError>) {
+    let Some(identifier) = ArchiveIdentifier::try_from_filename(file_name) else {
+        errors.push(ValidationError::InvalidFileName(file_name.to_owned()));
+        return;
+    };
+
+    let fields = [
+        (
+            "name",
+            identifier.name,
+            index_json.name.as_normalized().to_owned(),
+        ),
+        (
+            "version",
+            identifier.version,
+            index_json.version.to_string(),
+        ),
+        (
+            "build string",
+            identifier.build_string,
+            index_json.build.clone(),
+        ),
+    ];
+    for (field, file_name, index_json) in fields {
+        if file_name != index_json {
+            errors.push(ValidationError::FileNameMismatch {
+                field,
+                file_name,
+                index_json,
+            });
+        }
+    }
+}
+
+/// Checks that every entry in `paths.json` exists in the package with the
+/// expected type, size and hash, and that no files are missing from
+/// `paths.json`.
+fn validate_paths(
+    paths_json: &PathsJson,
+    contents: &BTreeMap<PathBuf, ContentEntry>,
+    errors: &mut Vec<ValidationError>,
+) {
+    for entry in &paths_json.paths {
+        let path = &entry.relative_path;
+        let Some(content) = contents.get(path) else {
+            // Empty directories are not necessarily stored in the archive.
+            if entry.path_type != PathType::Directory {
+                errors.push(ValidationError::MissingFile(path.clone()));
+            }
+            continue;
+        };
+
+        match (entry.path_type, content) {
+            (
+                PathType::HardLink,
+                ContentEntry::File {
+                    size,
+                    sha256,
+                    contains_placeholder,
+                },
+            ) => {
+                if let Some(expected) = entry.size_in_bytes {
+                    if expected != *size {
+                        errors.push(ValidationError::SizeMismatch {
+                            path: path.clone(),
+                            expected,
+                            actual: *size,
+                        });
+                    }
+                }
+                if let Some(expected) = entry.sha256 {
+                    if expected != *sha256 {
+                        errors.push(ValidationError::Sha256Mismatch {
+                            path: path.clone(),
+                            expected,
+                            actual: *sha256,
+                        });
+                    }
+                }
+                if let Some(placeholder) = &entry.prefix_placeholder {
+                    if !contains_placeholder {
+                        errors.push(ValidationError::MissingPrefixPlaceholder {
+                            path: path.clone(),
+                            placeholder: placeholder.placeholder.clone(),
+                        });
+                    }
+                }
+            }
+            (PathType::SoftLink, ContentEntry::Symlink(_))
+            | (PathType::Directory, ContentEntry::Directory) => {}
+            (expected, _) => errors.push(ValidationError::PathTypeMismatch {
+                path: path.clone(),
+                expected,
+            }),
+        }
+    }
+
+    let listed: HashSet<&Path> = paths_json
+        .paths
+        .iter()
+        .map(|entry| entry.relative_path.as_path())
+        .collect();
+    for (path, content) in contents {
+        if !matches!(content, ContentEntry::Directory) && !listed.contains(path.as_path()) {
+            errors.push(ValidationError::UnlistedFile(path.clone()));
+        }
+    }
+}
+
+/// Returns true if the symlink at `path` (relative to the prefix) points to a
+/// location outside of the prefix.
+fn escapes_prefix(path: &Path, target: &Path) -> bool {
+    if target.has_root() {
+        return true;
+    }
+
+    let mut depth = path.parent().map_or(0, |parent| {
+        parent
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count()
+    });
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Returns true if the path refers to compiled python bytecode.
+fn is_compiled_python_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "pyc" || ext == "pyo")
+        || path.components().any(|c| c.as_os_str() == "__pycache__")
+}
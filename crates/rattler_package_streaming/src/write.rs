@@ -1,14 +1,27 @@
 //! Functionality for writing conda packages
 use std::{
+    collections::BTreeSet,
     fs::{self, File},
-    io::{self, Read, Seek, Write},
+    io::{self, Cursor, Read, Seek, Write},
     path::{Path, PathBuf},
 };
 
 use chrono::{Datelike, Timelike};
-use rattler_conda_types::{compression_level::CompressionLevel, package::PackageMetadata};
+use rattler_conda_types::{
+    compression_level::CompressionLevel,
+    package::{ArchiveType, PackageMetadata},
+};
 use zip::DateTime;
 
+use crate::ExtractError;
+
+/// The name of the environment variable that can be used to specify the
+/// timestamp of reproducible builds. See <https://reproducible-builds.org/specs/source-date-epoch/>.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// 1-1-2023 00:00:00 (Fixed date in the past for reproducible builds)
+const DEFAULT_TIMESTAMP: i64 = 1672531200;
+
 /// Trait for progress bars
 pub trait ProgressBar {
     /// Set the current progress and progress message
@@ -255,15 +268,7 @@ pub fn write_conda_package<W: Write + Seek>(
     let mut outer_archive = zip::ZipWriter::new(writer);
 
     let last_modified_time = if let Some(time) = timestamp {
-        DateTime::from_date_and_time(
-            time.year() as u16,
-            time.month() as u8,
-            time.day() as u8,
-            time.hour() as u8,
-            time.minute() as u8,
-            time.second() as u8,
-        )
-        .expect("time should be in correct range")
+        zip_date_time(time)
     } else {
         // 1-1-2023 00:00:00 (Fixed date in the past for reproducible builds)
         DateTime::from_date_and_time(2023, 1, 1, 0, 0, 0)
@@ -314,6 +319,334 @@ pub fn write_conda_package<W: Write + Seek>(
     Ok(())
 }
 
+/// Converts a timestamp to a zip [`DateTime`]. Zip archives cannot represent
+/// dates before 1980 so earlier timestamps are clamped to 1-1-1980.
+pub(crate) fn zip_date_time(time: &chrono::DateTime<chrono::Utc>) -> DateTime {
+    if time.year() < 1980 {
+        return DateTime::default();
+    }
+    DateTime::from_date_and_time(
+        time.year() as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .expect("time should be in correct range")
+}
+
+/// Returns the timestamp specified by the [`SOURCE_DATE_EPOCH`] environment
+/// variable, or `None` if the variable is not set.
+///
+/// # Errors
+///
+/// Returns an error if the variable is set but does not contain a valid unix
+/// timestamp.
+pub fn source_date_epoch() -> Result<Option<chrono::DateTime<chrono::Utc>>, std::io::Error> {
+    let Ok(value) = std::env::var(SOURCE_DATE_EPOCH) else {
+        return Ok(None);
+    };
+    value
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(Some)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid {SOURCE_DATE_EPOCH} value: '{value}'"),
+            )
+        })
+}
+
+/// Determines the timestamp to use for a reproducible build. An explicit
+/// timestamp takes precedence over [`SOURCE_DATE_EPOCH`], if neither is set a
+/// fixed date in the past is used.
+fn reproducible_timestamp(
+    timestamp: Option<&chrono::DateTime<chrono::Utc>>,
+) -> Result<chrono::DateTime<chrono::Utc>, std::io::Error> {
+    if let Some(timestamp) = timestamp {
+        return Ok(*timestamp);
+    }
+    Ok(source_date_epoch()?.unwrap_or_else(|| {
+        chrono::DateTime::from_timestamp(DEFAULT_TIMESTAMP, 0).expect("valid timestamp")
+    }))
+}
+
+/// Removes duplicate paths, writing the same path twice would result in
+/// duplicate archive entries.
+fn dedup_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .cloned()
+        .collect()
+}
+
+/// Write a reproducible `.tar.bz2` package.
+///
+/// This behaves like [`write_tar_bz2_package`] but guarantees that the same
+/// input always results in a bit-for-bit identical archive:
+///
+/// * All archive entries use the same modification time. An explicit
+///   `timestamp` takes precedence over the [`SOURCE_DATE_EPOCH`] environment
+///   variable. If neither is set a fixed date in the past is used.
+/// * Ownership is normalized to uid and gid 0 without user or group names, and
+///   permissions are normalized to `0o644` or `0o755` for executables.
+/// * Paths are deduplicated and sorted, with `info/` paths first.
+///
+/// # Errors
+///
+/// Returns an error if [`SOURCE_DATE_EPOCH`] is invalid, if the writer returns
+/// an error, or if the paths are not relative to the base path.
+pub fn write_reproducible_tar_bz2_package<W: Write>(
+    writer: W,
+    base_path: &Path,
+    paths: &[PathBuf],
+    compression_level: CompressionLevel,
+    timestamp: Option<&chrono::DateTime<chrono::Utc>>,
+    progress_bar: Option<Box<dyn ProgressBar>>,
+) -> Result<(), std::io::Error> {
+    let timestamp = reproducible_timestamp(timestamp)?;
+    write_tar_bz2_package(
+        writer,
+        base_path,
+        &dedup_paths(paths),
+        compression_level,
+        Some(&timestamp),
+        progress_bar,
+    )
+}
+
+/// Write a reproducible `.conda` package.
+///
+/// This behaves like [`write_conda_package`] but guarantees that the same
+/// input always results in a bit-for-bit identical archive, see
+/// [`write_reproducible_tar_bz2_package`] for the normalizations that are
+/// applied to the inner archives. Additionally, zstd compression always uses
+/// at least one worker thread because the compressed output of multithreaded
+/// zstd does not depend on the number of workers, while single threaded
+/// compression produces a different output.
+///
+/// # Errors
+///
+/// Returns an error if [`SOURCE_DATE_EPOCH`] is invalid, if the writer returns
+/// an error, or if the paths are not relative to the base path.
+#[allow(clippy::too_many_arguments)]
+pub fn write_reproducible_conda_package<W: Write + Seek>(
+    writer: W,
+    base_path: &Path,
+    paths: &[PathBuf],
+    compression_level: CompressionLevel,
+    compression_num_threads: Option<u32>,
+    out_name: &str,
+    timestamp: Option<&chrono::DateTime<chrono::Utc>>,
+    progress_bar: Option<Box<dyn ProgressBar>>,
+) -> Result<(), std::io::Error> {
+    let timestamp = reproducible_timestamp(timestamp)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    let compression_num_threads = Some(
+        compression_num_threads
+            .unwrap_or_else(|| num_cpus::get() as u32)
+            .max(1),
+    );
+    write_conda_package(
+        writer,
+        base_path,
+        &dedup_paths(paths),
+        compression_level,
+        compression_num_threads,
+        out_name,
+        Some(&timestamp),
+        progress_bar,
+    )
+}
+
+/// The result of rebuilding a package with [`check_reproducible`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReproducibilityReport {
+    /// The size of the original package in bytes.
+    pub original_size: u64,
+
+    /// The size of the rebuilt package in bytes.
+    pub rebuilt_size: u64,
+
+    /// The offset of the first byte that differs between the original and the
+    /// rebuilt package or `None` if both are identical.
+    pub first_difference: Option<u64>,
+
+    /// The names of the members of the outer zip archive of a `.conda` package
+    /// that differ between the original and the rebuilt package. Always empty
+    /// for `.tar.bz2` packages.
+    pub differing_members: Vec<String>,
+}
+
+impl ReproducibilityReport {
+    /// Returns true if the rebuilt package is identical to the original.
+    pub fn is_reproducible(&self) -> bool {
+        self.first_difference.is_none()
+    }
+}
+
+/// Rebuilds a package from its extracted contents with the reproducible writer
+/// and compares the result with the original archive byte by byte.
+///
+/// The compression level of the original package cannot be recovered from the
+/// archive and must be provided. If no `timestamp` is given the modification
+/// time of the first entry of the original archive is used.
+///
+/// # Errors
+///
+/// Returns an error if the package cannot be read, extracted or rebuilt.
+pub fn check_reproducible(
+    package: &Path,
+    compression_level: CompressionLevel,
+    timestamp: Option<&chrono::DateTime<chrono::Utc>>,
+) -> Result<ReproducibilityReport, ExtractError> {
+    let archive_type =
+        ArchiveType::try_from(package).ok_or(ExtractError::UnsupportedArchiveType)?;
+    let original = fs::read(package)?;
+
+    // Determine the entries of the original package.
+    let mut entry_paths = Vec::new();
+    let mut first_mtime = None;
+    let out_name = match archive_type {
+        ArchiveType::TarBz2 => {
+            let mut archive = crate::read::stream_tar_bz2(Cursor::new(&original));
+            collect_entries(&mut archive, &mut entry_paths, &mut first_mtime)?;
+            None
+        }
+        ArchiveType::Conda => {
+            let mut content = crate::seek::stream_conda_content(Cursor::new(&original))?;
+            collect_entries(&mut content, &mut entry_paths, &mut first_mtime)?;
+            let mut info = crate::seek::stream_conda_info(Cursor::new(&original))?;
+            collect_entries(&mut info, &mut entry_paths, &mut first_mtime)?;
+
+            let archive = zip::ZipArchive::new(Cursor::new(&original))?;
+            let out_name = archive
+                .file_names()
+                .find_map(|name| name.strip_prefix("pkg-")?.strip_suffix(".tar.zst"))
+                .ok_or(ExtractError::MissingComponent)?
+                .to_owned();
+            Some(out_name)
+        }
+    };
+    let timestamp = match timestamp {
+        Some(timestamp) => Some(*timestamp),
+        None => first_mtime.and_then(|mtime| chrono::DateTime::from_timestamp(mtime as i64, 0)),
+    };
+
+    // Extract the package and rebuild it from the extracted files.
+    let extracted = tempfile::tempdir()?;
+    crate::fs::extract(package, extracted.path())?;
+    let paths = entry_paths
+        .iter()
+        .map(|path| extracted.path().join(path))
+        .collect::<Vec<_>>();
+
+    let mut rebuilt = Cursor::new(Vec::new());
+    match out_name {
+        None => write_reproducible_tar_bz2_package(
+            &mut rebuilt,
+            extracted.path(),
+            &paths,
+            compression_level,
+            timestamp.as_ref(),
+            None,
+        )?,
+        Some(out_name) => write_reproducible_conda_package(
+            &mut rebuilt,
+            extracted.path(),
+            &paths,
+            compression_level,
+            None,
+            &out_name,
+            timestamp.as_ref(),
+            None,
+        )?,
+    }
+    let rebuilt = rebuilt.into_inner();
+
+    let first_difference = first_difference(&original, &rebuilt);
+    let differing_members = if archive_type == ArchiveType::Conda && first_difference.is_some() {
+        differing_zip_members(&original, &rebuilt)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(ReproducibilityReport {
+        original_size: original.len() as u64,
+        rebuilt_size: rebuilt.len() as u64,
+        first_difference,
+        differing_members,
+    })
+}
+
+/// Collects the paths of all entries in a tar archive as well as the
+/// modification time of the first entry.
+fn collect_entries(
+    archive: &mut tar::Archive<impl Read>,
+    paths: &mut Vec<PathBuf>,
+    first_mtime: &mut Option<u64>,
+) -> Result<(), ExtractError> {
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if first_mtime.is_none() {
+            *first_mtime = entry.header().mtime().ok();
+        }
+        paths.push(entry.path()?.into_owned());
+    }
+    Ok(())
+}
+
+/// Returns the offset of the first byte that differs between two buffers.
+fn first_difference(a: &[u8], b: &[u8]) -> Option<u64> {
+    a.iter()
+        .zip(b.iter())
+        .position(|(a, b)| a != b)
+        .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+        .map(|pos| pos as u64)
+}
+
+/// Returns the names of the members that differ between two zip archives,
+/// including members that only exist in one of them.
+fn differing_zip_members(a: &[u8], b: &[u8]) -> Result<Vec<String>, ExtractError> {
+    fn read_members(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, ExtractError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        let mut members = Vec::with_capacity(archive.len());
+        for index in 0..archive.len() {
+            let mut file = archive.by_index_raw(index)?;
+            let mut content = Vec::with_capacity(file.compressed_size() as usize);
+            file.read_to_end(&mut content)?;
+            members.push((file.name().to_owned(), content));
+        }
+        Ok(members)
+    }
+
+    let a = read_members(a)?;
+    let b = read_members(b)?;
+    let names = a
+        .iter()
+        .chain(b.iter())
+        .map(|(name, _)| name.as_str())
+        .collect::<BTreeSet<_>>();
+    Ok(names
+        .into_iter()
+        .filter(|name| {
+            let a = a.iter().find(|(n, _)| n == name);
+            let b = b.iter().find(|(n, _)| n == name);
+            match (a, b) {
+                (Some((_, a)), Some((_, b))) => a != b,
+                _ => true,
+            }
+        })
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
 fn prepare_header(
     path: &Path,
     timestamp: Option<&chrono::DateTime<chrono::Utc>>,
@@ -328,8 +661,7 @@ fn prepare_header(
     if let Some(timestamp) = timestamp {
         header.set_mtime(timestamp.timestamp().unsigned_abs());
     } else {
-        // 1-1-2023 00:00:00 (Fixed date in the past for reproducible builds)
-        header.set_mtime(1672531200);
+        header.set_mtime(DEFAULT_TIMESTAMP.unsigned_abs());
     }
 
     Ok(header)
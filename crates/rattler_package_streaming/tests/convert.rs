@@ -0,0 +1,205 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use assert_matches::assert_matches;
+use rattler_conda_types::package::{IndexJson, PathsJson};
+use rattler_package_streaming::{
+    convert::{convert_package, verify_against_paths_json, ConvertError, ConvertOptions},
+    seek::{read_package_file, stream_conda_content, stream_conda_info},
+};
+
+fn test_package() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../test-data/clobber/clobber-python-0.1.0-cpython.conda")
+}
+
+/// Returns the path, mode, mtime and link name of every entry in the archive.
+fn entries(archive: &mut tar::Archive<impl Read>) -> Vec<(PathBuf, u32, u64, Option<PathBuf>)> {
+    archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (
+                entry.path().unwrap().into_owned(),
+                entry.header().mode().unwrap(),
+                entry.header().mtime().unwrap(),
+                entry.link_name().unwrap().map(std::borrow::Cow::into_owned),
+            )
+        })
+        .collect()
+}
+
+fn conda_entries(path: &Path) -> Vec<(PathBuf, u32, u64, Option<PathBuf>)> {
+    let mut result = entries(&mut stream_conda_info(File::open(path).unwrap()).unwrap());
+    result.extend(entries(
+        &mut stream_conda_content(File::open(path).unwrap()).unwrap(),
+    ));
+    result
+}
+
+#[test]
+fn test_convert_roundtrip() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let tar_bz2 = temp_dir.path().join("clobber-python-0.1.0-cpython.tar.bz2");
+    let conda = temp_dir.path().join("clobber-python-0.1.0-cpython.conda");
+
+    convert_package(&test_package(), &tar_bz2, &ConvertOptions::default()).unwrap();
+    convert_package(
+        &tar_bz2,
+        &conda,
+        &ConvertOptions {
+            zstd_window_log: Some(20),
+            ..ConvertOptions::default()
+        },
+    )
+    .unwrap();
+
+    // The converted packages contain the same entries with the same metadata.
+    let original = conda_entries(&test_package());
+    let tar_bz2_entries = entries(&mut rattler_package_streaming::read::stream_tar_bz2(
+        File::open(&tar_bz2).unwrap(),
+    ));
+    assert_eq!(original, tar_bz2_entries);
+    assert_eq!(original, conda_entries(&conda));
+
+    assert_eq!(
+        read_package_file::<IndexJson>(test_package()).unwrap(),
+        read_package_file::<IndexJson>(&conda).unwrap()
+    );
+}
+
+/// Writes a `.tar.bz2` package with `bin/foo` and a hard link `bin/bar` to
+/// it. The `paths.json` lists `sha256` as the hash of both files.
+fn write_hard_link_package(path: &Path, sha256: &str) {
+    let content = b"#!/bin/sh\necho foo\n";
+    let index_json = r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0, "subdir": "linux-64"}"#;
+    let paths_json = format!(
+        r#"{{
+            "paths": [
+                {{"_path": "bin/foo", "path_type": "hardlink", "size_in_bytes": {size}, "sha256": "{sha256}"}},
+                {{"_path": "bin/bar", "path_type": "hardlink", "size_in_bytes": {size}, "sha256": "{sha256}"}}
+            ],
+            "paths_version": 1
+        }}"#,
+        size = content.len()
+    );
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, data) in [
+        ("info/index.json", index_json.as_bytes()),
+        ("info/paths.json", paths_json.as_bytes()),
+        ("bin/foo", content.as_slice()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o755);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Link);
+    header.set_size(0);
+    header.set_mode(0o755);
+    builder
+        .append_link(&mut header, "bin/bar", "bin/foo")
+        .unwrap();
+
+    let mut encoder =
+        bzip2::write::BzEncoder::new(File::create(path).unwrap(), bzip2::Compression::fast());
+    std::io::Write::write_all(&mut encoder, &builder.into_inner().unwrap()).unwrap();
+    encoder.finish().unwrap();
+}
+
+/// Returns the sha256 hash of the content of `bin/foo` in the package that is
+/// written by [`write_hard_link_package`].
+fn hard_link_package_sha256() -> String {
+    format!(
+        "{:x}",
+        rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(b"#!/bin/sh\necho foo\n")
+    )
+}
+
+#[test]
+fn test_convert_hard_links() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let tar_bz2 = temp_dir.path().join("foo-1.0-0.tar.bz2");
+    let conda = temp_dir.path().join("foo-1.0-0.conda");
+    let roundtrip = temp_dir.path().join("roundtrip").join("foo-1.0-0.tar.bz2");
+    fs_err::create_dir(roundtrip.parent().unwrap()).unwrap();
+    write_hard_link_package(&tar_bz2, &hard_link_package_sha256());
+
+    convert_package(&tar_bz2, &conda, &ConvertOptions::default()).unwrap();
+    convert_package(&conda, &roundtrip, &ConvertOptions::default()).unwrap();
+
+    let link = conda_entries(&conda)
+        .into_iter()
+        .find(|(path, ..)| path == Path::new("bin/bar"))
+        .unwrap();
+    assert_eq!(link.3, Some(PathBuf::from("bin/foo")));
+}
+
+#[test]
+fn test_convert_failure_keeps_destination() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let tar_bz2 = temp_dir.path().join("foo-1.0-0.tar.bz2");
+    let conda = temp_dir.path().join("foo-1.0-0.conda");
+    write_hard_link_package(
+        &tar_bz2,
+        &format!("{:x}", rattler_digest::Sha256Hash::default()),
+    );
+    fs_err::write(&conda, "existing").unwrap();
+
+    // The converted package does not match its paths.json, the destination
+    // and the directory are left as they were.
+    let result = convert_package(&tar_bz2, &conda, &ConvertOptions::default());
+    assert_matches!(result, Err(ConvertError::PathMismatch(_, _)));
+    assert_eq!(fs_err::read_to_string(&conda).unwrap(), "existing");
+    assert_eq!(fs_err::read_dir(temp_dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn test_convert_same_archive_type() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let result = convert_package(
+        &test_package(),
+        &temp_dir.path().join("clobber-python-0.1.0-cpython.conda"),
+        &ConvertOptions::default(),
+    );
+    assert_matches!(result, Err(ConvertError::SameArchiveType));
+
+    // Converting a package onto itself does not touch it.
+    let package = temp_dir.path().join("clobber-python-0.1.0-cpython.conda");
+    fs_err::copy(test_package(), &package).unwrap();
+    let result = convert_package(&package, &package, &ConvertOptions::default());
+    assert_matches!(result, Err(ConvertError::SameArchiveType));
+    assert_eq!(
+        fs_err::read(&package).unwrap(),
+        fs_err::read(test_package()).unwrap()
+    );
+}
+
+#[test]
+fn test_verify_against_paths_json() {
+    let mut paths_json = read_package_file::<PathsJson>(test_package()).unwrap();
+    verify_against_paths_json(&test_package(), &paths_json).unwrap();
+
+    let entry = paths_json
+        .paths
+        .iter_mut()
+        .find(|entry| entry.size_in_bytes.is_some())
+        .unwrap();
+    entry.size_in_bytes = Some(entry.size_in_bytes.unwrap() + 1);
+    assert_matches!(
+        verify_against_paths_json(&test_package(), &paths_json),
+        Err(ConvertError::PathMismatch(_, _))
+    );
+
+    paths_json.paths[0].relative_path = PathBuf::from("does/not/exist");
+    assert_matches!(
+        verify_against_paths_json(&test_package(), &paths_json),
+        Err(ConvertError::MissingPath(_))
+    );
+}
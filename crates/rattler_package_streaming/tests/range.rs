@@ -0,0 +1,165 @@
+#![cfg(feature = "reqwest")]
+
+use std::{
+    future::IntoFuture,
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, get_service},
+    Router,
+};
+use rattler_conda_types::package::{IndexJson, PathsJson};
+use rattler_package_streaming::reqwest::range::{fetch_package_file, RemoteCondaArchive};
+use reqwest::Client;
+use reqwest_middleware::ClientWithMiddleware;
+use tower_http::services::ServeDir;
+use url::Url;
+
+fn test_data_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data")
+}
+
+/// Records the `Range` header of every request that hits the server.
+async fn record_ranges(
+    State(ranges): State<Arc<Mutex<Vec<Option<String>>>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let range = req
+        .headers()
+        .get(reqwest::header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned);
+    ranges.lock().unwrap().push(range);
+    next.run(req).await
+}
+
+/// Returns the byte range of the `pkg-*.tar.zst` member of a `.conda` archive,
+/// including its local header.
+fn pkg_member_range(package_path: &Path) -> Range<u64> {
+    let mut archive = zip::ZipArchive::new(fs_err::File::open(package_path).unwrap()).unwrap();
+    let name = archive
+        .file_names()
+        .find(|name| name.starts_with("pkg-") && name.ends_with(".tar.zst"))
+        .unwrap()
+        .to_owned();
+    let member = archive.by_name(&name).unwrap();
+    member.header_start()..member.data_start() + member.compressed_size()
+}
+
+/// Parses a `Range` header with a single range into an exclusive byte range of
+/// a file with the given size.
+fn parse_range(value: &str, file_size: u64) -> Range<u64> {
+    let (start, end) = value
+        .strip_prefix("bytes=")
+        .and_then(|range| range.split_once('-'))
+        .unwrap_or_else(|| panic!("invalid range '{value}'"));
+    if start.is_empty() {
+        let suffix: u64 = end.parse().unwrap();
+        return file_size.saturating_sub(suffix)..file_size;
+    }
+    let start: u64 = start.parse().unwrap();
+    let end = if end.is_empty() {
+        file_size
+    } else {
+        end.parse::<u64>().unwrap() + 1
+    };
+    start..end.min(file_size)
+}
+
+/// Spawns a server on a random port and returns the url to it.
+async fn serve(router: Router) -> Url {
+    let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+    Url::parse(&format!("http://localhost:{}/", addr.port())).unwrap()
+}
+
+#[tokio::test]
+async fn test_read_package_file_with_range_requests() {
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let router = Router::new()
+        .fallback_service(get_service(ServeDir::new(test_data_dir().join("clobber"))))
+        .layer(middleware::from_fn_with_state(
+            ranges.clone(),
+            record_ranges,
+        ));
+    let url = serve(router)
+        .await
+        .join("clobber-python-0.1.0-cpython.conda")
+        .unwrap();
+
+    let archive = RemoteCondaArchive::open(ClientWithMiddleware::from(Client::new()), url)
+        .await
+        .unwrap();
+    let index_json: IndexJson = archive.read_package_file().await.unwrap();
+    let paths_json: PathsJson = archive.read_package_file().await.unwrap();
+
+    let package_path = test_data_dir().join("clobber/clobber-python-0.1.0-cpython.conda");
+    assert_eq!(
+        index_json,
+        rattler_package_streaming::seek::read_package_file::<IndexJson>(&package_path).unwrap()
+    );
+    assert_eq!(
+        paths_json,
+        rattler_package_streaming::seek::read_package_file::<PathsJson>(&package_path).unwrap()
+    );
+
+    // Every request should have been a range request.
+    let ranges = ranges.lock().unwrap();
+    assert!(!ranges.is_empty());
+    assert!(ranges.iter().all(Option::is_some), "{ranges:?}");
+
+    // None of the requests should have touched the `pkg-*.tar.zst` member.
+    let file_size = fs_err::metadata(&package_path).unwrap().len();
+    let pkg_range = pkg_member_range(&package_path);
+    for range in ranges.iter().flatten() {
+        let requested = parse_range(range, file_size);
+        assert!(
+            requested.end <= pkg_range.start || requested.start >= pkg_range.end,
+            "request '{range}' overlaps the pkg member at {pkg_range:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_read_package_file_without_range_support() {
+    let package_path = test_data_dir().join("clobber/clobber-python-0.1.0-cpython.conda");
+    let bytes = fs_err::read(&package_path).unwrap();
+
+    // A server that ignores the `Range` header and always returns the whole file.
+    let router = Router::new().route(
+        "/clobber-python-0.1.0-cpython.conda",
+        get(move || async move { bytes }),
+    );
+    let url = serve(router)
+        .await
+        .join("clobber-python-0.1.0-cpython.conda")
+        .unwrap();
+
+    let index_json: IndexJson = fetch_package_file(ClientWithMiddleware::from(Client::new()), url)
+        .await
+        .unwrap();
+    assert_eq!(
+        index_json,
+        rattler_package_streaming::seek::read_package_file::<IndexJson>(&package_path).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_unsupported_archive_type() {
+    let url = Url::parse("http://localhost/foo-1.0-0.tar.bz2").unwrap();
+    let result = RemoteCondaArchive::open(ClientWithMiddleware::from(Client::new()), url).await;
+    assert!(matches!(
+        result,
+        Err(rattler_package_streaming::ExtractError::UnsupportedArchiveType)
+    ));
+}
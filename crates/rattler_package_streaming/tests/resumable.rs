@@ -0,0 +1,192 @@
+#![cfg(feature = "reqwest")]
+
+use std::{
+    future::IntoFuture,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, get_service},
+    Router,
+};
+use futures_util::{future::ready, stream, StreamExt};
+use rattler_digest::{compute_file_digest, Sha256, Sha256Hash};
+use rattler_package_streaming::{reqwest::resumable::extract, ExtractError};
+use reqwest::Client;
+use reqwest_middleware::ClientWithMiddleware;
+use tower_http::services::ServeDir;
+use url::Url;
+
+const PACKAGE: &str = "empty-0.1.0-h4616a5c_0.conda";
+
+fn package_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../test-data/packages")
+        .join(PACKAGE)
+}
+
+/// Records the `Range` header of every request and breaks the connection of
+/// the first response after 500 bytes.
+async fn interrupt_first_response(
+    State(ranges): State<Arc<Mutex<Vec<Option<String>>>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let range = req
+        .headers()
+        .get(reqwest::header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned);
+    let is_first = {
+        let mut ranges = ranges.lock().unwrap();
+        ranges.push(range);
+        ranges.len() == 1
+    };
+
+    let response = next.run(req).await;
+    if !is_first {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    // Give the server some time to flush the first bytes before the
+    // connection is broken.
+    let body = Body::from_stream(
+        stream::once(ready(Ok(bytes.slice(..500)))).chain(stream::once(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Err(std::io::Error::other("connection reset"))
+        })),
+    );
+    Response::from_parts(parts, body)
+}
+
+/// Spawns a server on a random port and returns the url to it.
+async fn serve(router: Router) -> Url {
+    let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+    Url::parse(&format!("http://localhost:{}/", addr.port())).unwrap()
+}
+
+#[tokio::test]
+async fn test_resume_interrupted_download() {
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let router = Router::new()
+        .fallback_service(get_service(ServeDir::new(package_path().parent().unwrap())))
+        .layer(middleware::from_fn_with_state(
+            ranges.clone(),
+            interrupt_first_response,
+        ));
+    let url = serve(router).await.join(PACKAGE).unwrap();
+    let sha256 = compute_file_digest::<Sha256>(package_path()).unwrap();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let destination = temp_dir.path().join("empty");
+    let partial_path = temp_dir.path().join("empty.partial");
+    let client = ClientWithMiddleware::from(Client::new());
+
+    // The first attempt is interrupted, the received bytes are kept.
+    let result = extract(
+        client.clone(),
+        url.clone(),
+        &destination,
+        &partial_path,
+        Some(sha256),
+        None,
+        None,
+    )
+    .await;
+    assert!(
+        matches!(result, Err(ExtractError::IoError(_))),
+        "{result:?}"
+    );
+    assert_eq!(fs_err::metadata(&partial_path).unwrap().len(), 500);
+
+    // The second attempt only requests the remaining bytes.
+    let result = extract(
+        client,
+        url,
+        &destination,
+        &partial_path,
+        Some(sha256),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(result.sha256, sha256);
+    assert!(destination.join("info/index.json").is_file());
+    assert!(!partial_path.exists());
+    assert_eq!(
+        *ranges.lock().unwrap(),
+        vec![None, Some(String::from("bytes=500-"))]
+    );
+}
+
+#[tokio::test]
+async fn test_restart_without_range_support() {
+    let bytes = Bytes::from(fs_err::read(package_path()).unwrap());
+
+    // A server that ignores the `Range` header and always returns the whole file.
+    let router = Router::new().route(&format!("/{PACKAGE}"), get(move || async move { bytes }));
+    let url = serve(router).await.join(PACKAGE).unwrap();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let destination = temp_dir.path().join("empty");
+    let partial_path = temp_dir.path().join("empty.partial");
+    fs_err::write(&partial_path, b"bytes of a previous attempt").unwrap();
+
+    let result = extract(
+        ClientWithMiddleware::from(Client::new()),
+        url,
+        &destination,
+        &partial_path,
+        Some(compute_file_digest::<Sha256>(package_path()).unwrap()),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        result.total_size,
+        fs_err::metadata(package_path()).unwrap().len()
+    );
+    assert!(destination.join("info/index.json").is_file());
+}
+
+#[tokio::test]
+async fn test_hash_mismatch_removes_partial_file() {
+    let router = Router::new()
+        .fallback_service(get_service(ServeDir::new(package_path().parent().unwrap())));
+    let url = serve(router).await.join(PACKAGE).unwrap();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let destination = temp_dir.path().join("empty");
+    let partial_path = temp_dir.path().join("empty.partial");
+
+    let result = extract(
+        ClientWithMiddleware::from(Client::new()),
+        url,
+        &destination,
+        &partial_path,
+        Some(Sha256Hash::default()),
+        None,
+        None,
+    )
+    .await;
+    assert!(
+        matches!(result, Err(ExtractError::HashMismatch { .. })),
+        "{result:?}"
+    );
+    assert!(!partial_path.exists());
+    assert!(!destination.exists());
+}
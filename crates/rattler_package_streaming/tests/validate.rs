@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::compression_level::CompressionLevel;
+use rattler_package_streaming::{
+    validate::{validate_package, ValidationError},
+    write::write_conda_package,
+};
+use rstest::rstest;
+
+fn test_data_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data")
+}
+
+#[rstest]
+#[case("clobber/clobber-python-0.1.0-cpython.conda")]
+#[case("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2")]
+#[case("clobber/clobber-fd-1-0.1.0-h4616a5c_0.conda")]
+fn test_validate_valid_package(#[case] package: &str) {
+    let report = validate_package(&test_data_dir().join(package)).unwrap();
+    assert!(report.is_valid(), "{:#?}", report.errors);
+}
+
+#[test]
+fn test_validate_invalid_package() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let base = temp_dir.path().join("pkg");
+    let files = [
+        (
+            "info/index.json",
+            r#"{"name": "foo", "version": "1.0", "build": "py_0", "build_number": 0, "noarch": "python", "subdir": "noarch"}"#,
+        ),
+        (
+            "info/paths.json",
+            r#"{
+                "paths": [
+                    {"_path": "site-packages/foo.py", "path_type": "hardlink", "size_in_bytes": 11, "sha256": "0000000000000000000000000000000000000000000000000000000000000000"},
+                    {"_path": "site-packages/__pycache__/foo.cpython-311.pyc", "path_type": "hardlink"},
+                    {"_path": "bin/foo", "path_type": "hardlink", "prefix_placeholder": "/opt/placeholder", "file_mode": "text"},
+                    {"_path": "missing.txt", "path_type": "hardlink"}
+                ],
+                "paths_version": 1
+            }"#,
+        ),
+        ("info/run_exports.json", "not json"),
+        ("site-packages/foo.py", "print('hi')"),
+        ("site-packages/__pycache__/foo.cpython-311.pyc", "bytecode"),
+        ("bin/foo", "#!/usr/bin/env python"),
+        ("unlisted.txt", "unlisted"),
+    ];
+    let mut paths = Vec::new();
+    for (path, content) in files {
+        let path = base.join(path);
+        fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+        fs_err::write(&path, content).unwrap();
+        paths.push(path);
+    }
+
+    #[cfg(unix)]
+    {
+        let link = base.join("site-packages/passwd");
+        std::os::unix::fs::symlink("../../../etc/passwd", &link).unwrap();
+        paths.push(link);
+    }
+
+    // The filename does not match the version in index.json.
+    let package = temp_dir.path().join("foo-2.0-py_0.conda");
+    write_conda_package(
+        File::create(&package).unwrap(),
+        &base,
+        &paths,
+        CompressionLevel::Default,
+        None,
+        "foo-2.0-py_0",
+        None,
+        None,
+    )
+    .unwrap();
+
+    let report = validate_package(&package).unwrap();
+    let errors = report.errors;
+    assert!(errors
+        .iter()
+        .any(|e| matches!(e, ValidationError::InvalidRunExportsJson(_))));
+    assert!(errors.contains(&ValidationError::FileNameMismatch {
+        field: "version",
+        file_name: String::from("2.0"),
+        index_json: String::from("1.0"),
+    }));
+    assert!(errors.iter().any(|e| matches!(
+        e,
+        ValidationError::Sha256Mismatch { path, .. } if path == Path::new("site-packages/foo.py")
+    )));
+    assert!(errors.contains(&ValidationError::MissingPrefixPlaceholder {
+        path: PathBuf::from("bin/foo"),
+        placeholder: String::from("/opt/placeholder"),
+    }));
+    assert!(errors.contains(&ValidationError::MissingFile(PathBuf::from("missing.txt"))));
+    assert!(
+        errors.contains(&ValidationError::UnlistedFile(PathBuf::from(
+            "unlisted.txt"
+        )))
+    );
+    assert!(
+        errors.contains(&ValidationError::CompiledFileInNoarchPython(PathBuf::from(
+            "site-packages/__pycache__/foo.cpython-311.pyc"
+        )))
+    );
+    #[cfg(unix)]
+    assert!(errors.contains(&ValidationError::SymlinkEscapesPrefix {
+        path: PathBuf::from("site-packages/passwd"),
+        target: PathBuf::from("../../../etc/passwd"),
+    }));
+}
+
+#[test]
+fn test_validate_hard_links() {
+    // The content of the file and its sha256 hash.
+    let content = b"#!/bin/sh\necho /opt/placeholder\n";
+    let sha256 = format!(
+        "{:x}",
+        rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(content)
+    );
+    let index_json = r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0, "subdir": "linux-64"}"#;
+    let paths_json = format!(
+        r#"{{
+            "paths": [
+                {{"_path": "bin/foo", "path_type": "hardlink", "size_in_bytes": {size}, "sha256": "{sha256}", "prefix_placeholder": "/opt/placeholder", "file_mode": "text"}},
+                {{"_path": "bin/bar", "path_type": "hardlink", "size_in_bytes": {size}, "sha256": "{sha256}"}},
+                {{"_path": "bin/baz", "path_type": "hardlink", "size_in_bytes": {size}, "sha256": "{sha256}", "prefix_placeholder": "/opt/other", "file_mode": "text"}}
+            ],
+            "paths_version": 1
+        }}"#,
+        size = content.len()
+    );
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, data) in [
+        ("info/index.json", index_json.as_bytes()),
+        ("info/paths.json", paths_json.as_bytes()),
+        ("bin/foo", content.as_slice()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o755);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+    for link in ["bin/bar", "bin/baz"] {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder.append_link(&mut header, link, "bin/foo").unwrap();
+    }
+    let tar = builder.into_inner().unwrap();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let package = temp_dir.path().join("foo-1.0-0.tar.bz2");
+    let mut encoder =
+        bzip2::write::BzEncoder::new(File::create(&package).unwrap(), bzip2::Compression::fast());
+    std::io::Write::write_all(&mut encoder, &tar).unwrap();
+    encoder.finish().unwrap();
+
+    // The hard links have the size and hash of their target. The target does
+    // not contain the placeholder of `bin/baz`.
+    let report = validate_package(&package).unwrap();
+    assert_eq!(
+        report.errors,
+        vec![ValidationError::MissingPrefixPlaceholder {
+            path: PathBuf::from("bin/baz"),
+            placeholder: String::from("/opt/other"),
+        }]
+    );
+}
@@ -1,7 +1,10 @@
 use rattler_conda_types::compression_level::CompressionLevel;
 use rattler_conda_types::package::ArchiveType;
 use rattler_package_streaming::read::{extract_conda_via_streaming, extract_tar_bz2};
-use rattler_package_streaming::write::{write_conda_package, write_tar_bz2_package};
+use rattler_package_streaming::write::{
+    check_reproducible, write_conda_package, write_reproducible_conda_package,
+    write_reproducible_tar_bz2_package, write_tar_bz2_package,
+};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
@@ -239,3 +242,85 @@ fn test_rewrite_conda() {
         compare_two_conda_archives(&file_path, &new_archive);
     }
 }
+
+fn extract_test_package(name: &str) -> (tempfile::TempDir, Vec<PathBuf>) {
+    let package = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../test-data/clobber")
+        .join(name);
+    let target_dir = tempfile::tempdir().unwrap();
+    extract_conda_via_streaming(File::open(package).unwrap(), target_dir.path()).unwrap();
+    let paths = find_all_package_files(target_dir.path());
+    (target_dir, paths)
+}
+
+#[test]
+fn test_reproducible_conda_independent_of_threads() {
+    let (target_dir, paths) = extract_test_package("clobber-python-0.1.0-cpython.conda");
+    let timestamp = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+    let write = |num_threads: Option<u32>| {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        write_reproducible_conda_package(
+            &mut buffer,
+            target_dir.path(),
+            &paths,
+            CompressionLevel::Default,
+            num_threads,
+            "clobber-python-0.1.0-cpython",
+            Some(&timestamp),
+            None,
+        )
+        .unwrap();
+        buffer.into_inner()
+    };
+
+    let single = write(Some(0));
+    assert_eq!(single, write(Some(1)));
+    assert_eq!(single, write(Some(4)));
+    assert_eq!(single, write(None));
+}
+
+#[test]
+fn test_check_reproducible() {
+    let (target_dir, paths) = extract_test_package("clobber-python-0.1.0-cpython.conda");
+    let timestamp = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+
+    let conda_path = output_dir.path().join("clobber-python-0.1.0-cpython.conda");
+    write_reproducible_conda_package(
+        File::create(&conda_path).unwrap(),
+        target_dir.path(),
+        &paths,
+        CompressionLevel::Default,
+        None,
+        "clobber-python-0.1.0-cpython",
+        Some(&timestamp),
+        None,
+    )
+    .unwrap();
+    let report = check_reproducible(&conda_path, CompressionLevel::Default, None).unwrap();
+    assert!(report.is_reproducible(), "{report:?}");
+
+    // A different compression level results in different inner archives.
+    let report = check_reproducible(&conda_path, CompressionLevel::Highest, None).unwrap();
+    assert!(!report.is_reproducible());
+    assert!(!report.differing_members.is_empty());
+    assert!(!report
+        .differing_members
+        .contains(&String::from("metadata.json")));
+
+    let tar_bz2_path = output_dir
+        .path()
+        .join("clobber-python-0.1.0-cpython.tar.bz2");
+    write_reproducible_tar_bz2_package(
+        File::create(&tar_bz2_path).unwrap(),
+        target_dir.path(),
+        &paths,
+        CompressionLevel::Default,
+        Some(&timestamp),
+        None,
+    )
+    .unwrap();
+    let report = check_reproducible(&tar_bz2_path, CompressionLevel::Default, None).unwrap();
+    assert!(report.is_reproducible(), "{report:?}");
+}
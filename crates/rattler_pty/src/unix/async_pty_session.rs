@@ -0,0 +1,353 @@
+use super::PtyProcess;
+use crate::unix::pty_process::PtyProcessOptions;
+use crate::unix::pty_session::exit_code;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::pty::Winsize;
+use nix::sys::wait::WaitStatus;
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    process::Command,
+};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::{timeout, timeout_at, Instant};
+
+/// The time after which a process that does not exit on `SIGTERM` is killed with `SIGKILL`.
+pub const DEFAULT_KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An asynchronous session with a process in a pty, for automating interactive programs.
+///
+/// The output of the process can be read as a stream through the [`AsyncRead`] implementation
+/// or matched with [`Self::expect`], input is sent through the [`AsyncWrite`] implementation or
+/// the `send` methods.
+///
+/// The session must be created from within a tokio runtime.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::{process::Command, time::Duration};
+/// use rattler_pty::unix::AsyncPtySession;
+///
+/// let mut session = AsyncPtySession::new(Command::new("bash"))?;
+/// session.send_line("echo $((1 + 2))").await?;
+/// let result = session.expect(r"(\d+)\r\n", Duration::from_secs(5)).await?;
+/// assert_eq!(result.get(1), Some("3"));
+/// session.exit(Duration::from_secs(1)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncPtySession {
+    process: PtyProcess,
+
+    /// A non-blocking handle of the pty
+    pty: AsyncFd<File>,
+
+    /// Output of the process that has been read but not consumed yet
+    buffer: Vec<u8>,
+}
+
+/// The result of a successful [`AsyncPtySession::expect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectMatch {
+    /// The output before the match
+    pub before: String,
+
+    /// The capture groups of the match. The first group is the whole match, groups that did not
+    /// participate in the match are `None`.
+    pub captures: Vec<Option<String>>,
+
+    names: HashMap<String, usize>,
+}
+
+impl ExpectMatch {
+    /// Returns the text of the whole match.
+    pub fn matched(&self) -> &str {
+        self.get(0).unwrap_or_default()
+    }
+
+    /// Returns the text of the capture group with the given index.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.captures.get(index)?.as_deref()
+    }
+
+    /// Returns the text of the capture group with the given name.
+    pub fn name(&self, name: &str) -> Option<&str> {
+        self.get(*self.names.get(name)?)
+    }
+}
+
+/// An error that can occur in [`AsyncPtySession::expect`].
+#[derive(Debug, thiserror::Error)]
+pub enum ExpectError {
+    /// The pattern is not a valid regular expression.
+    #[error("invalid pattern")]
+    InvalidPattern(#[from] regex::Error),
+
+    /// The pattern was not found within the timeout.
+    #[error("timed out after {timeout:?} waiting for '{pattern}'")]
+    Timeout {
+        /// The pattern that was expected
+        pattern: String,
+        /// The timeout that elapsed
+        timeout: Duration,
+        /// The output that was read but did not match
+        output: String,
+    },
+
+    /// The process closed the pty before the pattern was found.
+    #[error("the process exited before '{pattern}' was found")]
+    Eof {
+        /// The pattern that was expected
+        pattern: String,
+        /// The output that was read but did not match
+        output: String,
+    },
+
+    /// Reading from the pty failed.
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+impl AsyncPtySession {
+    /// Starts the command in a pty with echo enabled.
+    pub fn new(command: Command) -> io::Result<Self> {
+        Self::with_options(
+            command,
+            PtyProcessOptions {
+                echo: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Starts the command in a pty with the given options.
+    ///
+    /// The kill timeout of the process is set to [`DEFAULT_KILL_TIMEOUT`], so that dropping the
+    /// session does not hang on a process that ignores `SIGTERM`, e.g. an interactive shell.
+    pub fn with_options(command: Command, options: PtyProcessOptions) -> io::Result<Self> {
+        let mut process = PtyProcess::new(command, options)?;
+        process.set_kill_timeout(Some(DEFAULT_KILL_TIMEOUT));
+        let pty = process.get_file_handle()?;
+
+        // The pty is read and written without blocking, readiness is reported by tokio.
+        let flags = OFlag::from_bits_truncate(fcntl(&pty, FcntlArg::F_GETFL)?);
+        fcntl(&pty, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+
+        Ok(Self {
+            process,
+            pty: AsyncFd::new(pty)?,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Returns the process that runs in the pty.
+    pub fn process(&self) -> &PtyProcess {
+        &self.process
+    }
+
+    /// Returns the process that runs in the pty.
+    pub fn process_mut(&mut self) -> &mut PtyProcess {
+        &mut self.process
+    }
+
+    /// Sends bytes to the process.
+    pub async fn send<B: AsRef<[u8]>>(&mut self, data: B) -> io::Result<()> {
+        self.write_all(data.as_ref()).await?;
+        self.flush().await
+    }
+
+    /// Sends a line to the process.
+    pub async fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.send(format!("{line}\n")).await
+    }
+
+    /// Sends a control character to the process, e.g. `'c'` for Ctrl-C or `'d'` for Ctrl-D.
+    pub async fn send_control(&mut self, character: char) -> io::Result<()> {
+        let code = match character.to_ascii_uppercase() {
+            character @ '@'..='_' => character as u8 & 0x1f,
+            '?' => 0x7f,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{character}' is not a control character"),
+                ))
+            }
+        };
+        self.send([code]).await
+    }
+
+    /// Changes the window size of the pty. The process receives a `SIGWINCH` signal.
+    ///
+    /// The initial window size is set by the process when it starts, so wait for output of the
+    /// process before resizing.
+    pub fn resize(&self, rows: u16, columns: u16) -> io::Result<()> {
+        self.process.set_window_size(Winsize {
+            ws_row: rows,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        })?;
+        Ok(())
+    }
+
+    /// Reads the output of the process until `pattern` matches or `timeout` elapses, and
+    /// returns the captures of the match. The output up to the end of the match is consumed.
+    pub async fn expect(
+        &mut self,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<ExpectMatch, ExpectError> {
+        let regex = Regex::new(pattern)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(result) = self.try_match(&regex) {
+                return Ok(result);
+            }
+
+            match timeout_at(deadline, self.fill_buffer()).await {
+                Ok(Ok(0)) => {
+                    return Err(ExpectError::Eof {
+                        pattern: pattern.to_string(),
+                        output: String::from_utf8_lossy(&self.buffer).into_owned(),
+                    })
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(err.into()),
+                Err(_elapsed) => {
+                    return Err(ExpectError::Timeout {
+                        pattern: pattern.to_string(),
+                        timeout,
+                        output: String::from_utf8_lossy(&self.buffer).into_owned(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Waits until the process exits and returns its exit code. The output of the process is
+    /// kept and can still be read or matched afterwards.
+    pub async fn wait(&mut self) -> io::Result<Option<i32>> {
+        loop {
+            let status = self.process.status();
+            if status != Some(WaitStatus::StillAlive) {
+                return Ok(exit_code(status));
+            }
+
+            match timeout(Duration::from_millis(100), self.fill_buffer()).await {
+                // The pty was closed, the process is about to exit.
+                Ok(Ok(0)) => {
+                    let status = self.process.async_wait().await?;
+                    return Ok(exit_code(Some(status)));
+                }
+                Ok(Err(err)) => return Err(err),
+                Ok(Ok(_)) | Err(_) => {}
+            }
+        }
+    }
+
+    /// Terminates the process with `SIGTERM` and waits for it to exit. If it is still running
+    /// after `kill_timeout`, it is killed with `SIGKILL`. Returns the exit code of the process.
+    pub async fn exit(&mut self, kill_timeout: Duration) -> io::Result<Option<i32>> {
+        self.process.set_kill_timeout(Some(kill_timeout));
+        let status = self.process.async_exit().await?;
+        Ok(exit_code(Some(status)))
+    }
+
+    /// Matches the buffered output against `regex` and consumes it up to the end of the match.
+    fn try_match(&mut self, regex: &Regex) -> Option<ExpectMatch> {
+        let captures = regex.captures(&self.buffer)?;
+        let whole_match = captures.get(0)?;
+        let result = ExpectMatch {
+            before: String::from_utf8_lossy(&self.buffer[..whole_match.start()]).into_owned(),
+            captures: captures
+                .iter()
+                .map(|group| {
+                    group.map(|group| String::from_utf8_lossy(group.as_bytes()).into_owned())
+                })
+                .collect(),
+            names: regex
+                .capture_names()
+                .enumerate()
+                .filter_map(|(index, name)| Some((name?.to_string(), index)))
+                .collect(),
+        };
+        let end = whole_match.end();
+        self.buffer.drain(..end);
+        Some(result)
+    }
+
+    /// Reads the next chunk of output into the buffer. Returns the number of bytes read, zero if
+    /// the pty was closed.
+    async fn fill_buffer(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; 4096];
+        let bytes_read = poll_fn(|cx| self.poll_read_pty(cx, &mut chunk)).await?;
+        self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        Ok(bytes_read)
+    }
+
+    fn poll_read_pty(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.pty.poll_read_ready(cx))?;
+            match guard.try_io(|pty| pty.get_ref().read(buf)) {
+                Ok(Ok(bytes_read)) => return Poll::Ready(Ok(bytes_read)),
+                // Reading from a pty whose process has exited fails with EIO on Linux.
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::EIO) => return Poll::Ready(Ok(0)),
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+impl AsyncRead for AsyncPtySession {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // Return output that was buffered by `expect` or `wait` first.
+        if !this.buffer.is_empty() {
+            let len = this.buffer.len().min(buf.remaining());
+            buf.put_slice(&this.buffer[..len]);
+            this.buffer.drain(..len);
+            return Poll::Ready(Ok(()));
+        }
+
+        let bytes_read = ready!(this.poll_read_pty(cx, buf.initialize_unfilled()))?;
+        buf.advance(bytes_read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AsyncPtySession {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.pty.poll_write_ready(cx))?;
+            match guard.try_io(|pty| pty.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => {}
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
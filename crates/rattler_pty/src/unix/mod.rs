@@ -1,6 +1,8 @@
+mod async_pty_session;
 mod pty_process;
 mod pty_session;
 
+pub use async_pty_session::{AsyncPtySession, ExpectError, ExpectMatch, DEFAULT_KILL_TIMEOUT};
 pub use pty_process::PtyProcess;
 pub use pty_process::PtyProcessOptions;
 pub use pty_session::PtySession;
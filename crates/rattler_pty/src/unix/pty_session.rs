@@ -4,7 +4,11 @@ use libc::SIGWINCH;
 use nix::sys::select::FdSet;
 use nix::{
     errno::Errno,
-    sys::{select, time::TimeVal, wait::WaitStatus},
+    sys::{
+        select,
+        time::{TimeVal, TimeValLike},
+        wait::WaitStatus,
+    },
 };
 use signal_hook::iterator::Signals;
 use std::time::{Duration, Instant};
@@ -37,13 +41,19 @@ pub struct PtySession {
 impl PtySession {
     /// Constructs a new session
     pub fn new(command: Command) -> io::Result<Self> {
-        let process = PtyProcess::new(
+        Self::with_options(
             command,
             PtyProcessOptions {
                 echo: true,
                 ..Default::default()
             },
-        )?;
+        )
+    }
+
+    /// Constructs a new session with the given options for the pty, e.g. to start the process
+    /// with echo disabled.
+    pub fn with_options(command: Command, options: PtyProcessOptions) -> io::Result<Self> {
+        let process = PtyProcess::new(command, options)?;
 
         let process_stdin = process.get_file_handle()?;
         let process_stdout = process.get_file_handle()?;
@@ -86,19 +96,122 @@ impl PtySession {
         self.process.exit()
     }
 
+    /// Reads the output of the process until `pattern` is found or `timeout` has elapsed.
+    ///
+    /// The output up to and including the pattern is discarded. Output that follows the pattern
+    /// is kept and written to stdout by the next call to [`Self::interact`].
+    ///
+    /// Returns whether the pattern was found. An empty pattern is found immediately.
+    pub fn wait_until(&mut self, pattern: &str, timeout: Duration) -> io::Result<bool> {
+        if pattern.is_empty() {
+            return Ok(true);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(position) = self
+                .rolling_buffer
+                .windows(pattern.len())
+                .position(|window| window == pattern.as_bytes())
+            {
+                self.rolling_buffer.drain(..position + pattern.len());
+                return Ok(true);
+            }
+
+            // Only keep enough bytes to find a match across reads.
+            if self.rolling_buffer.len() > pattern.len() {
+                self.rolling_buffer
+                    .drain(..self.rolling_buffer.len() - pattern.len());
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+
+            match self.read_with_timeout(&mut buf, remaining)? {
+                Some(0) => return Ok(false),
+                Some(bytes_read) => self.rolling_buffer.extend_from_slice(&buf[..bytes_read]),
+                None => {}
+            }
+        }
+    }
+
+    /// Blocks until the process exits and returns its exit code. The output of the process is
+    /// discarded.
+    pub fn wait(&mut self) -> io::Result<Option<i32>> {
+        self.flush()?;
+        let mut buf = [0u8; 4096];
+        loop {
+            let status = self.process.status();
+            if status != Some(WaitStatus::StillAlive) {
+                return Ok(exit_code(status));
+            }
+            self.read_with_timeout(&mut buf, Duration::from_millis(100))?;
+        }
+    }
+
+    /// Reads the output of the process if there is any within `timeout`. Returns `None` if no
+    /// output was available and `Some(0)` if the pty was closed.
+    fn read_with_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Option<usize>> {
+        let mut fd_set = FdSet::new();
+        fd_set.insert(self.process_stdout.as_fd());
+        let mut select_timeout =
+            TimeVal::microseconds(i64::try_from(timeout.as_micros()).unwrap_or(i64::MAX));
+
+        match select::select(None, &mut fd_set, None, None, &mut select_timeout) {
+            Ok(0) | Err(Errno::EINTR) => Ok(None),
+            Ok(_) => match self.process_stdout.read(buf) {
+                Ok(bytes_read) => Ok(Some(bytes_read)),
+                // Reading from a pty whose process has exited fails with EIO on Linux.
+                Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(Some(0)),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(io::Error::from(err)),
+        }
+    }
+
     /// Interact with the process. This will put the current process into raw mode and
     /// forward all input from stdin to the process and all output from the process to stdout.
     /// This will block until the process exits.
+    ///
+    /// The original mode of the terminal is restored when the process exits or an error occurs.
     pub fn interact(&mut self, wait_until: Option<&str>) -> io::Result<Option<i32>> {
-        let pattern_timeout = Duration::from_secs(1);
-        let pattern_start = Instant::now();
+        // There is nothing to wait for with an empty pattern.
+        let wait_until = wait_until.filter(|pattern| !pattern.is_empty());
 
         // Make sure anything we have written so far has been flushed.
         self.flush()?;
 
+        // Write any output that was kept by `wait_until`.
+        if wait_until.is_none() && !self.rolling_buffer.is_empty() {
+            io::stdout().write_all(&self.rolling_buffer)?;
+            io::stdout().flush()?;
+            self.rolling_buffer.clear();
+        }
+
         // Put the process into raw mode
         let original_mode = self.process.set_raw()?;
 
+        let result = self.forward_io(wait_until);
+
+        // Restore the original terminal mode
+        self.process.set_mode(original_mode)?;
+
+        result
+    }
+
+    /// Forwards stdin to the process and the output of the process to stdout until the process
+    /// exits.
+    fn forward_io(&mut self, wait_until: Option<&str>) -> io::Result<Option<i32>> {
+        let pattern_timeout = Duration::from_secs(1);
+        let pattern_start = Instant::now();
+
         let process_stdout_clone = self.process_stdout.try_clone()?;
         let process_stdout_fd = process_stdout_clone.as_fd();
         let stdin = std::io::stdin();
@@ -160,7 +273,6 @@ impl PtySession {
             if let Err(error) = res {
                 // EINTR is not an error, it just means that we got interrupted by a signal (e.g. SIGWINCH)
                 if error != Errno::EINTR {
-                    self.process.set_mode(original_mode)?;
                     return Err(std::io::Error::from(error));
                 }
             } else {
@@ -217,13 +329,15 @@ impl PtySession {
             }
         };
 
-        // Restore the original terminal mode
-        self.process.set_mode(original_mode)?;
+        Ok(exit_code(exit_status))
+    }
+}
 
-        match exit_status {
-            Some(WaitStatus::Exited(_, code)) => Ok(Some(code)),
-            Some(WaitStatus::Signaled(_, signal, _)) => Ok(Some(128 + signal as i32)),
-            _ => Ok(None),
-        }
+/// Converts the status of an exited process to an exit code like a shell does.
+pub(crate) fn exit_code(status: Option<WaitStatus>) -> Option<i32> {
+    match status {
+        Some(WaitStatus::Exited(_, code)) => Some(code),
+        Some(WaitStatus::Signaled(_, signal, _)) => Some(128 + signal as i32),
+        _ => None,
     }
 }
@@ -4,6 +4,8 @@ mod tests {
 
     use rattler_pty::unix::PtyProcess;
     use rattler_pty::unix::PtyProcessOptions;
+    use rattler_pty::unix::PtySession;
+    use rattler_pty::unix::{AsyncPtySession, ExpectError};
 
     use nix::sys::{signal, wait};
     use std::io::{BufRead, BufReader, LineWriter, Write};
@@ -37,4 +39,113 @@ mod tests {
         assert_eq!(should, wait::waitpid(process.child_pid, None).unwrap());
         Ok(())
     }
+
+    #[test]
+    /// Wait for a pattern in the output of a shell, answer it and check the exit code
+    fn test_wait_until() -> std::io::Result<()> {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo READY; read code; exit $code"]);
+        let mut session = PtySession::with_options(
+            command,
+            PtyProcessOptions {
+                echo: false,
+                window_size: Option::default(),
+            },
+        )?;
+
+        assert!(session.wait_until("READY", time::Duration::from_secs(5))?);
+        assert!(!session.wait_until("NEVER", time::Duration::from_millis(200))?);
+        assert!(session.wait_until("", time::Duration::ZERO)?);
+
+        session.send_line("7")?;
+        assert_eq!(session.wait()?, Some(7));
+        Ok(())
+    }
+
+    /// Start `sh` without echo, so that only the output of the commands is read
+    fn async_sh() -> std::io::Result<AsyncPtySession> {
+        AsyncPtySession::with_options(
+            Command::new("sh"),
+            PtyProcessOptions {
+                echo: false,
+                window_size: Option::default(),
+            },
+        )
+    }
+
+    #[tokio::test]
+    /// Expect output of a command, return the captures and check the exit code
+    async fn test_async_expect() -> Result<(), ExpectError> {
+        let mut session = async_sh()?;
+        session
+            .send_line("echo 'result: 1 + 2 = '$((1 + 2))")
+            .await?;
+        let result = session
+            .expect(r"result: (?<sum>.*) = (\d+)", time::Duration::from_secs(5))
+            .await?;
+        assert_eq!(result.matched(), "result: 1 + 2 = 3");
+        assert_eq!(result.name("sum"), Some("1 + 2"));
+        assert_eq!(result.get(2), Some("3"));
+
+        let error = session
+            .expect("never printed", time::Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ExpectError::Timeout { .. }), "{error:?}");
+
+        session.send_line("exit 4").await?;
+        assert_eq!(session.wait().await?, Some(4));
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// Resize the pty and check that the process sees the new size
+    async fn test_async_resize() -> Result<(), ExpectError> {
+        let mut session = async_sh()?;
+        session.send_line("echo started").await?;
+        session
+            .expect("started", time::Duration::from_secs(5))
+            .await?;
+        session.resize(30, 100)?;
+        session.send_line("stty size").await?;
+        let result = session
+            .expect(r"(\d+) (\d+)", time::Duration::from_secs(5))
+            .await?;
+        assert_eq!(result.get(1), Some("30"));
+        assert_eq!(result.get(2), Some("100"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// Interrupt cat with ^C
+    async fn test_async_send_control() -> Result<(), ExpectError> {
+        let mut session = AsyncPtySession::new(Command::new("cat"))?;
+        session.send_line("hello cat").await?;
+        session
+            .expect("hello cat\r\n", time::Duration::from_secs(5))
+            .await?;
+        session.send_control('c').await?;
+        assert_eq!(
+            session.wait().await?,
+            Some(128 + signal::Signal::SIGINT as i32)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// Kill a process that ignores SIGTERM after the kill timeout
+    async fn test_async_exit_kill_timeout() -> Result<(), ExpectError> {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "trap '' TERM; echo ready; while true; do sleep 1; done",
+        ]);
+        let mut session = AsyncPtySession::new(command)?;
+        session
+            .expect("ready", time::Duration::from_secs(5))
+            .await?;
+        let exit_code = session.exit(time::Duration::from_millis(200)).await?;
+        assert_eq!(exit_code, Some(128 + signal::Signal::SIGKILL as i32));
+        Ok(())
+    }
 }
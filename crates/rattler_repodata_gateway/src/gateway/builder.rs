@@ -4,6 +4,10 @@ use coalesced_map::CoalescedMap;
 #[cfg(not(target_arch = "wasm32"))]
 use rattler_cache::package_cache::PackageCache;
 use rattler_networking::LazyClient;
+#[cfg(not(target_arch = "wasm32"))]
+use rattler_networking::{client_config::ClientConfigError, AuthenticationStorage, ClientConfig};
+#[cfg(not(target_arch = "wasm32"))]
+use rattler_signatures::SignatureVerifier;
 use reqwest::Client;
 use reqwest_middleware::ClientWithMiddleware;
 
@@ -48,6 +52,8 @@ pub struct GatewayBuilder {
     cache: Option<std::path::PathBuf>,
     #[cfg(not(target_arch = "wasm32"))]
     package_cache: Option<PackageCache>,
+    #[cfg(not(target_arch = "wasm32"))]
+    signature_verifier: Option<SignatureVerifier>,
     max_concurrent_requests: MaxConcurrency,
 }
 
@@ -58,6 +64,11 @@ impl GatewayBuilder {
     }
 
     /// Set the client to use for fetching repodata.
+    ///
+    /// Use [`Self::with_client_config`] or build a client with
+    /// `rattler_networking::ClientConfig` to honour the proxy, TLS and mirror
+    /// settings of a configuration. The same client can then be used to
+    /// download packages.
     #[must_use]
     pub fn with_client(mut self, client: impl Into<LazyClient>) -> Self {
         self.set_client(client);
@@ -70,6 +81,30 @@ impl GatewayBuilder {
         self
     }
 
+    /// Fetch repodata with a client that is built from `config` and
+    /// authenticates requests with `auth_storage`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_client_config(
+        mut self,
+        config: &ClientConfig,
+        auth_storage: AuthenticationStorage,
+    ) -> Result<Self, ClientConfigError> {
+        self.set_client_config(config, auth_storage)?;
+        Ok(self)
+    }
+
+    /// Fetch repodata with a client that is built from `config` and
+    /// authenticates requests with `auth_storage`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_client_config(
+        &mut self,
+        config: &ClientConfig,
+        auth_storage: AuthenticationStorage,
+    ) -> Result<&mut Self, ClientConfigError> {
+        let client = config.build_with_middleware(auth_storage, Vec::new())?;
+        Ok(self.set_client(client))
+    }
+
     /// Set the channel configuration to use for fetching repodata.
     #[must_use]
     pub fn with_channel_config(mut self, channel_config: ChannelConfig) -> Self {
@@ -112,6 +147,31 @@ impl GatewayBuilder {
         self
     }
 
+    /// Verifies the signature of every `repodata.json` before it is used.
+    ///
+    /// Sharded repodata and JLAP patches are not signed, so they are disabled
+    /// for all channels. Subdirectories whose repodata is not signed by a
+    /// trusted key or identity fail with
+    /// [`GatewayError::SignatureVerificationFailed`].
+    ///
+    /// [`GatewayError::SignatureVerificationFailed`]: crate::GatewayError::SignatureVerificationFailed
+    #[cfg(not(target_arch = "wasm32"))]
+    #[must_use]
+    pub fn with_signature_verifier(mut self, verifier: SignatureVerifier) -> Self {
+        self.set_signature_verifier(verifier);
+        self
+    }
+
+    /// Verifies the signature of every `repodata.json` before it is used.
+    ///
+    /// This function is similar to [`Self::with_signature_verifier`], but
+    /// modifies an existing instance.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_signature_verifier(&mut self, verifier: SignatureVerifier) -> &mut Self {
+        self.signature_verifier = Some(verifier);
+        self
+    }
+
     /// Sets the maximum number of concurrent HTTP requests to make.
     #[must_use]
     pub fn with_max_concurrent_requests(
@@ -170,8 +230,11 @@ impl GatewayBuilder {
                 cache,
                 #[cfg(not(target_arch = "wasm32"))]
                 package_cache,
+                #[cfg(not(target_arch = "wasm32"))]
+                signature_verifier: self.signature_verifier,
                 subdir_run_exports_cache: Arc::default(),
                 concurrent_requests_semaphore,
+                shard_cache_metrics: Arc::default(),
             }),
         }
     }
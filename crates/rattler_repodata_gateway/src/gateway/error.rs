@@ -60,6 +60,10 @@ pub enum GatewayError {
 
     #[error("direct url queries are not supported ({0})")]
     DirectUrlQueryNotSupported(String),
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("the signature of {0} could not be verified")]
+    SignatureVerificationFailed(String, #[source] rattler_signatures::SignatureError),
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -33,6 +33,8 @@ use rattler_networking::LazyClient;
 pub use repo_data::RepoData;
 use run_exports_extractor::{RunExportExtractor, SubdirRunExportsCache};
 pub use run_exports_extractor::{RunExportExtractorError, RunExportsReporter};
+use sharded_subdir::ShardCacheMetrics;
+pub use sharded_subdir::ShardCacheStats;
 use subdir::Subdir;
 use tracing::{instrument, Level};
 use url::Url;
@@ -191,6 +193,12 @@ impl Gateway {
             key.0.base_url != channel.base_url || !subdirs.contains(key.1.as_str())
         });
     }
+
+    /// Returns statistics about how many shard indices and shards of sharded
+    /// repodata were reused from the cache versus downloaded by this gateway.
+    pub fn shard_cache_stats(&self) -> ShardCacheStats {
+        self.inner.shard_cache_metrics.stats()
+    }
 }
 
 struct GatewayInner {
@@ -211,11 +219,18 @@ struct GatewayInner {
     #[cfg(not(target_arch = "wasm32"))]
     package_cache: PackageCache,
 
+    /// Verifies the signatures of `repodata.json` files, if configured.
+    #[cfg(not(target_arch = "wasm32"))]
+    signature_verifier: Option<rattler_signatures::SignatureVerifier>,
+
     /// A cache for global run exports.
     subdir_run_exports_cache: Arc<SubdirRunExportsCache>,
 
     /// A semaphore to limit the number of concurrent requests.
     concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
+
+    /// Statistics about the reuse of cached sharded repodata.
+    shard_cache_metrics: Arc<ShardCacheMetrics>,
 }
 
 impl GatewayInner {
@@ -788,4 +803,50 @@ mod test {
 
         assert!(run_exports_in_place(&repodata_records));
     }
+
+    #[tokio::test]
+    async fn test_signature_verification() {
+        use rattler_signatures::{IdentityPolicy, SignatureVerifier, TrustRoot};
+
+        let signatures_dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data/signatures");
+        let mut trust_root = TrustRoot::from_pem_file(signatures_dir.join("ca.pem")).unwrap();
+        trust_root
+            .add_transparency_log_pem(
+                &fs_err::read_to_string(signatures_dir.join("rekor.pem")).unwrap(),
+            )
+            .unwrap();
+        let local_channel = Channel::from_directory(&signatures_dir.join("channel"));
+        let server = SimpleChannelServer::new(signatures_dir.join("channel")).await;
+
+        for channel in [local_channel, server.channel()] {
+            let query = |identity: &str| {
+                let cache_dir = tempfile::tempdir().unwrap();
+                let gateway = Gateway::builder()
+                    .with_cache_dir(cache_dir.path())
+                    .with_signature_verifier(
+                        SignatureVerifier::new(trust_root.clone())
+                            .with_identity_policy(IdentityPolicy::new(identity)),
+                    )
+                    .finish();
+                let channel = channel.clone();
+                async move {
+                    let _cache_dir = cache_dir;
+                    gateway
+                        .query(
+                            vec![channel],
+                            vec![Platform::NoArch],
+                            vec![PackageName::from_str("empty").unwrap()].into_iter(),
+                        )
+                        .await
+                }
+            };
+
+            let records = query("publisher@example.com").await.unwrap();
+            assert_eq!(records.iter().map(RepoData::len).sum::<usize>(), 1);
+
+            let result = query("someone-else@example.com").await;
+            assert_matches!(result, Err(GatewayError::SignatureVerificationFailed(..)));
+        }
+    }
 }
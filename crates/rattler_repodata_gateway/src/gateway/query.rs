@@ -308,6 +308,14 @@ impl RepoDataQuery {
             }
         }
 
+        // Persist the cache bookkeeping of the subdirectories. Failing to do so
+        // only makes the next query less efficient.
+        for (_, subdir) in subdirs {
+            if let Err(err) = subdir.wait().await.flush().await {
+                tracing::warn!("failed to flush the cache of a subdirectory: {err}");
+            }
+        }
+
         Ok(result)
     }
 }
@@ -9,6 +9,8 @@ use crate::{
 };
 use rattler_conda_types::{Channel, Platform};
 use rattler_networking::LazyClient;
+use rattler_redaction::Redact;
+use rattler_signatures::SignatureVerifier;
 
 pub struct RemoteSubdirClient {
     pub(super) sparse: LocalSubdirClient,
@@ -21,18 +23,21 @@ impl RemoteSubdirClient {
         client: LazyClient,
         cache_dir: PathBuf,
         source_config: SourceConfig,
+        signature_verifier: Option<&SignatureVerifier>,
         reporter: Option<Arc<dyn Reporter>>,
     ) -> Result<Self, GatewayError> {
         let subdir_url = channel.platform_url(platform);
 
         // Fetch the repodata from the remote server
         let repodata = fetch_repo_data(
-            subdir_url,
-            client,
+            subdir_url.clone(),
+            client.clone(),
             cache_dir,
             FetchRepoDataOptions {
                 cache_action: source_config.cache_action,
-                jlap_enabled: source_config.jlap_enabled,
+                // Patching the repodata with JLAP does not reproduce the
+                // signed file byte for byte.
+                jlap_enabled: source_config.jlap_enabled && signature_verifier.is_none(),
                 zstd_enabled: source_config.zstd_enabled,
                 bz2_enabled: source_config.bz2_enabled,
                 ..FetchRepoDataOptions::default()
@@ -51,6 +56,18 @@ impl RemoteSubdirClient {
             e => GatewayError::FetchRepoDataError(e),
         })?;
 
+        if let Some(verifier) = signature_verifier {
+            let url = subdir_url
+                .join("repodata.json")
+                .expect("the platform url is a valid base url");
+            verifier
+                .verify_file(client.client(), &url, &repodata.repo_data_json_path)
+                .await
+                .map_err(|err| {
+                    GatewayError::SignatureVerificationFailed(url.redact().to_string(), err)
+                })?;
+        }
+
         // Create a new sparse repodata client that can be used to read records from the
         // repodata.
         let sparse = simple_spawn_blocking::tokio::run_blocking_task(move || {
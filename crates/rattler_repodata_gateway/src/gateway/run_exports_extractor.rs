@@ -116,9 +116,10 @@ impl RunExportExtractor {
     }
 
     /// Extract the run exports from a package by first checking the
-    /// `run_exports.json` file in the channel subdirectory, and if that fails,
-    /// it will download the package to the cache and read the
-    /// `run_exports.json` file from there.
+    /// `run_exports.json` file in the channel subdirectory. If that fails, it
+    /// tries to read `info/run_exports.json` from a remote `.conda` archive
+    /// using range requests, and as a last resort it will download the package
+    /// to the cache and read the `run_exports.json` file from there.
     #[instrument(skip_all, fields(record = %record.url.as_str()))]
     pub async fn extract(
         mut self,
@@ -139,6 +140,11 @@ impl RunExportExtractor {
             return Ok(subdir_run_exports.get(record).cloned());
         }
 
+        // Try to read only the info section of the remote package.
+        if let Some(remote_run_exports) = self.fetch_from_remote_package(record).await {
+            return Ok(remote_run_exports);
+        }
+
         // Otherwise, fall back to extracting from the package cache.
         if let Some(package_cache_run_exports) = self
             .extract_into_package_cache(record, progress_reporter)
@@ -305,6 +311,48 @@ impl RunExportExtractor {
             .unwrap_or(None)
     }
 
+    /// Read the `run_exports.json` file from a remote `.conda` archive by only
+    /// requesting the parts of the archive that contain the info section.
+    ///
+    /// Returns `None` if the run exports could not be read this way, in which
+    /// case the caller should fall back to downloading the whole package.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn fetch_from_remote_package(
+        &self,
+        record: &RepoDataRecord,
+    ) -> Option<Option<RunExportsJson>> {
+        use rattler_conda_types::package::ArchiveType;
+        use rattler_package_streaming::{reqwest::range::fetch_package_file, ExtractError};
+
+        let client = self.client.as_ref()?;
+        if ArchiveType::try_from(Path::new(record.url.path())) != Some(ArchiveType::Conda) {
+            return None;
+        }
+
+        let _permit = self.acquire_request_permit().await;
+        match fetch_package_file::<RunExportsJson>(client.client().clone(), record.url.clone())
+            .await
+        {
+            Ok(run_exports) => Some(Some(run_exports)),
+            Err(ExtractError::MissingComponent) => Some(None),
+            Err(err) => {
+                tracing::debug!(
+                    "failed to read run exports from '{}' using range requests: {err}",
+                    record.url
+                );
+                None
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn fetch_from_remote_package(
+        &self,
+        _record: &RepoDataRecord,
+    ) -> Option<Option<RunExportsJson>> {
+        None
+    }
+
     /// Extract the run exports from a package by downloading it to the cache
     /// and then reading the `run_exports.json` file.
     #[cfg(not(target_arch = "wasm32"))]
@@ -0,0 +1,191 @@
+//! Bookkeeping of the shards of a subdir that are present in the local cache.
+//!
+//! Shards are cached by their content hash, so a cached shard never becomes
+//! invalid. However, when the shard index changes, the shard for a package
+//! might be replaced by a shard with a different hash. The [`ShardManifest`]
+//! records which shard was last cached for each package so that a freshly
+//! fetched index can be compared against it without sending any additional
+//! requests.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rattler_conda_types::ShardedRepodata;
+use rattler_digest::{serde::SerializableHash, Sha256, Sha256Hash};
+use serde::{Deserialize, Serialize};
+
+/// Maps package names to the hash of the shard that is stored in the local
+/// cache for that package.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShardManifest {
+    #[serde_as(as = "HashMap<_, SerializableHash<Sha256>>")]
+    shards: HashMap<String, Sha256Hash>,
+}
+
+/// The result of comparing a [`ShardManifest`] with a shard index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardManifestDelta {
+    /// The number of cached shards that are still referenced by the index.
+    pub current: usize,
+
+    /// The number of cached shards that have been replaced or removed.
+    pub stale: usize,
+}
+
+impl ShardManifest {
+    /// Removes all entries that no longer match the shard index and returns
+    /// how many entries were kept and removed.
+    pub fn retain_current(&mut self, index: &ShardedRepodata) -> ShardManifestDelta {
+        let before = self.shards.len();
+        self.shards
+            .retain(|name, hash| index.shards.get(name) == Some(hash));
+        ShardManifestDelta {
+            current: self.shards.len(),
+            stale: before - self.shards.len(),
+        }
+    }
+
+    /// Returns the hashes of the cached shards that are no longer referenced
+    /// by any package in the shard index.
+    #[cfg(any(target_arch = "wasm32", test))]
+    pub fn unreferenced_shards(&self, index: &ShardedRepodata) -> Vec<Sha256Hash> {
+        let referenced: std::collections::HashSet<&Sha256Hash> = index.shards.values().collect();
+        self.shards
+            .values()
+            .filter(|hash| !referenced.contains(hash))
+            .copied()
+            .collect()
+    }
+
+    /// Records that the shard with the given hash is cached for a package.
+    /// Returns true if the manifest changed.
+    pub fn insert(&mut self, name: &str, hash: Sha256Hash) -> bool {
+        self.shards.insert(name.to_owned(), hash) != Some(hash)
+    }
+}
+
+/// A snapshot of the statistics of the sharded repodata cache of a
+/// [`crate::Gateway`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardCacheStats {
+    /// The number of shard indices that were reused from the cache, either
+    /// because they were still fresh or because the server reported that
+    /// they were not modified.
+    pub index_reused: usize,
+
+    /// The number of shard indices that were downloaded.
+    pub index_downloaded: usize,
+
+    /// The number of previously cached shards that were invalidated because
+    /// the shard index changed.
+    pub shards_stale: usize,
+
+    /// The number of shards that were read from the cache.
+    pub shards_reused: usize,
+
+    /// The number of shards that were downloaded.
+    pub shards_downloaded: usize,
+}
+
+/// Thread-safe counters that are shared between all sharded subdirs of a
+/// gateway.
+#[derive(Debug, Default)]
+pub struct ShardCacheMetrics {
+    index_reused: AtomicUsize,
+    index_downloaded: AtomicUsize,
+    shards_stale: AtomicUsize,
+    shards_reused: AtomicUsize,
+    shards_downloaded: AtomicUsize,
+}
+
+impl ShardCacheMetrics {
+    pub fn record_index_reused(&self) {
+        self.index_reused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_index_downloaded(&self) {
+        self.index_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_stale_shards(&self, count: usize) {
+        self.shards_stale.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_shard_reused(&self) {
+        self.shards_reused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_shard_downloaded(&self) {
+        self.shards_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of all counters.
+    pub fn stats(&self) -> ShardCacheStats {
+        ShardCacheStats {
+            index_reused: self.index_reused.load(Ordering::Relaxed),
+            index_downloaded: self.index_downloaded.load(Ordering::Relaxed),
+            shards_stale: self.shards_stale.load(Ordering::Relaxed),
+            shards_reused: self.shards_reused.load(Ordering::Relaxed),
+            shards_downloaded: self.shards_downloaded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::{ShardedRepodata, ShardedSubdirInfo};
+    use rattler_digest::{compute_bytes_digest, Sha256};
+
+    use super::ShardManifest;
+
+    fn index(shards: &[(&str, &str)]) -> ShardedRepodata {
+        ShardedRepodata {
+            info: ShardedSubdirInfo {
+                subdir: String::from("noarch"),
+                base_url: String::from("./"),
+                shards_base_url: String::from("./shards/"),
+                created_at: None,
+            },
+            shards: shards
+                .iter()
+                .map(|(name, content)| {
+                    ((*name).to_owned(), compute_bytes_digest::<Sha256>(content))
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_retain_current() {
+        let old_index = index(&[("foo", "foo-1"), ("bar", "bar-1"), ("baz", "baz-1")]);
+        let mut manifest = ShardManifest::default();
+        for (name, hash) in &old_index.shards {
+            assert!(manifest.insert(name, *hash));
+        }
+        assert!(!manifest.insert("foo", old_index.shards["foo"]));
+
+        // `bar` changed and `baz` was removed from the index.
+        let new_index = index(&[("foo", "foo-1"), ("bar", "bar-2"), ("qux", "qux-1")]);
+        let mut unreferenced = manifest.unreferenced_shards(&new_index);
+        unreferenced.sort();
+        let mut expected = vec![old_index.shards["bar"], old_index.shards["baz"]];
+        expected.sort();
+        assert_eq!(unreferenced, expected);
+
+        let delta = manifest.retain_current(&new_index);
+        assert_eq!(delta.current, 1);
+        assert_eq!(delta.stale, 2);
+        assert_eq!(manifest.shards.len(), 1);
+        assert_eq!(manifest.shards.get("foo"), new_index.shards.get("foo"));
+
+        // The manifest survives a roundtrip through its on-disk format.
+        let bytes = rmp_serde::to_vec(&manifest).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<ShardManifest>(&bytes).unwrap(),
+            manifest
+        );
+    }
+}
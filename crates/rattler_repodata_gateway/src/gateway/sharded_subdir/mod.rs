@@ -7,6 +7,11 @@ use url::Url;
 
 use crate::{fetch::FetchRepoDataError, GatewayError};
 
+mod manifest;
+
+pub use manifest::ShardCacheStats;
+pub(crate) use manifest::{ShardCacheMetrics, ShardManifest};
+
 cfg_if! {
     if #[cfg(target_arch = "wasm32")] {
         mod wasm;
@@ -3,7 +3,7 @@ use std::{path::Path, str::FromStr, sync::Arc, time::SystemTime};
 use super::ShardedRepodata;
 use crate::{
     fetch::CacheAction,
-    gateway::sharded_subdir::decode_zst_bytes_async,
+    gateway::sharded_subdir::{decode_zst_bytes_async, ShardCacheMetrics},
     reporter::{DownloadReporter, ResponseReporterExt},
     utils::url_to_cache_filename,
     GatewayError, Reporter,
@@ -34,6 +34,7 @@ pub async fn fetch_index(
     cache_dir: &Path,
     cache_action: CacheAction,
     concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
+    metrics: &ShardCacheMetrics,
     reporter: Option<&dyn Reporter>,
 ) -> Result<ShardedRepodata, GatewayError> {
     async fn from_response(
@@ -153,6 +154,7 @@ pub async fn fetch_index(
             if cache_action == CacheAction::ForceCacheOnly {
                 if let Ok(shard_index) = read_shard_index_from_reader(&mut cache_reader).await {
                     tracing::debug!("using locally cached shard index for {channel_base_url}");
+                    metrics.record_index_reused();
                     return Ok(shard_index);
                 }
             } else {
@@ -165,6 +167,7 @@ pub async fn fetch_index(
                             read_shard_index_from_reader(&mut cache_reader).await
                         {
                             tracing::debug!("shard index cache hit");
+                            metrics.record_index_reused();
                             return Ok(shard_index);
                         }
                     }
@@ -222,6 +225,7 @@ pub async fn fetch_index(
                                         }
                                         // If reading the file failed for some reason we'll just
                                         // fetch it again.
+                                        metrics.record_index_reused();
                                         return Ok(shard_index);
                                     }
                                     Err(e) => {
@@ -237,6 +241,7 @@ pub async fn fetch_index(
                             AfterResponse::Modified(policy, _) => {
                                 // Close the old file so we can create a new one.
                                 tracing::debug!("shard index cache has become stale");
+                                metrics.record_index_downloaded();
                                 return from_response(
                                     cache_reader.into_inner(),
                                     &cache_path,
@@ -298,6 +303,7 @@ pub async fn fetch_index(
         .await?;
 
     let policy = CachePolicy::new(&canonical_request, &response);
+    metrics.record_index_downloaded();
     from_response(
         cache_reader.into_inner(),
         &cache_path,
@@ -1,18 +1,29 @@
 mod index;
 
-use std::{io::Write, path::PathBuf, sync::Arc};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use super::{add_trailing_slash, decode_zst_bytes_async, parse_records};
+use super::{
+    add_trailing_slash, decode_zst_bytes_async, parse_records, ShardCacheMetrics, ShardManifest,
+};
 use crate::{
     fetch::{CacheAction, FetchRepoDataError},
     gateway::{error::SubdirNotFoundError, subdir::SubdirClient},
     reporter::ResponseReporterExt,
+    utils::url_to_cache_filename,
     GatewayError, Reporter,
 };
 use fs_err::tokio as tokio_fs;
 use futures::future::OptionFuture;
 use http::{header::CACHE_CONTROL, HeaderValue, StatusCode};
 use rattler_conda_types::{Channel, PackageName, RepoDataRecord, ShardedRepodata};
+use rattler_digest::Sha256Hash;
 use rattler_networking::LazyClient;
 use simple_spawn_blocking::tokio::run_blocking_task;
 use url::Url;
@@ -26,9 +37,14 @@ pub struct ShardedSubdir {
     concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
     cache_dir: PathBuf,
     cache_action: CacheAction,
+    manifest: parking_lot::Mutex<ShardManifest>,
+    manifest_path: PathBuf,
+    manifest_dirty: AtomicBool,
+    metrics: Arc<ShardCacheMetrics>,
 }
 
 impl ShardedSubdir {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         channel: Channel,
         subdir: String,
@@ -36,6 +52,7 @@ impl ShardedSubdir {
         cache_dir: PathBuf,
         cache_action: CacheAction,
         concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
+        metrics: Arc<ShardCacheMetrics>,
         reporter: Option<&dyn Reporter>,
     ) -> Result<Self, GatewayError> {
         // Construct the base url for the shards (e.g. `<channel>/<subdir>`).
@@ -52,6 +69,7 @@ impl ShardedSubdir {
             &cache_dir,
             cache_action,
             concurrent_requests_semaphore.clone(),
+            &metrics,
             reporter,
         )
         .await
@@ -86,6 +104,25 @@ impl ShardedSubdir {
                 ))
             })?;
 
+        // Read the manifest of shards that we previously cached for this subdir
+        // and drop the entries that are no longer referenced by the index.
+        let manifest_path = cache_dir.join(format!(
+            "{}.shards-manifest-v1",
+            url_to_cache_filename(&index_base_url)
+        ));
+        let mut manifest = if cache_action == CacheAction::NoCache {
+            ShardManifest::default()
+        } else {
+            read_manifest(&manifest_path).await
+        };
+        let delta = manifest.retain_current(&sharded_repodata);
+        metrics.record_stale_shards(delta.stale);
+        tracing::debug!(
+            "{} cached shards of {index_base_url} are current, {} are stale",
+            delta.current,
+            delta.stale
+        );
+
         // Determine the cache directory and make sure it exists.
         let cache_dir = cache_dir.join("shards-v1");
         tokio_fs::create_dir_all(&cache_dir)
@@ -101,8 +138,19 @@ impl ShardedSubdir {
             cache_dir,
             cache_action,
             concurrent_requests_semaphore,
+            manifest: parking_lot::Mutex::new(manifest),
+            manifest_path,
+            manifest_dirty: AtomicBool::new(delta.stale > 0),
+            metrics,
         })
     }
+
+    /// Records that the shard with the given hash is stored in the cache.
+    fn record_cached_shard(&self, name: &PackageName, shard: Sha256Hash) {
+        if self.manifest.lock().insert(name.as_normalized(), shard) {
+            self.manifest_dirty.store(true, Ordering::Relaxed);
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -124,6 +172,9 @@ impl SubdirClient for ShardedSubdir {
         if self.cache_action != CacheAction::NoCache {
             match tokio_fs::read(&shard_cache_path).await {
                 Ok(cached_bytes) => {
+                    self.metrics.record_shard_reused();
+                    self.record_cached_shard(name, *shard);
+
                     // Decode the cached shard
                     return parse_records(
                         cached_bytes,
@@ -195,6 +246,7 @@ impl SubdirClient for ShardedSubdir {
             bytes
         };
 
+        self.metrics.record_shard_downloaded();
         let shard_bytes = decode_zst_bytes_async(shard_bytes).await?;
 
         // Create a future to write the cached bytes to disk
@@ -209,6 +261,7 @@ impl SubdirClient for ShardedSubdir {
 
         // Await both futures concurrently.
         let (_, records) = tokio::try_join!(write_to_cache_fut, parse_records_fut)?;
+        self.record_cached_shard(name, *shard);
 
         Ok(records.into())
     }
@@ -216,6 +269,27 @@ impl SubdirClient for ShardedSubdir {
     fn package_names(&self) -> Vec<String> {
         self.sharded_repodata.shards.keys().cloned().collect()
     }
+
+    async fn flush(&self) -> Result<(), GatewayError> {
+        if !self.manifest_dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let manifest = self.manifest.lock().clone();
+        let path = self.manifest_path.clone();
+        let result = run_blocking_task(move || {
+            write_manifest(&path, &manifest).map_err(|err| {
+                GatewayError::IoError(
+                    format!("failed to write shard manifest to {}", path.display()),
+                    err,
+                )
+            })
+        })
+        .await;
+        if result.is_err() {
+            self.manifest_dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
 }
 
 /// Atomically writes the shard bytes to the cache.
@@ -268,3 +342,29 @@ async fn write_shard_to_cache(
     })
     .await
 }
+
+/// Reads the shard manifest from disk. A missing or corrupt manifest is
+/// treated as empty.
+async fn read_manifest(path: &Path) -> ShardManifest {
+    match tokio_fs::read(path).await {
+        Ok(bytes) => rmp_serde::from_slice(&bytes).unwrap_or_else(|err| {
+            tracing::warn!("ignoring corrupt shard manifest {}: {err}", path.display());
+            ShardManifest::default()
+        }),
+        Err(_) => ShardManifest::default(),
+    }
+}
+
+/// Atomically writes the shard manifest to disk.
+///
+/// Concurrent processes may overwrite each other's manifest. This is fine
+/// because the manifest is only used to determine which shards are current,
+/// the shards themselves are always looked up by their hash.
+fn write_manifest(path: &Path, manifest: &ShardManifest) -> std::io::Result<()> {
+    let bytes = rmp_serde::to_vec(manifest).map_err(std::io::Error::other)?;
+    let parent = path.parent().expect("file path must have a parent");
+    let mut temp_file = tempfile::NamedTempFile::new_in(parent)?;
+    temp_file.write_all(&bytes)?;
+    temp_file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
@@ -1,23 +1,43 @@
-use std::sync::Arc;
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
 
 use bytes::Bytes;
 use futures::future::OptionFuture;
+use http::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    HeaderValue, StatusCode,
+};
 use rattler_networking::LazyClient;
 use url::Url;
 
 use super::ShardedRepodata;
 use crate::{
-    gateway::sharded_subdir::decode_zst_bytes_async, reporter::ResponseReporterExt, GatewayError,
-    Reporter,
+    gateway::sharded_subdir::{decode_zst_bytes_async, ShardCacheMetrics},
+    reporter::ResponseReporterExt,
+    GatewayError, Reporter,
 };
 
 const REPODATA_SHARDS_FILENAME: &str = "repodata_shards.msgpack.zst";
 
+/// A previously fetched shard index together with the validators that are
+/// used to conditionally request it again.
+struct CachedIndex {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    index: ShardedRepodata,
+}
+
+thread_local! {
+    /// Shard indices that have been fetched during the lifetime of the
+    /// page, keyed by their url.
+    static INDEX_CACHE: RefCell<HashMap<Url, CachedIndex>> = RefCell::new(HashMap::new());
+}
+
 // Fetches the shard index from the url or read it from the cache.
 pub async fn fetch_index(
     client: LazyClient,
     channel_base_url: &Url,
     concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
+    metrics: &ShardCacheMetrics,
     reporter: Option<&dyn Reporter>,
 ) -> Result<ShardedRepodata, GatewayError> {
     // Determine the actual URL to use for the request
@@ -25,10 +45,23 @@ pub async fn fetch_index(
         .join(REPODATA_SHARDS_FILENAME)
         .expect("invalid shard base url");
 
-    // Construct the actual request that we will send
-    let request = client
-        .client()
-        .get(shards_url.clone())
+    // Construct the actual request that we will send. If we fetched the index
+    // before we only ask for it if it changed.
+    let validators = INDEX_CACHE.with_borrow(|cache| {
+        cache
+            .get(&shards_url)
+            .map(|cached| (cached.etag.clone(), cached.last_modified.clone()))
+    });
+    let mut request_builder = client.client().get(shards_url.clone());
+    if let Some((etag, last_modified)) = &validators {
+        if let Some(etag) = etag {
+            request_builder = request_builder.header(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = last_modified {
+            request_builder = request_builder.header(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+    let request = request_builder
         .build()
         .expect("failed to build request for shard index");
 
@@ -51,7 +84,22 @@ pub async fn fetch_index(
         )
         .await?;
 
+    if response.status() == StatusCode::NOT_MODIFIED && validators.is_some() {
+        if let Some(cached_index) =
+            INDEX_CACHE.with_borrow(|cache| cache.get(&shards_url).map(|c| c.index.clone()))
+        {
+            if let Some((reporter, index)) = reporter {
+                reporter.on_download_complete(&shards_url, index);
+            }
+            tracing::debug!("shard index was not modified");
+            metrics.record_index_reused();
+            return Ok(cached_index);
+        }
+    }
+
     let response = response.error_for_status()?;
+    let etag = response.headers().get(ETAG).cloned();
+    let last_modified = response.headers().get(LAST_MODIFIED).cloned();
 
     // Read the bytes of the response
     let response_url = response.url().clone();
@@ -68,7 +116,7 @@ pub async fn fetch_index(
     drop(request_permit);
 
     // Parse the bytes
-    let sharded_index = rmp_serde::from_slice(&decoded_bytes)
+    let sharded_index: ShardedRepodata = rmp_serde::from_slice(&decoded_bytes)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
         .map_err(|e| {
             GatewayError::IoError(
@@ -77,5 +125,19 @@ pub async fn fetch_index(
             )
         })?;
 
+    metrics.record_index_downloaded();
+    if etag.is_some() || last_modified.is_some() {
+        INDEX_CACHE.with_borrow_mut(|cache| {
+            cache.insert(
+                shards_url,
+                CachedIndex {
+                    etag,
+                    last_modified,
+                    index: sharded_index.clone(),
+                },
+            );
+        });
+    }
+
     Ok(sharded_index)
 }
@@ -1,14 +1,23 @@
-use std::sync::Arc;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use futures::future::OptionFuture;
 use http::StatusCode;
 use rattler_conda_types::{Channel, PackageName, RepoDataRecord, ShardedRepodata};
+use rattler_digest::Sha256Hash;
 use rattler_networking::LazyClient;
 use url::Url;
 
-use super::add_trailing_slash;
+use super::{add_trailing_slash, ShardCacheMetrics, ShardManifest};
 
 mod index;
+mod storage;
 
 use crate::{
     fetch::FetchRepoDataError,
@@ -21,6 +30,58 @@ use crate::{
     GatewayError, Reporter,
 };
 
+/// The maximum total size of the decoded shards that are kept in memory.
+const MAX_SHARD_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The name of the file that stores the manifest of a subdir in the
+/// persistent storage, relative to the url of the subdir.
+const MANIFEST_FILENAME: &str = "repodata_shards.manifest-v1";
+
+thread_local! {
+    /// Decoded shards that were recently used, keyed by their hash. All
+    /// downloaded shards are also stored in the persistent storage.
+    static SHARD_CACHE: RefCell<ShardCache> = RefCell::new(ShardCache::default());
+
+    /// The manifest of cached shards for every subdir, keyed by the url of the
+    /// subdir.
+    static MANIFESTS: RefCell<HashMap<Url, ShardManifest>> = RefCell::new(HashMap::new());
+}
+
+/// A cache of decoded shards whose total size is limited to
+/// [`MAX_SHARD_CACHE_SIZE`]. The least recently used shards are evicted first.
+#[derive(Default)]
+struct ShardCache {
+    shards: HashMap<Sha256Hash, Arc<[u8]>>,
+    recently_used: VecDeque<Sha256Hash>,
+    size: usize,
+}
+
+impl ShardCache {
+    fn get(&mut self, hash: &Sha256Hash) -> Option<Arc<[u8]>> {
+        let bytes = self.shards.get(hash)?.clone();
+        self.recently_used.retain(|other| other != hash);
+        self.recently_used.push_back(*hash);
+        Some(bytes)
+    }
+
+    fn insert(&mut self, hash: Sha256Hash, bytes: Arc<[u8]>) {
+        if bytes.len() > MAX_SHARD_CACHE_SIZE || self.shards.contains_key(&hash) {
+            return;
+        }
+        while self.size + bytes.len() > MAX_SHARD_CACHE_SIZE {
+            let Some(evicted) = self.recently_used.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.shards.remove(&evicted) {
+                self.size -= evicted.len();
+            }
+        }
+        self.size += bytes.len();
+        self.recently_used.push_back(hash);
+        self.shards.insert(hash, bytes);
+    }
+}
+
 pub struct ShardedSubdir {
     channel: Channel,
     client: LazyClient,
@@ -28,6 +89,9 @@ pub struct ShardedSubdir {
     package_base_url: Url,
     sharded_repodata: ShardedRepodata,
     concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
+    index_base_url: Url,
+    manifest_dirty: AtomicBool,
+    metrics: Arc<ShardCacheMetrics>,
 }
 
 impl ShardedSubdir {
@@ -36,6 +100,7 @@ impl ShardedSubdir {
         subdir: String,
         client: LazyClient,
         concurrent_requests_semaphore: Option<Arc<tokio::sync::Semaphore>>,
+        metrics: Arc<ShardCacheMetrics>,
         reporter: Option<&dyn Reporter>,
     ) -> Result<Self, GatewayError> {
         // Construct the base url for the shards (e.g. `<channel>/<subdir>`).
@@ -50,6 +115,7 @@ impl ShardedSubdir {
             client.clone(),
             &index_base_url,
             concurrent_requests_semaphore.clone(),
+            &metrics,
             reporter,
         )
         .await
@@ -84,13 +150,42 @@ impl ShardedSubdir {
                 ))
             })?;
 
+        // Read the manifest of shards that we previously cached for this subdir
+        // and drop the entries that are no longer referenced by the index. The
+        // shards that are not referenced anymore are removed from the storage.
+        let cache = storage::open().await;
+        let manifest_url = index_base_url
+            .join(MANIFEST_FILENAME)
+            .expect("invalid manifest url");
+        let mut manifest =
+            match MANIFESTS.with_borrow(|manifests| manifests.get(&index_base_url).cloned()) {
+                Some(manifest) => manifest,
+                None => read_manifest(cache.as_ref(), &manifest_url).await,
+            };
+        let unreferenced = manifest.unreferenced_shards(&sharded_repodata);
+        let delta = manifest.retain_current(&sharded_repodata);
+        metrics.record_stale_shards(delta.stale);
+        MANIFESTS.with_borrow_mut(|manifests| {
+            manifests.insert(index_base_url.clone(), manifest);
+        });
+        let shards_base_url = add_trailing_slash(&shards_base_url).into_owned();
+        if let Some(cache) = &cache {
+            for shard in unreferenced {
+                let shard_url = shard_url(&shards_base_url, &shard);
+                storage::delete(cache, &shard_url).await;
+            }
+        }
+
         Ok(Self {
             channel,
             client,
-            shards_base_url: add_trailing_slash(&shards_base_url).into_owned(),
+            shards_base_url,
             package_base_url: add_trailing_slash(&package_base_url).into_owned(),
             sharded_repodata,
             concurrent_requests_semaphore,
+            index_base_url,
+            manifest_dirty: AtomicBool::new(delta.stale > 0),
+            metrics,
         })
     }
 }
@@ -107,11 +202,33 @@ impl SubdirClient for ShardedSubdir {
             return Ok(vec![].into());
         };
 
+        // Check if we already have the shard in memory or in the persistent
+        // storage.
+        let shard_url = shard_url(&self.shards_base_url, shard);
+        let mut cached_bytes = SHARD_CACHE.with_borrow_mut(|cache| cache.get(shard));
+        let cache = storage::open().await;
+        if cached_bytes.is_none() {
+            if let Some(cache) = &cache {
+                if let Some(bytes) = storage::read(cache, &shard_url).await {
+                    let bytes: Arc<[u8]> = decode_zst_bytes_async(bytes).await?.into();
+                    SHARD_CACHE.with_borrow_mut(|cache| cache.insert(*shard, bytes.clone()));
+                    cached_bytes = Some(bytes);
+                }
+            }
+        }
+        if let Some(cached_bytes) = cached_bytes {
+            self.metrics.record_shard_reused();
+            self.record_cached_shard(name, *shard);
+            return parse_records(
+                cached_bytes,
+                self.channel.base_url.clone(),
+                self.package_base_url.clone(),
+            )
+            .await
+            .map(Arc::from);
+        }
+
         // Download the shard
-        let shard_url = self
-            .shards_base_url
-            .join(&format!("{shard:x}.msgpack.zst"))
-            .expect("invalid shard url");
 
         let shard_request = self
             .client
@@ -150,7 +267,14 @@ impl SubdirClient for ShardedSubdir {
             bytes
         };
 
-        let shard_bytes = decode_zst_bytes_async(shard_bytes).await?;
+        self.metrics.record_shard_downloaded();
+        let compressed_bytes = shard_bytes.to_vec();
+        let shard_bytes: Arc<[u8]> = decode_zst_bytes_async(shard_bytes).await?.into();
+        SHARD_CACHE.with_borrow_mut(|cache| cache.insert(*shard, shard_bytes.clone()));
+        if let Some(cache) = &cache {
+            storage::write(cache, &shard_url, compressed_bytes).await;
+        }
+        self.record_cached_shard(name, *shard);
 
         // Create a future to parse the records from the shard
         let records = parse_records(
@@ -166,4 +290,68 @@ impl SubdirClient for ShardedSubdir {
     fn package_names(&self) -> Vec<String> {
         self.sharded_repodata.shards.keys().cloned().collect()
     }
+
+    async fn flush(&self) -> Result<(), GatewayError> {
+        if !self.manifest_dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let Some(cache) = storage::open().await else {
+            return Ok(());
+        };
+        let manifest = MANIFESTS.with_borrow(|manifests| {
+            manifests
+                .get(&self.index_base_url)
+                .cloned()
+                .unwrap_or_default()
+        });
+        let bytes = rmp_serde::to_vec(&manifest).map_err(|err| {
+            GatewayError::IoError(
+                "failed to serialize shard manifest".to_string(),
+                std::io::Error::other(err),
+            )
+        })?;
+        let manifest_url = self
+            .index_base_url
+            .join(MANIFEST_FILENAME)
+            .expect("invalid manifest url");
+        storage::write(&cache, &manifest_url, bytes).await;
+        Ok(())
+    }
+}
+
+impl ShardedSubdir {
+    /// Records that the shard with the given hash is stored in the cache.
+    fn record_cached_shard(&self, name: &PackageName, shard: Sha256Hash) {
+        let changed = MANIFESTS.with_borrow_mut(|manifests| {
+            manifests
+                .entry(self.index_base_url.clone())
+                .or_default()
+                .insert(name.as_normalized(), shard)
+        });
+        if changed {
+            self.manifest_dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the url of the shard with the given hash.
+fn shard_url(shards_base_url: &Url, shard: &Sha256Hash) -> Url {
+    shards_base_url
+        .join(&format!("{shard:x}.msgpack.zst"))
+        .expect("invalid shard url")
+}
+
+/// Reads the shard manifest from the persistent storage. A missing or corrupt
+/// manifest is treated as empty.
+async fn read_manifest(cache: Option<&web_sys::Cache>, manifest_url: &Url) -> ShardManifest {
+    let Some(bytes) = OptionFuture::from(cache.map(|cache| storage::read(cache, manifest_url)))
+        .await
+        .flatten()
+    else {
+        return ShardManifest::default();
+    };
+    rmp_serde::from_slice(&bytes).unwrap_or_else(|err| {
+        tracing::warn!("ignoring corrupt shard manifest {manifest_url}: {err}");
+        ShardManifest::default()
+    })
 }
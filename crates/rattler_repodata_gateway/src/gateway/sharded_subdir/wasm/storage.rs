@@ -0,0 +1,61 @@
+//! Persistent storage of shards and shard manifests in the Cache Storage of
+//! the browser.
+//!
+//! Entries are keyed by url. All operations are best effort: if the Cache
+//! Storage is not available, for instance in an insecure context, nothing is
+//! stored and every lookup misses.
+
+use js_sys::{Reflect, Uint8Array};
+use url::Url;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Cache, CacheStorage, Response};
+
+/// The name of the cache in the Cache Storage.
+const CACHE_NAME: &str = "rattler-shards-v1";
+
+/// Opens the cache, returns `None` if the Cache Storage is not available.
+pub async fn open() -> Option<Cache> {
+    let caches = Reflect::get(&js_sys::global(), &JsValue::from_str("caches"))
+        .ok()?
+        .dyn_into::<CacheStorage>()
+        .ok()?;
+    match JsFuture::from(caches.open(CACHE_NAME)).await {
+        Ok(cache) => cache.dyn_into::<Cache>().ok(),
+        Err(err) => {
+            tracing::debug!("failed to open the shard cache: {err:?}");
+            None
+        }
+    }
+}
+
+/// Reads the bytes stored for `key`.
+pub async fn read(cache: &Cache, key: &Url) -> Option<Vec<u8>> {
+    let response = JsFuture::from(cache.match_with_str(key.as_str()))
+        .await
+        .ok()?
+        .dyn_into::<Response>()
+        .ok()?;
+    let buffer = JsFuture::from(response.array_buffer().ok()?).await.ok()?;
+    Some(Uint8Array::new(&buffer).to_vec())
+}
+
+/// Stores `bytes` for `key`.
+pub async fn write(cache: &Cache, key: &Url, mut bytes: Vec<u8>) {
+    let result = match Response::new_with_opt_u8_array(Some(&mut bytes)) {
+        Ok(response) => JsFuture::from(cache.put_with_str(key.as_str(), &response))
+            .await
+            .map(drop),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        tracing::warn!("failed to store {key} in the shard cache: {err:?}");
+    }
+}
+
+/// Removes the entry for `key`.
+pub async fn delete(cache: &Cache, key: &Url) {
+    if let Err(err) = JsFuture::from(cache.delete_with_str(key.as_str())).await {
+        tracing::debug!("failed to remove {key} from the shard cache: {err:?}");
+    }
+}
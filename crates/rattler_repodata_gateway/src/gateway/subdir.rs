@@ -22,6 +22,14 @@ impl Subdir {
             Subdir::NotFound => None,
         }
     }
+
+    /// Writes any pending cache bookkeeping of the subdirectory.
+    pub async fn flush(&self) -> Result<(), GatewayError> {
+        match self {
+            Subdir::Found(subdir) => subdir.client.flush().await,
+            Subdir::NotFound => Ok(()),
+        }
+    }
 }
 
 /// Fetches and caches repodata records by package name for a specific
@@ -85,4 +93,10 @@ pub trait SubdirClient: Send + Sync {
 
     /// Returns the names of all packages in the subdirectory.
     fn package_names(&self) -> Vec<String>;
+
+    /// Writes any state that is kept in memory to the cache. This is called
+    /// after a query completed.
+    async fn flush(&self) -> Result<(), GatewayError> {
+        Ok(())
+    }
 }
@@ -2,6 +2,8 @@ use std::{path::Path, sync::Arc};
 
 use file_url::url_to_path;
 use rattler_conda_types::{Channel, Platform};
+#[cfg(not(target_arch = "wasm32"))]
+use rattler_redaction::Redact;
 
 use crate::{
     fetch::FetchRepoDataError,
@@ -58,10 +60,16 @@ impl<'g> SubdirBuilder<'g> {
         {
             let source_config = self.gateway.channel_config.get(&self.channel.base_url);
 
+            // Sharded repodata is not signed, skip it if signatures are verified.
+            #[cfg(not(target_arch = "wasm32"))]
+            let verify_signatures = self.gateway.signature_verifier.is_some();
+            #[cfg(target_arch = "wasm32")]
+            let verify_signatures = false;
+
             // Use sharded repodata if enabled
-            let subdir_data = if source_config.sharded_enabled
-                || gateway::force_sharded_repodata(&url)
-            {
+            let subdir_data = if verify_signatures {
+                None
+            } else if source_config.sharded_enabled || gateway::force_sharded_repodata(&url) {
                 match self.build_sharded(source_config).await {
                     Ok(client) => Some(client),
                     Err(GatewayError::SubdirNotFoundError(_)) => {
@@ -124,6 +132,8 @@ impl<'g> SubdirBuilder<'g> {
             #[cfg(not(target_arch = "wasm32"))]
             self.gateway.cache.clone(),
             source_config.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            self.gateway.signature_verifier.as_ref(),
             self.reporter.clone(),
         )
         .await?;
@@ -143,6 +153,7 @@ impl<'g> SubdirBuilder<'g> {
             #[cfg(not(target_arch = "wasm32"))]
             _source_config.cache_action,
             self.gateway.concurrent_requests_semaphore.clone(),
+            self.gateway.shard_cache_metrics.clone(),
             self.reporter.as_deref(),
         )
         .await?;
@@ -154,6 +165,22 @@ impl<'g> SubdirBuilder<'g> {
         let channel = self.channel.clone();
         let platform = self.platform;
         let path = path.join("repodata.json");
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(verifier) = &self.gateway.signature_verifier {
+            let url = self
+                .channel
+                .platform_url(platform)
+                .join("repodata.json")
+                .expect("the platform url is a valid base url");
+            verifier
+                .verify_file(self.gateway.client.client(), &url, &path)
+                .await
+                .map_err(|err| {
+                    GatewayError::SignatureVerificationFailed(url.clone().redact().to_string(), err)
+                })?;
+        }
+
         let build_client =
             move || LocalSubdirClient::from_file(&path, channel.clone(), platform.as_str());
 
@@ -73,8 +73,8 @@ mod gateway;
 
 #[cfg(feature = "gateway")]
 pub use gateway::{
-    ChannelConfig, Gateway, GatewayBuilder, GatewayError, MaxConcurrency, RepoData, SourceConfig,
-    SubdirSelection,
+    ChannelConfig, Gateway, GatewayBuilder, GatewayError, MaxConcurrency, RepoData,
+    ShardCacheStats, SourceConfig, SubdirSelection,
 };
 #[cfg(feature = "indicatif")]
 pub use gateway::{IndicatifReporter, IndicatifReporterBuilder};
@@ -1,5 +1,7 @@
 #[cfg(feature = "clap")]
 pub mod clap;
+#[cfg(feature = "opendal")]
+pub mod opendal;
 
 use aws_config::{BehaviorVersion, Region};
 use aws_credential_types::provider::error::CredentialsError;
@@ -0,0 +1,90 @@
+//! Send the requests of [`opendal`] operators with a [`ClientWithMiddleware`].
+//!
+//! By default an operator sends its requests with a plain `reqwest` client
+//! that ignores the proxy and TLS settings of the configuration. Build the
+//! client with [`rattler_networking::ClientConfig`] and add it to the
+//! operator with [`http_client_layer`] instead.
+
+use futures::{future, TryStreamExt};
+use opendal::{
+    layers::HttpClientLayer,
+    raw::{parse_content_encoding, parse_content_length, HttpBody, HttpClient, HttpFetch},
+    Buffer, Error, ErrorKind,
+};
+use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
+
+/// Returns a layer that makes an operator send its requests with `client`.
+pub fn http_client_layer(client: ClientWithMiddleware) -> HttpClientLayer {
+    HttpClientLayer::new(HttpClient::with(MiddlewareFetcher(client)))
+}
+
+/// Implements [`HttpFetch`] for a [`ClientWithMiddleware`].
+struct MiddlewareFetcher(ClientWithMiddleware);
+
+impl HttpFetch for MiddlewareFetcher {
+    async fn fetch(&self, req: http::Request<Buffer>) -> opendal::Result<http::Response<HttpBody>> {
+        let uri = req.uri().clone();
+        let is_head = req.method() == http::Method::HEAD;
+        let (parts, body) = req.into_parts();
+
+        let url = Url::parse(&uri.to_string()).map_err(|err| {
+            Error::new(ErrorKind::Unexpected, "request url is invalid")
+                .with_context("url", uri.to_string())
+                .set_source(err)
+        })?;
+        let mut request = self
+            .0
+            .request(parts.method, url)
+            .headers(parts.headers)
+            .version(parts.version);
+        if !body.is_empty() {
+            request = request.body(body.to_bytes());
+        }
+
+        let mut response = request.send().await.map_err(|err| {
+            let temporary = match &err {
+                reqwest_middleware::Error::Reqwest(err) => {
+                    err.is_request() || err.is_body() || err.is_decode()
+                }
+                reqwest_middleware::Error::Middleware(_) => false,
+            };
+            Error::new(ErrorKind::Unexpected, "send http request")
+                .with_context("url", uri.to_string())
+                .with_temporary(temporary)
+                .set_source(err)
+        })?;
+
+        // The content length of compressed responses and responses to HEAD
+        // requests does not match the body.
+        let content_length = if is_head || parse_content_encoding(response.headers())?.is_some() {
+            None
+        } else {
+            parse_content_length(response.headers())?
+        };
+
+        let mut builder = http::Response::builder()
+            .status(response.status())
+            .version(response.version())
+            .extension(uri.clone());
+        std::mem::swap(
+            builder.headers_mut().expect("the builder is valid"),
+            response.headers_mut(),
+        );
+
+        let body = HttpBody::new(
+            response
+                .bytes_stream()
+                .try_filter(|bytes| future::ready(!bytes.is_empty()))
+                .map_ok(Buffer::from)
+                .map_err(move |err| {
+                    Error::new(ErrorKind::Unexpected, "read data from http response")
+                        .with_context("url", uri.to_string())
+                        .with_temporary(true)
+                        .set_source(err)
+                }),
+            content_length,
+        );
+        Ok(builder.body(body).expect("the response is valid"))
+    }
+}
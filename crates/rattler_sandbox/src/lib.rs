@@ -1,3 +1,8 @@
+pub mod policy;
+pub mod violation;
+pub use policy::{EnvPolicy, SandboxPolicy, SandboxProfile};
+pub use violation::Violation;
+
 // A shim for the sandbox that is used on non-supported platforms
 #[cfg(not(any(
     all(target_os = "linux", target_arch = "x86_64"),
@@ -28,4 +33,6 @@ pub mod sandbox;
     all(target_os = "macos", target_arch = "x86_64"),
     all(target_os = "macos", target_arch = "aarch64"),
 ))]
-pub use sandbox::{init_sandbox, sandboxed_command, Exception, Opts};
+pub use sandbox::{
+    exit_with_status, init_sandbox, sandboxed_command, Exception, Opts, ViolationReport,
+};
@@ -6,7 +6,7 @@
 ))]
 fn main() {
     use clap::Parser;
-    use rattler_sandbox::{sandboxed_command, Exception, Opts};
+    use rattler_sandbox::{exit_with_status, Opts};
 
     // Initialize the sandbox trampoline - this checks if we're being called
     // with __sandbox_trampoline__ and if so, sets up the actual sandbox.
@@ -22,33 +22,19 @@ fn main() {
         std::process::exit(1);
     }
 
-    // Build the list of exceptions based on the command line options
-    let mut exceptions = Vec::new();
-
-    if let Some(fs_exec_and_read) = opt.fs_exec_and_read {
-        for path in fs_exec_and_read {
-            exceptions.push(Exception::ExecuteAndRead(path));
-        }
-    }
-
-    if let Some(fs_write_and_read) = opt.fs_write_and_read {
-        for path in fs_write_and_read {
-            exceptions.push(Exception::ReadAndWrite(path));
-        }
-    }
-
-    if let Some(fs_read) = opt.fs_read {
-        for path in fs_read {
-            exceptions.push(Exception::Read(path));
+    // Build the policy from the command line options
+    let policy = match opt.to_policy() {
+        Ok(policy) => policy,
+        Err(err) => {
+            eprintln!("Error: Failed to read the sandbox policy: {err}");
+            std::process::exit(1);
         }
-    }
-
-    if opt.network {
-        exceptions.push(Exception::Networking);
-    }
+    };
 
     // Create a sandboxed command
-    let mut command = sandboxed_command(&opt.args[0], &exceptions);
+    let mut command = policy
+        .command(&opt.args[0])
+        .expect("Failed to create the sandboxed command");
 
     // Add any additional arguments to the command
     command.args(&opt.args[1..]);
@@ -56,7 +42,8 @@ fn main() {
     // Execute the sandboxed command
     let status = command.status().expect("Failed to execute command");
 
-    std::process::exit(status.code().unwrap_or(1));
+    // Exit with the same code or signal as the sandboxed command
+    exit_with_status(status);
 }
 
 #[cfg(not(any(
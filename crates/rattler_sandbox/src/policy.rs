@@ -0,0 +1,434 @@
+//! Typed sandbox policies.
+//!
+//! A [`SandboxPolicy`] describes what a sandboxed process may access: the
+//! paths it can read, execute and write, whether it can use the network and
+//! which environment variables it inherits. Policies are serializable and can
+//! be composed with [`SandboxPolicy::merge`], for example by starting from one
+//! of the predefined [`SandboxProfile`]s.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The environment variables that every profile passes into the sandbox.
+/// The variables of the temporary directory are set by the sandbox, see
+/// [`SandboxPolicy::temp_dir`].
+const BASE_ENV: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "LC_*", "TZ",
+];
+
+/// The directories of the operating system that programs need to run, which
+/// can be read and executed.
+#[cfg(not(target_os = "macos"))]
+const SYSTEM_EXECUTE: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/libx32",
+];
+#[cfg(target_os = "macos")]
+const SYSTEM_EXECUTE: &[&str] = &["/usr", "/bin", "/sbin", "/System", "/Library"];
+
+/// The directories of the operating system that programs need to run, which
+/// can only be read.
+#[cfg(not(target_os = "macos"))]
+const SYSTEM_READ: &[&str] = &["/etc", "/dev"];
+#[cfg(target_os = "macos")]
+const SYSTEM_READ: &[&str] = &["/private/etc", "/dev"];
+
+/// The environment variables that point to the temporary directory.
+pub(crate) const TEMP_DIR_ENV: &[&str] = &["TMPDIR", "TMP", "TEMP"];
+
+/// The environment variables that are additionally passed into the sandbox
+/// by [`SandboxProfile::Build`].
+const BUILD_ENV: &[&str] = &[
+    "CPU_COUNT",
+    "MAKEFLAGS",
+    "SOURCE_DATE_EPOCH",
+    "PREFIX",
+    "BUILD_PREFIX",
+    "SRC_DIR",
+    "RECIPE_DIR",
+    "CONDA_*",
+];
+
+/// The environment variables that are additionally passed into the sandbox
+/// by [`SandboxProfile::Test`].
+const TEST_ENV: &[&str] = &["CPU_COUNT", "PREFIX", "CONDA_*", "PYTHONHASHSEED"];
+
+/// The environment variables that are additionally passed into the sandbox
+/// by [`SandboxProfile::LinkScript`].
+const LINK_SCRIPT_ENV: &[&str] = &["PREFIX", "PKG_NAME", "PKG_VERSION", "PKG_BUILDNUM"];
+
+/// Determines which environment variables are passed into the sandbox.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvPolicy {
+    /// All environment variables are passed into the sandbox.
+    Inherit,
+
+    /// Only the listed environment variables are passed into the sandbox. A
+    /// name that ends with `*` matches all variables that start with the rest
+    /// of the name, e.g. `LC_*`.
+    Allow(BTreeSet<String>),
+}
+
+impl Default for EnvPolicy {
+    fn default() -> Self {
+        EnvPolicy::Allow(BTreeSet::new())
+    }
+}
+
+impl EnvPolicy {
+    /// Returns true if the variable `name` is passed into the sandbox.
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            EnvPolicy::Inherit => true,
+            EnvPolicy::Allow(names) => {
+                names.iter().any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => name == pattern,
+                })
+            }
+        }
+    }
+
+    /// Combines two policies so that a variable is passed into the sandbox if
+    /// either of them allows it.
+    fn merge(self, other: EnvPolicy) -> Self {
+        match (self, other) {
+            (EnvPolicy::Allow(mut names), EnvPolicy::Allow(other)) => {
+                names.extend(other);
+                EnvPolicy::Allow(names)
+            }
+            _ => EnvPolicy::Inherit,
+        }
+    }
+}
+
+/// Describes what a sandboxed process may access. Everything that is not
+/// explicitly allowed is denied.
+///
+/// ```rust
+/// use rattler_sandbox::{SandboxPolicy, SandboxProfile};
+///
+/// let policy = SandboxPolicy::from_profile(SandboxProfile::Test, "/path/to/tests")
+///     .with_write("/path/to/cache")
+///     .with_env("PYTEST_ADDOPTS");
+/// assert!(!policy.network);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxPolicy {
+    /// Paths that can be read.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub read: BTreeSet<PathBuf>,
+
+    /// Paths that can be read and executed.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub execute: BTreeSet<PathBuf>,
+
+    /// Paths that can be read and written.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub write: BTreeSet<PathBuf>,
+
+    /// Whether the network can be accessed.
+    pub network: bool,
+
+    /// Whether the process gets a private temporary directory that it can
+    /// write to. The directory is created when the process starts, passed in
+    /// `TMPDIR`, `TMP` and `TEMP`, and removed when the process exits.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub temp_dir: bool,
+
+    /// The environment variables that are passed into the sandbox.
+    pub env: EnvPolicy,
+}
+
+impl SandboxPolicy {
+    /// Constructs a policy that denies everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs the policy of a profile that may write to and execute
+    /// `dir`, see [`SandboxProfile`].
+    pub fn from_profile(profile: SandboxProfile, dir: impl AsRef<Path>) -> Self {
+        let env = match profile {
+            SandboxProfile::Build => BUILD_ENV,
+            SandboxProfile::Test => TEST_ENV,
+            SandboxProfile::LinkScript => LINK_SCRIPT_ENV,
+        };
+        Self::new()
+            .with_system_paths()
+            .with_execute(dir.as_ref())
+            .with_write(dir.as_ref())
+            .with_temp_dir(true)
+            .with_envs(BASE_ENV.iter().chain(env))
+    }
+
+    /// Allows reading and executing the directories of the operating system
+    /// that programs need to run, e.g. `/usr` and `/etc`. Other directories,
+    /// like the home directory of the user, stay hidden.
+    #[must_use]
+    pub fn with_system_paths(self) -> Self {
+        let policy = SYSTEM_EXECUTE
+            .iter()
+            .fold(self, SandboxPolicy::with_execute);
+        SYSTEM_READ.iter().fold(policy, SandboxPolicy::with_read)
+    }
+
+    /// Allows reading and executing the whole filesystem, including private
+    /// files like `~/.ssh`. The profiles only allow the system directories,
+    /// this is the explicit opt-in for wider access.
+    #[must_use]
+    pub fn with_full_read_access(self) -> Self {
+        self.with_execute("/")
+    }
+
+    /// Allows reading `path`.
+    #[must_use]
+    pub fn with_read(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read.insert(path.into());
+        self
+    }
+
+    /// Allows reading and executing `path`.
+    #[must_use]
+    pub fn with_execute(mut self, path: impl Into<PathBuf>) -> Self {
+        self.execute.insert(path.into());
+        self
+    }
+
+    /// Allows reading and writing `path`.
+    #[must_use]
+    pub fn with_write(mut self, path: impl Into<PathBuf>) -> Self {
+        self.write.insert(path.into());
+        self
+    }
+
+    /// Sets whether the network can be accessed.
+    #[must_use]
+    pub fn with_network(self, network: bool) -> Self {
+        Self { network, ..self }
+    }
+
+    /// Sets whether the process gets a private temporary directory, see
+    /// [`Self::temp_dir`].
+    #[must_use]
+    pub fn with_temp_dir(self, temp_dir: bool) -> Self {
+        Self { temp_dir, ..self }
+    }
+
+    /// Passes the environment variable `name` into the sandbox. `name` may
+    /// end with `*` to match all variables with that prefix.
+    #[must_use]
+    pub fn with_env(self, name: impl Into<String>) -> Self {
+        self.with_envs([name.into()])
+    }
+
+    /// Passes the given environment variables into the sandbox.
+    #[must_use]
+    pub fn with_envs(self, names: impl IntoIterator<Item = impl ToString>) -> Self {
+        let names = names.into_iter().map(|name| name.to_string()).collect();
+        Self {
+            env: self.env.merge(EnvPolicy::Allow(names)),
+            ..self
+        }
+    }
+
+    /// Passes all environment variables into the sandbox.
+    #[must_use]
+    pub fn with_inherited_env(self) -> Self {
+        Self {
+            env: EnvPolicy::Inherit,
+            ..self
+        }
+    }
+
+    /// Combines two policies. The result allows everything that is allowed by
+    /// either of them.
+    #[must_use]
+    pub fn merge(mut self, other: SandboxPolicy) -> Self {
+        self.read.extend(other.read);
+        self.execute.extend(other.execute);
+        self.write.extend(other.write);
+        Self {
+            network: self.network || other.network,
+            temp_dir: self.temp_dir || other.temp_dir,
+            env: self.env.merge(other.env),
+            ..self
+        }
+    }
+
+    /// Returns the environment variables of the current process that are
+    /// passed into the sandbox.
+    pub fn allowed_env_vars(&self) -> Vec<String> {
+        std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter(|name| self.env.allows(name))
+            .collect()
+    }
+}
+
+/// Predefined policies for common tasks. Every profile can read and execute
+/// the directories of the operating system, read, write and execute a single
+/// directory, and has a private temporary directory but no network access.
+/// More paths can be added with [`SandboxPolicy::with_read`] and the other
+/// methods, or everything with [`SandboxPolicy::with_full_read_access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxProfile {
+    /// Building a package. The directory contains the sources and the
+    /// environments of the build.
+    Build,
+
+    /// Running the tests of a package. The directory is the working directory
+    /// of the tests.
+    Test,
+
+    /// Running the link scripts of a package. The directory is the prefix the
+    /// package is installed into.
+    LinkScript,
+}
+
+impl SandboxProfile {
+    /// Returns the name of the profile.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SandboxProfile::Build => "build",
+            SandboxProfile::Test => "test",
+            SandboxProfile::LinkScript => "link-script",
+        }
+    }
+}
+
+impl Display for SandboxProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error that is returned when parsing an unknown [`SandboxProfile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSandboxProfileError(String);
+
+impl Display for ParseSandboxProfileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown sandbox profile '{}', expected 'build', 'test' or 'link-script'",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseSandboxProfileError {}
+
+impl FromStr for SandboxProfile {
+    type Err = ParseSandboxProfileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "build" => Ok(SandboxProfile::Build),
+            "test" => Ok(SandboxProfile::Test),
+            "link-script" => Ok(SandboxProfile::LinkScript),
+            _ => Err(ParseSandboxProfileError(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_env_patterns() {
+        let env = EnvPolicy::Allow(["PATH".to_string(), "LC_*".to_string()].into());
+        assert!(env.allows("PATH"));
+        assert!(env.allows("LC_ALL"));
+        assert!(!env.allows("PATHEXT"));
+        assert!(!env.allows("AWS_SECRET_ACCESS_KEY"));
+        assert!(EnvPolicy::Inherit.allows("AWS_SECRET_ACCESS_KEY"));
+        assert!(!EnvPolicy::default().allows("PATH"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let build = SandboxPolicy::from_profile(SandboxProfile::Build, "/work");
+        let test = SandboxPolicy::from_profile(SandboxProfile::Test, "/tests").with_network(true);
+        let merged = build.merge(test);
+
+        assert!(merged.write.contains(&PathBuf::from("/work")));
+        assert!(merged.write.contains(&PathBuf::from("/tests")));
+        assert!(merged.network);
+        assert!(merged.env.allows("SRC_DIR"));
+        assert!(merged.env.allows("PYTHONHASHSEED"));
+
+        let merged = merged.merge(SandboxPolicy::new().with_inherited_env());
+        assert_eq!(merged.env, EnvPolicy::Inherit);
+    }
+
+    #[test]
+    fn test_profile_access() {
+        let policy = SandboxPolicy::from_profile(SandboxProfile::LinkScript, "/prefix");
+        assert!(!policy.execute.contains(Path::new("/")));
+        assert!(policy.execute.contains(Path::new("/usr")));
+        assert!(policy.execute.contains(Path::new("/prefix")));
+        assert!(policy.write.contains(Path::new("/prefix")));
+        assert_eq!(policy.write.len(), 1);
+        assert!(policy.temp_dir);
+        assert!(!policy.env.allows("TMPDIR"));
+
+        let policy = policy.with_full_read_access();
+        assert!(policy.execute.contains(Path::new("/")));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let policy = SandboxPolicy::new()
+            .with_execute("/")
+            .with_write("/prefix")
+            .with_env("PATH");
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(
+            json,
+            r#"{"execute":["/"],"write":["/prefix"],"network":false,"env":{"allow":["PATH"]}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<SandboxPolicy>(&json).unwrap(),
+            policy
+        );
+
+        let policy: SandboxPolicy = serde_json::from_str(r#"{"env":"inherit"}"#).unwrap();
+        assert_eq!(policy.env, EnvPolicy::Inherit);
+        assert!(policy.write.is_empty());
+        assert!(!policy.temp_dir);
+
+        let policy = SandboxPolicy::new().with_temp_dir(true);
+        assert_eq!(
+            serde_json::to_string(&policy).unwrap(),
+            r#"{"network":false,"temp_dir":true,"env":{"allow":[]}}"#
+        );
+    }
+
+    #[test]
+    fn test_profile_names() {
+        for profile in [
+            SandboxProfile::Build,
+            SandboxProfile::Test,
+            SandboxProfile::LinkScript,
+        ] {
+            assert_eq!(profile.to_string().parse::<SandboxProfile>(), Ok(profile));
+            assert_eq!(
+                serde_json::to_string(&profile).unwrap(),
+                format!("\"{profile}\"")
+            );
+        }
+        assert!("install".parse::<SandboxProfile>().is_err());
+    }
+}
@@ -1,12 +1,43 @@
+use std::ffi::{CString, OsString};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
 use birdcage::process::Command;
 use birdcage::{Birdcage, Sandbox};
-use clap::Parser;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::policy::{EnvPolicy, SandboxPolicy, TEMP_DIR_ENV};
+use sandbox_impl::{PARENT_PID_ENV, REPORT_FD_ENV};
 
+#[cfg(target_os = "linux")]
+mod monitor;
 pub mod sandbox_impl;
 #[cfg(feature = "tokio")]
 pub mod tokio;
 
-pub use sandbox_impl::{sandboxed_command, Exception};
+pub use sandbox_impl::{sandboxed_command, Exception, ViolationReport};
+
+/// The first argument of the current executable when it is started to run a
+/// sandboxed process. It is followed by the policy as JSON, the program and
+/// its arguments.
+pub(crate) const TRAMPOLINE_ARG: &str = "__sandbox_trampoline__";
+
+/// The first argument of the current executable when it is started to monitor
+/// a sandboxed process, see the `monitor` module. It is followed by a pidfd
+/// of the trampoline, the file descriptor to report to or `-1`, `1` if the
+/// network is allowed or `0` otherwise, the program and its arguments.
+#[cfg(target_os = "linux")]
+const MONITOR_ARG: &str = "__sandbox_monitor__";
+
+/// The exit code of the trampoline if the sandboxed process cannot be started.
+const SPAWN_FAILED_EXIT_CODE: i32 = 127;
 
 #[derive(clap::Parser)]
 pub struct Opts {
@@ -22,59 +53,341 @@ pub struct Opts {
     #[clap(long)]
     pub network: bool,
 
+    /// Pass an environment variable into the sandbox, a trailing `*` matches
+    /// all variables with that prefix.
+    #[clap(long)]
+    pub allow_env: Option<Vec<String>>,
+
+    /// Pass all environment variables into the sandbox.
+    #[clap(long)]
+    pub inherit_env: bool,
+
+    /// A JSON file with a sandbox policy that is combined with the other
+    /// options.
+    #[clap(long)]
+    pub policy: Option<PathBuf>,
+
     #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
     pub args: Vec<String>,
 }
 
-// This function checks if the current executable should execute as a sandboxed process
-fn init() {
-    let mut args = std::env::args().collect::<Vec<String>>();
-    // Remove the first `__sandbox_trampoline__` argument
-    args.remove(1);
-    let opts = Opts::parse_from(args.iter());
-    // Allow access to our test executable.
+impl Opts {
+    /// Returns the policy that is described by the options.
+    pub fn to_policy(&self) -> std::io::Result<SandboxPolicy> {
+        let mut policy = match &self.policy {
+            Some(path) => serde_json::from_str(&fs_err::read_to_string(path)?)?,
+            None => SandboxPolicy::new(),
+        };
+        for path in self.fs_exec_and_read.iter().flatten() {
+            policy = policy.with_execute(path);
+        }
+        for path in self.fs_read.iter().flatten() {
+            policy = policy.with_read(path);
+        }
+        for path in self.fs_write_and_read.iter().flatten() {
+            policy = policy.with_write(path);
+        }
+        if self.network {
+            policy = policy.with_network(true);
+        }
+        if let Some(names) = &self.allow_env {
+            policy = policy.with_envs(names);
+        }
+        if self.inherit_env {
+            policy = policy.with_inherited_env();
+        }
+        Ok(policy)
+    }
+}
+
+/// Exits the current process the same way as a process with the given
+/// status: with the same exit code, or by the same signal.
+pub fn exit_with_status(status: ExitStatus) -> ! {
+    if let Some(code) = status.code() {
+        std::process::exit(code);
+    }
+    if let Some(signal) = status.signal() {
+        exit_with_signal(signal);
+    }
+    std::process::exit(1)
+}
+
+/// Terminates the current process by `signal`. If the default action of the
+/// signal does not terminate the process, it exits with `128 + signal` like a
+/// shell does.
+fn exit_with_signal(signal: i32) -> ! {
+    let _ = signal_hook::low_level::emulate_default_handler(signal);
+    std::process::exit(128 + signal)
+}
+
+/// Builds the sandbox that is described by the policy.
+fn sandbox(policy: &SandboxPolicy) -> Birdcage {
     let mut sandbox = Birdcage::new();
 
-    if let Some(fs_exec_and_read) = opts.fs_exec_and_read {
-        for path in fs_exec_and_read {
-            let _ = sandbox.add_exception(birdcage::Exception::ExecuteAndRead(path.into()));
-        }
+    // Paths that do not exist are ignored.
+    for path in &policy.execute {
+        let _ = sandbox.add_exception(birdcage::Exception::ExecuteAndRead(path.clone()));
+    }
+    for path in &policy.read {
+        let _ = sandbox.add_exception(birdcage::Exception::Read(path.clone()));
+    }
+    for path in &policy.write {
+        let _ = sandbox.add_exception(birdcage::Exception::WriteAndRead(path.clone()));
     }
 
-    if let Some(fs_read) = opts.fs_read {
-        for path in fs_read {
-            let _ = sandbox.add_exception(birdcage::Exception::Read(path.into()));
-        }
+    if policy.network {
+        let _ = sandbox.add_exception(birdcage::Exception::Networking);
     }
 
-    if let Some(fs_write_and_read) = opts.fs_write_and_read {
-        for path in fs_write_and_read {
-            let _ = sandbox.add_exception(birdcage::Exception::WriteAndRead(path.into()));
+    match &policy.env {
+        EnvPolicy::Inherit => {
+            let _ = sandbox.add_exception(birdcage::Exception::FullEnvironment);
+        }
+        EnvPolicy::Allow(_) => {
+            for name in policy.allowed_env_vars() {
+                let _ = sandbox.add_exception(birdcage::Exception::Environment(name));
+            }
         }
     }
 
-    if opts.network {
-        let _ = sandbox.add_exception(birdcage::Exception::Networking);
+    sandbox
+}
+
+/// Creates the private temporary directory of the policy, if it has one, and
+/// allows the sandboxed process to use it.
+fn create_temp_dir(policy: SandboxPolicy) -> std::io::Result<(SandboxPolicy, Option<PathBuf>)> {
+    if !policy.temp_dir {
+        return Ok((policy, None));
+    }
+
+    let template = std::env::temp_dir().join("rattler-sandbox-XXXXXX");
+    let mut template = CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+    // SAFETY: `template` is a writable, nul terminated string.
+    if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+        return Err(std::io::Error::last_os_error());
     }
+    template.pop();
 
-    if let Some((exe, args)) = opts.args.split_first() {
-        // Initialize the sandbox; by default everything is prohibited.
+    // The sandbox only matches paths after resolving symlinks.
+    let dir = fs_err::canonicalize(OsString::from_vec(template))?;
+    for name in TEMP_DIR_ENV {
+        std::env::set_var(name, &dir);
+    }
+    Ok((policy.with_write(&dir).with_envs(TEMP_DIR_ENV), Some(dir)))
+}
+
+/// Removes the private temporary directory, if there is one.
+fn remove_temp_dir(dir: Option<&Path>) {
+    if let Some(dir) = dir {
+        let _ = fs_err::remove_dir_all(dir);
+    }
+}
+
+// Runs the sandboxed process that is described by the arguments of the
+// trampoline and exits with its status.
+fn init() -> ! {
+    let mut args = std::env::args_os().skip(2);
+    let policy = args
+        .next()
+        .and_then(|policy| policy.into_string().ok())
+        .map(|policy| serde_json::from_str::<SandboxPolicy>(&policy));
+    let policy = match policy {
+        Some(Ok(policy)) => policy,
+        Some(Err(err)) => {
+            eprintln!("invalid sandbox policy: {err}");
+            std::process::exit(SPAWN_FAILED_EXIT_CODE);
+        }
+        None => {
+            eprintln!("no sandbox policy provided");
+            std::process::exit(SPAWN_FAILED_EXIT_CODE);
+        }
+    };
+    let Some(exe) = args.next() else {
+        eprintln!("no executable provided");
+        std::process::exit(SPAWN_FAILED_EXIT_CODE);
+    };
+
+    // The trampoline terminates the sandbox when the caller exits.
+    #[cfg(target_os = "linux")]
+    let parent = watch_parent();
+
+    // The temporary directory is created outside of the sandbox, so that it
+    // can be removed after the sandboxed process exited.
+    let (policy, temp_dir) = match create_temp_dir(policy) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("failed to create the temporary directory: {err}");
+            std::process::exit(SPAWN_FAILED_EXIT_CODE);
+        }
+    };
+
+    // On Linux the sandboxed process is started by a monitor inside the
+    // sandbox. Elsewhere violations are not reported, and the report is
+    // closed without violations when the trampoline exits.
+    #[cfg(target_os = "linux")]
+    let (policy, command) = monitor_command(policy, exe, args);
+    #[cfg(not(target_os = "linux"))]
+    let command = {
+        std::env::remove_var(PARENT_PID_ENV);
+        std::env::remove_var(REPORT_FD_ENV);
         let mut command = Command::new(exe);
         command.args(args);
+        command
+    };
+
+    // Signals that would terminate the trampoline terminate the sandboxed
+    // process instead. The sandbox can only be spawned from a single
+    // threaded process, so the thread that handles the signals is started
+    // afterwards. The handlers are registered before, so that signals that
+    // arrive in the meantime are not lost.
+    let signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGQUIT]);
+
+    let mut child = match sandbox(&policy).spawn(command) {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to start the sandboxed process: {err}");
+            remove_temp_dir(temp_dir.as_deref());
+            std::process::exit(SPAWN_FAILED_EXIT_CODE);
+        }
+    };
+
+    // The sandboxed process is killed because the init process of the
+    // sandbox does not handle signals, afterwards the trampoline terminates
+    // itself with the same signal.
+    let pid = child.id() as libc::pid_t;
+    let received_signal = Arc::new(AtomicI32::new(0));
+    if let Ok(mut signals) = signals {
+        let received_signal = received_signal.clone();
+        std::thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                received_signal.store(signal, Ordering::SeqCst);
+                // SAFETY: `kill` has no memory safety requirements.
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(parent) = parent {
+        std::thread::spawn(move || {
+            wait_for_exit(&parent);
+            // SAFETY: `kill` has no memory safety requirements.
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        });
+    }
+
+    let status = child.wait();
+    remove_temp_dir(temp_dir.as_deref());
+    let status = match status {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("failed to wait for the sandboxed process: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    match received_signal.load(Ordering::SeqCst) {
+        0 => exit_with_status(status),
+        signal => exit_with_signal(signal),
+    }
+}
+
+/// Opens a pidfd of the process `pid`.
+#[cfg(target_os = "linux")]
+fn pidfd_open(pid: libc::pid_t) -> std::io::Result<OwnedFd> {
+    // SAFETY: `pidfd_open` has no memory safety requirements and returns a
+    // new file descriptor on success.
+    unsafe {
+        let fd = libc::syscall(libc::SYS_pidfd_open, pid, 0);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(OwnedFd::from_raw_fd(fd as RawFd))
+    }
+}
 
-        let mut child = sandbox.spawn(command).unwrap();
+/// Blocks until the process of a pidfd exits.
+#[cfg(target_os = "linux")]
+fn wait_for_exit(pidfd: &OwnedFd) {
+    let mut fd = libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fd` is a valid `pollfd`.
+    while unsafe { libc::poll(&mut fd, 1, -1) } <= 0 {}
+}
 
-        let status = child.wait().unwrap();
-        std::process::exit(status.code().unwrap());
-    } else {
-        panic!("No executable provided");
+/// Returns a pidfd of the process that started the trampoline, or exits if
+/// that process already exited.
+#[cfg(target_os = "linux")]
+fn watch_parent() -> Option<OwnedFd> {
+    let parent = std::env::var(PARENT_PID_ENV);
+    std::env::remove_var(PARENT_PID_ENV);
+    let parent = parent.ok()?.parse().ok()?;
+    let pidfd = pidfd_open(parent).ok()?;
+    // If the parent exited before the pidfd was opened, the trampoline was
+    // already reparented.
+    // SAFETY: `getppid` has no memory safety requirements.
+    if unsafe { libc::getppid() } != parent {
+        std::process::exit(1);
     }
+    Some(pidfd)
+}
+
+/// Returns the policy and the command that run `exe` through the monitor.
+#[cfg(target_os = "linux")]
+fn monitor_command(
+    policy: SandboxPolicy,
+    exe: std::ffi::OsString,
+    args: impl Iterator<Item = std::ffi::OsString>,
+) -> (SandboxPolicy, Command) {
+    let report_fd = std::env::var(REPORT_FD_ENV).unwrap_or_else(|_err| "-1".to_string());
+    std::env::remove_var(REPORT_FD_ENV);
+
+    let monitor = std::env::current_exe();
+    // The monitor inherits the pidfd, so it must not be closed on exec.
+    // SAFETY: `getpid` has no memory safety requirements.
+    let trampoline = pidfd_open(unsafe { libc::getpid() }).and_then(|pidfd| {
+        // SAFETY: `fcntl` has no memory safety requirements.
+        if unsafe { libc::fcntl(pidfd.as_raw_fd(), libc::F_SETFD, 0) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(pidfd.into_raw_fd())
+    });
+    let (monitor, trampoline) = match (monitor, trampoline) {
+        (Ok(monitor), Ok(trampoline)) => (monitor, trampoline),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("failed to start the sandbox monitor: {err}");
+            std::process::exit(SPAWN_FAILED_EXIT_CODE);
+        }
+    };
+
+    let mut command = Command::new(&monitor);
+    command
+        .arg(MONITOR_ARG)
+        .arg(trampoline.to_string())
+        .arg(report_fd)
+        .arg(if policy.network { "1" } else { "0" })
+        .arg(exe)
+        .args(args);
+    (policy.with_execute(monitor), command)
 }
 
 pub fn init_sandbox() {
     // TODO ideally we check that we are single threaded, but birdcage will also check it later on
-    if std::env::args().any(|arg| arg == "__sandbox_trampoline__") {
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == TRAMPOLINE_ARG)
+    {
         // This is a sandboxed process, so we initialize the sandbox
         init();
     }
+    #[cfg(target_os = "linux")]
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == MONITOR_ARG)
+    {
+        monitor::run();
+    }
 }
@@ -0,0 +1,646 @@
+//! Supervises a sandboxed process from inside the sandbox.
+//!
+//! On Linux the trampoline starts the current executable once more inside the
+//! sandbox as a monitor, which starts the sandboxed process. The monitor
+//! tears down the sandbox if the trampoline is killed, because the init
+//! process of the sandbox keeps running on its own.
+//!
+//! The monitor also reports the accesses that the sandbox denies, if the
+//! caller asked for it. The sandbox itself does not tell which accesses it
+//! denied, the system calls of the sandboxed process just fail. So the
+//! monitor installs a seccomp filter that notifies it of the system calls
+//! that write to the filesystem or connect to the network before it starts
+//! the sandboxed process, which inherits the filter. For every notification
+//! the monitor checks whether the sandbox denies the access, reports it and
+//! lets the system call continue, so that the kernel fails it as usual.
+
+use std::{
+    collections::HashSet,
+    ffi::{CString, OsString},
+    fs::File,
+    io::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStringExt, fs::FileExt},
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::{exit_with_status, SPAWN_FAILED_EXIT_CODE};
+use crate::violation::Violation;
+
+/// The maximum number of violations that are reported for one process.
+const MAX_VIOLATIONS: usize = 32;
+
+/// The flags of `open` that write to a file.
+const WRITE_FLAGS: i32 = libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC;
+
+// Definitions of `linux/seccomp.h` and `linux/audit.h` that are missing in
+// `libc`.
+const SECCOMP_FILTER_FLAG_NEW_LISTENER: libc::c_ulong = 1 << 3;
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+const SECCOMP_IOCTL_NOTIF_RECV: libc::c_ulong = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: libc::c_ulong = 0xc018_2101;
+const SECCOMP_IOCTL_NOTIF_ID_VALID: libc::c_ulong = 0x4008_2102;
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// The offsets of the fields of `seccomp_data`.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const ARGS_OFFSET: u32 = 16;
+
+/// A path argument of a system call, relative to the directory file
+/// descriptor in argument `dirfd` or to the working directory.
+#[derive(Clone, Copy)]
+struct PathArg {
+    dirfd: Option<usize>,
+    path: usize,
+}
+
+const fn path(path: usize) -> PathArg {
+    PathArg { dirfd: None, path }
+}
+
+const fn path_at(dirfd: usize, path: usize) -> PathArg {
+    PathArg {
+        dirfd: Some(dirfd),
+        path,
+    }
+}
+
+/// Where the flags of a system call that opens a file are found.
+#[derive(Clone, Copy)]
+enum OpenFlags {
+    /// In an argument.
+    Arg(usize),
+    /// In the `open_how` struct that an argument points to.
+    How(usize),
+    /// The flags of `creat`.
+    Create,
+}
+
+/// The access of a monitored system call.
+#[derive(Clone, Copy)]
+enum Access {
+    /// Opens a file, which writes to it if the flags ask for it.
+    Open { path: PathArg, flags: OpenFlags },
+    /// Creates a new directory entry.
+    Create(PathArg),
+    /// Removes a directory entry.
+    Remove(PathArg),
+    /// Moves a directory entry.
+    Rename { from: PathArg, to: PathArg },
+    /// Changes the size of a file.
+    Truncate(PathArg),
+    /// Connects to the socket address that an argument points to.
+    Connect { addr: usize },
+}
+
+struct Syscall {
+    nr: libc::c_long,
+    access: Access,
+}
+
+const SYSCALLS: &[Syscall] = &[
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_open,
+        access: Access::Open {
+            path: path(0),
+            flags: OpenFlags::Arg(1),
+        },
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_creat,
+        access: Access::Open {
+            path: path(0),
+            flags: OpenFlags::Create,
+        },
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_mkdir,
+        access: Access::Create(path(0)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_mknod,
+        access: Access::Create(path(0)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_link,
+        access: Access::Create(path(1)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_symlink,
+        access: Access::Create(path(1)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_unlink,
+        access: Access::Remove(path(0)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_rmdir,
+        access: Access::Remove(path(0)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_rename,
+        access: Access::Rename {
+            from: path(0),
+            to: path(1),
+        },
+    },
+    Syscall {
+        nr: libc::SYS_openat,
+        access: Access::Open {
+            path: path_at(0, 1),
+            flags: OpenFlags::Arg(2),
+        },
+    },
+    Syscall {
+        nr: libc::SYS_openat2,
+        access: Access::Open {
+            path: path_at(0, 1),
+            flags: OpenFlags::How(2),
+        },
+    },
+    Syscall {
+        nr: libc::SYS_mkdirat,
+        access: Access::Create(path_at(0, 1)),
+    },
+    Syscall {
+        nr: libc::SYS_mknodat,
+        access: Access::Create(path_at(0, 1)),
+    },
+    Syscall {
+        nr: libc::SYS_linkat,
+        access: Access::Create(path_at(2, 3)),
+    },
+    Syscall {
+        nr: libc::SYS_symlinkat,
+        access: Access::Create(path_at(1, 2)),
+    },
+    Syscall {
+        nr: libc::SYS_unlinkat,
+        access: Access::Remove(path_at(0, 1)),
+    },
+    #[cfg(target_arch = "x86_64")]
+    Syscall {
+        nr: libc::SYS_renameat,
+        access: Access::Rename {
+            from: path_at(0, 1),
+            to: path_at(2, 3),
+        },
+    },
+    Syscall {
+        nr: libc::SYS_renameat2,
+        access: Access::Rename {
+            from: path_at(0, 1),
+            to: path_at(2, 3),
+        },
+    },
+    Syscall {
+        nr: libc::SYS_truncate,
+        access: Access::Truncate(path(0)),
+    },
+    Syscall {
+        nr: libc::SYS_connect,
+        access: Access::Connect { addr: 1 },
+    },
+    Syscall {
+        nr: libc::SYS_sendto,
+        access: Access::Connect { addr: 4 },
+    },
+];
+
+const fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Installs a seccomp filter that notifies about the monitored system calls
+/// and returns the file descriptor that receives the notifications. Network
+/// access is only monitored if `network` is false.
+fn install_filter(network: bool) -> io::Result<OwnedFd> {
+    let mut filter = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH_OFFSET),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            AUDIT_ARCH,
+            1,
+            0,
+        ),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR_OFFSET),
+    ];
+    for syscall in SYSCALLS {
+        let nr = syscall.nr as u32;
+        match syscall.access {
+            Access::Connect { .. } if network => {}
+            // Only opening a file for writing is monitored, which is checked
+            // in the filter because files are mostly opened for reading.
+            Access::Open {
+                flags: OpenFlags::Arg(arg),
+                ..
+            } => filter.extend([
+                jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr, 0, 4),
+                stmt(
+                    libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                    ARGS_OFFSET + 8 * arg as u32,
+                ),
+                jump(
+                    libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K,
+                    WRITE_FLAGS as u32,
+                    0,
+                    1,
+                ),
+                stmt(libc::BPF_RET | libc::BPF_K, SECCOMP_RET_USER_NOTIF),
+                stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+            ]),
+            _ => filter.extend([
+                jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr, 0, 1),
+                stmt(libc::BPF_RET | libc::BPF_K, SECCOMP_RET_USER_NOTIF),
+            ]),
+        }
+    }
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    // SAFETY: `prctl` with `PR_SET_NO_NEW_PRIVS` has no memory safety
+    // requirements and `program` points to a valid filter.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            &program,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OwnedFd::from_raw_fd(fd as RawFd))
+    }
+}
+
+/// Reads a nul terminated string at `addr` from the memory of a process.
+fn read_c_string(mem: &File, addr: u64) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 256];
+    while bytes.len() < libc::PATH_MAX as usize {
+        let offset = addr + bytes.len() as u64;
+        // Reading must not cross a page boundary, the next page might not be
+        // mapped.
+        let len = buf.len().min(4096 - (offset % 4096) as usize);
+        let read = mem.read_at(&mut buf[..len], offset).ok()?;
+        if read == 0 {
+            return None;
+        }
+        if let Some(end) = buf[..read].iter().position(|&byte| byte == 0) {
+            bytes.extend_from_slice(&buf[..end]);
+            return Some(bytes);
+        }
+        bytes.extend_from_slice(&buf[..read]);
+    }
+    None
+}
+
+/// Reads the socket address at `addr` from the memory of a process, if it is
+/// an internet address.
+fn read_socket_addr(mem: &File, addr: u64) -> Option<SocketAddr> {
+    let mut family = [0u8; 2];
+    mem.read_exact_at(&mut family, addr).ok()?;
+    match i32::from(u16::from_ne_bytes(family)) {
+        libc::AF_INET => {
+            let mut bytes = [0u8; 8];
+            mem.read_exact_at(&mut bytes, addr).ok()?;
+            let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+            let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+            Some(SocketAddrV4::new(ip, port).into())
+        }
+        libc::AF_INET6 => {
+            let mut bytes = [0u8; 24];
+            mem.read_exact_at(&mut bytes, addr).ok()?;
+            let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+            let ip: [u8; 16] = bytes[8..24].try_into().ok()?;
+            Some(SocketAddrV6::new(Ipv6Addr::from(ip), port, 0, 0).into())
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether `path` is on a read-only mount. The sandbox mounts every
+/// path that the policy does not allow writing to as read-only.
+fn is_read_only(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_encoded_bytes()) else {
+        return false;
+    };
+    // SAFETY: `statvfs` is plain old data and `path` is nul terminated.
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        libc::statvfs(path.as_ptr(), &mut stat) == 0 && stat.f_flag & libc::ST_RDONLY != 0
+    }
+}
+
+/// Returns whether an entry at `path` cannot be created because the closest
+/// parent directory that exists is read-only.
+fn is_creation_denied(path: &Path) -> bool {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.exists())
+        .is_some_and(is_read_only)
+}
+
+/// Returns whether the entry at `path` cannot be removed because its parent
+/// directory is read-only.
+fn is_removal_denied(path: &Path) -> bool {
+    path.symlink_metadata().is_ok() && path.parent().is_some_and(is_read_only)
+}
+
+/// Watches the trampoline and handles the notifications of the seccomp
+/// filter.
+struct Monitor {
+    /// A pidfd of the trampoline.
+    trampoline: OwnedFd,
+    /// The notifications of the seccomp filter, if violations are reported.
+    listener: Option<OwnedFd>,
+    report: Option<File>,
+    network: bool,
+    reported: HashSet<Violation>,
+}
+
+impl Monitor {
+    /// Waits up to `timeout` milliseconds for the trampoline to exit or for a
+    /// notification and handles it.
+    fn poll(&mut self, timeout: i32) {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.trampoline.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.listener.as_ref().map_or(-1, AsRawFd::as_raw_fd),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // SAFETY: `fds` are valid `pollfd`s.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } <= 0 {
+            return;
+        }
+
+        if fds[0].revents != 0 {
+            // The trampoline was killed. Killing every process that the
+            // monitor can signal kills all processes in the sandbox except
+            // for its init process, which exits together with the monitor.
+            // SAFETY: `kill` has no memory safety requirements.
+            unsafe { libc::kill(-1, libc::SIGKILL) };
+            std::process::exit(1);
+        }
+        if fds[1].revents != 0 {
+            self.handle_notification(fds[1].fd);
+        }
+    }
+
+    /// Handles a notification of the seccomp filter.
+    fn handle_notification(&mut self, listener: RawFd) {
+        // SAFETY: `seccomp_notif` is plain old data that the kernel fills in.
+        let mut notif: libc::seccomp_notif = unsafe { std::mem::zeroed() };
+        // SAFETY: `notif` is a valid `seccomp_notif`.
+        if unsafe { libc::ioctl(listener, SECCOMP_IOCTL_NOTIF_RECV as _, &mut notif) } != 0 {
+            return;
+        }
+
+        let violation = self.violation(listener, &notif);
+
+        let mut response = libc::seccomp_notif_resp {
+            id: notif.id,
+            val: 0,
+            error: 0,
+            flags: libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32,
+        };
+        // SAFETY: `response` is a valid `seccomp_notif_resp`. This fails if
+        // the process was killed in the meantime, which is fine.
+        unsafe { libc::ioctl(listener, SECCOMP_IOCTL_NOTIF_SEND as _, &mut response) };
+
+        if let Some(violation) = violation {
+            self.report(violation);
+        }
+    }
+
+    /// Returns whether the process of the notification still waits for the
+    /// response, and its pid was not reused by another process.
+    fn is_valid(listener: RawFd, id: u64) -> bool {
+        let mut id = id;
+        // SAFETY: `id` is a valid `u64`.
+        unsafe { libc::ioctl(listener, SECCOMP_IOCTL_NOTIF_ID_VALID as _, &mut id) == 0 }
+    }
+
+    /// Returns the violation of a notified system call, if the sandbox
+    /// denies it.
+    fn violation(&self, listener: RawFd, notif: &libc::seccomp_notif) -> Option<Violation> {
+        let access = SYSCALLS
+            .iter()
+            .find(|syscall| syscall.nr == libc::c_long::from(notif.data.nr))?
+            .access;
+        let mem = File::open(format!("/proc/{}/mem", notif.pid)).ok()?;
+        if !Self::is_valid(listener, notif.id) {
+            return None;
+        }
+
+        let args = &notif.data.args;
+        let resolve = |arg: PathArg| resolve_path(&mem, notif.pid, args, arg);
+        let write = |path: PathBuf| Some(Violation::Write { path });
+        match access {
+            Access::Open { path, flags } => {
+                let flags = match flags {
+                    OpenFlags::Arg(arg) => args[arg] as i32,
+                    OpenFlags::How(arg) => {
+                        let mut flags = [0u8; 8];
+                        mem.read_exact_at(&mut flags, args[arg]).ok()?;
+                        u64::from_ne_bytes(flags) as i32
+                    }
+                    OpenFlags::Create => libc::O_CREAT | libc::O_WRONLY | libc::O_TRUNC,
+                };
+                if flags & WRITE_FLAGS == 0 {
+                    return None;
+                }
+                let path = resolve(path)?;
+                let denied = match path.metadata() {
+                    Ok(metadata) => {
+                        (metadata.is_file() || flags & libc::O_TMPFILE == libc::O_TMPFILE)
+                            && is_read_only(&path)
+                    }
+                    Err(_) => flags & libc::O_CREAT != 0 && is_creation_denied(&path),
+                };
+                denied.then_some(path).and_then(write)
+            }
+            Access::Create(path) => {
+                let path = resolve(path)?;
+                (path.symlink_metadata().is_err() && is_creation_denied(&path))
+                    .then_some(path)
+                    .and_then(write)
+            }
+            Access::Remove(path) => {
+                let path = resolve(path)?;
+                is_removal_denied(&path).then_some(path).and_then(write)
+            }
+            Access::Rename { from, to } => {
+                let from = resolve(from)?;
+                if is_removal_denied(&from) {
+                    return write(from);
+                }
+                let to = resolve(to)?;
+                is_creation_denied(&to).then_some(to).and_then(write)
+            }
+            Access::Truncate(path) => {
+                let path = resolve(path)?;
+                (path.metadata().is_ok_and(|metadata| metadata.is_file()) && is_read_only(&path))
+                    .then_some(path)
+                    .and_then(write)
+            }
+            Access::Connect { addr } => {
+                if self.network || args[addr] == 0 {
+                    return None;
+                }
+                let address = read_socket_addr(&mem, args[addr])?;
+                Some(Violation::Network {
+                    address: address.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Writes a violation to the report, unless it was reported before or
+    /// too many violations were reported already.
+    fn report(&mut self, violation: Violation) {
+        if self.reported.len() >= MAX_VIOLATIONS || self.reported.contains(&violation) {
+            return;
+        }
+        let Some(report) = &mut self.report else {
+            return;
+        };
+        let Ok(mut line) = serde_json::to_vec(&violation) else {
+            return;
+        };
+        line.push(b'\n');
+        // The report is non-blocking, if nobody reads it the violation is
+        // dropped instead of blocking the sandboxed process.
+        let _ = report.write_all(&line);
+        self.reported.insert(violation);
+    }
+}
+
+/// Resolves a path argument of a system call in the process `pid`.
+fn resolve_path(mem: &File, pid: u32, args: &[u64; 6], arg: PathArg) -> Option<PathBuf> {
+    let path = PathBuf::from(OsString::from_vec(read_c_string(mem, args[arg.path])?));
+    if path.as_os_str().is_empty() {
+        return None;
+    }
+    if path.is_absolute() {
+        return Some(path);
+    }
+    let base = match arg.dirfd.map(|dirfd| args[dirfd] as i32) {
+        None | Some(libc::AT_FDCWD) => format!("/proc/{pid}/cwd"),
+        Some(dirfd) => format!("/proc/{pid}/fd/{dirfd}"),
+    };
+    let base = std::fs::read_link(&base).unwrap_or_else(|_err| PathBuf::from(base));
+    Some(base.join(path))
+}
+
+/// Sets a flag of the file status flags or the file descriptor flags.
+fn add_fd_flag(fd: RawFd, get: i32, set: i32, flag: i32) {
+    // SAFETY: `fcntl` has no memory safety requirements.
+    unsafe {
+        let flags = libc::fcntl(fd, get);
+        if flags != -1 {
+            libc::fcntl(fd, set, flags | flag);
+        }
+    }
+}
+
+/// Runs the sandboxed process that is described by the arguments after
+/// [`super::MONITOR_ARG`] and exits with its status.
+pub(crate) fn run() -> ! {
+    let mut args = std::env::args_os().skip(2);
+    let fds =
+        [args.next(), args.next()].map(|fd| fd.and_then(|fd| fd.to_str()?.parse::<RawFd>().ok()));
+    let network = args.next().is_some_and(|network| network == "1");
+    let ([Some(trampoline), Some(report)], Some(exe)) = (fds, args.next()) else {
+        eprintln!("invalid arguments for the sandbox monitor");
+        std::process::exit(SPAWN_FAILED_EXIT_CODE);
+    };
+
+    // The sandboxed process must not inherit the file descriptors of the
+    // monitor.
+    add_fd_flag(trampoline, libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC);
+    // SAFETY: the trampoline passes file descriptors that only the monitor
+    // uses.
+    let trampoline = unsafe { OwnedFd::from_raw_fd(trampoline) };
+    let report = (report >= 0).then(|| {
+        add_fd_flag(report, libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC);
+        add_fd_flag(report, libc::F_GETFL, libc::F_SETFL, libc::O_NONBLOCK);
+        // SAFETY: see above.
+        unsafe { File::from_raw_fd(report) }
+    });
+
+    // If the kernel does not support seccomp notifications, the process runs
+    // without reporting violations.
+    let listener = report.as_ref().and_then(|_| install_filter(network).ok());
+
+    let mut child = match Command::new(exe).args(args).spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to start the sandboxed process: {err}");
+            std::process::exit(SPAWN_FAILED_EXIT_CODE);
+        }
+    };
+
+    let mut monitor = Monitor {
+        trampoline,
+        listener,
+        report,
+        network,
+        reported: HashSet::new(),
+    };
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => exit_with_status(status),
+            Ok(None) => monitor.poll(50),
+            Err(err) => {
+                eprintln!("failed to wait for the sandboxed process: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
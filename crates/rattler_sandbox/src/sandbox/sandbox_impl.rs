@@ -1,5 +1,21 @@
 //! We expose some sandbox items that then call itself through the trampoline
-use std::process::Command;
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, PipeReader, PipeWriter, Read},
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    process::Command,
+};
+
+use super::TRAMPOLINE_ARG;
+use crate::{policy::SandboxPolicy, violation::Violation};
+
+/// The environment variable that passes the file descriptor that violations
+/// are reported to from the caller to the trampoline.
+pub(crate) const REPORT_FD_ENV: &str = "RATTLER_SANDBOX_REPORT_FD";
+
+/// The environment variable that passes the process id of the caller to the
+/// trampoline, which terminates the sandbox when the caller exits.
+pub(crate) const PARENT_PID_ENV: &str = "RATTLER_SANDBOX_PARENT_PID";
 
 /// Add exceptions to the sandbox
 pub enum Exception {
@@ -9,32 +25,122 @@ pub enum Exception {
     Networking,
 }
 
-/// Create a `Command` that will run the current executable with the given exceptions
-pub fn sandboxed_command(exe: &str, exceptions: &[Exception]) -> Command {
-    let self_exe = std::env::current_exe().unwrap();
-    let mut cmd = Command::new(self_exe);
-    cmd.arg("__sandbox_trampoline__");
-
-    for exception in exceptions {
-        match exception {
-            Exception::ExecuteAndRead(path) => {
-                cmd.arg("--fs-exec-and-read").arg(path);
-            }
-            Exception::Read(path) => {
-                cmd.arg("--fs-read").arg(path);
-            }
-            Exception::ReadAndWrite(path) => {
-                cmd.arg("--fs-write-and-read").arg(path);
-            }
-            Exception::Networking => {
-                cmd.arg("--network");
-            }
+impl Exception {
+    /// Adds the exception to a policy.
+    fn add_to(&self, policy: SandboxPolicy) -> SandboxPolicy {
+        match self {
+            Exception::ExecuteAndRead(path) => policy.with_execute(path),
+            Exception::Read(path) => policy.with_read(path),
+            Exception::ReadAndWrite(path) => policy.with_write(path),
+            Exception::Networking => policy.with_network(true),
+        }
+    }
+}
+
+/// Converts a list of exceptions to a policy that allows nothing else.
+pub(crate) fn policy_from_exceptions(exceptions: &[Exception]) -> SandboxPolicy {
+    exceptions
+        .iter()
+        .fold(SandboxPolicy::new(), |policy, exception| {
+            exception.add_to(policy)
+        })
+}
+
+/// Returns the arguments that make the current executable run `exe` in the
+/// sandbox described by `policy`.
+pub(crate) fn trampoline_args(
+    policy: &SandboxPolicy,
+    exe: impl AsRef<OsStr>,
+) -> io::Result<Vec<OsString>> {
+    Ok(vec![
+        OsString::from(TRAMPOLINE_ARG),
+        OsString::from(serde_json::to_string(policy)?),
+        exe.as_ref().to_os_string(),
+    ])
+}
+
+impl SandboxPolicy {
+    /// Creates a `Command` that runs `exe` in a sandbox with this policy.
+    ///
+    /// The sandbox is set up by running the current executable, which must
+    /// call [`crate::init_sandbox`] at the start of `main`. Fails if the
+    /// current executable cannot be determined or the policy contains paths
+    /// that are not valid unicode.
+    ///
+    /// To stop the process early, send `SIGTERM` to it. On Linux the sandbox
+    /// is also torn down if the process is killed or the current process
+    /// exits, on macOS killing it leaves the sandboxed process running.
+    pub fn command(&self, exe: impl AsRef<OsStr>) -> io::Result<Command> {
+        let mut cmd = Command::new(std::env::current_exe()?);
+        cmd.args(trampoline_args(self, exe)?)
+            .env(PARENT_PID_ENV, std::process::id().to_string());
+        Ok(cmd)
+    }
+
+    /// Like [`Self::command`], but the sandbox also reports the writes and
+    /// network connections that it denies to the returned
+    /// [`ViolationReport`].
+    ///
+    /// Violations are only reported on Linux, and only if the policy allows
+    /// executing the current executable and the libraries it links to, as
+    /// the sandbox profiles do. Otherwise the report stays empty.
+    pub fn command_with_report(
+        &self,
+        exe: impl AsRef<OsStr>,
+    ) -> io::Result<(Command, ViolationReport)> {
+        let mut cmd = self.command(exe)?;
+        let (reader, writer) = io::pipe()?;
+        let fd = writer.as_raw_fd();
+        cmd.env(REPORT_FD_ENV, fd.to_string());
+        // SAFETY: `fcntl` is async-signal-safe. The pipe is only made
+        // inheritable in the child, so that no other process that is spawned
+        // at the same time inherits it.
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
         }
+        Ok((
+            cmd,
+            ViolationReport {
+                reader,
+                writer: Some(writer),
+            },
+        ))
     }
+}
+
+/// The violations of a process that was started with a command of
+/// [`SandboxPolicy::command_with_report`].
+pub struct ViolationReport {
+    reader: PipeReader,
+    writer: Option<PipeWriter>,
+}
 
-    cmd.arg(exe);
+impl ViolationReport {
+    /// Returns the violations that were reported. This waits until the
+    /// sandboxed process exited, so call it after waiting for the process.
+    pub fn violations(mut self) -> io::Result<Vec<Violation>> {
+        // Reading only finishes when all copies of the writer are closed.
+        drop(self.writer.take());
+        let mut report = String::new();
+        self.reader.read_to_string(&mut report)?;
 
-    cmd
+        // A violation that did not fit into the pipe might be cut off.
+        let complete = report.rfind('\n').map_or(0, |end| end + 1);
+        report[..complete]
+            .lines()
+            .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+            .collect()
+    }
+}
+
+/// Create a `Command` that will run the current executable with the given exceptions
+pub fn sandboxed_command(exe: &str, exceptions: &[Exception]) -> Command {
+    policy_from_exceptions(exceptions).command(exe).unwrap()
 }
 
 #[cfg(test)]
@@ -60,19 +166,18 @@ mod tests {
         // args to string to compare
         let args: Vec<&OsStr> = args.collect();
 
+        assert_eq!(args.len(), 3);
+        assert_eq!(args[0], "__sandbox_trampoline__");
+        assert_eq!(args[2], "test");
+
+        let policy: SandboxPolicy = serde_json::from_str(args[1].to_str().unwrap()).unwrap();
         assert_eq!(
-            args,
-            vec![
-                "__sandbox_trampoline__",
-                "--fs-exec-and-read",
-                "/bin",
-                "--fs-read",
-                "/etc",
-                "--fs-write-and-read",
-                "/tmp",
-                "--network",
-                "test",
-            ]
+            policy,
+            SandboxPolicy::new()
+                .with_execute("/bin")
+                .with_read("/etc")
+                .with_write("/tmp")
+                .with_network(true)
         );
     }
 }
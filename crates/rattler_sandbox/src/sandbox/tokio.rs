@@ -1,31 +1,139 @@
 //! We expose some sandbox items that then call itself through the trampoline
-use crate::sandbox::Exception;
-use tokio::process::Command;
+use std::{
+    ffi::OsStr,
+    io,
+    process::{ExitStatus, Output, Stdio},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, ChildStderr, ChildStdout, Command},
+};
+
+use super::sandbox_impl::{policy_from_exceptions, trampoline_args, PARENT_PID_ENV};
+use crate::{policy::SandboxPolicy, sandbox::Exception};
 
 /// Create a `Command` that will run the current executable with the given exceptions
 pub fn sandboxed_command(exe: &str, exceptions: &[Exception]) -> Command {
-    let self_exe = std::env::current_exe().unwrap();
-    let mut cmd = Command::new(self_exe);
-    cmd.arg("__sandbox_trampoline__");
-
-    for exception in exceptions {
-        match exception {
-            Exception::ExecuteAndRead(path) => {
-                cmd.arg("--fs-exec-and-read").arg(path);
-            }
-            Exception::Read(path) => {
-                cmd.arg("--fs-read").arg(path);
-            }
-            Exception::ReadAndWrite(path) => {
-                cmd.arg("--fs-write-and-read").arg(path);
-            }
-            Exception::Networking => {
-                cmd.arg("--network");
+    command(&policy_from_exceptions(exceptions), exe).unwrap()
+}
+
+/// Creates a `Command` that runs `exe` in a sandbox with the given policy,
+/// see [`SandboxPolicy::command`].
+pub fn command(policy: &SandboxPolicy, exe: impl AsRef<OsStr>) -> io::Result<Command> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(trampoline_args(policy, exe)?)
+        .env(PARENT_PID_ENV, std::process::id().to_string());
+    Ok(cmd)
+}
+
+/// A sandboxed process that was started with [`spawn`].
+///
+/// The sandbox is terminated when this handle is dropped before the process
+/// exited, e.g. when a future that waits for it is cancelled by a timeout.
+pub struct SandboxedChild {
+    child: Child,
+}
+
+impl SandboxedChild {
+    /// Returns the process id of the trampoline that runs the sandbox, or
+    /// `None` if it has exited and was waited for.
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// Takes the standard output of the sandboxed process, if it is piped.
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    /// Takes the standard error of the sandboxed process, if it is piped.
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
+    /// Terminates the sandbox by sending `SIGTERM` to the trampoline. The
+    /// status of the process then reports `SIGTERM`. Does nothing if the
+    /// process already exited.
+    pub fn terminate(&mut self) -> io::Result<()> {
+        let Some(pid) = self.child.id() else {
+            return Ok(());
+        };
+        // SAFETY: `kill` has no memory safety requirements. The process was
+        // not waited for yet, so its pid was not reused.
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits for the sandboxed process to exit.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+
+    /// Waits for the sandboxed process to exit and collects the standard
+    /// output and standard error that were not taken.
+    pub async fn wait_with_output(mut self) -> io::Result<Output> {
+        async fn read_to_end(pipe: Option<impl AsyncRead + Unpin>) -> io::Result<Vec<u8>> {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut buf).await?;
             }
+            Ok(buf)
         }
+
+        let stdout = self.child.stdout.take();
+        let stderr = self.child.stderr.take();
+        let (status, stdout, stderr) =
+            tokio::try_join!(self.child.wait(), read_to_end(stdout), read_to_end(stderr))?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
     }
+}
 
-    cmd.arg(exe);
+impl Drop for SandboxedChild {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            let _ = self.terminate();
+        }
+    }
+}
+
+/// Spawns `exe` with `args` in a sandbox with the given policy, capturing
+/// its output. Standard input is closed.
+///
+/// Stop the process with [`SandboxedChild::terminate`] or by dropping the
+/// handle. Killing the trampoline with `SIGKILL` also tears down the sandbox
+/// on Linux, but on macOS it leaves the sandboxed process running.
+pub fn spawn(
+    policy: &SandboxPolicy,
+    exe: impl AsRef<OsStr>,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+) -> io::Result<SandboxedChild> {
+    let child = command(policy, exe)?
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    Ok(SandboxedChild { child })
+}
 
-    cmd
+/// Runs `exe` with `args` in a sandbox with the given policy and captures its
+/// output. Standard input is closed.
+///
+/// If the sandboxed process is terminated by a signal, the returned status
+/// reports the same signal. If the returned future is dropped before the
+/// process exited, e.g. by `tokio::time::timeout`, the sandbox is
+/// terminated.
+pub async fn spawn_with_output(
+    policy: &SandboxPolicy,
+    exe: impl AsRef<OsStr>,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+) -> io::Result<Output> {
+    spawn(policy, exe, args)?.wait_with_output().await
 }
@@ -0,0 +1,58 @@
+//! Violations of a [`crate::SandboxPolicy`] that are reported by the sandbox.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// An access of a sandboxed process that was denied by the sandbox.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Violation {
+    /// A write to a path that the policy does not allow writing to.
+    Write {
+        /// The path that the process tried to write.
+        path: PathBuf,
+    },
+
+    /// A connection to the network although the policy denies network
+    /// access.
+    Network {
+        /// The address that the process tried to connect to.
+        address: String,
+    },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Write { path } => write!(f, "write to {}", path.display()),
+            Violation::Network { address } => write!(f, "connection to {address}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let violation = Violation::Write {
+            path: PathBuf::from("/usr/bin/python"),
+        };
+        let json = serde_json::to_string(&violation).unwrap();
+        assert_eq!(json, r#"{"kind":"write","path":"/usr/bin/python"}"#);
+        assert_eq!(serde_json::from_str::<Violation>(&json).unwrap(), violation);
+
+        let violation = Violation::Network {
+            address: "1.1.1.1:443".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&violation).unwrap(),
+            r#"{"kind":"network","address":"1.1.1.1:443"}"#
+        );
+    }
+}
@@ -4,8 +4,14 @@
     all(target_os = "linux", target_arch = "aarch64"),
 ))]
 
+use std::{
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
 use libtest_mimic::{Failed, Trial};
-use rattler_sandbox::sandboxed_command;
+use rattler_sandbox::{sandboxed_command, SandboxPolicy, SandboxProfile, Violation};
 
 fn test_cannot_ls() -> Result<(), Failed> {
     let mut cmd = sandboxed_command("ls", &[]);
@@ -15,6 +21,238 @@ fn test_cannot_ls() -> Result<(), Failed> {
     Ok(())
 }
 
+/// A policy that can run `sh` from the system but not write anywhere.
+fn read_only_policy() -> SandboxPolicy {
+    SandboxPolicy::new().with_system_paths().with_env("PATH")
+}
+
+fn test_exit_code() -> Result<(), Failed> {
+    let status = read_only_policy()
+        .command("sh")?
+        .args(["-c", "exit 42"])
+        .status()?;
+    assert_eq!(status.code(), Some(42));
+    Ok(())
+}
+
+fn test_signal() -> Result<(), Failed> {
+    let status = read_only_policy()
+        .command("sh")?
+        .args(["-c", "kill -TERM $$"])
+        .status()?;
+    assert_eq!(status.code(), None);
+    assert_eq!(status.signal(), Some(libc::SIGTERM));
+    Ok(())
+}
+
+fn test_terminate() -> Result<(), Failed> {
+    let mut child = read_only_policy()
+        .command("sh")?
+        .args(["-c", "sleep 30"])
+        .spawn()?;
+    std::thread::sleep(Duration::from_millis(200));
+    let start = Instant::now();
+    // SAFETY: `kill` has no memory safety requirements.
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let status = child.wait()?;
+    assert_eq!(status.signal(), Some(libc::SIGTERM));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    Ok(())
+}
+
+/// Starts a sandboxed process that writes a file after a second, and waits
+/// long enough to see whether it did.
+fn assert_stopped(stop: impl FnOnce(SandboxPolicy, &Path)) -> Result<(), Failed> {
+    let dir = tempfile::tempdir()?;
+    stop(read_only_policy().with_write(dir.path()), dir.path());
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(!dir.path().join("late").exists());
+    Ok(())
+}
+
+/// The script of [`assert_stopped`].
+const LATE_WRITE: &str = "sleep 1; echo late > \"$0/late\"";
+
+#[cfg(target_os = "linux")]
+fn test_kill() -> Result<(), Failed> {
+    assert_stopped(|policy, dir| {
+        let mut child = policy
+            .command("sh")
+            .unwrap()
+            .args(["-c", LATE_WRITE])
+            .arg(dir)
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    })
+}
+
+fn test_env_allow_list() -> Result<(), Failed> {
+    let output = read_only_policy()
+        .with_env("SANDBOX_TEST_*")
+        .command("sh")?
+        .args(["-c", "echo \"$SANDBOX_TEST_ALLOWED:$SANDBOX_SECRET\""])
+        .env("SANDBOX_TEST_ALLOWED", "allowed")
+        .env("SANDBOX_SECRET", "secret")
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "allowed:\n");
+    Ok(())
+}
+
+fn test_profile_write() -> Result<(), Failed> {
+    let allowed = tempfile::tempdir()?;
+    let denied = tempfile::tempdir()?;
+    let output = SandboxPolicy::from_profile(SandboxProfile::Test, allowed.path())
+        .command("sh")?
+        .args([
+            "-c",
+            "echo test > \"$0/allowed\"; echo test > \"$1/denied\"",
+        ])
+        .arg(allowed.path())
+        .arg(denied.path())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(allowed.path().join("allowed").exists());
+    assert!(!denied.path().join("denied").exists());
+    Ok(())
+}
+
+fn test_profile_read() -> Result<(), Failed> {
+    let allowed = tempfile::tempdir()?;
+    let hidden = tempfile::tempdir()?;
+    fs_err::write(hidden.path().join("secret"), "secret")?;
+    let output = SandboxPolicy::from_profile(SandboxProfile::Test, allowed.path())
+        .command("cat")?
+        .arg(hidden.path().join("secret"))
+        .output()?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = SandboxPolicy::from_profile(SandboxProfile::Test, allowed.path())
+        .with_full_read_access()
+        .command("cat")?
+        .arg(hidden.path().join("secret"))
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"secret");
+    Ok(())
+}
+
+fn test_profile_temp_dir() -> Result<(), Failed> {
+    let allowed = tempfile::tempdir()?;
+    let output = SandboxPolicy::from_profile(SandboxProfile::Test, allowed.path())
+        .command("sh")?
+        .args([
+            "-c",
+            "echo test > \"$TMPDIR/file\" && test \"$TMP\" = \"$TMPDIR\" && printf %s \"$TMPDIR\"",
+        ])
+        .output()?;
+    assert!(output.status.success());
+    let temp_dir = String::from_utf8(output.stdout)?;
+    assert!(!temp_dir.is_empty());
+    assert!(!Path::new(&temp_dir).exists());
+    Ok(())
+}
+
+fn test_report_violations() -> Result<(), Failed> {
+    let allowed = tempfile::tempdir()?;
+    let denied = tempfile::tempdir()?;
+    let (mut command, report) = SandboxPolicy::from_profile(SandboxProfile::Test, allowed.path())
+        .with_read(denied.path())
+        .command_with_report("sh")?;
+    let output = command
+        .args([
+            "-c",
+            "echo test > \"$0/allowed\"; mkdir \"$1/dir\"; echo test > \"$1/denied\"",
+        ])
+        .arg(allowed.path())
+        .arg(denied.path())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(allowed.path().join("allowed").exists());
+    assert_eq!(
+        report.violations()?,
+        [
+            Violation::Write {
+                path: denied.path().join("dir")
+            },
+            Violation::Write {
+                path: denied.path().join("denied")
+            },
+        ]
+    );
+    Ok(())
+}
+
+fn test_report_without_violations() -> Result<(), Failed> {
+    let (mut command, report) = read_only_policy().command_with_report("sh")?;
+    let status = command.args(["-c", "exit 3"]).status()?;
+    assert_eq!(status.code(), Some(3));
+    assert!(report.violations()?.is_empty());
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+fn test_tokio_spawn_with_output() -> Result<(), Failed> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let output = runtime.block_on(rattler_sandbox::sandbox::tokio::spawn_with_output(
+        &read_only_policy(),
+        "sh",
+        ["-c", "echo out; echo err >&2; exit 3"],
+    ))?;
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"out\n");
+    assert_eq!(output.stderr, b"err\n");
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+fn test_tokio_drop() -> Result<(), Failed> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    assert_stopped(|policy, dir| {
+        let child = runtime
+            .block_on(async {
+                rattler_sandbox::sandbox::tokio::spawn(
+                    &policy,
+                    "sh",
+                    ["-c".as_ref(), LATE_WRITE.as_ref(), dir.as_os_str()],
+                )
+            })
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        drop(child);
+    })?;
+    drop(runtime);
+    Ok(())
+}
+
 pub fn tests() -> Vec<Trial> {
-    vec![Trial::test("test_cannot_ls", test_cannot_ls)]
+    vec![
+        Trial::test("test_cannot_ls", test_cannot_ls),
+        Trial::test("test_exit_code", test_exit_code),
+        Trial::test("test_signal", test_signal),
+        Trial::test("test_terminate", test_terminate),
+        #[cfg(target_os = "linux")]
+        Trial::test("test_kill", test_kill),
+        Trial::test("test_env_allow_list", test_env_allow_list),
+        Trial::test("test_profile_write", test_profile_write),
+        Trial::test("test_profile_read", test_profile_read),
+        Trial::test("test_profile_temp_dir", test_profile_temp_dir),
+        Trial::test("test_report_violations", test_report_violations),
+        Trial::test(
+            "test_report_without_violations",
+            test_report_without_violations,
+        ),
+        #[cfg(feature = "tokio")]
+        Trial::test("test_tokio_spawn_with_output", test_tokio_spawn_with_output),
+        #[cfg(feature = "tokio")]
+        Trial::test("test_tokio_drop", test_tokio_drop),
+    ]
 }
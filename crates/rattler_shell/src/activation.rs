@@ -3,8 +3,6 @@
 //! This crate provides helper functions to activate and deactivate virtual
 //! environments.
 
-#[cfg(target_family = "unix")]
-use std::io::Write;
 use std::{
     collections::HashMap,
     ffi::OsStr,
@@ -12,19 +10,24 @@ use std::{
     process::ExitStatus,
 };
 
-#[cfg(target_family = "unix")]
-use anyhow::{Context, Result};
 use fs_err as fs;
 use indexmap::IndexMap;
 use itertools::Itertools;
 use rattler_conda_types::Platform;
-#[cfg(target_family = "unix")]
-use rattler_pty::unix::PtySession;
+use serde::{Deserialize, Serialize};
 
 use crate::shell::{Shell, ShellError, ShellScript};
 
+mod cache;
+mod report;
+
+pub use cache::{ActivationCache, ActivationCacheKey};
+pub use report::{ActivationReport, ActivationStep, ActivationStepSource, EnvChange, EnvDiff};
+
 const ENV_START_SEPARATOR: &str = "____RATTLER_ENV_START____";
 
+type EnvMap = HashMap<String, String>;
+
 /// Type of modification done to the `PATH` variable
 #[derive(Default, Clone)]
 pub enum PathModificationBehavior {
@@ -328,6 +331,43 @@ pub struct ActivationResult<T: Shell + 'static> {
     pub path: Vec<PathBuf>,
 }
 
+/// The environment of an activated prefix as plain data, as computed by
+/// [`Activator::activated_environment`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivatedEnvironment {
+    /// The environment variables that are added or changed by the activation,
+    /// including the new value of the `PATH` variable.
+    pub env_vars: HashMap<String, String>,
+
+    /// The environment variables that are removed by the activation, e.g.
+    /// the variables of a previously activated prefix.
+    pub removed_env_vars: Vec<String>,
+
+    /// The path entries that are added to the `PATH` variable.
+    pub added_paths: Vec<PathBuf>,
+
+    /// The path entries of a previously activated prefix that are removed from
+    /// the `PATH` variable.
+    pub removed_paths: Vec<PathBuf>,
+}
+
+impl ActivatedEnvironment {
+    /// Applies the activation to the given environment and returns the
+    /// resulting environment.
+    pub fn apply(&self, env: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut env = env.clone();
+        for key in &self.removed_env_vars {
+            env.remove(key);
+        }
+        env.extend(
+            self.env_vars
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        env
+    }
+}
+
 impl<T: Shell + Clone> Activator<T> {
     /// Return unique env var keys from both `env_vars` and `post_activation_env_vars` in insertion order.
     fn unique_env_keys(&self) -> impl Iterator<Item = &str> {
@@ -390,80 +430,37 @@ impl<T: Shell + Clone> Activator<T> {
         })
     }
 
-    /// Starts a UNIX shell.
-    /// # Arguments
-    /// - `shell`: The type of shell to start. Must implement the `Shell` and
-    ///   `Copy` traits.
-    /// - `args`: A vector of arguments to pass to the shell.
-    /// - `env`: A `HashMap` containing environment variables to set in the
-    ///   shell.
-    /// - `prompt`: Prompt to the shell
-    #[cfg(target_family = "unix")]
-    #[allow(dead_code)]
-    async fn start_unix_shell<T_: Shell + Copy + 'static>(
-        shell: T_,
-        args: Vec<&str>,
-        env: &HashMap<String, String>,
-        prompt: String,
-    ) -> Result<Option<i32>> {
-        const DONE_STR: &str = "RATTLER_SHELL_ACTIVATION_DONE";
-        // create a tempfile for activation
-        let mut temp_file = tempfile::Builder::new()
-            .prefix("rattler_env_")
-            .suffix(&format!(".{}", shell.extension()))
-            .rand_bytes(3)
-            .tempfile()
-            .context("Failed to create tmp file")?;
-
-        let mut shell_script = ShellScript::new(shell, Platform::current());
-        for (key, value) in env {
-            shell_script
-                .set_env_var(key, value)
-                .context("Failed to set env var")?;
-        }
-
-        shell_script.echo(DONE_STR)?;
-
-        temp_file
-            .write_all(shell_script.contents()?.as_bytes())
-            .context("Failed to write shell script content")?;
-
-        // Write custom prompt to the env file
-        temp_file.write_all(prompt.as_bytes())?;
-
-        let mut command = std::process::Command::new(shell.executable());
-        command.args(args);
-
-        // Space added before `source` to automatically ignore it in history.
-        let mut source_command = " ".to_string();
-        shell
-            .run_script(&mut source_command, temp_file.path())
-            .context("Failed to run the script")?;
-
-        // Remove automatically added `\n`, if for some reason this fails, just ignore.
-        let source_command = source_command
-            .strip_suffix('\n')
-            .unwrap_or(source_command.as_str());
-
-        // Start process and send env activation to the shell.
-        let mut process = PtySession::new(command)?;
-        process
-            .send_line(source_command)
-            .context("Failed to send command to shell")?;
-
-        process
-            .interact(Some(DONE_STR))
-            .context("Failed to interact with shell process")
-    }
-
     /// Create an activation script for a given shell and platform. This
     /// returns a tuple of the newly computed PATH variable and the activation
     /// script.
     pub fn activation(
         &self,
         variables: ActivationVariables,
+    ) -> Result<ActivationResult<T>, ActivationError> {
+        self.activation_script(variables, false)
+    }
+
+    /// Creates the activation script. If `record_env` is true, the script
+    /// prints the environment, enclosed in [`ENV_START_SEPARATOR`], at the
+    /// start, after the environment variables of the prefix are set, after
+    /// each activation script and after the post activation environment
+    /// variables are set.
+    fn activation_script(
+        &self,
+        variables: ActivationVariables,
+        record_env: bool,
     ) -> Result<ActivationResult<T>, ActivationError> {
         let mut script = ShellScript::new(self.shell_type.clone(), self.platform);
+        let record = |script: &mut ShellScript<T>| -> Result<(), std::fmt::Error> {
+            if record_env {
+                script
+                    .echo(ENV_START_SEPARATOR)?
+                    .print_env()?
+                    .echo(ENV_START_SEPARATOR)?;
+            }
+            Ok(())
+        };
+        record(&mut script)?;
 
         let mut path = variables.path.clone().unwrap_or_default();
         if let Some(conda_prefix) = variables.conda_prefix {
@@ -515,17 +512,22 @@ impl<T: Shell + Clone> Activator<T> {
 
         // For each environment variable that was set during activation
         script.apply_env_vars_with_backup(&variables.current_env, new_shlvl, &self.env_vars)?;
+        record(&mut script)?;
 
         for activation_script in &self.activation_scripts {
             script.run_script(activation_script)?;
+            record(&mut script)?;
         }
 
         // Set environment variables that should be applied after activation scripts
-        script.apply_env_vars_with_backup(
-            &variables.current_env,
-            new_shlvl,
-            &self.post_activation_env_vars,
-        )?;
+        if !self.post_activation_env_vars.is_empty() {
+            script.apply_env_vars_with_backup(
+                &variables.current_env,
+                new_shlvl,
+                &self.post_activation_env_vars,
+            )?;
+            record(&mut script)?;
+        }
 
         Ok(ActivationResult { script, path })
     }
@@ -619,6 +621,167 @@ impl<T: Shell + Clone> Activator<T> {
         variables: ActivationVariables,
         environment: Option<HashMap<&OsStr, &OsStr>>,
     ) -> Result<HashMap<String, String>, ActivationError> {
+        let (before_env, after_env) = self.evaluate_activation(variables, environment)?;
+
+        // Find and return the differences
+        Ok(after_env
+            .into_iter()
+            .filter(|(key, value)| before_env.get(key) != Some(value))
+            .collect())
+    }
+
+    /// Computes the environment of the activated prefix without spawning a
+    /// shell, if possible.
+    ///
+    /// The environment variables from `conda-meta/state` and
+    /// `etc/conda/env_vars.d` and the `PATH` are applied in process. Only if
+    /// the prefix has activation scripts, or the previously activated prefix
+    /// (`variables.conda_prefix`) has deactivation scripts, the activation is
+    /// evaluated by the shell like [`Self::run_activation`] does.
+    ///
+    /// The environment variables of `variables.current_env` are used as the
+    /// environment that is activated. If it is empty, the environment of the
+    /// current process is used when a shell has to be spawned.
+    pub fn activated_environment(
+        &self,
+        variables: ActivationVariables,
+    ) -> Result<ActivatedEnvironment, ActivationError> {
+        let deactivate = variables
+            .conda_prefix
+            .as_deref()
+            .map(|conda_prefix| {
+                Activator::from_path(conda_prefix, self.shell_type.clone(), self.platform)
+            })
+            .transpose()?;
+
+        let removed_paths = deactivate
+            .as_ref()
+            .map(|deactivate| {
+                let path = variables.path.as_deref().unwrap_or_default();
+                deactivate
+                    .paths
+                    .iter()
+                    .filter(|entry| path.contains(entry))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let needs_shell = !self.activation_scripts.is_empty()
+            || deactivate
+                .as_ref()
+                .is_some_and(|deactivate| !deactivate.deactivation_scripts.is_empty());
+
+        let (before_env, after_env) = if needs_shell {
+            let current_env = variables.current_env.clone();
+            let environment = (!current_env.is_empty()).then(|| {
+                current_env
+                    .iter()
+                    .map(|(key, value)| (OsStr::new(key), OsStr::new(value)))
+                    .collect()
+            });
+            self.evaluate_activation(variables, environment)?
+        } else {
+            let after_env = self.apply_activation(&variables, deactivate.as_ref());
+            (variables.current_env, after_env)
+        };
+
+        let mut removed_env_vars = before_env
+            .keys()
+            .filter(|key| !after_env.contains_key(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        removed_env_vars.sort();
+
+        Ok(ActivatedEnvironment {
+            env_vars: after_env
+                .into_iter()
+                .filter(|(key, value)| before_env.get(key) != Some(value))
+                .collect(),
+            removed_env_vars,
+            added_paths: self.paths.clone(),
+            removed_paths,
+        })
+    }
+
+    /// Applies the same modifications as the script returned by
+    /// [`Self::activation`] to `variables.current_env`, without running any
+    /// activation or deactivation scripts.
+    fn apply_activation(
+        &self,
+        variables: &ActivationVariables,
+        deactivate: Option<&Activator<T>>,
+    ) -> HashMap<String, String> {
+        let mut env = variables.current_env.clone();
+
+        let mut path = variables.path.clone().unwrap_or_default();
+        if let Some(deactivate) = deactivate {
+            for key in deactivate.env_vars.keys() {
+                env.remove(key);
+            }
+            path.retain(|x| !deactivate.paths.contains(x));
+        }
+
+        // Compute the new PATH the same way the shell would expand it.
+        let path_var = self.shell_type.path_var(&self.platform).to_string();
+        let separator = if self.platform.is_windows() { ";" } else { ":" };
+        let mut path_entries = self
+            .paths
+            .iter()
+            .chain(path.iter())
+            .map(|entry| entry.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let current_path = env.get(&path_var).filter(|path| !path.is_empty()).cloned();
+        match (variables.path_modification_behavior.clone(), current_path) {
+            (PathModificationBehavior::Prepend, Some(current_path)) => {
+                path_entries.push(current_path);
+            }
+            (PathModificationBehavior::Append, Some(current_path)) => {
+                path_entries.insert(0, current_path);
+            }
+            _ => {}
+        }
+        env.insert(path_var, path_entries.join(separator));
+
+        let shlvl = variables
+            .current_env
+            .get("CONDA_SHLVL")
+            .and_then(|s| s.parse::<i32>().ok())
+            .unwrap_or(0);
+        let new_shlvl = shlvl + 1;
+        env.insert(String::from("CONDA_SHLVL"), new_shlvl.to_string());
+
+        if let Some(existing_prefix) = variables.current_env.get("CONDA_PREFIX") {
+            env.insert(
+                format!("CONDA_ENV_SHLVL_{new_shlvl}_CONDA_PREFIX"),
+                existing_prefix.clone(),
+            );
+        }
+        env.insert(
+            String::from("CONDA_PREFIX"),
+            self.target_prefix.to_string_lossy().into_owned(),
+        );
+
+        for (key, value) in self.env_vars.iter().chain(&self.post_activation_env_vars) {
+            if let Some(existing_value) = variables.current_env.get(key) {
+                env.insert(
+                    format!("CONDA_ENV_SHLVL_{new_shlvl}_{key}"),
+                    existing_value.clone(),
+                );
+            }
+            env.insert(key.clone(), value.clone());
+        }
+
+        env
+    }
+
+    /// Runs the activation script in the shell and returns the environment
+    /// before and after the activation.
+    fn evaluate_activation(
+        &self,
+        variables: ActivationVariables,
+        environment: Option<HashMap<&OsStr, &OsStr>>,
+    ) -> Result<(EnvMap, EnvMap), ActivationError> {
         let activation_script = self.activation(variables)?.script;
 
         // Create a script that starts by emitting all environment variables, then runs
@@ -633,6 +796,70 @@ impl<T: Shell + Clone> Activator<T> {
             .echo(ENV_START_SEPARATOR)?
             .print_env()?;
 
+        let stdout = self.run_detection_script(&activation_detection_script, environment)?;
+        let (before_env, rest) = stdout
+            .split_once(ENV_START_SEPARATOR)
+            .unwrap_or(("", stdout.as_ref()));
+        let (_, after_env) = rest.rsplit_once(ENV_START_SEPARATOR).unwrap_or(("", ""));
+
+        Ok((self.parse_env(before_env), self.parse_env(after_env)))
+    }
+
+    /// Runs the activation in the shell and records the changes that each
+    /// step of the activation makes to the environment.
+    ///
+    /// Like [`Self::run_activation`], the environment of the current process
+    /// is activated, unless `environment` is given.
+    pub fn activation_report(
+        &self,
+        variables: ActivationVariables,
+        environment: Option<HashMap<&OsStr, &OsStr>>,
+    ) -> Result<ActivationReport, ActivationError> {
+        let script = self.activation_script(variables, true)?.script;
+        let stdout = self.run_detection_script(&script, environment)?;
+
+        // Every other part of the output is an environment printed between two
+        // separators, the other parts are the output of the activation.
+        let envs = stdout
+            .split(ENV_START_SEPARATOR)
+            .skip(1)
+            .step_by(2)
+            .map(|env| self.parse_env(env))
+            .collect::<Vec<_>>();
+
+        let sources = std::iter::once(ActivationStepSource::EnvVars)
+            .chain(
+                self.activation_scripts
+                    .iter()
+                    .cloned()
+                    .map(ActivationStepSource::Script),
+            )
+            .chain(
+                (!self.post_activation_env_vars.is_empty())
+                    .then_some(ActivationStepSource::PostActivationEnvVars),
+            );
+        let steps = sources
+            .zip(envs.iter().tuple_windows())
+            .map(|(source, (before, after))| ActivationStep {
+                source,
+                diff: EnvDiff::new(before, after),
+            })
+            .collect();
+
+        Ok(ActivationReport {
+            prefix: self.target_prefix.clone(),
+            path_entries: self.paths.clone(),
+            steps,
+        })
+    }
+
+    /// Writes `script` to a temporary file, runs it with the shell and
+    /// returns its stdout.
+    fn run_detection_script(
+        &self,
+        script: &ShellScript<T>,
+        environment: Option<HashMap<&OsStr, &OsStr>>,
+    ) -> Result<String, ActivationError> {
         // Create a temporary file that we can execute with our shell.
         let activation_script_dir = tempfile::TempDir::new()?;
         let activation_script_path = activation_script_dir
@@ -641,17 +868,14 @@ impl<T: Shell + Clone> Activator<T> {
 
         // Write the activation script to the temporary file, closing the file
         // afterwards
-        fs::write(
-            &activation_script_path,
-            activation_detection_script.contents()?,
-        )?;
+        fs::write(&activation_script_path, script.contents()?)?;
         // Get only the path to the temporary file
         let mut activation_command = self
             .shell_type
             .create_run_script_command(&activation_script_path);
 
         // Overwrite the environment variables with the ones provided
-        if let Some(environment) = environment.clone() {
+        if let Some(environment) = environment {
             activation_command.env_clear().envs(environment);
         }
 
@@ -659,33 +883,27 @@ impl<T: Shell + Clone> Activator<T> {
 
         if !activation_result.status.success() {
             return Err(ActivationError::FailedToRunActivationScript {
-                script: activation_detection_script.contents()?,
+                script: script.contents()?,
                 stdout: String::from_utf8_lossy(&activation_result.stdout).into_owned(),
                 stderr: String::from_utf8_lossy(&activation_result.stderr).into_owned(),
                 status: activation_result.status,
             });
         }
 
-        let stdout = String::from_utf8_lossy(&activation_result.stdout);
-        let (before_env, rest) = stdout
-            .split_once(ENV_START_SEPARATOR)
-            .unwrap_or(("", stdout.as_ref()));
-        let (_, after_env) = rest.rsplit_once(ENV_START_SEPARATOR).unwrap_or(("", ""));
-
-        // Parse both environments and find the difference
-        let before_env = self.shell_type.parse_env(before_env);
-        let after_env = self.shell_type.parse_env(after_env);
+        Ok(String::from_utf8_lossy(&activation_result.stdout).into_owned())
+    }
 
-        // Find and return the differences
-        Ok(after_env
+    /// Parses the environment printed by [`ShellScript::print_env`].
+    fn parse_env(&self, env: &str) -> EnvMap {
+        self.shell_type
+            .parse_env(env)
             .into_iter()
-            .filter(|(key, value)| before_env.get(key) != Some(value))
             // this happens on Windows for some reason
             // @SET "=C:=C:\Users\robostack\Programs\pixi"
             // @SET "=ExitCode=00000000"
             .filter(|(key, _)| !key.is_empty())
             .map(|(key, value)| (key.to_owned(), value.to_owned()))
-            .collect())
+            .collect()
     }
 }
 
@@ -860,6 +1078,189 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_activated_environment_without_scripts() {
+        let tdir = TempDir::new("test").unwrap();
+        let prefix = tdir.path().join("env");
+        let state_path = prefix.join("conda-meta/state");
+        fs::create_dir_all(state_path.parent().unwrap()).unwrap();
+        fs::write(&state_path, r#"{"env_vars": {"STATE": "new"}}"#).unwrap();
+
+        // A previously activated prefix whose variables should be removed.
+        let old_prefix = tdir.path().join("old");
+        let old_state_path = old_prefix.join("conda-meta/state");
+        fs::create_dir_all(old_state_path.parent().unwrap()).unwrap();
+        fs::write(&old_state_path, r#"{"env_vars": {"OLD": "value"}}"#).unwrap();
+
+        let activator = Activator::from_path(&prefix, shell::Bash, Platform::Linux64).unwrap();
+        let current_env = HashMap::from([
+            (String::from("PATH"), String::from("/usr/bin")),
+            (String::from("STATE"), String::from("old")),
+            (String::from("OLD"), String::from("value")),
+            (
+                String::from("CONDA_PREFIX"),
+                old_prefix.to_string_lossy().into_owned(),
+            ),
+            (String::from("CONDA_SHLVL"), String::from("1")),
+        ]);
+        let environment = activator
+            .activated_environment(ActivationVariables {
+                conda_prefix: Some(old_prefix.clone()),
+                path: Some(vec![old_prefix.join("bin"), PathBuf::from("/usr/bin")]),
+                path_modification_behavior: PathModificationBehavior::Replace,
+                current_env: current_env.clone(),
+            })
+            .unwrap();
+
+        let new_path = format!("{}:/usr/bin", prefix.join("bin").display());
+        let old_prefix = old_prefix.to_string_lossy().into_owned();
+        let prefix_str = prefix.to_string_lossy().into_owned();
+        assert_eq!(
+            environment.env_vars,
+            HashMap::from([
+                (String::from("PATH"), new_path.clone()),
+                (String::from("STATE"), String::from("new")),
+                (String::from("CONDA_ENV_SHLVL_2_STATE"), String::from("old")),
+                (String::from("CONDA_PREFIX"), prefix_str.clone()),
+                (
+                    String::from("CONDA_ENV_SHLVL_2_CONDA_PREFIX"),
+                    old_prefix.clone()
+                ),
+                (String::from("CONDA_SHLVL"), String::from("2")),
+            ])
+        );
+        assert_eq!(environment.removed_env_vars, vec![String::from("OLD")]);
+        assert_eq!(environment.added_paths, vec![prefix.join("bin")]);
+        assert_eq!(
+            environment.removed_paths,
+            vec![PathBuf::from(&old_prefix).join("bin")]
+        );
+
+        let activated = environment.apply(&current_env);
+        assert_eq!(activated["PATH"], new_path);
+        assert_eq!(activated["CONDA_PREFIX"], prefix_str);
+        assert!(!activated.contains_key("OLD"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_activated_environment_with_scripts() {
+        let tdir = TempDir::new("test").unwrap();
+        let state_path = tdir.path().join("conda-meta/state");
+        fs::create_dir_all(state_path.parent().unwrap()).unwrap();
+        fs::write(&state_path, r#"{"env_vars": {"STATE": "value"}}"#).unwrap();
+
+        let activate_dir = tdir.path().join("etc/conda/activate.d");
+        fs::create_dir_all(&activate_dir).unwrap();
+        fs::write(
+            activate_dir.join("script.sh"),
+            "export SCRIPT_ENV=\"$STATE from script\"\nunset REMOVED\n",
+        )
+        .unwrap();
+
+        let activator =
+            Activator::from_path(tdir.path(), shell::Bash, Platform::current()).unwrap();
+        let environment = activator
+            .activated_environment(ActivationVariables {
+                conda_prefix: None,
+                path: None,
+                path_modification_behavior: PathModificationBehavior::Prepend,
+                current_env: HashMap::from([
+                    (String::from("PATH"), String::from("/usr/bin:/bin")),
+                    (String::from("REMOVED"), String::from("value")),
+                ]),
+            })
+            .unwrap();
+
+        assert_eq!(environment.env_vars["SCRIPT_ENV"], "value from script");
+        assert_eq!(environment.env_vars["STATE"], "value");
+        assert_eq!(
+            environment.env_vars["PATH"],
+            format!("{}:/usr/bin:/bin", tdir.path().join("bin").display())
+        );
+        assert_eq!(environment.removed_env_vars, vec![String::from("REMOVED")]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_activation_report() {
+        let tdir = TempDir::new("test").unwrap();
+        let state_path = tdir.path().join("conda-meta/state");
+        fs::create_dir_all(state_path.parent().unwrap()).unwrap();
+        fs::write(&state_path, r#"{"env_vars": {"JAVA_HOME": "/state/java"}}"#).unwrap();
+
+        let activate_dir = tdir.path().join("etc/conda/activate.d");
+        fs::create_dir_all(&activate_dir).unwrap();
+        fs::write(activate_dir.join("a.sh"), "export LD_LIBRARY_PATH=/a/lib\n").unwrap();
+        fs::write(
+            activate_dir.join("b.sh"),
+            "echo noise\nexport JAVA_HOME=/b/java\nunset REMOVED\n",
+        )
+        .unwrap();
+
+        let activator =
+            Activator::from_path(tdir.path(), shell::Bash, Platform::current()).unwrap();
+        let report = activator
+            .activation_report(
+                ActivationVariables::default(),
+                Some(HashMap::from([
+                    (OsStr::new("PATH"), OsStr::new("/usr/bin:/bin")),
+                    (OsStr::new("REMOVED"), OsStr::new("value")),
+                ])),
+            )
+            .unwrap();
+
+        assert_eq!(report.path_entries, vec![tdir.path().join("bin")]);
+        let sources = report
+            .steps
+            .iter()
+            .map(|step| step.source.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            vec![
+                ActivationStepSource::EnvVars,
+                ActivationStepSource::Script(activate_dir.join("a.sh")),
+                ActivationStepSource::Script(activate_dir.join("b.sh")),
+            ]
+        );
+
+        let env_vars = &report.steps[0].diff;
+        assert_eq!(env_vars.added["JAVA_HOME"], "/state/java");
+        assert!(env_vars.changed.contains_key("PATH"));
+
+        let script_a = &report.steps[1].diff;
+        assert_eq!(
+            script_a.added,
+            BTreeMap::from([(String::from("LD_LIBRARY_PATH"), String::from("/a/lib"))])
+        );
+        assert!(script_a.changed.is_empty() && script_a.removed.is_empty());
+
+        let script_b = &report.steps[2].diff;
+        assert_eq!(script_b.changed["JAVA_HOME"].old, "/state/java");
+        assert_eq!(script_b.changed["JAVA_HOME"].new, "/b/java");
+        assert_eq!(script_b.removed["REMOVED"], "value");
+
+        let clobbering = report
+            .steps_changing("JAVA_HOME")
+            .map(|step| step.source.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            clobbering,
+            vec![
+                ActivationStepSource::EnvVars,
+                ActivationStepSource::Script(activate_dir.join("b.sh")),
+            ]
+        );
+
+        let text = report.to_string();
+        assert!(text.contains("~ JAVA_HOME=/b/java (was /state/java)"));
+        assert!(text.contains("- REMOVED (was value)"));
+
+        let json: ActivationReport = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json, report);
+    }
+
     #[test]
     fn test_add_to_path() {
         let prefix = PathBuf::from_str("/opt/conda").unwrap();
@@ -983,6 +1384,28 @@ mod tests {
         insta::assert_snapshot!(script);
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_activation_script_tcsh() {
+        let script = get_script(shell::Tcsh, PathModificationBehavior::Append);
+        insta::assert_snapshot!("test_activation_script_tcsh_append", script);
+        let script = get_script(shell::Tcsh, PathModificationBehavior::Replace);
+        insta::assert_snapshot!("test_activation_script_tcsh_replace", script);
+        let script = get_script(shell::Tcsh, PathModificationBehavior::Prepend);
+        insta::assert_snapshot!("test_activation_script_tcsh_prepend", script);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_activation_script_elvish() {
+        let script = get_script(shell::Elvish, PathModificationBehavior::Append);
+        insta::assert_snapshot!("test_activation_script_elvish_append", script);
+        let script = get_script(shell::Elvish, PathModificationBehavior::Replace);
+        insta::assert_snapshot!("test_activation_script_elvish_replace", script);
+        let script = get_script(shell::Elvish, PathModificationBehavior::Prepend);
+        insta::assert_snapshot!("test_activation_script_elvish_prepend", script);
+    }
+
     fn test_run_activation(shell: ShellEnum, with_unicode: bool) {
         let environment_dir = tempfile::TempDir::new().unwrap();
 
@@ -1089,6 +1512,20 @@ mod tests {
         test_run_activation(crate::shell::Xonsh.into(), false);
     }
 
+    #[test]
+    #[cfg(unix)]
+    #[ignore]
+    fn test_run_activation_tcsh() {
+        test_run_activation(crate::shell::Tcsh.into(), false);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[ignore]
+    fn test_run_activation_elvish() {
+        test_run_activation(crate::shell::Elvish.into(), false);
+    }
+
     #[test]
     fn test_deactivation() {
         let tmp_dir = TempDir::new("test_deactivation").unwrap();
@@ -1111,6 +1548,8 @@ mod tests {
                 ShellEnum::PowerShell(shell::PowerShell::default()),
             ),
             ("nushell", ShellEnum::NuShell(shell::NuShell)),
+            ("tcsh", ShellEnum::Tcsh(shell::Tcsh)),
+            ("elvish", ShellEnum::Elvish(shell::Elvish)),
         ];
 
         for (shell_name, shell_type) in shell_types {
@@ -1168,6 +1607,8 @@ mod tests {
                 ShellEnum::PowerShell(shell::PowerShell::default()),
             ),
             ("nushell", ShellEnum::NuShell(shell::NuShell)),
+            ("tcsh", ShellEnum::Tcsh(shell::Tcsh)),
+            ("elvish", ShellEnum::Elvish(shell::Elvish)),
         ];
 
         for (shell_name, shell_type) in shell_types {
@@ -1233,6 +1674,8 @@ mod tests {
                 ShellEnum::PowerShell(shell::PowerShell::default()),
             ),
             ("nushell", ShellEnum::NuShell(shell::NuShell)),
+            ("tcsh", ShellEnum::Tcsh(shell::Tcsh)),
+            ("elvish", ShellEnum::Elvish(shell::Elvish)),
         ];
 
         // now lets activate again an environment
@@ -1352,6 +1795,8 @@ mod tests {
                 ShellEnum::PowerShell(shell::PowerShell::default()),
             ),
             ("nushell", ShellEnum::NuShell(shell::NuShell)),
+            ("tcsh", ShellEnum::Tcsh(shell::Tcsh)),
+            ("elvish", ShellEnum::Elvish(shell::Elvish)),
         ];
 
         // now lets activate again an environment
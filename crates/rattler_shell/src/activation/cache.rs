@@ -0,0 +1,251 @@
+//! Caching of activated environments on disk.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use fs_err as fs;
+use rattler_digest::{digest::Digest, Sha256};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ActivatedEnvironment, ActivationError, ActivationVariables, Activator, PathModificationBehavior,
+};
+use crate::shell::Shell;
+
+/// A key that identifies the inputs of an activation. If any of the inputs
+/// changes, the key changes as well.
+///
+/// The key is derived from the modification time of the `conda-meta`
+/// directory of the prefix, which changes whenever packages are installed or
+/// removed, the hashes of the activation scripts, the environment variables
+/// of the prefix and the [`ActivationVariables`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ActivationCacheKey(String);
+
+impl ActivationCacheKey {
+    /// Computes the key for activating the prefix of the `activator` with the
+    /// given `variables`.
+    pub fn new<T: Shell + Clone>(
+        activator: &Activator<T>,
+        variables: &ActivationVariables,
+    ) -> Result<Self, ActivationError> {
+        let mut hasher = Sha256::new();
+        let mut update = |value: &str| {
+            hasher.update(value.as_bytes());
+            hasher.update([0]);
+        };
+
+        update(&activator.target_prefix.to_string_lossy());
+        update(activator.platform.as_str());
+        update(activator.shell_type.executable());
+        update(&modification_time(
+            &activator.target_prefix.join("conda-meta"),
+        )?);
+        update(&modification_time(
+            &activator.target_prefix.join("conda-meta/state"),
+        )?);
+
+        for script in &activator.activation_scripts {
+            update(&script.to_string_lossy());
+            let digest = rattler_digest::compute_file_digest::<Sha256>(script)?;
+            update(&format!("{digest:x}"));
+        }
+        for (key, value) in activator
+            .env_vars
+            .iter()
+            .chain(&activator.post_activation_env_vars)
+        {
+            update(key);
+            update(value);
+        }
+        for path in &activator.paths {
+            update(&path.to_string_lossy());
+        }
+
+        if let Some(conda_prefix) = &variables.conda_prefix {
+            update(&conda_prefix.to_string_lossy());
+        }
+        for path in variables.path.iter().flatten() {
+            update(&path.to_string_lossy());
+        }
+        update(match variables.path_modification_behavior {
+            PathModificationBehavior::Replace => "replace",
+            PathModificationBehavior::Append => "append",
+            PathModificationBehavior::Prepend => "prepend",
+        });
+        let mut current_env = variables.current_env.iter().collect::<Vec<_>>();
+        current_env.sort();
+        for (key, value) in current_env {
+            update(key);
+            update(value);
+        }
+
+        Ok(Self(format!("{:x}", hasher.finalize())))
+    }
+
+    /// Returns the key as a hex string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Returns the modification time of `path` as a string, or an empty string if
+/// the path does not exist.
+fn modification_time(path: &Path) -> Result<String, std::io::Error> {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let modified = metadata
+                .modified()?
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            Ok(modified.as_nanos().to_string())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// A cache of activated environments on disk.
+///
+/// The cache stores a single entry per prefix: the environment of the last
+/// activation together with its [`ActivationCacheKey`]. When the key of an
+/// activation differs from the stored key, the environment is computed again
+/// and replaces the stored entry.
+#[derive(Debug, Clone)]
+pub struct ActivationCache {
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    key: ActivationCacheKey,
+    environment: ActivatedEnvironment,
+}
+
+impl ActivationCache {
+    /// Constructs a new cache that stores its entries in the given directory.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Returns the directory in which the entries are stored.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the cached environment of `prefix` if it was stored with the
+    /// given key.
+    pub fn get(&self, prefix: &Path, key: &ActivationCacheKey) -> Option<ActivatedEnvironment> {
+        let contents = fs::read_to_string(self.entry_path(prefix)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+        (&entry.key == key).then_some(entry.environment)
+    }
+
+    /// Stores the environment of `prefix` under the given key, replacing any
+    /// previous entry of the prefix.
+    pub fn insert(
+        &self,
+        prefix: &Path,
+        key: ActivationCacheKey,
+        environment: ActivatedEnvironment,
+    ) -> Result<(), std::io::Error> {
+        fs::create_dir_all(&self.path)?;
+        let entry = CacheEntry { key, environment };
+
+        // Write to a temporary file first so that concurrent readers never
+        // observe a partially written entry.
+        let mut file = tempfile::NamedTempFile::new_in(&self.path)?;
+        serde_json::to_writer(&mut file, &entry)?;
+        file.flush()?;
+        file.persist(self.entry_path(prefix))
+            .map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    /// Returns the activated environment of the `activator`, either from the
+    /// cache or by calling [`Activator::activated_environment`] and storing
+    /// the result.
+    pub fn activated_environment<T: Shell + Clone>(
+        &self,
+        activator: &Activator<T>,
+        variables: ActivationVariables,
+    ) -> Result<ActivatedEnvironment, ActivationError> {
+        let key = ActivationCacheKey::new(activator, &variables)?;
+        if let Some(environment) = self.get(&activator.target_prefix, &key) {
+            return Ok(environment);
+        }
+
+        let environment = activator.activated_environment(variables)?;
+        if let Err(err) = self.insert(&activator.target_prefix, key, environment.clone()) {
+            tracing::warn!(
+                "failed to cache the activated environment of {}: {err}",
+                activator.target_prefix.display()
+            );
+        }
+        Ok(environment)
+    }
+
+    fn entry_path(&self, prefix: &Path) -> PathBuf {
+        let digest =
+            rattler_digest::compute_bytes_digest::<Sha256>(prefix.to_string_lossy().as_bytes());
+        self.path.join(format!("{digest:x}.json"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use fs_err as fs;
+    use rattler_conda_types::Platform;
+
+    use super::{ActivationCache, ActivationCacheKey};
+    use crate::{
+        activation::{ActivationVariables, Activator},
+        shell,
+    };
+
+    #[test]
+    fn test_activation_cache() {
+        let prefix = tempfile::TempDir::new().unwrap();
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        let state_path = prefix.path().join("conda-meta/state");
+        fs::create_dir_all(state_path.parent().unwrap()).unwrap();
+        fs::write(&state_path, r#"{"env_vars": {"STATE": "first"}}"#).unwrap();
+
+        let cache = ActivationCache::new(cache_dir.path());
+        let variables = || ActivationVariables {
+            current_env: HashMap::from([(String::from("PATH"), String::from("/usr/bin"))]),
+            ..ActivationVariables::default()
+        };
+
+        let activator =
+            Activator::from_path(prefix.path(), shell::Bash, Platform::Linux64).unwrap();
+        let key = ActivationCacheKey::new(&activator, &variables()).unwrap();
+        assert!(cache.get(prefix.path(), &key).is_none());
+
+        let environment = cache
+            .activated_environment(&activator, variables())
+            .unwrap();
+        assert_eq!(environment.env_vars["STATE"], "first");
+        assert_eq!(cache.get(prefix.path(), &key), Some(environment));
+
+        // Changing the environment variables of the prefix invalidates the entry.
+        fs::write(&state_path, r#"{"env_vars": {"STATE": "second"}}"#).unwrap();
+        let activator =
+            Activator::from_path(prefix.path(), shell::Bash, Platform::Linux64).unwrap();
+        let new_key = ActivationCacheKey::new(&activator, &variables()).unwrap();
+        assert_ne!(key, new_key);
+        assert!(cache.get(prefix.path(), &new_key).is_none());
+
+        let environment = cache
+            .activated_environment(&activator, variables())
+            .unwrap();
+        assert_eq!(environment.env_vars["STATE"], "second");
+        assert!(cache.get(prefix.path(), &key).is_none());
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+    }
+}
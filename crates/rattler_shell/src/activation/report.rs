@@ -0,0 +1,181 @@
+//! A report of the changes that each step of an activation makes to the
+//! environment, see [`super::Activator::activation_report`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The changes that the activation of a prefix makes to the environment,
+/// recorded step by step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivationReport {
+    /// The prefix that was activated.
+    pub prefix: PathBuf,
+
+    /// The entries that are added to the `PATH` variable for the prefix, see
+    /// [`super::prefix_path_entries`].
+    pub path_entries: Vec<PathBuf>,
+
+    /// The steps of the activation in the order in which they ran.
+    pub steps: Vec<ActivationStep>,
+}
+
+/// A single step of an activation and the changes it made to the environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivationStep {
+    /// What ran in this step.
+    pub source: ActivationStepSource,
+
+    /// The changes that the step made to the environment.
+    pub diff: EnvDiff,
+}
+
+/// What ran in an [`ActivationStep`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "path", rename_all = "snake_case")]
+pub enum ActivationStepSource {
+    /// The deactivation of a previously activated prefix, the `PATH` and the
+    /// environment variables from `conda-meta/state` and
+    /// `etc/conda/env_vars.d`.
+    EnvVars,
+
+    /// An activation script from `etc/conda/activate.d`.
+    Script(PathBuf),
+
+    /// The environment variables that are set after the activation scripts.
+    PostActivationEnvVars,
+}
+
+impl Display for ActivationStepSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ActivationStepSource::EnvVars => write!(f, "environment variables of the prefix"),
+            ActivationStepSource::Script(path) => write!(f, "script {}", path.display()),
+            ActivationStepSource::PostActivationEnvVars => {
+                write!(f, "post activation environment variables")
+            }
+        }
+    }
+}
+
+/// The difference between two environments.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvDiff {
+    /// The variables that were added, with their new value.
+    pub added: BTreeMap<String, String>,
+
+    /// The variables whose value changed.
+    pub changed: BTreeMap<String, EnvChange>,
+
+    /// The variables that were removed, with their old value.
+    pub removed: BTreeMap<String, String>,
+}
+
+/// The old and new value of a changed variable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvChange {
+    /// The value before the change.
+    pub old: String,
+
+    /// The value after the change.
+    pub new: String,
+}
+
+impl EnvDiff {
+    /// Computes the changes from `before` to `after`.
+    pub fn new(before: &HashMap<String, String>, after: &HashMap<String, String>) -> Self {
+        let mut diff = Self::default();
+        for (key, new) in after {
+            match before.get(key) {
+                None => {
+                    diff.added.insert(key.clone(), new.clone());
+                }
+                Some(old) if old != new => {
+                    diff.changed.insert(
+                        key.clone(),
+                        EnvChange {
+                            old: old.clone(),
+                            new: new.clone(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, old) in before {
+            if !after.contains_key(key) {
+                diff.removed.insert(key.clone(), old.clone());
+            }
+        }
+        diff
+    }
+
+    /// Returns true if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Returns true if the variable `key` was added, changed or removed.
+    pub fn contains(&self, key: &str) -> bool {
+        self.added.contains_key(key)
+            || self.changed.contains_key(key)
+            || self.removed.contains_key(key)
+    }
+}
+
+impl Display for EnvDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "  (no changes)");
+        }
+        for (key, value) in &self.added {
+            writeln!(f, "  + {key}={value}")?;
+        }
+        for (key, EnvChange { old, new }) in &self.changed {
+            writeln!(f, "  ~ {key}={new} (was {old})")?;
+        }
+        for (key, value) in &self.removed {
+            writeln!(f, "  - {key} (was {value})")?;
+        }
+        Ok(())
+    }
+}
+
+impl ActivationReport {
+    /// Returns the steps that added, changed or removed the variable `key`.
+    pub fn steps_changing<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> impl Iterator<Item = &'a ActivationStep> + 'a {
+        self.steps
+            .iter()
+            .filter(move |step| step.diff.contains(key))
+    }
+
+    /// Renders the report as pretty printed JSON.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Renders the report as human readable text.
+impl Display for ActivationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Activation of {}", self.prefix.display())?;
+        writeln!(f)?;
+        writeln!(f, "PATH entries of the prefix:")?;
+        for entry in &self.path_entries {
+            writeln!(f, "  {}", entry.display())?;
+        }
+        for step in &self.steps {
+            writeln!(f)?;
+            writeln!(f, "Changes by the {}:", step.source)?;
+            write!(f, "{}", step.diff)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! Interactive shell sessions in an activated environment.
+//!
+//! An [`InteractiveShell`] starts the shell of the user in a pseudo terminal,
+//! sources an activation script in it and then hands control to the user,
+//! like `conda activate` or `pixi shell` do. The activation is sourced while
+//! echo is disabled and the output up to the end of the activation is hidden,
+//! so that the user only sees the prompt of the activated shell.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use rattler_conda_types::Platform;
+use rattler_pty::unix::{PtyProcessOptions, PtySession};
+use tempfile::NamedTempFile;
+
+use crate::{
+    activation::{ActivationError, ActivationVariables, Activator},
+    shell::{Shell, ShellError, ShellScript},
+};
+
+/// The text that is echoed by the activation script when it has finished.
+const ACTIVATION_DONE: &str = "RATTLER_SHELL_ACTIVATION_DONE";
+
+/// An error that can occur when starting an interactive shell.
+#[derive(Debug, thiserror::Error)]
+pub enum InteractiveShellError {
+    /// An IO error occurred while starting or talking to the shell.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    /// The activation script could not be generated.
+    #[error(transparent)]
+    ShellError(#[from] ShellError),
+
+    /// The shell did not finish sourcing the activation script in time.
+    #[error("the shell did not finish the activation within {0:?}")]
+    ActivationTimeout(Duration),
+}
+
+/// A builder for an interactive shell in an activated environment.
+///
+/// # Example
+///
+/// ```no_run
+/// use rattler_conda_types::Platform;
+/// use rattler_shell::{
+///     activation::{ActivationVariables, Activator},
+///     interactive::InteractiveShell,
+///     shell::Bash,
+/// };
+///
+/// let activator = Activator::from_path("/opt/env".as_ref(), Bash, Platform::current()).unwrap();
+/// let variables = ActivationVariables::from_env().unwrap();
+/// let exit_code = InteractiveShell::from_activator(&activator, variables)
+///     .unwrap()
+///     .run()
+///     .unwrap();
+/// ```
+pub struct InteractiveShell<T: Shell> {
+    shell: T,
+    activation: ShellScript<T>,
+    executable: Option<PathBuf>,
+    args: Vec<String>,
+    env: HashMap<String, String>,
+    cwd: Option<PathBuf>,
+    activation_timeout: Duration,
+}
+
+impl<T: Shell + Clone + 'static> InteractiveShell<T> {
+    /// Constructs a new interactive shell that sources the given activation
+    /// script.
+    pub fn new(shell: T, activation: ShellScript<T>) -> Self {
+        Self {
+            shell,
+            activation,
+            executable: None,
+            args: Vec::new(),
+            env: HashMap::new(),
+            cwd: None,
+            activation_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Constructs a new interactive shell that activates the prefix of the
+    /// `activator`.
+    pub fn from_activator(
+        activator: &Activator<T>,
+        variables: ActivationVariables,
+    ) -> Result<Self, ActivationError> {
+        let activation = activator.activation(variables)?;
+        Ok(Self::new(activator.shell_type.clone(), activation.script))
+    }
+
+    /// Sets the path of the shell executable. Defaults to
+    /// [`Shell::executable`].
+    #[must_use]
+    pub fn with_executable(mut self, executable: impl Into<PathBuf>) -> Self {
+        self.executable = Some(executable.into());
+        self
+    }
+
+    /// Sets the arguments that are passed to the shell.
+    #[must_use]
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets an environment variable for the shell process, before the
+    /// activation is sourced.
+    #[must_use]
+    pub fn with_env_var(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the working directory of the shell.
+    #[must_use]
+    pub fn with_cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Sets how long to wait for the shell to finish sourcing the activation
+    /// script, including the time it takes to read its startup files.
+    /// Defaults to 10 seconds.
+    #[must_use]
+    pub fn with_activation_timeout(mut self, timeout: Duration) -> Self {
+        self.activation_timeout = timeout;
+        self
+    }
+
+    /// Starts the shell, sources the activation script and hands control to
+    /// the user. Blocks until the shell exits and returns its exit code.
+    pub fn run(self) -> Result<Option<i32>, InteractiveShellError> {
+        Ok(self.spawn()?.interact()?)
+    }
+
+    /// Starts the shell and sources the activation script, without handing
+    /// control to the user yet.
+    pub fn spawn(self) -> Result<ActivatedShell, InteractiveShellError> {
+        // Write the activation script, which turns echo back on and signals
+        // that it is done at the end.
+        let mut script = ShellScript::new(self.shell.clone(), Platform::current());
+        script.append_script(&self.activation);
+        script
+            .run_command(["stty", "echo"])
+            .and_then(|script| script.echo(ACTIVATION_DONE))
+            .map_err(ShellError::from)?;
+
+        let mut activation_script = tempfile::Builder::new()
+            .prefix("rattler_activate_")
+            .suffix(&format!(".{}", self.shell.extension()))
+            .tempfile()?;
+        self.shell
+            .write_script(&mut activation_script, &script.contents()?)?;
+        activation_script.flush()?;
+
+        let executable = self
+            .executable
+            .unwrap_or_else(|| PathBuf::from(self.shell.executable()));
+        let mut command = Command::new(executable);
+        command.args(&self.args).envs(&self.env);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut session = PtySession::with_options(
+            command,
+            PtyProcessOptions {
+                echo: false,
+                ..PtyProcessOptions::default()
+            },
+        )?;
+
+        // A leading space keeps the command out of the history of most shells.
+        session.send_line(&format!(
+            " {}",
+            source_command(&self.shell, activation_script.path())?
+        ))?;
+        if !session.wait_until(ACTIVATION_DONE, self.activation_timeout)? {
+            return Err(InteractiveShellError::ActivationTimeout(
+                self.activation_timeout,
+            ));
+        }
+
+        Ok(ActivatedShell {
+            session,
+            _activation_script: activation_script,
+        })
+    }
+}
+
+/// Returns the command that sources the script at `path` in `shell`.
+fn source_command<T: Shell>(shell: &T, path: &Path) -> Result<String, ShellError> {
+    let mut command = String::new();
+    shell.run_script(&mut command, path)?;
+    Ok(command.trim_end().to_string())
+}
+
+/// A shell in a pseudo terminal that has sourced the activation script.
+pub struct ActivatedShell {
+    session: PtySession,
+    // Kept until the shell exits, the activation script is removed on drop.
+    _activation_script: NamedTempFile,
+}
+
+impl ActivatedShell {
+    /// Returns the pty session of the shell, e.g. to send input to it.
+    pub fn session(&mut self) -> &mut PtySession {
+        &mut self.session
+    }
+
+    /// Hands control to the user: the terminal is put in raw mode, input is
+    /// forwarded to the shell, its output to stdout and changes of the window
+    /// size to the pseudo terminal. Blocks until the shell exits, restores
+    /// the terminal and returns the exit code of the shell.
+    pub fn interact(mut self) -> std::io::Result<Option<i32>> {
+        self.session.interact(None)
+    }
+
+    /// Blocks until the shell exits without handing control to the user and
+    /// returns its exit code.
+    pub fn wait(mut self) -> std::io::Result<Option<i32>> {
+        self.session.wait()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rattler_conda_types::Platform;
+
+    use super::InteractiveShell;
+    use crate::shell::{Bash, ShellScript};
+
+    #[test]
+    fn test_activated_shell() {
+        let mut activation = ShellScript::new(Bash, Platform::current());
+        activation.set_env_var("RATTLER_TEST", "activated").unwrap();
+
+        let mut shell = InteractiveShell::new(Bash, activation)
+            .with_args(["--norc", "--noprofile"])
+            .spawn()
+            .unwrap();
+
+        let session = shell.session();
+        session.send_line("echo value=$RATTLER_TEST").unwrap();
+        assert!(session
+            .wait_until("value=activated", Duration::from_secs(5))
+            .unwrap());
+        session.send_line("exit 3").unwrap();
+        assert_eq!(shell.wait().unwrap(), Some(3));
+    }
+}
@@ -2,6 +2,8 @@
 #![deny(missing_docs)]
 
 pub mod activation;
+#[cfg(all(unix, feature = "interactive"))]
+pub mod interactive;
 pub mod run;
 pub mod shell;
-pub use run::run_in_environment;
+pub use run::{run_in_environment, run_in_environment_with_options};
@@ -1,8 +1,16 @@
 //! Helpers to run commands in an activated environment.
 
 use rattler_conda_types::Platform;
-use std::process::{Command, Output};
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use tempfile::NamedTempFile;
 
 use crate::activation::{ActivationError, PathModificationBehavior};
 use crate::shell::ShellEnum;
@@ -24,6 +32,123 @@ pub enum RunError {
     IoError(#[from] std::io::Error),
 }
 
+/// The standard stream a line of output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    /// The standard output of the process.
+    Stdout,
+    /// The standard error of the process.
+    Stderr,
+}
+
+/// A callback that receives the output of a process line by line.
+///
+/// Lines are passed as raw bytes including the trailing line ending (if
+/// any), so they can be forwarded as is.
+pub type OutputCallback<'a> = Box<dyn FnMut(OutputStream, &[u8]) + 'a>;
+
+/// Determines what happens with the standard streams of the process.
+#[derive(Default)]
+pub enum StdioMode<'a> {
+    /// Collect stdout and stderr in [`RunResult::stdout`] and
+    /// [`RunResult::stderr`].
+    #[default]
+    Capture,
+
+    /// The process inherits stdin, stdout and stderr from the current process.
+    Inherit,
+
+    /// Pass every line of stdout and stderr to the callback as soon as it is
+    /// written by the process.
+    Stream(OutputCallback<'a>),
+}
+
+impl std::fmt::Debug for StdioMode<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StdioMode::Capture => write!(f, "Capture"),
+            StdioMode::Inherit => write!(f, "Inherit"),
+            StdioMode::Stream(_) => write!(f, "Stream"),
+        }
+    }
+}
+
+/// A function that wraps the command that runs the activated script, see
+/// [`RunOptions::wrap_command`].
+pub type CommandWrapper<'a> = Box<dyn FnOnce(Command) -> std::io::Result<Command> + 'a>;
+
+/// Options for [`run_in_environment_with_options`].
+#[derive(Default)]
+pub struct RunOptions<'a> {
+    /// Additional environment variables that are set before the environment
+    /// is activated.
+    pub env_vars: HashMap<String, String>,
+
+    /// The working directory of the process. Defaults to the working
+    /// directory of the current process.
+    pub cwd: Option<PathBuf>,
+
+    /// What happens with the standard streams of the process.
+    pub stdio: StdioMode<'a>,
+
+    /// The process is killed if it does not finish within this duration,
+    /// together with all processes it started. On unix the process is
+    /// started in its own process group when a timeout is set, so it does not
+    /// receive signals from the terminal (e.g. Ctrl+C).
+    pub timeout: Option<Duration>,
+
+    /// Receives the shell command that runs the activated script and returns
+    /// the command that is spawned instead, e.g. to run the shell in a
+    /// sandbox. The working directory and standard streams are configured on
+    /// the returned command. An error is returned as [`RunError::IoError`].
+    pub wrap_command: Option<CommandWrapper<'a>>,
+}
+
+impl std::fmt::Debug for RunOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunOptions")
+            .field("env_vars", &self.env_vars)
+            .field("cwd", &self.cwd)
+            .field("stdio", &self.stdio)
+            .field("timeout", &self.timeout)
+            .field("wrap_command", &self.wrap_command.is_some())
+            .finish()
+    }
+}
+
+/// The result of running a script in an activated environment.
+#[derive(Debug)]
+pub struct RunResult {
+    /// The exit status of the process. If the process timed out this is the
+    /// status of the killed process.
+    pub status: ExitStatus,
+
+    /// True if the process was killed because it exceeded the timeout.
+    pub timed_out: bool,
+
+    /// How long the process ran.
+    pub duration: Duration,
+
+    /// The captured standard output. Only filled with [`StdioMode::Capture`].
+    pub stdout: Vec<u8>,
+
+    /// The captured standard error. Only filled with [`StdioMode::Capture`].
+    pub stderr: Vec<u8>,
+}
+
+impl RunResult {
+    /// Returns true if the process finished in time and exited successfully.
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.status.success()
+    }
+
+    /// Returns the exit code of the process, or `None` if the process was
+    /// terminated by a signal.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+}
+
 /// Execute a script in an activated environment.
 pub fn run_in_environment(
     prefix: &Path,
@@ -31,6 +156,166 @@ pub fn run_in_environment(
     shell: ShellEnum,
     env_vars: &HashMap<String, String>,
 ) -> Result<Output, RunError> {
+    let result = run_in_environment_with_options(
+        prefix,
+        script,
+        shell,
+        RunOptions {
+            env_vars: env_vars.clone(),
+            ..RunOptions::default()
+        },
+    )?;
+    Ok(Output {
+        status: result.status,
+        stdout: result.stdout,
+        stderr: result.stderr,
+    })
+}
+
+/// Execute a script in an activated environment with the given shell.
+///
+/// Unlike [`run_in_environment`] this allows streaming the output of the
+/// script while it runs, setting a working directory and a timeout.
+///
+/// ```rust,no_run
+/// # use std::{io::Write, path::Path, time::Duration};
+/// use rattler_shell::{
+///     run::{run_in_environment_with_options, RunOptions, StdioMode},
+///     shell::{Bash, ShellEnum},
+/// };
+///
+/// let result = run_in_environment_with_options(
+///     Path::new("/path/to/env"),
+///     Path::new("build.sh"),
+///     ShellEnum::Bash(Bash),
+///     RunOptions {
+///         stdio: StdioMode::Stream(Box::new(|_stream, line| {
+///             std::io::stdout().write_all(line).unwrap();
+///         })),
+///         timeout: Some(Duration::from_secs(3600)),
+///         ..RunOptions::default()
+///     },
+/// )
+/// .unwrap();
+/// assert!(result.success());
+/// ```
+pub fn run_in_environment_with_options(
+    prefix: &Path,
+    script: &Path,
+    shell: ShellEnum,
+    options: RunOptions<'_>,
+) -> Result<RunResult, RunError> {
+    let file = write_activated_script(prefix, script, &shell, &options.env_vars)?;
+
+    let mut command = shell.create_run_script_command(file.path());
+    if let Some(wrap_command) = options.wrap_command {
+        command = wrap_command(command)?;
+    }
+    if let Some(cwd) = &options.cwd {
+        command.current_dir(cwd);
+    }
+    match options.stdio {
+        StdioMode::Inherit => {
+            command
+                .stdin(Stdio::inherit())
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit());
+        }
+        StdioMode::Capture | StdioMode::Stream(_) => {
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        }
+    }
+
+    // Start the process in its own process group so that the processes it
+    // starts can be killed together with it.
+    #[cfg(unix)]
+    if options.timeout.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    }
+
+    let start = Instant::now();
+    let deadline = options.timeout.map(|timeout| start + timeout);
+    let mut child = command.spawn()?;
+
+    // Read both pipes on separate threads so that neither of them can fill up
+    // and block the process.
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, OutputStream::Stdout, sender.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, OutputStream::Stderr, sender.clone());
+    }
+    drop(sender);
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut callback = match options.stdio {
+        StdioMode::Stream(callback) => Some(callback),
+        StdioMode::Capture | StdioMode::Inherit => None,
+    };
+    let mut timed_out = false;
+    loop {
+        let message = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(remaining) {
+                    Ok(message) => message,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        timed_out = true;
+                        break;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match receiver.recv() {
+                Ok(message) => message,
+                Err(mpsc::RecvError) => break,
+            },
+        };
+
+        let (stream, line) = message;
+        match &mut callback {
+            Some(callback) => callback(stream, &line),
+            None if stream == OutputStream::Stdout => stdout.extend_from_slice(&line),
+            None => stderr.extend_from_slice(&line),
+        }
+    }
+
+    // All output has been read, or the process timed out while it was still
+    // writing output.
+    let status = if timed_out {
+        None
+    } else {
+        wait_until(&mut child, deadline)?
+    };
+    let status = if let Some(status) = status {
+        status
+    } else {
+        timed_out = true;
+        kill(&mut child)?
+    };
+
+    Ok(RunResult {
+        status,
+        timed_out,
+        duration: start.elapsed(),
+        stdout,
+        stderr,
+    })
+}
+
+/// Writes a script that activates the environment in `prefix` and then runs
+/// `script` to a temporary file.
+fn write_activated_script(
+    prefix: &Path,
+    script: &Path,
+    shell: &ShellEnum,
+    env_vars: &HashMap<String, String>,
+) -> Result<NamedTempFile, RunError> {
     let mut shell_script = shell::ShellScript::new(shell.clone(), Platform::current());
 
     for (k, v) in env_vars.iter() {
@@ -67,13 +352,336 @@ pub fn run_in_environment(
         file.path(),
         shell_script.contents().map_err(ActivationError::from)?,
     )?;
+    Ok(file)
+}
+
+/// Spawns a thread that sends every line that is read from `reader` to
+/// `sender`.
+fn forward_lines(
+    reader: impl Read + Send + 'static,
+    stream: OutputStream,
+    sender: mpsc::Sender<(OutputStream, Vec<u8>)>,
+) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if sender.send((stream, line)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Waits for the child to exit. Returns `None` if the deadline passed first.
+fn wait_until(child: &mut Child, deadline: Option<Instant>) -> std::io::Result<Option<ExitStatus>> {
+    let Some(deadline) = deadline else {
+        return child.wait().map(Some);
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        thread::sleep(remaining.min(Duration::from_millis(10)));
+    }
+}
+
+/// Kills the child and all processes it started and returns the exit status
+/// of the child.
+fn kill(child: &mut Child) -> std::io::Result<ExitStatus> {
+    kill_descendants(child);
+    match child.kill() {
+        // The process might have exited in the meantime.
+        Ok(()) | Err(_) => child.wait(),
+    }
+}
+
+/// Kills the process group of the child, which was started as the leader of
+/// its own group.
+#[cfg(unix)]
+fn kill_descendants(child: &Child) {
+    let Ok(pgid) = libc::pid_t::try_from(child.id()) else {
+        return;
+    };
+    // SAFETY: `kill` has no memory safety requirements. The group still
+    // exists because the child has not been waited for.
+    unsafe {
+        libc::kill(-pgid, libc::SIGKILL);
+    }
+}
+
+/// Kills the process tree of the child.
+#[cfg(windows)]
+fn kill_descendants(child: &Child) {
+    let result = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if let Err(err) = result {
+        tracing::warn!(
+            "failed to kill the processes started by {}: {err}",
+            child.id()
+        );
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn kill_descendants(_child: &Child) {}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use super::{run_in_environment_with_options, RunOptions};
+    #[cfg(unix)]
+    use super::{OutputStream, StdioMode};
+    #[cfg(unix)]
+    use crate::shell::Bash;
+    use crate::shell::{Shell, ShellEnum};
+
+    #[cfg(unix)]
+    fn write_script(dir: &Path, contents: &str) -> std::path::PathBuf {
+        let script = dir.join("script.sh");
+        fs_err::write(&script, contents).unwrap();
+        script
+    }
+
+    /// Runs `script` and `sleep_script`, which are written for `shell`. The
+    /// first script has to print `$FOO` and the working directory to stdout
+    /// and `error` to stderr, the second one has to print `started` and then
+    /// sleep for longer than the timeout.
+    fn check_shell(shell: ShellEnum, script: &str, sleep_script: &str) {
+        let prefix = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = prefix.path().join(format!("{name}.{}", shell.extension()));
+            fs_err::write(&path, contents).unwrap();
+            path
+        };
+
+        let result = run_in_environment_with_options(
+            prefix.path(),
+            &write("script", script),
+            shell.clone(),
+            RunOptions {
+                env_vars: [(String::from("FOO"), String::from("bar"))].into(),
+                cwd: Some(cwd.path().to_path_buf()),
+                ..RunOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result.success(), "{result:?}");
+        let stdout = String::from_utf8(result.stdout).unwrap();
+        let mut lines = stdout.lines().map(str::trim);
+        assert_eq!(lines.next(), Some("bar"));
+        assert_eq!(
+            Path::new(lines.next().unwrap()).canonicalize().unwrap(),
+            cwd.path().canonicalize().unwrap()
+        );
+        assert_eq!(String::from_utf8(result.stderr).unwrap().trim(), "error");
+
+        let result = run_in_environment_with_options(
+            prefix.path(),
+            &write("sleep", sleep_script),
+            shell,
+            RunOptions {
+                timeout: Some(Duration::from_millis(500)),
+                ..RunOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result.timed_out);
+        assert!(!result.success());
+        assert!(result.duration < Duration::from_secs(10));
+        assert_eq!(String::from_utf8(result.stdout).unwrap().trim(), "started");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_capture_and_cwd() {
+        let prefix = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        let script = write_script(
+            prefix.path(),
+            "echo \"$FOO\"\npwd\necho error >&2\nexit 3\n",
+        );
+
+        let result = run_in_environment_with_options(
+            prefix.path(),
+            &script,
+            ShellEnum::Bash(Bash),
+            RunOptions {
+                env_vars: [(String::from("FOO"), String::from("bar"))].into(),
+                cwd: Some(cwd.path().to_path_buf()),
+                ..RunOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result.code(), Some(3));
+        assert!(!result.success());
+        assert!(!result.timed_out);
+        let stdout = String::from_utf8(result.stdout).unwrap();
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some("bar"));
+        assert_eq!(
+            Path::new(lines.next().unwrap()).canonicalize().unwrap(),
+            cwd.path().canonicalize().unwrap()
+        );
+        assert_eq!(result.stderr, b"error\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stream() {
+        let prefix = tempfile::tempdir().unwrap();
+        let script = write_script(prefix.path(), "echo one\necho two >&2\necho three\n");
+
+        let mut lines = Vec::new();
+        let result = run_in_environment_with_options(
+            prefix.path(),
+            &script,
+            ShellEnum::Bash(Bash),
+            RunOptions {
+                stdio: StdioMode::Stream(Box::new(|stream, line| {
+                    lines.push((stream, String::from_utf8_lossy(line).into_owned()));
+                })),
+                ..RunOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result.success());
+        assert!(result.stdout.is_empty());
+        let stdout = lines
+            .iter()
+            .filter(|(stream, _)| *stream == OutputStream::Stdout)
+            .map(|(_, line)| line.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(stdout, vec!["one\n", "three\n"]);
+        assert!(lines.contains(&(OutputStream::Stderr, String::from("two\n"))));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_timeout() {
+        let prefix = tempfile::tempdir().unwrap();
+        let script = write_script(
+            prefix.path(),
+            "echo started\necho $$ > shell.pid\nsleep 30\n",
+        );
+
+        let result = run_in_environment_with_options(
+            prefix.path(),
+            &script,
+            ShellEnum::Bash(Bash),
+            RunOptions {
+                cwd: Some(prefix.path().to_path_buf()),
+                timeout: Some(Duration::from_millis(500)),
+                ..RunOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result.timed_out);
+        assert!(!result.success());
+        assert!(result.duration < Duration::from_secs(10));
+        assert_eq!(result.stdout, b"started\n");
+
+        // The `sleep` that was started by the shell is killed as well. It is
+        // the only other member of the process group of the shell.
+        let pgid = fs_err::read_to_string(prefix.path().join("shell.pid")).unwrap();
+        let alive = || {
+            let output = std::process::Command::new("ps")
+                .args(["-A", "-o", "pgid=,stat="])
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .any(|line| {
+                    let mut fields = line.split_whitespace();
+                    fields.next() == Some(pgid.trim())
+                        && fields.next().is_some_and(|stat| !stat.starts_with('Z'))
+                })
+        };
+        let start = std::time::Instant::now();
+        while alive() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!alive(), "the child of the shell is still running");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_wrap_command() {
+        let prefix = tempfile::tempdir().unwrap();
+        let script = write_script(prefix.path(), "echo \"$0 $1\"\n");
+
+        let result = run_in_environment_with_options(
+            prefix.path(),
+            &script,
+            ShellEnum::Bash(Bash),
+            RunOptions {
+                wrap_command: Some(Box::new(|command| {
+                    let mut wrapped = std::process::Command::new("env");
+                    wrapped
+                        .arg(command.get_program())
+                        .args(command.get_args())
+                        .arg("wrapped");
+                    Ok(wrapped)
+                })),
+                ..RunOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result.success());
+        let stdout = String::from_utf8(result.stdout).unwrap();
+        assert!(stdout.ends_with(" wrapped\n"), "{stdout}");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(not(target_os = "macos"), ignore)]
+    fn test_zsh() {
+        check_shell(
+            crate::shell::Zsh.into(),
+            "echo \"$FOO\"\npwd\necho error >&2\n",
+            "echo started\nsleep 30\n",
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[ignore]
+    fn test_fish() {
+        check_shell(
+            crate::shell::Fish.into(),
+            "echo \"$FOO\"\npwd\necho error >&2\n",
+            "echo started\nsleep 30\n",
+        );
+    }
 
-    match shell {
-        ShellEnum::Bash(_) => Ok(Command::new(shell.executable()).arg(file.path()).output()?),
-        ShellEnum::CmdExe(_) => Ok(Command::new(shell.executable())
-            .arg("/c")
-            .arg(file.path())
-            .output()?),
-        _ => unimplemented!("Unsupported shell: {:?}", shell),
+    #[test]
+    #[cfg(windows)]
+    fn test_cmd() {
+        check_shell(
+            crate::shell::CmdExe.into(),
+            "@echo %FOO%\r\n@cd\r\n@>&2 echo error\r\n",
+            "@echo started\r\n@ping -n 30 127.0.0.1 > NUL\r\n",
+        );
     }
 }
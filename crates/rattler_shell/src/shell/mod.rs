@@ -875,6 +875,273 @@ impl Shell for NuShell {
     }
 }
 
+/// A part of the value of an environment variable, see
+/// [`split_env_var_references`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValuePart<'a> {
+    /// Text that is used as is
+    Literal(&'a str),
+    /// The name of a referenced environment variable
+    Variable(&'a str),
+}
+
+/// Splits `value` into literal text and references to other environment
+/// variables, which are written as `$NAME` or `${NAME}`. A `$` that does not
+/// start a reference is kept as literal text.
+fn split_env_var_references(value: &str) -> Vec<ValuePart<'_>> {
+    let is_name = |name: &str| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+
+    let mut parts = Vec::new();
+    let mut literal_start = 0;
+    let mut search_start = 0;
+    while let Some(offset) = value[search_start..].find('$') {
+        let dollar = search_start + offset;
+        let after = &value[dollar + 1..];
+        // The name of the variable and the length of the reference after the `$`
+        let reference = if let Some(braced) = after.strip_prefix('{') {
+            braced.find('}').map(|end| (&braced[..end], end + 2))
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            Some((&after[..end], end))
+        };
+        match reference {
+            Some((name, len)) if is_name(name) => {
+                if literal_start < dollar {
+                    parts.push(ValuePart::Literal(&value[literal_start..dollar]));
+                }
+                parts.push(ValuePart::Variable(name));
+                search_start = dollar + 1 + len;
+                literal_start = search_start;
+            }
+            _ => search_start = dollar + 1,
+        }
+    }
+    if literal_start < value.len() {
+        parts.push(ValuePart::Literal(&value[literal_start..]));
+    }
+    parts
+}
+
+/// Quotes a string for tcsh/csh using single quotes. Single quotes inside the
+/// string are closed, escaped and reopened.
+fn csh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// A [`Shell`] implementation for the tcsh and csh shells.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tcsh;
+
+impl Shell for Tcsh {
+    fn set_env_var(&self, f: &mut impl Write, env_var: &str, value: &str) -> ShellResult {
+        validate_env_var_name(env_var)?;
+
+        let parts = split_env_var_references(value);
+        if !parts
+            .iter()
+            .any(|part| matches!(part, ValuePart::Variable(_)))
+        {
+            return Ok(writeln!(f, "setenv {env_var} {}", csh_quote(value))?);
+        }
+
+        // Referencing an undefined variable is a fatal error in csh, so the value
+        // is assembled from the referenced variables that are defined.
+        writeln!(f, "set _rattler_value = ''")?;
+        for part in parts {
+            match part {
+                ValuePart::Literal(text) => writeln!(
+                    f,
+                    "set _rattler_value = \"${{_rattler_value}}\"{}",
+                    csh_quote(text)
+                )?,
+                ValuePart::Variable(name) => writeln!(
+                    f,
+                    "if ( $?{name} ) then\n    set _rattler_value = \"${{_rattler_value}}${{{name}}}\"\nendif"
+                )?,
+            }
+        }
+        writeln!(f, "setenv {env_var} \"${{_rattler_value}}\"")?;
+        Ok(writeln!(f, "unset _rattler_value")?)
+    }
+
+    fn unset_env_var(&self, f: &mut impl Write, env_var: &str) -> ShellResult {
+        validate_env_var_name(env_var)?;
+        Ok(writeln!(f, "unsetenv {env_var}")?)
+    }
+
+    fn run_script(&self, f: &mut impl Write, path: &Path) -> ShellResult {
+        Ok(writeln!(
+            f,
+            "source {}",
+            csh_quote(&path.to_string_lossy())
+        )?)
+    }
+
+    fn set_path(
+        &self,
+        f: &mut impl Write,
+        paths: &[PathBuf],
+        modification_behavior: PathModificationBehavior,
+        _platform: &Platform,
+    ) -> ShellResult {
+        // The `path` shell variable is kept in sync with the `PATH` environment
+        // variable by csh. `$path:q` keeps entries with spaces intact.
+        let paths = paths
+            .iter()
+            .map(|path| csh_quote(&path.to_string_lossy()))
+            .join(" ");
+        match modification_behavior {
+            PathModificationBehavior::Replace => Ok(writeln!(f, "set path = ( {paths} )")?),
+            PathModificationBehavior::Prepend => Ok(writeln!(f, "set path = ( {paths} $path:q )")?),
+            PathModificationBehavior::Append => Ok(writeln!(f, "set path = ( $path:q {paths} )")?),
+        }
+    }
+
+    fn path_var(&self, _platform: &Platform) -> &str {
+        "PATH"
+    }
+
+    fn extension(&self) -> &str {
+        "csh"
+    }
+
+    fn executable(&self) -> &str {
+        "tcsh"
+    }
+
+    fn create_run_script_command(&self, path: &Path) -> Command {
+        let mut cmd = Command::new(self.executable());
+        // Don't read the `.tcshrc` of the user.
+        cmd.arg("-f").arg(path);
+        cmd
+    }
+
+    fn echo(&self, f: &mut impl Write, text: &str) -> std::fmt::Result {
+        writeln!(f, "echo {}", csh_quote(text))
+    }
+
+    fn restore_env_var(&self, f: &mut impl Write, key: &str, backup_key: &str) -> ShellResult {
+        validate_env_var_name(key)?;
+        validate_env_var_name(backup_key)?;
+        Ok(writeln!(
+            f,
+            r#"if ( $?{backup_key} ) then
+                setenv {key} "${{{backup_key}}}"
+                unsetenv {backup_key}
+            else
+                unsetenv {key}
+            endif"#
+        )?)
+    }
+}
+
+/// Quotes a string for Elvish using single quotes, in which a single quote is
+/// written as two single quotes.
+fn elvish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// A [`Shell`] implementation for the Elvish shell.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Elvish;
+
+impl Shell for Elvish {
+    fn set_env_var(&self, f: &mut impl Write, env_var: &str, value: &str) -> ShellResult {
+        validate_env_var_name(env_var)?;
+
+        // References to other variables are replaced by the variables of the `E:`
+        // namespace, which are empty if the variable is not defined.
+        let value = match split_env_var_references(value).as_slice() {
+            [] => elvish_quote(""),
+            parts => parts
+                .iter()
+                .map(|part| match part {
+                    ValuePart::Literal(text) => elvish_quote(text),
+                    ValuePart::Variable(name) => format!("$E:{name}"),
+                })
+                .join(""),
+        };
+        Ok(writeln!(f, "set-env {} {value}", elvish_quote(env_var))?)
+    }
+
+    fn unset_env_var(&self, f: &mut impl Write, env_var: &str) -> ShellResult {
+        validate_env_var_name(env_var)?;
+        Ok(writeln!(f, "unset-env {}", elvish_quote(env_var))?)
+    }
+
+    fn run_script(&self, f: &mut impl Write, path: &Path) -> ShellResult {
+        // Elvish has no `source` command, the script is evaluated in the current
+        // namespace instead.
+        Ok(writeln!(
+            f,
+            "eval (slurp < {})",
+            elvish_quote(&path.to_string_lossy())
+        )?)
+    }
+
+    fn set_path(
+        &self,
+        f: &mut impl Write,
+        paths: &[PathBuf],
+        modification_behavior: PathModificationBehavior,
+        _platform: &Platform,
+    ) -> ShellResult {
+        // `$paths` is the list representation of the `PATH` environment variable.
+        let paths = paths
+            .iter()
+            .map(|path| elvish_quote(&path.to_string_lossy()))
+            .join(" ");
+        match modification_behavior {
+            PathModificationBehavior::Replace => Ok(writeln!(f, "set paths = [{paths}]")?),
+            PathModificationBehavior::Prepend => Ok(writeln!(f, "set paths = [{paths} $@paths]")?),
+            PathModificationBehavior::Append => Ok(writeln!(f, "set paths = [$@paths {paths}]")?),
+        }
+    }
+
+    fn format_env_var(&self, var_name: &str) -> String {
+        format!("$E:{var_name}")
+    }
+
+    fn extension(&self) -> &str {
+        "elv"
+    }
+
+    fn executable(&self) -> &str {
+        "elvish"
+    }
+
+    fn create_run_script_command(&self, path: &Path) -> Command {
+        let mut cmd = Command::new(self.executable());
+        cmd.arg(path);
+        cmd
+    }
+
+    fn echo(&self, f: &mut impl Write, text: &str) -> std::fmt::Result {
+        writeln!(f, "echo {}", elvish_quote(text))
+    }
+
+    fn restore_env_var(&self, f: &mut impl Write, key: &str, backup_key: &str) -> ShellResult {
+        validate_env_var_name(key)?;
+        validate_env_var_name(backup_key)?;
+        let key = elvish_quote(key);
+        let backup_key = elvish_quote(backup_key);
+        Ok(writeln!(
+            f,
+            r#"if (has-env {backup_key}) {{
+                set-env {key} (get-env {backup_key})
+                unset-env {backup_key}
+            }} else {{
+                unset-env {key}
+            }}"#
+        )?)
+    }
+}
+
 /// A generic [`Shell`] implementation for concrete shell types.
 #[enum_dispatch]
 #[allow(missing_docs)]
@@ -887,6 +1154,8 @@ pub enum ShellEnum {
     PowerShell,
     Fish,
     NuShell,
+    Tcsh,
+    Elvish,
 }
 
 // The default shell is determined by the current OS.
@@ -959,6 +1228,10 @@ impl ShellEnum {
                 Some(Xonsh.into())
             } else if parent_process_name.contains("fish") {
                 Some(Fish.into())
+            } else if parent_process_name.contains("csh") {
+                Some(Tcsh.into())
+            } else if parent_process_name.contains("elvish") {
+                Some(Elvish.into())
             } else if parent_process_name.contains("nu") {
                 Some(NuShell.into())
             } else if parent_process_name.contains("powershell")
@@ -1009,6 +1282,8 @@ impl FromStr for ShellEnum {
             "fish" => Ok(Fish.into()),
             "cmd" => Ok(CmdExe.into()),
             "nu" | "nushell" => Ok(NuShell.into()),
+            "tcsh" | "csh" => Ok(Tcsh.into()),
+            "elvish" => Ok(Elvish.into()),
             "powershell" | "powershell_ise" => Ok(PowerShell::default().into()),
             _ => Err(ParseShellEnumError(format!(
                 "'{s}' is an unknown shell variant"
@@ -1096,6 +1371,15 @@ impl<T: Shell + 'static> ShellScript<T> {
         Ok(self)
     }
 
+    /// Run a command in the generated shell script.
+    pub fn run_command<'a>(
+        &mut self,
+        command: impl IntoIterator<Item = &'a str> + 'a,
+    ) -> Result<&mut Self, std::fmt::Error> {
+        self.shell.run_command(&mut self.contents, command)?;
+        Ok(self)
+    }
+
     /// Source completion scripts for the shell from a given directory with
     /// completion scripts.
     pub fn source_completions(&mut self, completions_dir: &Path) -> Result<&mut Self, ShellError> {
@@ -1227,6 +1511,99 @@ mod tests {
         insta::assert_snapshot!(script.contents);
     }
 
+    #[test]
+    fn test_tcsh() {
+        let mut script = ShellScript::new(Tcsh, Platform::Linux64);
+
+        let paths = vec![PathBuf::from("bar"), PathBuf::from("a b")];
+
+        script
+            .set_env_var("FOO", "bar")
+            .unwrap()
+            .set_env_var("FOO2", "it's")
+            .unwrap()
+            .set_env_var("FOO3", "${UNEXPANDED_VAR}")
+            .unwrap()
+            .set_env_var("FOO4", "$UNEXPANDED_VAR:$OTHER_VAR/it's")
+            .unwrap()
+            .unset_env_var("FOO")
+            .unwrap()
+            .set_path(&paths, PathModificationBehavior::Append)
+            .unwrap()
+            .set_path(&paths, PathModificationBehavior::Prepend)
+            .unwrap()
+            .set_path(&paths, PathModificationBehavior::Replace)
+            .unwrap()
+            .run_script(&PathBuf::from_str("foo.csh").unwrap())
+            .unwrap();
+
+        insta::assert_snapshot!(script.contents);
+    }
+
+    #[test]
+    fn test_elvish() {
+        let mut script = ShellScript::new(Elvish, Platform::Linux64);
+
+        let paths = vec![PathBuf::from("bar"), PathBuf::from("a b")];
+
+        script
+            .set_env_var("FOO", "bar")
+            .unwrap()
+            .set_env_var("FOO2", "it's")
+            .unwrap()
+            .set_env_var("FOO3", "${UNEXPANDED_VAR}:$OTHER_VAR/it's")
+            .unwrap()
+            .unset_env_var("FOO")
+            .unwrap()
+            .set_path(&paths, PathModificationBehavior::Append)
+            .unwrap()
+            .set_path(&paths, PathModificationBehavior::Prepend)
+            .unwrap()
+            .set_path(&paths, PathModificationBehavior::Replace)
+            .unwrap()
+            .run_script(&PathBuf::from_str("foo.elv").unwrap())
+            .unwrap();
+
+        insta::assert_snapshot!(script.contents);
+    }
+
+    #[test]
+    fn test_split_env_var_references() {
+        use ValuePart::{Literal, Variable};
+
+        assert_eq!(split_env_var_references(""), vec![]);
+        assert_eq!(split_env_var_references("a b"), vec![Literal("a b")]);
+        assert_eq!(
+            split_env_var_references("$FOO:${BAR}/baz"),
+            vec![
+                Variable("FOO"),
+                Literal(":"),
+                Variable("BAR"),
+                Literal("/baz")
+            ]
+        );
+        assert_eq!(
+            split_env_var_references("a$1$ ${b"),
+            vec![Literal("a$1$ ${b")]
+        );
+    }
+
+    #[test]
+    fn test_from_shell_path() {
+        assert!(matches!(
+            ShellEnum::from_shell_path("/bin/tcsh"),
+            Some(ShellEnum::Tcsh(_))
+        ));
+        assert!(matches!(
+            ShellEnum::from_shell_path("/bin/csh"),
+            Some(ShellEnum::Tcsh(_))
+        ));
+        assert!(matches!(
+            ShellEnum::from_shell_path("/usr/local/bin/elvish"),
+            Some(ShellEnum::Elvish(_))
+        ));
+    }
+
     #[cfg(feature = "sysinfo")]
     #[test]
     fn test_from_parent_process_doesnt_crash() {
@@ -0,0 +1,273 @@
+//! Parsing of Sigstore bundles and detached signatures.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+
+use crate::SignatureError;
+
+/// The material that proves that an artifact was signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureMaterial {
+    /// A Sigstore bundle (`<artifact>.sigstore.json`) as created by e.g.
+    /// `cosign sign-blob --bundle`.
+    Bundle(Bundle),
+
+    /// A detached DER encoded ECDSA signature (`<artifact>.sig`) as created by
+    /// e.g. `cosign sign-blob --key`. The signature has to be made with one of
+    /// the public keys of the trust root.
+    Detached(Vec<u8>),
+}
+
+impl SignatureMaterial {
+    /// Parses a detached signature. The signature can either be base64
+    /// encoded, which is what cosign produces, or raw DER.
+    pub fn from_detached(bytes: &[u8]) -> Self {
+        let signature = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|text| STANDARD.decode(text.trim()).ok())
+            .unwrap_or_else(|| bytes.to_vec());
+        Self::Detached(signature)
+    }
+}
+
+/// The parts of a Sigstore bundle that are needed to verify a signature of a
+/// blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    /// The DER encoded certificate chain of the signer, leaf first. Empty if
+    /// the artifact was signed with a key.
+    pub certificates: Vec<Vec<u8>>,
+
+    /// The entries of the transparency log that record the signature.
+    pub log_entries: Vec<LogEntry>,
+
+    /// The sha256 digest of the signed artifact, if the bundle records it.
+    pub message_digest: Option<Vec<u8>>,
+
+    /// The DER encoded ECDSA signature.
+    pub signature: Vec<u8>,
+}
+
+/// An entry of a transparency log (Rekor) that records the signature of a
+/// bundle. The log promises to include the entry with its signed entry
+/// timestamp.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The index of the entry in the log.
+    pub log_index: u64,
+
+    /// The sha256 digest of the public key of the log.
+    pub log_id: Vec<u8>,
+
+    /// The time at which the entry was integrated in the log, in seconds
+    /// since the Unix epoch.
+    pub integrated_time: u64,
+
+    /// The canonicalized body of the entry, which records the signature, the
+    /// signing certificate and the digest of the artifact.
+    pub canonicalized_body: Vec<u8>,
+
+    /// The signature of the log over the entry, if the bundle contains the
+    /// inclusion promise of the log.
+    pub signed_entry_timestamp: Option<Vec<u8>>,
+}
+
+impl Bundle {
+    /// Parses a bundle from its JSON representation.
+    pub fn from_json(json: &str) -> Result<Self, SignatureError> {
+        let bundle: RawBundle = serde_json::from_str(json)
+            .map_err(|err| SignatureError::InvalidBundle(err.to_string()))?;
+
+        let Some(message_signature) = bundle.message_signature else {
+            return Err(SignatureError::InvalidBundle(String::from(
+                "the bundle does not contain a message signature, DSSE envelopes are not supported",
+            )));
+        };
+
+        let material = bundle.verification_material;
+        let certificates = match (material.certificate, material.x509_certificate_chain) {
+            (Some(certificate), _) => vec![certificate],
+            (None, Some(chain)) => chain.certificates,
+            (None, None) => Vec::new(),
+        };
+        let certificates = certificates
+            .into_iter()
+            .map(|certificate| decode(&certificate.raw_bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let log_entries = material
+            .tlog_entries
+            .into_iter()
+            .map(|entry| {
+                Ok(LogEntry {
+                    log_index: entry.log_index,
+                    log_id: decode(&entry.log_id.key_id)?,
+                    integrated_time: entry.integrated_time,
+                    canonicalized_body: decode(&entry.canonicalized_body)?,
+                    signed_entry_timestamp: entry
+                        .inclusion_promise
+                        .map(|promise| decode(&promise.signed_entry_timestamp))
+                        .transpose()?,
+                })
+            })
+            .collect::<Result<Vec<_>, SignatureError>>()?;
+
+        let message_digest = match message_signature.message_digest {
+            Some(digest) if digest.algorithm == "SHA2_256" => Some(decode(&digest.digest)?),
+            Some(digest) => {
+                return Err(SignatureError::InvalidBundle(format!(
+                    "unsupported message digest algorithm '{}'",
+                    digest.algorithm
+                )))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            certificates,
+            log_entries,
+            message_digest,
+            signature: decode(&message_signature.signature)?,
+        })
+    }
+}
+
+fn decode(base64: &str) -> Result<Vec<u8>, SignatureError> {
+    STANDARD
+        .decode(base64)
+        .map_err(|err| SignatureError::InvalidBundle(err.to_string()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBundle {
+    verification_material: VerificationMaterial,
+    message_signature: Option<MessageSignature>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMaterial {
+    certificate: Option<RawCertificate>,
+    x509_certificate_chain: Option<CertificateChain>,
+    #[serde(default)]
+    tlog_entries: Vec<TransparencyLogEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCertificate {
+    raw_bytes: String,
+}
+
+#[derive(Deserialize)]
+struct CertificateChain {
+    certificates: Vec<RawCertificate>,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransparencyLogEntry {
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    log_index: u64,
+    log_id: LogId,
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    integrated_time: u64,
+    inclusion_promise: Option<InclusionPromise>,
+    canonicalized_body: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogId {
+    key_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionPromise {
+    signed_entry_timestamp: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageSignature {
+    message_digest: Option<MessageDigest>,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct MessageDigest {
+    algorithm: String,
+    digest: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bundle, LogEntry, SignatureMaterial};
+    use crate::SignatureError;
+
+    #[test]
+    fn test_parse_bundle() {
+        let bundle = Bundle::from_json(
+            r#"{
+                "mediaType": "application/vnd.dev.sigstore.bundle.v0.3+json",
+                "verificationMaterial": {
+                    "certificate": { "rawBytes": "AQID" },
+                    "tlogEntries": [{
+                        "logIndex": "1",
+                        "logId": { "keyId": "CAk=" },
+                        "integratedTime": "1700000000",
+                        "inclusionPromise": { "signedEntryTimestamp": "Cgs=" },
+                        "canonicalizedBody": "DA0="
+                    }]
+                },
+                "messageSignature": {
+                    "messageDigest": { "algorithm": "SHA2_256", "digest": "BAU=" },
+                    "signature": "Bgc="
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            bundle,
+            Bundle {
+                certificates: vec![vec![1, 2, 3]],
+                log_entries: vec![LogEntry {
+                    log_index: 1,
+                    log_id: vec![8, 9],
+                    integrated_time: 1_700_000_000,
+                    canonicalized_body: vec![12, 13],
+                    signed_entry_timestamp: Some(vec![10, 11]),
+                }],
+                message_digest: Some(vec![4, 5]),
+                signature: vec![6, 7],
+            }
+        );
+
+        let result = Bundle::from_json(
+            r#"{
+                "verificationMaterial": { "publicKey": { "hint": "abc" } },
+                "dsseEnvelope": {}
+            }"#,
+        );
+        assert!(
+            matches!(result, Err(SignatureError::InvalidBundle(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_detached_signature() {
+        assert_eq!(
+            SignatureMaterial::from_detached(b"AQID\n"),
+            SignatureMaterial::Detached(vec![1, 2, 3])
+        );
+        assert_eq!(
+            SignatureMaterial::from_detached(&[0x30, 0x01, 0x00]),
+            SignatureMaterial::Detached(vec![0x30, 0x01, 0x00])
+        );
+    }
+}
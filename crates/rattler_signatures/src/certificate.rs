@@ -0,0 +1,297 @@
+//! Verification of signing certificates and extraction of the identity they
+//! were issued for.
+
+use std::time::{Duration, SystemTime};
+
+use rustls_pki_types::{CertificateDer, SignatureVerificationAlgorithm, UnixTime};
+use webpki::{EndEntityCert, KeyUsage};
+
+use crate::SignatureError;
+
+/// The DER encoded object identifier of the `codeSigning` extended key usage
+/// (1.3.6.1.5.5.7.3.3).
+const CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+
+/// The DER encoded object identifier of the subject alternative name
+/// extension (2.5.29.17).
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// The DER encoded object identifier of the deprecated Fulcio issuer
+/// extension (1.3.6.1.4.1.57264.1.1). Its value is the raw issuer string.
+const FULCIO_ISSUER_V1: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x01];
+
+/// The DER encoded object identifier of the Fulcio issuer extension
+/// (1.3.6.1.4.1.57264.1.8). Its value is a DER encoded `UTF8String`.
+const FULCIO_ISSUER_V2: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x08];
+
+/// The signature algorithms that are accepted for certificates in a chain.
+static SUPPORTED_SIG_ALGS: &[&dyn SignatureVerificationAlgorithm] = &[
+    webpki::ring::ECDSA_P256_SHA256,
+    webpki::ring::ECDSA_P256_SHA384,
+    webpki::ring::ECDSA_P384_SHA256,
+    webpki::ring::ECDSA_P384_SHA384,
+    webpki::ring::ED25519,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA256,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA384,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA512,
+    webpki::ring::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    webpki::ring::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    webpki::ring::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+];
+
+/// The information of a signing certificate that has been verified against
+/// the trust root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningCertificate {
+    /// The DER encoded subject public key info of the certificate.
+    pub public_key: Vec<u8>,
+
+    /// The email addresses and URIs from the subject alternative name
+    /// extension.
+    pub identities: Vec<String>,
+
+    /// The OIDC issuer that authenticated the identity, if the certificate
+    /// was issued by Fulcio.
+    pub issuer: Option<String>,
+}
+
+/// Verifies that `chain[0]` was issued for code signing by one of the
+/// `trusted_certificates`, possibly via the intermediate certificates in the
+/// rest of the chain. The certificates must be valid at `time`.
+pub(crate) fn verify_certificate_chain(
+    chain: &[Vec<u8>],
+    trusted_certificates: &[Vec<u8>],
+    time: SystemTime,
+) -> Result<SigningCertificate, SignatureError> {
+    let (leaf, intermediates) = chain.split_first().ok_or_else(|| {
+        SignatureError::InvalidCertificate(String::from("the certificate chain is empty"))
+    })?;
+    let leaf = CertificateDer::from(leaf.as_slice());
+    let end_entity = EndEntityCert::try_from(&leaf)
+        .map_err(|err| SignatureError::InvalidCertificate(err.to_string()))?;
+
+    let trusted_certificates = trusted_certificates
+        .iter()
+        .map(|der| CertificateDer::from(der.as_slice()))
+        .collect::<Vec<_>>();
+    let trust_anchors = trusted_certificates
+        .iter()
+        .filter_map(|der| webpki::anchor_from_trusted_cert(der).ok())
+        .collect::<Vec<_>>();
+    let intermediates = intermediates
+        .iter()
+        .map(|der| CertificateDer::from(der.as_slice()))
+        .collect::<Vec<_>>();
+
+    let time = UnixTime::since_unix_epoch(
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO),
+    );
+    end_entity
+        .verify_for_usage(
+            SUPPORTED_SIG_ALGS,
+            &trust_anchors,
+            &intermediates,
+            time,
+            KeyUsage::required(CODE_SIGNING),
+            None,
+            None,
+        )
+        .map_err(|err| SignatureError::UntrustedCertificate(err.to_string()))?;
+
+    let (identities, issuer) = parse_identity(&leaf).ok_or_else(|| {
+        SignatureError::InvalidCertificate(String::from(
+            "could not read the extensions of the certificate",
+        ))
+    })?;
+
+    Ok(SigningCertificate {
+        public_key: end_entity.subject_public_key_info().as_ref().to_vec(),
+        identities,
+        issuer,
+    })
+}
+
+/// Reads the subject alternative names and the Fulcio issuer from the
+/// extensions of a DER encoded certificate.
+fn parse_identity(certificate: &[u8]) -> Option<(Vec<String>, Option<String>)> {
+    let mut certificate = Der::new(certificate).expect(SEQUENCE)?;
+    let mut tbs_certificate = certificate.expect(SEQUENCE)?;
+
+    // Skip the version, serial number, signature algorithm, issuer, validity,
+    // subject and subject public key info.
+    let mut extensions = None;
+    while !tbs_certificate.is_empty() {
+        let (tag, value) = tbs_certificate.read()?;
+        if tag == EXTENSIONS {
+            extensions = Some(Der::new(value).expect(SEQUENCE)?);
+        }
+    }
+
+    let mut identities = Vec::new();
+    let mut issuer = None;
+    let Some(mut extensions) = extensions else {
+        return Some((identities, issuer));
+    };
+    while !extensions.is_empty() {
+        let mut extension = extensions.expect(SEQUENCE)?;
+        let oid = extension.expect_value(OBJECT_IDENTIFIER)?;
+        let (mut tag, mut value) = extension.read()?;
+        if tag == BOOLEAN {
+            (tag, value) = extension.read()?;
+        }
+        if tag != OCTET_STRING {
+            return None;
+        }
+
+        match oid {
+            SUBJECT_ALT_NAME => {
+                let mut names = Der::new(value).expect(SEQUENCE)?;
+                while !names.is_empty() {
+                    let (tag, name) = names.read()?;
+                    if tag == RFC822_NAME || tag == UNIFORM_RESOURCE_IDENTIFIER {
+                        identities.push(String::from_utf8(name.to_vec()).ok()?);
+                    }
+                }
+            }
+            FULCIO_ISSUER_V2 => {
+                let value = Der::new(value).expect_value(UTF8_STRING)?;
+                issuer = Some(String::from_utf8(value.to_vec()).ok()?);
+            }
+            FULCIO_ISSUER_V1 if issuer.is_none() => {
+                issuer = Some(String::from_utf8(value.to_vec()).ok()?);
+            }
+            _ => {}
+        }
+    }
+
+    Some((identities, issuer))
+}
+
+const BOOLEAN: u8 = 0x01;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0c;
+const SEQUENCE: u8 = 0x30;
+const RFC822_NAME: u8 = 0x81;
+const UNIFORM_RESOURCE_IDENTIFIER: u8 = 0x86;
+const EXTENSIONS: u8 = 0xa3;
+
+/// A minimal reader for the DER encoded values in a certificate.
+struct Der<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Reads the next tag and value. Returns `None` at the end of the input or
+    /// if the input is malformed.
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.input.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (len, rest) = if first < 0x80 {
+            (usize::from(first), rest)
+        } else {
+            let len_bytes = usize::from(first & 0x7f);
+            if len_bytes == 0 || len_bytes > std::mem::size_of::<usize>() || rest.len() < len_bytes
+            {
+                return None;
+            }
+            let (len, rest) = rest.split_at(len_bytes);
+            let len = len
+                .iter()
+                .fold(0usize, |len, &byte| (len << 8) | usize::from(byte));
+            (len, rest)
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        self.input = rest;
+        Some((tag, value))
+    }
+
+    /// Reads the next value and checks that it has the expected tag.
+    fn expect_value(&mut self, expected_tag: u8) -> Option<&'a [u8]> {
+        let (tag, value) = self.read()?;
+        (tag == expected_tag).then_some(value)
+    }
+
+    /// Reads the next value as a nested reader.
+    fn expect(&mut self, expected_tag: u8) -> Option<Der<'a>> {
+        self.expect_value(expected_tag).map(Der::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::verify_certificate_chain;
+    use crate::{trust_root::parse_pem, SignatureError};
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/signatures")
+            .join(name);
+        let pem = fs_err::read_to_string(path).unwrap();
+        parse_pem(&pem).unwrap().remove(0).1
+    }
+
+    /// A time at which `leaf.pem` is valid.
+    fn signed_at() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_735_689_900)
+    }
+
+    #[test]
+    fn test_verify_certificate_chain() {
+        let certificate =
+            verify_certificate_chain(&[fixture("leaf.pem")], &[fixture("ca.pem")], signed_at())
+                .unwrap();
+        assert_eq!(certificate.identities, vec!["publisher@example.com"]);
+        assert_eq!(
+            certificate.issuer.as_deref(),
+            Some("https://issuer.example.com")
+        );
+    }
+
+    #[test]
+    fn test_untrusted_certificate() {
+        // The leaf is not a trust anchor for itself.
+        let result =
+            verify_certificate_chain(&[fixture("leaf.pem")], &[fixture("leaf.pem")], signed_at());
+        assert!(
+            matches!(result, Err(SignatureError::UntrustedCertificate(_))),
+            "{result:?}"
+        );
+
+        // The certificate is not valid yet.
+        let result = verify_certificate_chain(
+            &[fixture("leaf.pem")],
+            &[fixture("ca.pem")],
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        );
+        assert!(
+            matches!(result, Err(SignatureError::UntrustedCertificate(_))),
+            "{result:?}"
+        );
+
+        // The certificate expired ten minutes after it was issued.
+        let result = verify_certificate_chain(
+            &[fixture("leaf.pem")],
+            &[fixture("ca.pem")],
+            SystemTime::now(),
+        );
+        assert!(
+            matches!(result, Err(SignatureError::UntrustedCertificate(_))),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+#![deny(missing_docs)]
+
+//! Verification of signatures of conda artifacts, i.e. package archives and
+//! `repodata.json` files.
+//!
+//! The signature of an artifact is looked up next to it: first a Sigstore
+//! bundle at `<artifact>.sigstore.json` (as created by
+//! `cosign sign-blob --bundle`) and otherwise a detached signature at
+//! `<artifact>.sig` (as created by `cosign sign-blob --key`). A
+//! [`SignatureVerifier`] checks the signature against a [`TrustRoot`] of
+//! public keys and certificate authorities and, for certificate based
+//! signatures, against an [`IdentityPolicy`].
+//!
+//! Only ECDSA P-256 signatures over the sha256 digest of the artifact are
+//! supported, which is what cosign produces by default. Signatures that come
+//! with a short-lived certificate (keyless signing) must also be recorded in a
+//! transparency log (Rekor) whose key is part of the trust root. The signed
+//! entry timestamp of the log is verified, and the certificate must have been
+//! valid at the time the entry was integrated in the log. Inclusion proofs
+//! are not verified.
+
+mod bundle;
+mod certificate;
+mod transparency_log;
+mod trust_root;
+mod verifier;
+
+pub use bundle::{Bundle, LogEntry, SignatureMaterial};
+pub use certificate::SigningCertificate;
+pub use trust_root::{IdentityPolicy, TrustRoot};
+pub use verifier::SignatureVerifier;
+
+use simple_spawn_blocking::Cancelled;
+
+/// An error that can occur while verifying the signature of an artifact.
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    /// Neither a Sigstore bundle nor a detached signature was found for the
+    /// artifact.
+    #[error("no signature found for {0}")]
+    MissingSignature(String),
+
+    /// The signature of the artifact could not be fetched.
+    #[error("failed to fetch the signature from {0}")]
+    FetchError(String, #[source] reqwest_middleware::Error),
+
+    /// A file could not be read.
+    #[error("failed to read {0}")]
+    IoError(String, #[source] std::io::Error),
+
+    /// The Sigstore bundle could not be parsed.
+    #[error("invalid sigstore bundle: {0}")]
+    InvalidBundle(String),
+
+    /// A key of the trust root could not be parsed.
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+
+    /// The key is not an ECDSA P-256 key.
+    #[error("unsupported key: {0}")]
+    UnsupportedKey(String),
+
+    /// A certificate could not be parsed.
+    #[error("invalid certificate: {0}")]
+    InvalidCertificate(String),
+
+    /// The signing certificate does not chain up to a trusted certificate
+    /// authority or was not valid at the time of signing.
+    #[error("the signing certificate is not trusted: {0}")]
+    UntrustedCertificate(String),
+
+    /// The bundle of a certificate based signature has no transparency log
+    /// entry that is signed by a trusted log and records the signature.
+    #[error("the transparency log entry of the signature cannot be verified: {0}")]
+    UnverifiedLogEntry(String),
+
+    /// The signing certificate was issued for a different identity than the
+    /// one that is required by the [`IdentityPolicy`].
+    #[error(
+        "the artifact was signed by '{}' (issuer: {}) but '{expected}' is required",
+        .identities.join(", "),
+        .issuer.as_deref().unwrap_or("unknown")
+    )]
+    IdentityMismatch {
+        /// The identity that is required by the policy.
+        expected: String,
+        /// The identities the certificate was issued for.
+        identities: Vec<String>,
+        /// The issuer that authenticated the identities.
+        issuer: Option<String>,
+    },
+
+    /// The artifact was signed with a certificate but the verifier has no
+    /// [`IdentityPolicy`] to check the identity of the signer against.
+    #[error("the artifact was signed with a certificate but no identity policy is configured")]
+    MissingIdentityPolicy,
+
+    /// The digest that was signed is not the digest of the artifact.
+    #[error("the signed digest {expected} does not match the digest of the artifact {actual}")]
+    DigestMismatch {
+        /// The digest recorded in the bundle.
+        expected: String,
+        /// The digest of the artifact.
+        actual: String,
+    },
+
+    /// The signature is not a valid signature of the artifact by a trusted key
+    /// or certificate.
+    #[error("the signature is invalid")]
+    InvalidSignature,
+
+    /// The operation was cancelled.
+    #[error("the operation was cancelled")]
+    Cancelled,
+}
+
+impl From<Cancelled> for SignatureError {
+    fn from(_: Cancelled) -> Self {
+        Self::Cancelled
+    }
+}
@@ -0,0 +1,149 @@
+//! Verification of the transparency log (Rekor) entries of Sigstore bundles.
+//!
+//! Sigstore certificates are only valid for a few minutes. A signature is
+//! accepted later on if the transparency log promises, with its signed entry
+//! timestamp, that it recorded the signature while the certificate was valid.
+
+use std::time::{Duration, SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::EncodePublicKey,
+    PublicKey,
+};
+use rattler_digest::{compute_bytes_digest, Sha256, Sha256Hash};
+use serde::{Deserialize, Serialize};
+
+use crate::{bundle::LogEntry, trust_root::parse_pem, SignatureError};
+
+/// Verifies that one of the `log_keys` signed the entry and that the entry
+/// records `signature` of the artifact with the digest `sha256` made with the
+/// key of `certificate`. Returns the time at which the entry was integrated
+/// in the log.
+pub(crate) fn verify_log_entry(
+    entry: &LogEntry,
+    log_keys: &[PublicKey],
+    certificate: &[u8],
+    signature: &[u8],
+    sha256: &Sha256Hash,
+) -> Result<SystemTime, SignatureError> {
+    let unverified = |reason: &str| SignatureError::UnverifiedLogEntry(reason.to_string());
+
+    let Some(signed_entry_timestamp) = &entry.signed_entry_timestamp else {
+        return Err(unverified(
+            "the entry has no signed entry timestamp, inclusion proofs are not supported",
+        ));
+    };
+    let log_key = log_keys
+        .iter()
+        .find(|key| log_id(key).as_deref() == Some(entry.log_id.as_slice()))
+        .ok_or_else(|| unverified("the entry was not recorded by a trusted log"))?;
+
+    // The log signs the canonical JSON of the entry, i.e. with sorted keys and
+    // without whitespace.
+    let payload = serde_json::to_vec(&SignedEntry {
+        body: STANDARD.encode(&entry.canonicalized_body),
+        integrated_time: entry.integrated_time,
+        log_id: entry
+            .log_id
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+        log_index: entry.log_index,
+    })
+    .expect("the signed entry can always be serialized");
+    let signed_entry_timestamp = Signature::from_der(signed_entry_timestamp)
+        .map_err(|_err| unverified("the signed entry timestamp is invalid"))?;
+    VerifyingKey::from(log_key)
+        .verify(&payload, &signed_entry_timestamp)
+        .map_err(|_err| unverified("the signed entry timestamp is invalid"))?;
+
+    // The entry must record this signature, otherwise the timestamp of any
+    // entry of the log could be used.
+    let body: HashedRekord = serde_json::from_slice(&entry.canonicalized_body)
+        .map_err(|err| unverified(&format!("the entry is invalid: {err}")))?;
+    if body.kind != "hashedrekord" {
+        return Err(unverified(&format!(
+            "entries of kind '{}' are not supported",
+            body.kind
+        )));
+    }
+    let hash = &body.spec.data.hash;
+    if hash.algorithm != "sha256" || hash.value != format!("{sha256:x}") {
+        return Err(unverified("the entry records a different artifact"));
+    }
+    if STANDARD
+        .decode(&body.spec.signature.content)
+        .ok()
+        .as_deref()
+        != Some(signature)
+    {
+        return Err(unverified("the entry records a different signature"));
+    }
+    let recorded_certificate = STANDARD
+        .decode(&body.spec.signature.public_key.content)
+        .ok()
+        .and_then(|pem| String::from_utf8(pem).ok())
+        .and_then(|pem| parse_pem(&pem).ok())
+        .and_then(|mut blocks| (!blocks.is_empty()).then(|| blocks.remove(0).1));
+    if recorded_certificate.as_deref() != Some(certificate) {
+        return Err(unverified("the entry records a different certificate"));
+    }
+
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(entry.integrated_time))
+}
+
+/// Returns the id of the log with the given key, which is the sha256 digest
+/// of its DER encoded public key.
+fn log_id(key: &PublicKey) -> Option<Vec<u8>> {
+    let der = key.to_public_key_der().ok()?;
+    Some(compute_bytes_digest::<Sha256>(der.as_bytes()).to_vec())
+}
+
+/// The entry that is signed by the log. The fields are sorted by name.
+#[derive(Serialize)]
+struct SignedEntry {
+    body: String,
+    #[serde(rename = "integratedTime")]
+    integrated_time: u64,
+    #[serde(rename = "logID")]
+    log_id: String,
+    #[serde(rename = "logIndex")]
+    log_index: u64,
+}
+
+#[derive(Deserialize)]
+struct HashedRekord {
+    kind: String,
+    spec: HashedRekordSpec,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordSpec {
+    data: HashedRekordData,
+    signature: HashedRekordSignature,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordData {
+    hash: HashedRekordHash,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordHash {
+    algorithm: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HashedRekordSignature {
+    content: String,
+    public_key: HashedRekordPublicKey,
+}
+
+#[derive(Deserialize)]
+struct HashedRekordPublicKey {
+    content: String,
+}
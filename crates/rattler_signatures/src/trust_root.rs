@@ -0,0 +1,267 @@
+//! Defines the keys and certificates that signatures are verified against.
+
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use p256::{pkcs8::DecodePublicKey, PublicKey};
+use rustls_pki_types::CertificateDer;
+
+use crate::SignatureError;
+
+/// The keys and certificate authorities that are trusted to sign artifacts.
+///
+/// Signatures that are made with one of the [public keys](Self::add_public_key_der)
+/// are accepted as is. Signatures that come with a certificate are accepted
+/// if the certificate chains up to one of the
+/// [certificate authorities](Self::add_certificate_der), e.g. the Sigstore
+/// Fulcio root, matches the [`IdentityPolicy`] and was recorded in one of the
+/// [transparency logs](Self::add_transparency_log_key_der), e.g. Rekor.
+#[derive(Debug, Clone, Default)]
+pub struct TrustRoot {
+    pub(crate) public_keys: Vec<PublicKey>,
+    pub(crate) certificates: Vec<Vec<u8>>,
+    pub(crate) transparency_log_keys: Vec<PublicKey>,
+}
+
+impl TrustRoot {
+    /// Constructs an empty trust root that trusts nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a trust root from all `PUBLIC KEY` and `CERTIFICATE` blocks
+    /// in a PEM document.
+    pub fn from_pem(pem: &str) -> Result<Self, SignatureError> {
+        let mut trust_root = Self::new();
+        trust_root.add_pem(pem)?;
+        Ok(trust_root)
+    }
+
+    /// Constructs a trust root from a PEM file. See [`Self::from_pem`].
+    pub fn from_pem_file(path: impl AsRef<Path>) -> Result<Self, SignatureError> {
+        let path = path.as_ref();
+        let pem = fs_err::read_to_string(path)
+            .map_err(|err| SignatureError::IoError(path.display().to_string(), err))?;
+        Self::from_pem(&pem)
+    }
+
+    /// Adds all `PUBLIC KEY` and `CERTIFICATE` blocks in a PEM document.
+    pub fn add_pem(&mut self, pem: &str) -> Result<&mut Self, SignatureError> {
+        let blocks = parse_pem(pem)?;
+        if blocks.is_empty() {
+            return Err(SignatureError::InvalidKey(String::from(
+                "the document does not contain any PEM blocks",
+            )));
+        }
+        for (label, der) in blocks {
+            match label.as_str() {
+                "PUBLIC KEY" => self.add_public_key_der(&der)?,
+                "CERTIFICATE" => self.add_certificate_der(der)?,
+                _ => {
+                    return Err(SignatureError::InvalidKey(format!(
+                        "unsupported PEM block '{label}'"
+                    )))
+                }
+            };
+        }
+        Ok(self)
+    }
+
+    /// Adds a trusted public key from its DER encoded subject public key info.
+    /// Only ECDSA P-256 keys are supported.
+    pub fn add_public_key_der(&mut self, der: &[u8]) -> Result<&mut Self, SignatureError> {
+        let key = PublicKey::from_public_key_der(der)
+            .map_err(|err| SignatureError::UnsupportedKey(err.to_string()))?;
+        self.public_keys.push(key);
+        Ok(self)
+    }
+
+    /// Adds a trusted certificate authority from its DER encoded certificate.
+    pub fn add_certificate_der(&mut self, der: Vec<u8>) -> Result<&mut Self, SignatureError> {
+        webpki::anchor_from_trusted_cert(&CertificateDer::from(der.as_slice()))
+            .map_err(|err| SignatureError::InvalidCertificate(err.to_string()))?;
+        self.certificates.push(der);
+        Ok(self)
+    }
+
+    /// Adds the public key of a trusted transparency log from its DER encoded
+    /// subject public key info. Only ECDSA P-256 keys are supported, which is
+    /// what Rekor uses.
+    pub fn add_transparency_log_key_der(
+        &mut self,
+        der: &[u8],
+    ) -> Result<&mut Self, SignatureError> {
+        let key = PublicKey::from_public_key_der(der)
+            .map_err(|err| SignatureError::UnsupportedKey(err.to_string()))?;
+        self.transparency_log_keys.push(key);
+        Ok(self)
+    }
+
+    /// Adds the `PUBLIC KEY` blocks of a PEM document as keys of trusted
+    /// transparency logs. See [`Self::add_transparency_log_key_der`].
+    pub fn add_transparency_log_pem(&mut self, pem: &str) -> Result<&mut Self, SignatureError> {
+        let blocks = parse_pem(pem)?;
+        if blocks.is_empty() {
+            return Err(SignatureError::InvalidKey(String::from(
+                "the document does not contain any PEM blocks",
+            )));
+        }
+        for (label, der) in blocks {
+            if label != "PUBLIC KEY" {
+                return Err(SignatureError::InvalidKey(format!(
+                    "unsupported PEM block '{label}'"
+                )));
+            }
+            self.add_transparency_log_key_der(&der)?;
+        }
+        Ok(self)
+    }
+
+    /// Returns true if the trust root does not trust any key or certificate.
+    pub fn is_empty(&self) -> bool {
+        self.public_keys.is_empty() && self.certificates.is_empty()
+    }
+}
+
+/// Restricts which identities are allowed to sign artifacts with a
+/// certificate.
+///
+/// Sigstore issues short-lived certificates for identities that are
+/// authenticated by an OIDC provider. The identity (an email address or a URI,
+/// e.g. of a CI workflow) is stored in the subject alternative name and the
+/// provider in a Fulcio specific extension of the certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityPolicy {
+    identity: String,
+    issuer: Option<String>,
+}
+
+impl IdentityPolicy {
+    /// Only accept certificates that were issued for the given identity.
+    pub fn new(identity: impl Into<String>) -> Self {
+        Self {
+            identity: identity.into(),
+            issuer: None,
+        }
+    }
+
+    /// Additionally requires that the identity was authenticated by the given
+    /// OIDC issuer, e.g. `https://token.actions.githubusercontent.com`.
+    #[must_use]
+    pub fn with_issuer(self, issuer: impl Into<String>) -> Self {
+        Self {
+            issuer: Some(issuer.into()),
+            ..self
+        }
+    }
+
+    /// Returns the identity that is allowed to sign.
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// Returns the issuer that must have authenticated the identity.
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// Returns true if one of the `identities` was authenticated by the
+    /// required issuer.
+    pub(crate) fn matches(&self, identities: &[String], issuer: Option<&str>) -> bool {
+        identities.iter().any(|identity| identity == &self.identity)
+            && self
+                .issuer
+                .as_deref()
+                .is_none_or(|expected| Some(expected) == issuer)
+    }
+}
+
+/// Parses the blocks of a PEM document into their labels and DER contents.
+pub(crate) fn parse_pem(pem: &str) -> Result<Vec<(String, Vec<u8>)>, SignatureError> {
+    let mut blocks = Vec::new();
+    let mut lines = pem.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|line| line.strip_suffix("-----"))
+        else {
+            continue;
+        };
+        let end = format!("-----END {label}-----");
+        let mut contents = String::new();
+        loop {
+            match lines.next() {
+                Some(line) if line == end => break,
+                Some(line) => contents.push_str(line),
+                None => {
+                    return Err(SignatureError::InvalidKey(format!(
+                        "the PEM block '{label}' is not terminated"
+                    )))
+                }
+            }
+        }
+        let der = STANDARD.decode(contents).map_err(|err| {
+            SignatureError::InvalidKey(format!("the PEM block '{label}' is invalid: {err}"))
+        })?;
+        blocks.push((label.to_string(), der));
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{IdentityPolicy, TrustRoot};
+    use crate::SignatureError;
+
+    #[test]
+    fn test_from_pem() {
+        let signatures = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data/signatures");
+        let trust_root = TrustRoot::from_pem_file(signatures.join("ca.pem")).unwrap();
+        assert_eq!(trust_root.certificates.len(), 1);
+        assert!(trust_root.public_keys.is_empty());
+
+        let mut trust_root = TrustRoot::new();
+        trust_root
+            .add_transparency_log_pem(
+                &fs_err::read_to_string(signatures.join("rekor.pem")).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(trust_root.transparency_log_keys.len(), 1);
+        assert!(trust_root.public_keys.is_empty());
+        let result = TrustRoot::new()
+            .add_transparency_log_pem(&fs_err::read_to_string(signatures.join("ca.pem")).unwrap())
+            .map(|_| ());
+        assert!(
+            matches!(result, Err(SignatureError::InvalidKey(_))),
+            "{result:?}"
+        );
+
+        // Private keys are not accepted.
+        let result = TrustRoot::from_pem_file(signatures.join("leaf-key.pem"));
+        assert!(
+            matches!(result, Err(SignatureError::InvalidKey(_))),
+            "{result:?}"
+        );
+
+        let result = TrustRoot::from_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n");
+        assert!(
+            matches!(result, Err(SignatureError::InvalidKey(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_identity_policy() {
+        let identities = vec![String::from("publisher@example.com")];
+        let policy = IdentityPolicy::new("publisher@example.com");
+        assert!(policy.matches(&identities, None));
+        assert!(!policy.matches(&[], None));
+
+        let policy = policy.with_issuer("https://issuer.example.com");
+        assert!(policy.matches(&identities, Some("https://issuer.example.com")));
+        assert!(!policy.matches(&identities, Some("https://other.example.com")));
+        assert!(!policy.matches(&identities, None));
+    }
+}
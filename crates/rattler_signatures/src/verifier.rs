@@ -0,0 +1,583 @@
+use std::{path::Path, sync::Arc, time::SystemTime};
+
+use p256::{
+    ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    PublicKey,
+};
+use rattler_digest::{Sha256, Sha256Hash};
+use rattler_redaction::Redact;
+use reqwest::StatusCode;
+use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
+
+use crate::{
+    certificate::verify_certificate_chain, transparency_log::verify_log_entry, Bundle,
+    IdentityPolicy, SignatureError, SignatureMaterial, TrustRoot,
+};
+
+/// Verifies the signatures of artifacts against a [`TrustRoot`].
+///
+/// Signatures that come with a certificate are only accepted if an
+/// [`IdentityPolicy`] is configured, otherwise any identity that the
+/// certificate authority vouches for would be trusted. The bundle must also
+/// contain an entry of a trusted transparency log that records the signature,
+/// and the certificate chain must have been valid when the entry was
+/// integrated in the log.
+///
+/// Cloning a verifier is cheap.
+#[derive(Debug, Clone)]
+pub struct SignatureVerifier {
+    trust_root: Arc<TrustRoot>,
+    identity_policy: Option<IdentityPolicy>,
+}
+
+impl SignatureVerifier {
+    /// Constructs a new verifier that accepts signatures made by the keys and
+    /// certificate authorities of the trust root.
+    pub fn new(trust_root: TrustRoot) -> Self {
+        Self {
+            trust_root: Arc::new(trust_root),
+            identity_policy: None,
+        }
+    }
+
+    /// Only accept certificate based signatures from the identity of the
+    /// policy.
+    #[must_use]
+    pub fn with_identity_policy(self, identity_policy: IdentityPolicy) -> Self {
+        Self {
+            identity_policy: Some(identity_policy),
+            ..self
+        }
+    }
+
+    /// Returns the trust root of the verifier.
+    pub fn trust_root(&self) -> &TrustRoot {
+        &self.trust_root
+    }
+
+    /// Returns the identity policy of the verifier.
+    pub fn identity_policy(&self) -> Option<&IdentityPolicy> {
+        self.identity_policy.as_ref()
+    }
+
+    /// Verifies that `signature` is a valid signature of an artifact with the
+    /// given sha256 digest.
+    pub fn verify_digest(
+        &self,
+        sha256: &Sha256Hash,
+        signature: &SignatureMaterial,
+    ) -> Result<(), SignatureError> {
+        match signature {
+            SignatureMaterial::Detached(signature) => self.verify_with_keys(sha256, signature),
+            SignatureMaterial::Bundle(bundle) => self.verify_bundle(sha256, bundle),
+        }
+    }
+
+    fn verify_bundle(&self, sha256: &Sha256Hash, bundle: &Bundle) -> Result<(), SignatureError> {
+        if let Some(digest) = &bundle.message_digest {
+            if digest.as_slice() != sha256.as_slice() {
+                return Err(SignatureError::DigestMismatch {
+                    expected: digest.iter().map(|byte| format!("{byte:02x}")).collect(),
+                    actual: format!("{sha256:x}"),
+                });
+            }
+        }
+
+        if bundle.certificates.is_empty() {
+            return self.verify_with_keys(sha256, &bundle.signature);
+        }
+
+        // Sigstore certificates expire minutes after they were issued, so the
+        // chain is validated at the time the log recorded the signature.
+        let signed_at = self.verify_log_entries(sha256, bundle)?;
+        let certificate = verify_certificate_chain(
+            &bundle.certificates,
+            &self.trust_root.certificates,
+            signed_at,
+        )?;
+        let policy = self
+            .identity_policy
+            .as_ref()
+            .ok_or(SignatureError::MissingIdentityPolicy)?;
+        if !policy.matches(&certificate.identities, certificate.issuer.as_deref()) {
+            return Err(SignatureError::IdentityMismatch {
+                expected: policy.identity().to_string(),
+                identities: certificate.identities,
+                issuer: certificate.issuer,
+            });
+        }
+
+        let key = PublicKey::from_public_key_der(&certificate.public_key)
+            .map_err(|err| SignatureError::UnsupportedKey(err.to_string()))?;
+        verify_prehash(&key, sha256, &bundle.signature)
+    }
+
+    /// Returns the time at which a trusted transparency log recorded the
+    /// signature of the bundle.
+    fn verify_log_entries(
+        &self,
+        sha256: &Sha256Hash,
+        bundle: &Bundle,
+    ) -> Result<SystemTime, SignatureError> {
+        if self.trust_root.transparency_log_keys.is_empty() {
+            return Err(SignatureError::UnverifiedLogEntry(String::from(
+                "the trust root does not contain a transparency log key",
+            )));
+        }
+        let mut result = Err(SignatureError::UnverifiedLogEntry(String::from(
+            "the bundle does not contain a transparency log entry",
+        )));
+        for entry in &bundle.log_entries {
+            result = verify_log_entry(
+                entry,
+                &self.trust_root.transparency_log_keys,
+                &bundle.certificates[0],
+                &bundle.signature,
+                sha256,
+            );
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn verify_with_keys(
+        &self,
+        sha256: &Sha256Hash,
+        signature: &[u8],
+    ) -> Result<(), SignatureError> {
+        if self
+            .trust_root
+            .public_keys
+            .iter()
+            .any(|key| verify_prehash(key, sha256, signature).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(SignatureError::InvalidSignature)
+        }
+    }
+
+    /// Fetches the signature of the artifact at `url`.
+    ///
+    /// A Sigstore bundle at `<url>.sigstore.json` takes precedence over a
+    /// detached signature at `<url>.sig`. Both `file://` and remote urls are
+    /// supported.
+    pub async fn fetch_signature(
+        client: &ClientWithMiddleware,
+        url: &Url,
+    ) -> Result<SignatureMaterial, SignatureError> {
+        if let Some(bundle) = fetch(client, &with_suffix(url, ".sigstore.json")).await? {
+            let bundle = String::from_utf8(bundle)
+                .map_err(|err| SignatureError::InvalidBundle(err.to_string()))?;
+            return Ok(SignatureMaterial::Bundle(Bundle::from_json(&bundle)?));
+        }
+        if let Some(signature) = fetch(client, &with_suffix(url, ".sig")).await? {
+            return Ok(SignatureMaterial::from_detached(&signature));
+        }
+        Err(SignatureError::MissingSignature(
+            url.clone().redact().to_string(),
+        ))
+    }
+
+    /// Verifies that the file at `path`, which was downloaded from `url`, is
+    /// signed by a trusted key or identity.
+    pub async fn verify_file(
+        &self,
+        client: &ClientWithMiddleware,
+        url: &Url,
+        path: &Path,
+    ) -> Result<(), SignatureError> {
+        let signature = Self::fetch_signature(client, url).await?;
+        let path = path.to_path_buf();
+        let sha256 = simple_spawn_blocking::tokio::run_blocking_task(move || {
+            rattler_digest::compute_file_digest::<Sha256>(&path)
+                .map_err(|err| SignatureError::IoError(path.display().to_string(), err))
+        })
+        .await?;
+        self.verify_digest(&sha256, &signature)
+    }
+}
+
+fn verify_prehash(
+    key: &PublicKey,
+    sha256: &Sha256Hash,
+    signature: &[u8],
+) -> Result<(), SignatureError> {
+    let signature =
+        Signature::from_der(signature).map_err(|_err| SignatureError::InvalidSignature)?;
+    VerifyingKey::from(key)
+        .verify_prehash(sha256, &signature)
+        .map_err(|_err| SignatureError::InvalidSignature)
+}
+
+fn with_suffix(url: &Url, suffix: &str) -> Url {
+    let mut url = url.clone();
+    let path = format!("{}{suffix}", url.path());
+    url.set_path(&path);
+    url
+}
+
+/// Returns the contents of the file at `url` or `None` if it does not exist.
+async fn fetch(
+    client: &ClientWithMiddleware,
+    url: &Url,
+) -> Result<Option<Vec<u8>>, SignatureError> {
+    if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|_err| SignatureError::MissingSignature(url.to_string()))?;
+        return match fs_err::tokio::read(&path).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(SignatureError::IoError(path.display().to_string(), err)),
+        };
+    }
+
+    let fetch_error = |err| SignatureError::FetchError(url.clone().redact().to_string(), err);
+    let response = client.get(url.clone()).send().await.map_err(fetch_error)?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let bytes = async { response.error_for_status()?.bytes().await }
+        .await
+        .map_err(|err| fetch_error(reqwest_middleware::Error::Reqwest(err)))?;
+    Ok(Some(bytes.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use p256::{
+        ecdsa::{
+            signature::{hazmat::PrehashSigner, Signer},
+            Signature, SigningKey,
+        },
+        pkcs8::{DecodePrivateKey, EncodePublicKey},
+        PublicKey,
+    };
+    use rattler_digest::{compute_bytes_digest, Sha256, Sha256Hash};
+    use reqwest_middleware::ClientWithMiddleware;
+    use url::Url;
+
+    use super::SignatureVerifier;
+    use crate::{
+        trust_root::parse_pem, Bundle, IdentityPolicy, LogEntry, SignatureError, SignatureMaterial,
+        TrustRoot,
+    };
+
+    const ARTIFACT: &[u8] = b"{\"packages\": {}}";
+
+    /// A time at which `leaf.pem` is valid. The certificate is only valid
+    /// for ten minutes, like the certificates issued by Fulcio.
+    const SIGNED_AT: u64 = 1_735_689_900;
+
+    fn signatures_dir() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data/signatures")
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32]).unwrap()
+    }
+
+    fn pem_key(name: &str) -> SigningKey {
+        let pem = fs_err::read_to_string(signatures_dir().join(name)).unwrap();
+        SigningKey::from_pkcs8_der(&parse_pem(&pem).unwrap().remove(0).1).unwrap()
+    }
+
+    fn leaf_key() -> SigningKey {
+        pem_key("leaf-key.pem")
+    }
+
+    fn leaf_certificate() -> Vec<u8> {
+        let leaf = fs_err::read_to_string(signatures_dir().join("leaf.pem")).unwrap();
+        parse_pem(&leaf).unwrap().remove(0).1
+    }
+
+    fn sign(key: &SigningKey, sha256: &Sha256Hash) -> Vec<u8> {
+        let signature: Signature = key.sign_prehash(sha256).unwrap();
+        signature.to_der().as_bytes().to_vec()
+    }
+
+    fn key_verifier() -> SignatureVerifier {
+        let der = PublicKey::from(signing_key().verifying_key())
+            .to_public_key_der()
+            .unwrap();
+        let mut trust_root = TrustRoot::new();
+        trust_root.add_public_key_der(der.as_bytes()).unwrap();
+        SignatureVerifier::new(trust_root)
+    }
+
+    fn certificate_verifier() -> SignatureVerifier {
+        let mut trust_root = TrustRoot::from_pem_file(signatures_dir().join("ca.pem")).unwrap();
+        trust_root
+            .add_transparency_log_pem(
+                &fs_err::read_to_string(signatures_dir().join("rekor.pem")).unwrap(),
+            )
+            .unwrap();
+        SignatureVerifier::new(trust_root)
+    }
+
+    /// Records the signature of an artifact in the test transparency log.
+    fn log_entry(sha256: &Sha256Hash, signature: &[u8], integrated_time: u64) -> LogEntry {
+        let leaf = fs_err::read_to_string(signatures_dir().join("leaf.pem")).unwrap();
+        let body = serde_json::json!({
+            "apiVersion": "0.0.1",
+            "kind": "hashedrekord",
+            "spec": {
+                "data": { "hash": { "algorithm": "sha256", "value": format!("{sha256:x}") } },
+                "signature": {
+                    "content": STANDARD.encode(signature),
+                    "publicKey": { "content": STANDARD.encode(leaf) },
+                },
+            },
+        })
+        .to_string()
+        .into_bytes();
+
+        let log_key = pem_key("rekor-key.pem");
+        let log_id = compute_bytes_digest::<Sha256>(
+            PublicKey::from(log_key.verifying_key())
+                .to_public_key_der()
+                .unwrap()
+                .as_bytes(),
+        );
+        let payload = format!(
+            r#"{{"body":"{}","integratedTime":{integrated_time},"logID":"{log_id:x}","logIndex":7}}"#,
+            STANDARD.encode(&body)
+        );
+        let signed_entry_timestamp: Signature = log_key.sign(payload.as_bytes());
+        LogEntry {
+            log_index: 7,
+            log_id: log_id.to_vec(),
+            integrated_time,
+            canonicalized_body: body,
+            signed_entry_timestamp: Some(signed_entry_timestamp.to_der().as_bytes().to_vec()),
+        }
+    }
+
+    fn certificate_bundle(sha256: &Sha256Hash) -> Bundle {
+        let signature = sign(&leaf_key(), sha256);
+        Bundle {
+            certificates: vec![leaf_certificate()],
+            log_entries: vec![log_entry(sha256, &signature, SIGNED_AT)],
+            message_digest: Some(sha256.to_vec()),
+            signature,
+        }
+    }
+
+    fn verify_bundle(bundle: Bundle) -> Result<(), SignatureError> {
+        let sha256 = compute_bytes_digest::<Sha256>(ARTIFACT);
+        certificate_verifier()
+            .with_identity_policy(IdentityPolicy::new("publisher@example.com"))
+            .verify_digest(&sha256, &SignatureMaterial::Bundle(bundle))
+    }
+
+    #[test]
+    fn test_detached_signature() {
+        let sha256 = compute_bytes_digest::<Sha256>(ARTIFACT);
+        let verifier = key_verifier();
+        let signature = SignatureMaterial::Detached(sign(&signing_key(), &sha256));
+        verifier.verify_digest(&sha256, &signature).unwrap();
+
+        let other = compute_bytes_digest::<Sha256>(b"tampered");
+        let result = verifier.verify_digest(&other, &signature);
+        assert!(
+            matches!(result, Err(SignatureError::InvalidSignature)),
+            "{result:?}"
+        );
+
+        // A signature by an unknown key is rejected.
+        let signature = SignatureMaterial::Detached(sign(&leaf_key(), &sha256));
+        let result = verifier.verify_digest(&sha256, &signature);
+        assert!(
+            matches!(result, Err(SignatureError::InvalidSignature)),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_certificate_bundle() {
+        let sha256 = compute_bytes_digest::<Sha256>(ARTIFACT);
+        let bundle = SignatureMaterial::Bundle(certificate_bundle(&sha256));
+
+        certificate_verifier()
+            .with_identity_policy(
+                IdentityPolicy::new("publisher@example.com")
+                    .with_issuer("https://issuer.example.com"),
+            )
+            .verify_digest(&sha256, &bundle)
+            .unwrap();
+
+        let result = certificate_verifier()
+            .with_identity_policy(IdentityPolicy::new("someone-else@example.com"))
+            .verify_digest(&sha256, &bundle);
+        assert!(
+            matches!(result, Err(SignatureError::IdentityMismatch { .. })),
+            "{result:?}"
+        );
+
+        // Without a policy any identity of the certificate authority would be
+        // accepted.
+        let result = certificate_verifier().verify_digest(&sha256, &bundle);
+        assert!(
+            matches!(result, Err(SignatureError::MissingIdentityPolicy)),
+            "{result:?}"
+        );
+
+        // The certificate authority is not trusted.
+        let mut trust_root = TrustRoot::new();
+        trust_root
+            .add_transparency_log_pem(
+                &fs_err::read_to_string(signatures_dir().join("rekor.pem")).unwrap(),
+            )
+            .unwrap();
+        let result = SignatureVerifier::new(trust_root)
+            .with_identity_policy(IdentityPolicy::new("publisher@example.com"))
+            .verify_digest(&sha256, &bundle);
+        assert!(
+            matches!(result, Err(SignatureError::UntrustedCertificate(_))),
+            "{result:?}"
+        );
+
+        // The bundle was created for a different artifact.
+        let other = compute_bytes_digest::<Sha256>(b"tampered");
+        let result = certificate_verifier()
+            .with_identity_policy(IdentityPolicy::new("publisher@example.com"))
+            .verify_digest(&other, &bundle);
+        assert!(
+            matches!(result, Err(SignatureError::DigestMismatch { .. })),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_expired_certificate() {
+        let sha256 = compute_bytes_digest::<Sha256>(ARTIFACT);
+
+        // The certificate expired long ago, but it was valid when the
+        // signature was recorded in the log.
+        verify_bundle(certificate_bundle(&sha256)).unwrap();
+
+        // The signature was recorded after the certificate expired.
+        let mut bundle = certificate_bundle(&sha256);
+        bundle.log_entries = vec![log_entry(&sha256, &bundle.signature, SIGNED_AT + 600)];
+        let result = verify_bundle(bundle);
+        assert!(
+            matches!(result, Err(SignatureError::UntrustedCertificate(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_log_entry() {
+        let sha256 = compute_bytes_digest::<Sha256>(ARTIFACT);
+
+        let mut bundle = certificate_bundle(&sha256);
+        bundle.log_entries.clear();
+        let result = verify_bundle(bundle);
+        assert!(
+            matches!(result, Err(SignatureError::UnverifiedLogEntry(_))),
+            "{result:?}"
+        );
+
+        // The integrated time is covered by the signed entry timestamp.
+        let mut bundle = certificate_bundle(&sha256);
+        bundle.log_entries[0].integrated_time -= 60;
+        let result = verify_bundle(bundle);
+        assert!(
+            matches!(result, Err(SignatureError::UnverifiedLogEntry(_))),
+            "{result:?}"
+        );
+
+        // The entry records the signature of another artifact.
+        let other = compute_bytes_digest::<Sha256>(b"tampered");
+        let mut bundle = certificate_bundle(&sha256);
+        bundle.log_entries = vec![log_entry(&other, &sign(&leaf_key(), &other), SIGNED_AT)];
+        let result = verify_bundle(bundle);
+        assert!(
+            matches!(result, Err(SignatureError::UnverifiedLogEntry(_))),
+            "{result:?}"
+        );
+
+        // The log is not trusted.
+        let bundle = certificate_bundle(&sha256);
+        let result = SignatureVerifier::new(
+            TrustRoot::from_pem_file(signatures_dir().join("ca.pem")).unwrap(),
+        )
+        .with_identity_policy(IdentityPolicy::new("publisher@example.com"))
+        .verify_digest(&sha256, &SignatureMaterial::Bundle(bundle));
+        assert!(
+            matches!(result, Err(SignatureError::UnverifiedLogEntry(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_bundle_fixture() {
+        let channel = signatures_dir().join("channel/noarch");
+        let bundle = fs_err::read_to_string(channel.join("repodata.json.sigstore.json")).unwrap();
+        let sha256 =
+            rattler_digest::compute_file_digest::<Sha256>(channel.join("repodata.json")).unwrap();
+        certificate_verifier()
+            .with_identity_policy(IdentityPolicy::new("publisher@example.com"))
+            .verify_digest(
+                &sha256,
+                &SignatureMaterial::Bundle(Bundle::from_json(&bundle).unwrap()),
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let artifact = temp_dir.path().join("repodata.json");
+        fs_err::write(&artifact, ARTIFACT).unwrap();
+        let url = Url::from_file_path(&artifact).unwrap();
+        let client = ClientWithMiddleware::from(reqwest::Client::new());
+        let verifier = key_verifier();
+
+        let result = verifier.verify_file(&client, &url, &artifact).await;
+        assert!(
+            matches!(result, Err(SignatureError::MissingSignature(_))),
+            "{result:?}"
+        );
+
+        let sha256 = compute_bytes_digest::<Sha256>(ARTIFACT);
+        let signature = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            sign(&signing_key(), &sha256),
+        );
+        fs_err::write(temp_dir.path().join("repodata.json.sig"), signature).unwrap();
+        verifier
+            .verify_file(&client, &url, &artifact)
+            .await
+            .unwrap();
+
+        // The bundle takes precedence over the detached signature.
+        fs_err::write(
+            temp_dir.path().join("repodata.json.sigstore.json"),
+            serde_json::json!({
+                "verificationMaterial": { "publicKey": { "hint": "" } },
+                "messageSignature": {
+                    "signature": base64::Engine::encode(
+                        &base64::engine::general_purpose::STANDARD,
+                        sign(&leaf_key(), &sha256),
+                    ),
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let result = verifier.verify_file(&client, &url, &artifact).await;
+        assert!(
+            matches!(result, Err(SignatureError::InvalidSignature)),
+            "{result:?}"
+        );
+    }
+}
@@ -1,10 +1,14 @@
 pub mod upload;
 pub(crate) mod utils;
 
+use std::path::PathBuf;
+
 use miette::IntoDiagnostic;
 use rattler_conda_types::package::ArchiveType;
+use rattler_package_streaming::validate::validate_package;
 use upload::opt::{
-    AnacondaData, ArtifactoryData, CondaForgeData, PrefixData, QuetzData, ServerType, UploadOpts,
+    AnacondaData, ArtifactoryData, CondaForgeData, OciData, PrefixData, QuetzData, ServerType,
+    UploadOpts,
 };
 
 use crate::utils::tool_configuration;
@@ -25,9 +29,14 @@ pub async fn upload_from_args(args: UploadOpts) -> miette::Result<()> {
         }
     }
 
+    if !args.skip_validation {
+        validate_packages(&args.package_files).await?;
+    }
+
     // Initialize authentication store
     let store = tool_configuration::get_auth_store(args.common.auth_file, args.auth_store)
         .into_diagnostic()?;
+    let client_config = args.client_config.unwrap_or_default();
 
     // Upload handler based on server type
     match args.server_type {
@@ -49,10 +58,16 @@ pub async fn upload_from_args(args: UploadOpts) -> miette::Result<()> {
             let anaconda_data = AnacondaData::from(anaconda_opts);
             upload::upload_package_to_anaconda(&store, &args.package_files, anaconda_data).await
         }
+        ServerType::Oci(oci_opts) => {
+            let oci_data = OciData::from(oci_opts);
+            upload::upload_package_to_oci(&store, &client_config, &args.package_files, oci_data)
+                .await
+        }
         #[cfg(feature = "s3")]
         ServerType::S3(s3_opts) => {
             upload::upload_package_to_s3(
                 &store,
+                &client_config,
                 s3_opts.channel,
                 s3_opts.credentials.into(),
                 &args.package_files,
@@ -70,3 +85,29 @@ pub async fn upload_from_args(args: UploadOpts) -> miette::Result<()> {
         }
     }
 }
+
+/// Validates the package files before they are uploaded and returns an error
+/// listing all problems that were found.
+async fn validate_packages(package_files: &[PathBuf]) -> miette::Result<()> {
+    for package_file in package_files {
+        let path = package_file.clone();
+        let report = tokio::task::spawn_blocking(move || validate_package(&path))
+            .await
+            .into_diagnostic()?
+            .into_diagnostic()?;
+        if !report.is_valid() {
+            let errors = report
+                .errors
+                .iter()
+                .map(|e| format!("  - {e}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Err(miette::miette!(
+                help = "pass --skip-validation to upload the package anyway",
+                "The package {} failed validation:\n{errors}",
+                package_file.display()
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use fs_err::tokio as fs;
+use miette::IntoDiagnostic;
+use rattler_conda_types::{package::ArchiveType, ChannelInfo, PackageRecord, RepoData};
+use rattler_digest::{compute_file_digest, Md5, Sha256};
+use rattler_networking::{AuthenticationStorage, ClientConfig, OciMiddleware};
+use reqwest::StatusCode;
+use tracing::info;
+
+use super::{opt::OciData, package::ExtractedPackage};
+
+/// Uploads package files to an OCI registry and adds them to the
+/// `repodata.json` of their subdirectory.
+///
+/// Artifacts are stored in the layout that is read by [`OciMiddleware`], so
+/// the channel can be used as `oci://<registry>/<channel>` afterwards.
+pub async fn upload_package_to_oci(
+    storage: &AuthenticationStorage,
+    client_config: &ClientConfig,
+    package_files: &Vec<PathBuf>,
+    oci_data: OciData,
+) -> miette::Result<()> {
+    let middleware = OciMiddleware::new(storage.clone());
+    let retry = reqwest_retry::RetryTransientMiddleware::new_with_policy(
+        reqwest_retry::policies::ExponentialBackoff::builder().build_with_max_retries(3),
+    );
+    let client = client_config
+        .build_with_middleware(storage.clone(), vec![Arc::new(retry)])
+        .into_diagnostic()?;
+    let pull_client = client_config.build(storage.clone()).into_diagnostic()?;
+
+    let mut records: BTreeMap<String, Vec<(String, PackageRecord)>> = BTreeMap::new();
+    for package_file in package_files {
+        let package = ExtractedPackage::from_package_file(package_file)?;
+        let subdir = package.subdir().ok_or_else(|| {
+            miette::miette!(
+                "index.json of package {} has no subdirectory. Cannot determine which directory to upload to",
+                package_file.display()
+            )
+        })?;
+        let filename = package.filename().ok_or_else(|| {
+            miette::miette!("Package file {} has no filename", package_file.display())
+        })?;
+
+        let url = oci_data
+            .channel
+            .join(&format!("{subdir}/{filename}"))
+            .into_diagnostic()?;
+        info!("Pushing {} to {}", filename, url);
+
+        let data = fs::read(package_file).await.into_diagnostic()?;
+        let record = package_record(package_file, &package, data.len() as u64)?;
+        middleware
+            .push(&client, &url, data)
+            .await
+            .into_diagnostic()?;
+
+        records
+            .entry(subdir.clone())
+            .or_default()
+            .push((filename.to_string(), record));
+    }
+
+    for (subdir, records) in records {
+        let url = oci_data
+            .channel
+            .join(&format!("{subdir}/repodata.json"))
+            .into_diagnostic()?;
+
+        let response = pull_client
+            .get(url.clone())
+            .send()
+            .await
+            .into_diagnostic()?;
+        let mut repodata = if response.status() == StatusCode::NOT_FOUND {
+            RepoData {
+                info: Some(ChannelInfo {
+                    subdir: Some(subdir.clone()),
+                    base_url: None,
+                }),
+                packages: HashMap::default(),
+                conda_packages: HashMap::default(),
+                removed: HashSet::default(),
+                version: Some(2),
+            }
+        } else {
+            response
+                .error_for_status()
+                .into_diagnostic()?
+                .json::<RepoData>()
+                .await
+                .into_diagnostic()?
+        };
+
+        for (filename, record) in records {
+            match ArchiveType::try_from(Path::new(&filename)) {
+                Some(ArchiveType::Conda) => repodata.conda_packages.insert(filename, record),
+                _ => repodata.packages.insert(filename, record),
+            };
+        }
+
+        info!("Pushing {}", url);
+        let data = serde_json::to_vec(&repodata).into_diagnostic()?;
+        middleware
+            .push(&client, &url, data)
+            .await
+            .into_diagnostic()?;
+    }
+
+    info!("Packages successfully uploaded to the OCI registry");
+
+    Ok(())
+}
+
+fn package_record(
+    package_file: &Path,
+    package: &ExtractedPackage<'_>,
+    size: u64,
+) -> miette::Result<PackageRecord> {
+    let sha256 = compute_file_digest::<Sha256>(package_file).into_diagnostic()?;
+    let md5 = compute_file_digest::<Md5>(package_file).into_diagnostic()?;
+    PackageRecord::from_index_json(
+        package.index_json().clone(),
+        Some(size),
+        Some(sha256),
+        Some(md5),
+    )
+    .into_diagnostic()
+}
@@ -7,7 +7,7 @@ use rattler_conda_types::{
 };
 #[cfg(feature = "s3")]
 use rattler_networking::s3_middleware;
-use rattler_networking::{mirror_middleware, AuthenticationStorage};
+use rattler_networking::{mirror_middleware, AuthenticationStorage, ClientConfig};
 use rattler_solve::ChannelPriority;
 use tracing::warn;
 use url::Url;
@@ -170,8 +170,15 @@ pub struct UploadOpts {
     #[clap(flatten)]
     pub common: CommonOpts,
 
+    /// Skip validating the packages before uploading them
+    #[arg(long, global = true)]
+    pub skip_validation: bool,
+
     #[clap(skip)]
     pub auth_store: Option<AuthenticationStorage>,
+
+    #[clap(skip)]
+    pub client_config: Option<ClientConfig>,
 }
 
 impl UploadOpts {
@@ -179,6 +186,13 @@ impl UploadOpts {
         self.auth_store = auth_store;
         self
     }
+
+    /// Use the proxy and TLS settings of `client_config` for the uploads to
+    /// OCI registries and S3 buckets.
+    pub fn with_client_config(mut self, client_config: Option<ClientConfig>) -> Self {
+        self.client_config = client_config;
+        self
+    }
 }
 
 /// Server type.
@@ -189,6 +203,7 @@ pub enum ServerType {
     Artifactory(ArtifactoryOpts),
     Prefix(PrefixOpts),
     Anaconda(AnacondaOpts),
+    Oci(OciOpts),
     #[cfg(feature = "s3")]
     S3(S3Opts),
     #[clap(hide = true)]
@@ -409,6 +424,49 @@ pub struct AnacondaOpts {
     pub force: bool,
 }
 
+fn parse_oci_url(value: &str) -> Result<Url, String> {
+    let url: Url =
+        Url::parse(value).map_err(|err| format!("`{value}` isn't a valid URL: {err}"))?;
+    if url.scheme() == "oci" && url.host_str().is_some() {
+        Ok(url)
+    } else {
+        Err(format!(
+            "Only OCI URLs of format oci://registry/... can be used, not `{value}`"
+        ))
+    }
+}
+
+/// Options for uploading to an OCI registry.
+/// Authentication is used from the keychain / auth-file.
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct OciOpts {
+    /// The channel URL in the OCI registry to upload the package to, e.g.,
+    /// `oci://ghcr.io/my-org/my-channel`
+    #[arg(short, long, env = "OCI_CHANNEL", value_parser = parse_oci_url)]
+    pub channel: Url,
+}
+
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct OciData {
+    pub channel: UrlWithTrailingSlash,
+}
+
+impl From<OciOpts> for OciData {
+    fn from(value: OciOpts) -> Self {
+        Self::new(value.channel)
+    }
+}
+
+impl OciData {
+    /// Create a new instance of `OciData`
+    pub fn new(channel: Url) -> Self {
+        Self {
+            channel: channel.into(),
+        }
+    }
+}
+
 #[cfg(feature = "s3")]
 fn parse_s3_url(value: &str) -> Result<Url, String> {
     let url: Url =
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use miette::IntoDiagnostic;
 use opendal::{services::S3Config, Configurator, ErrorKind, Operator};
 use rattler_digest::{HashingReader, Md5, Sha256};
-use rattler_networking::AuthenticationStorage;
+use rattler_networking::{AuthenticationStorage, ClientConfig};
 use rattler_s3::{ResolvedS3Credentials, S3Credentials};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::bytes::BytesMut;
@@ -17,6 +17,7 @@ const DESIRED_CHUNK_SIZE: usize = 1024 * 1024 * 10;
 #[allow(clippy::too_many_arguments)]
 pub async fn upload_package_to_s3(
     auth_storage: &AuthenticationStorage,
+    client_config: &ClientConfig,
     channel: Url,
     credentials: Option<S3Credentials>,
     package_files: &Vec<PathBuf>,
@@ -50,7 +51,13 @@ pub async fn upload_package_to_s3(
         resolved_credentials.addressing_style == rattler_s3::S3AddressingStyle::VirtualHost;
 
     let builder = s3_config.into_builder();
-    let op = Operator::new(builder).into_diagnostic()?.finish();
+    let client = client_config
+        .build(auth_storage.clone())
+        .into_diagnostic()?;
+    let op = Operator::new(builder)
+        .into_diagnostic()?
+        .layer(rattler_s3::opendal::http_client_layer(client))
+        .finish();
 
     for package_file in package_files {
         let package = ExtractedPackage::from_package_file(package_file)?;
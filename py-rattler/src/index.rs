@@ -34,6 +34,7 @@ pub fn py_index_fs(
             write_zst,
             write_shards,
             force,
+            validate_packages: false,
             max_parallel: max_parallel.unwrap_or_else(default_max_concurrent_solves),
             multi_progress: None,
         })
@@ -87,6 +88,7 @@ pub fn py_index_s3<'py>(
             write_zst,
             write_shards,
             force,
+            validate_packages: false,
             max_parallel: max_parallel.unwrap_or_else(default_max_concurrent_solves),
             multi_progress: None,
         })
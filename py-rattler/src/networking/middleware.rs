@@ -86,7 +86,7 @@ impl PyOciMiddleware {
 
 impl From<PyOciMiddleware> for OciMiddleware {
     fn from(_value: PyOciMiddleware) -> Self {
-        OciMiddleware
+        OciMiddleware::default()
     }
 }
 